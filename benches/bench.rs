@@ -1,5 +1,3 @@
-#![feature(test)]
-
 // Basic microbenchmark.
 //
 // Usage:
@@ -9,10 +7,12 @@
 
 extern crate docopt;
 extern crate shortcut;
-extern crate test;
 extern crate time;
 
+use std::alloc::{GlobalAlloc, Layout, System};
 use std::borrow::Cow;
+use std::hint;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use docopt::Docopt;
 use shortcut::cmp;
@@ -20,18 +20,39 @@ use shortcut::idx;
 use shortcut::Store;
 use time::PreciseTime;
 
-const USAGE: &'static str = "
+const USAGE: &str = "
 Benchmark shortcut.
 
 Usage:
-  bench [--rounds=N --use-index --bench]
+  bench [--rounds=N --use-index --batch --bench]
 
 Options:
   --rounds=N               Number of rounds to run. [default: 1000000]
   --use-index              Install a hash index for fast lookups.
+  --batch                  Insert all rows with a single call to `insert_many`.
   --bench                  Appease `cargo bench`. No effect.
 ";
 
+/// The system allocator, but counting every allocation, so that we can tell how many allocations
+/// each lookup makes.
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
 fn main() {
     let args = Docopt::new(USAGE)
         .and_then(|dopt| dopt.parse())
@@ -48,25 +69,36 @@ fn main() {
     let t0 = PreciseTime::now();
 
     // Put.
-    for i in 0..rounds {
-        let istr = format!("{}", i);
-        store.insert(vec![istr.clone(), istr])
+    if args.get_bool("--batch") {
+        store.insert_many((0..rounds).map(|i| {
+            let istr = format!("{}", i);
+            vec![istr.clone(), istr]
+        }));
+    } else {
+        for i in 0..rounds {
+            let istr = format!("{}", i);
+            store.insert(vec![istr.clone(), istr]);
+        }
     }
 
     let t1 = PreciseTime::now();
 
     // Get.
+    let mut allocations = 0;
     for i in 0..rounds {
         let cmp = [cmp::Condition {
             column: 0,
             cmp: cmp::Comparison::Equal(cmp::Value::Const(Cow::Owned(format!("{}", i)))),
         }];
 
+        // only count the allocations made by the lookup itself, not those made for its key
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
         let rows = store.find(&cmp);
 
         for row in rows {
-            test::black_box(row);
+            hint::black_box(row);
         }
+        allocations += ALLOCATIONS.load(Ordering::Relaxed) - before;
     }
 
     let t2 = PreciseTime::now();
@@ -82,6 +114,11 @@ fn main() {
         t1.to(t2).num_milliseconds(),
         ops_per_sec(rounds, t1, t2)
     );
+
+    println!(
+        "allocations per get: {:.2}",
+        allocations as f64 / rounds as f64
+    );
 }
 
 fn ops_per_sec(rounds: u32, start: PreciseTime, end: PreciseTime) -> f64 {
@@ -0,0 +1,299 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::BinaryHeap;
+use std::iter::Sum;
+use std::ops::Bound;
+
+use EqualityIndex;
+use Index;
+use Row;
+use Store;
+use cmp::{Condition, Nullable};
+
+/// The result of `Store::aggregate`: the number of matching rows, along with the smallest and
+/// largest value in the aggregated column among those rows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Aggregate<'s, T: 's> {
+    /// The number of matching rows.
+    pub count: usize,
+    /// The smallest value among the matching rows, or `None` if no rows matched.
+    pub min: Option<&'s T>,
+    /// The largest value among the matching rows, or `None` if no rows matched.
+    pub max: Option<&'s T>,
+}
+
+/// The result of `Store::column_stats`: a summary of all the values in one column.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnSummary<'s, T: 's> {
+    /// The number of rows in the `Store`.
+    pub rows: usize,
+    /// The number of distinct values in the column.
+    pub distinct: usize,
+    /// The smallest value in the column, or `None` if the `Store` is empty.
+    pub min: Option<&'s T>,
+    /// The largest value in the column, or `None` if the `Store` is empty.
+    pub max: Option<&'s T>,
+}
+
+impl<T, R> Store<T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Count the rows matching all the given `Condition`s, and find the smallest and largest
+    /// value in column `col` among them. Rows are found as by `find`.
+    ///
+    /// If there are no conditions, and `col` has an `Index::Range`, the smallest and largest
+    /// values are taken from either end of the index, without looking at any other rows.
+    ///
+    /// Panics if `col` is out of range for this `Store`.
+    pub fn aggregate<'c, 's: 'c>(&'s self,
+                                 conds: &'c [Condition<'c, T>],
+                                 col: usize)
+                                 -> Aggregate<'s, T> {
+        assert!(col < self.cols,
+                "aggregated column {} is out of range for a store with {} columns",
+                col,
+                self.cols);
+        if let (&[], Some(Index::Range(ri))) = (conds, self.complete_index(col)) {
+            let value = |rowid| self.rows[&rowid].index(col);
+            return Aggregate {
+                count: self.len(),
+                min: self.live(ri.between(Bound::Unbounded, Bound::Unbounded)).next().map(value),
+                max: self.live(ri.between_rev(Bound::Unbounded, Bound::Unbounded))
+                    .next()
+                    .map(value),
+            };
+        }
+
+        let init = Aggregate {
+            count: 0,
+            min: None,
+            max: None,
+        };
+        self.find(conds).map(|r| r.index(col)).fold(init, |agg, v| {
+            Aggregate {
+                count: agg.count + 1,
+                min: Some(agg.min.map_or(v, |min| min.min(v))),
+                max: Some(agg.max.map_or(v, |max| max.max(v))),
+            }
+        })
+    }
+
+    /// Sum the values in column `col` of all rows matching all the given `Condition`s. The sum
+    /// can be of any type that can be summed from references to values, such as `usize` for a
+    /// `Store<usize>`. The sum over no rows is whatever that type considers empty, typically
+    /// zero.
+    ///
+    /// Panics if `col` is out of range for this `Store`.
+    pub fn sum<'c, 's: 'c, S>(&'s self, conds: &'c [Condition<'c, T>], col: usize) -> S
+        where S: Sum<&'s T>
+    {
+        assert!(col < self.cols,
+                "aggregated column {} is out of range for a store with {} columns",
+                col,
+                self.cols);
+        self.find(conds).map(|r| r.index(col)).sum()
+    }
+
+    /// Group the rows matching all the given `Condition`s by their value in column `group_col`.
+    /// Returns an iterator that yields each distinct value along with the rows that have it, in
+    /// the order they were inserted. Values that no matching row has are not yielded.
+    ///
+    /// If there are no conditions, and `group_col` has an index that can enumerate its values
+    /// (see `EqualityIndex::keys`), the groups are read straight from the index, and are yielded
+    /// in the order the index gives its values in. Otherwise, the matching rows are found as by
+    /// `find`, and the groups are yielded in ascending order of value.
+    ///
+    /// Panics if `group_col` is out of range for this `Store`.
+    pub fn group_by<'c, 's: 'c>(&'s self,
+                                conds: &'c [Condition<'c, T>],
+                                group_col: usize)
+                                -> Box<dyn Iterator<Item = (&'s T, Vec<&'s R>)> + 's> {
+        assert!(group_col < self.cols,
+                "grouped column {} is out of range for a store with {} columns",
+                group_col,
+                self.cols);
+        if conds.is_empty() {
+            if let Some(keys) = self.complete_index(group_col).and_then(|idx| idx.keys()) {
+                let idx = &self.indices[&group_col];
+                return Box::new(keys.filter_map(move |key| {
+                    let mut rowids = self.live(idx.lookup(key)).collect::<Vec<_>>();
+                    if rowids.is_empty() {
+                        return None;
+                    }
+                    rowids.sort_unstable();
+                    Some((key, rowids.into_iter().map(|rowid| &self.rows[&rowid]).collect()))
+                }));
+            }
+        }
+
+        let mut groups: BTreeMap<&'s T, Vec<&'s R>> = BTreeMap::new();
+        for row in self.find(conds) {
+            groups.entry(row.index(group_col)).or_default().push(row);
+        }
+        Box::new(groups.into_iter())
+    }
+
+    /// Returns an iterator that yields every distinct value in column `col`, once.
+    ///
+    /// If `col` has an index that can enumerate its values (see `EqualityIndex::keys`), the values
+    /// are read straight from the index, in the order the index gives them in; for an
+    /// `Index::Range`, that is ascending order. Otherwise, all rows are scanned, and the values
+    /// are yielded in ascending order.
+    ///
+    /// Panics if `col` is out of range for this `Store`.
+    pub fn distinct<'s>(&'s self, col: usize) -> Box<dyn Iterator<Item = &'s T> + 's> {
+        assert!(col < self.cols,
+                "column {} is out of range for a store with {} columns",
+                col,
+                self.cols);
+        if let Some(keys) = self.complete_index(col).and_then(|idx| idx.keys()) {
+            if self.dead == 0 {
+                return keys;
+            }
+            // some values may only be held by rows that have been marked as deleted
+            let idx = &self.indices[&col];
+            return Box::new(keys.filter(move |key| self.live(idx.lookup(key)).next().is_some()));
+        }
+
+        let values: BTreeSet<_> = self.rows().map(|row| row.index(col)).collect();
+        Box::new(values.into_iter())
+    }
+
+    /// Returns the number of distinct values in column `col`. See `distinct`.
+    ///
+    /// Panics if `col` is out of range for this `Store`.
+    pub fn cardinality(&self, col: usize) -> usize {
+        self.distinct(col).count()
+    }
+
+    /// Summarize the values in column `col`: the number of rows, the number of distinct values,
+    /// and the smallest and largest value. Values are compared using `Ord`, so for a type that
+    /// wraps floating point numbers, its `Ord` implementation decides where NaN sorts.
+    ///
+    /// If `col` has an `Index::Range`, the smallest and largest values are taken from either end
+    /// of the index, as by `aggregate`. If `col` has an index that can enumerate its values (see
+    /// `EqualityIndex::keys`), the number of distinct values is counted from the index, as by
+    /// `cardinality`. Otherwise, all rows are scanned once.
+    ///
+    /// Panics if `col` is out of range for this `Store`.
+    pub fn column_stats(&self, col: usize) -> ColumnSummary<'_, T> {
+        assert!(col < self.cols,
+                "column {} is out of range for a store with {} columns",
+                col,
+                self.cols);
+        let ends = match self.complete_index(col) {
+            Some(Index::Range(ri)) => {
+                let value = |rowid| self.rows[&rowid].index(col);
+                let all = (Bound::Unbounded, Bound::Unbounded);
+                Some((self.live(ri.between(all.0, all.1)).next().map(value),
+                      self.live(ri.between_rev(all.0, all.1)).next().map(value)))
+            }
+            _ => None,
+        };
+        let keyed = self.complete_index(col).map(|idx| idx.keys().is_some()) == Some(true);
+        let (distinct, (min, max)) = match (keyed, ends) {
+            (true, Some(ends)) => (self.cardinality(col), ends),
+            (true, None) => {
+                let ends = self.rows().map(|row| row.index(col)).fold((None, None), |ends, v| {
+                    let (min, max): (Option<&T>, Option<&T>) = ends;
+                    (Some(min.map_or(v, |min| min.min(v))), Some(max.map_or(v, |max| max.max(v))))
+                });
+                (self.cardinality(col), ends)
+            }
+            (false, ends) => {
+                let values: BTreeSet<_> = self.rows().map(|row| row.index(col)).collect();
+                let ends = ends.unwrap_or((values.first().copied(), values.last().copied()));
+                (values.len(), ends)
+            }
+        };
+        ColumnSummary {
+            rows: self.len(),
+            distinct,
+            min,
+            max,
+        }
+    }
+
+    /// Returns the number of rows whose value in column `col` is null (see `Nullable`). All rows
+    /// are scanned.
+    ///
+    /// Panics if `col` is out of range for this `Store`.
+    pub fn null_count(&self, col: usize) -> usize
+        where T: Nullable
+    {
+        assert!(col < self.cols,
+                "column {} is out of range for a store with {} columns",
+                col,
+                self.cols);
+        self.rows().filter(|row| row.index(col).is_null()).count()
+    }
+
+    /// Returns the `k` rows with the smallest values in column `col` (or the largest, if
+    /// `descending` is true), ordered by that value. Exactly `k` rows are returned (or all rows,
+    /// if there are fewer than `k`), even if more rows share the value of the last row returned.
+    ///
+    /// If `col` has an `Index::Range`, the index is walked from the appropriate end, and only the
+    /// rows that are returned are looked at. Among rows that share a value, they are then returned
+    /// in the order the index gives them in. Otherwise, all rows are scanned, keeping only the
+    /// best `k` rows seen so far, and among rows that share a value, the ones inserted first are
+    /// preferred.
+    ///
+    /// Panics if `col` is out of range for this `Store`.
+    pub fn top_k(&self, col: usize, k: usize, descending: bool) -> Vec<&R> {
+        assert!(col < self.cols,
+                "column {} is out of range for a store with {} columns",
+                col,
+                self.cols);
+        if let Some(Index::Range(ri)) = self.complete_index(col) {
+            let rowids = if descending {
+                ri.between_rev(Bound::Unbounded, Bound::Unbounded)
+            } else {
+                ri.between(Bound::Unbounded, Bound::Unbounded)
+            };
+            return self.live(rowids).take(k).map(|rowid| &self.rows[&rowid]).collect();
+        }
+
+        let rows = self.rows.iter().filter(|&(&rowid, _)| !self.is_dead(rowid));
+        let rowids = if descending {
+            select(rows.map(|(&rowid, row)| (Reverse(row.index(col)), rowid)), k)
+        } else {
+            select(rows.map(|(&rowid, row)| (row.index(col), rowid)), k)
+        };
+        rowids.into_iter().map(|rowid| &self.rows[&rowid]).collect()
+    }
+
+    /// Returns the row with the smallest value in column `col`, or `None` if the `Store` is
+    /// empty. If several rows share that value, which one is returned is decided as by `top_k`.
+    ///
+    /// Panics if `col` is out of range for this `Store`.
+    pub fn min_row(&self, col: usize) -> Option<&R> {
+        self.top_k(col, 1, false).pop()
+    }
+
+    /// Returns the row with the largest value in column `col`, or `None` if the `Store` is empty.
+    /// If several rows share that value, which one is returned is decided as by `top_k`.
+    ///
+    /// Panics if `col` is out of range for this `Store`.
+    pub fn max_row(&self, col: usize) -> Option<&R> {
+        self.top_k(col, 1, true).pop()
+    }
+}
+
+/// Returns the row identifiers of the `k` smallest keys among the given keys and row
+/// identifiers, in order. Only `k` keys are kept in memory at a time.
+fn select<K: Ord, I: Iterator<Item = (K, usize)>>(keys: I, k: usize) -> Vec<usize> {
+    if k == 0 {
+        return Vec::new();
+    }
+    let mut best = BinaryHeap::with_capacity(k + 1);
+    for key in keys {
+        best.push(key);
+        if best.len() > k {
+            // drop the largest key kept so far
+            best.pop();
+        }
+    }
+    best.into_sorted_vec().into_iter().map(|(_, rowid)| rowid).collect()
+}
@@ -0,0 +1,305 @@
+use arrow_crate::array::{Array, ArrayRef, BooleanArray, BooleanBuilder, Float64Array,
+                         Float64Builder, Int64Array, Int64Builder, RecordBatch, StringArray,
+                         StringBuilder, UInt64Array, UInt64Builder};
+use arrow_crate::datatypes::{DataType, SchemaRef};
+use arrow_crate::error::ArrowError;
+
+use std::sync::Arc;
+
+use Row;
+use Store;
+use cmp::Condition;
+
+/// A single value in an Arrow array, as produced by the conversion function passed to
+/// `Store::to_record_batch`, and consumed by the one passed to `Store::from_record_batch`.
+///
+/// Each variant corresponds to one of the Arrow data types that are supported for export and
+/// import: `Boolean`, `Int64`, `UInt64`, `Float64`, and `Utf8`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Scalar {
+    /// A missing value, of any type.
+    Null,
+    /// A `DataType::Boolean` value.
+    Boolean(bool),
+    /// A `DataType::Int64` value.
+    Int64(i64),
+    /// A `DataType::UInt64` value.
+    UInt64(u64),
+    /// A `DataType::Float64` value.
+    Float64(f64),
+    /// A `DataType::Utf8` value.
+    Utf8(String),
+}
+
+/// A builder for the Arrow array of a single column.
+enum Builder {
+    Boolean(BooleanBuilder),
+    Int64(Int64Builder),
+    UInt64(UInt64Builder),
+    Float64(Float64Builder),
+    Utf8(StringBuilder),
+}
+
+impl Builder {
+    /// Allocate a builder for an array of the given type, with room for `capacity` values.
+    fn new(data_type: &DataType, capacity: usize) -> Result<Self, ArrowError> {
+        Ok(match *data_type {
+            DataType::Boolean => Builder::Boolean(BooleanBuilder::with_capacity(capacity)),
+            DataType::Int64 => Builder::Int64(Int64Builder::with_capacity(capacity)),
+            DataType::UInt64 => Builder::UInt64(UInt64Builder::with_capacity(capacity)),
+            DataType::Float64 => Builder::Float64(Float64Builder::with_capacity(capacity)),
+            // the size of the strings is not known up front, so only reserve room for offsets
+            DataType::Utf8 => Builder::Utf8(StringBuilder::with_capacity(capacity, 0)),
+            ref t => return Err(unsupported(t)),
+        })
+    }
+
+    /// Append a value to the array, which must be of the builder's type (or null).
+    fn append(&mut self, value: Scalar) -> Result<(), ArrowError> {
+        match (self, value) {
+            (Builder::Boolean(b), Scalar::Null) => b.append_null(),
+            (Builder::Int64(b), Scalar::Null) => b.append_null(),
+            (Builder::UInt64(b), Scalar::Null) => b.append_null(),
+            (Builder::Float64(b), Scalar::Null) => b.append_null(),
+            (Builder::Utf8(b), Scalar::Null) => b.append_null(),
+            (Builder::Boolean(b), Scalar::Boolean(v)) => b.append_value(v),
+            (Builder::Int64(b), Scalar::Int64(v)) => b.append_value(v),
+            (Builder::UInt64(b), Scalar::UInt64(v)) => b.append_value(v),
+            (Builder::Float64(b), Scalar::Float64(v)) => b.append_value(v),
+            (Builder::Utf8(b), Scalar::Utf8(v)) => b.append_value(v),
+            (_, v) => {
+                return Err(ArrowError::InvalidArgumentError(format!("value {:?} does not \
+                                                                     match the column's type",
+                                                                    v)))
+            }
+        }
+        Ok(())
+    }
+
+    /// Build the array from all values appended so far.
+    fn finish(&mut self) -> ArrayRef {
+        match *self {
+            Builder::Boolean(ref mut b) => Arc::new(b.finish()),
+            Builder::Int64(ref mut b) => Arc::new(b.finish()),
+            Builder::UInt64(ref mut b) => Arc::new(b.finish()),
+            Builder::Float64(ref mut b) => Arc::new(b.finish()),
+            Builder::Utf8(ref mut b) => Arc::new(b.finish()),
+        }
+    }
+}
+
+/// The Arrow array of a single column, downcast to its concrete type.
+enum Column<'a> {
+    Boolean(&'a BooleanArray),
+    Int64(&'a Int64Array),
+    UInt64(&'a UInt64Array),
+    Float64(&'a Float64Array),
+    Utf8(&'a StringArray),
+}
+
+impl<'a> Column<'a> {
+    /// Downcast the given array to its concrete type.
+    fn new(array: &'a dyn Array) -> Result<Self, ArrowError> {
+        let any = array.as_any();
+        let column = match *array.data_type() {
+            DataType::Boolean => any.downcast_ref().map(Column::Boolean),
+            DataType::Int64 => any.downcast_ref().map(Column::Int64),
+            DataType::UInt64 => any.downcast_ref().map(Column::UInt64),
+            DataType::Float64 => any.downcast_ref().map(Column::Float64),
+            DataType::Utf8 => any.downcast_ref().map(Column::Utf8),
+            ref t => return Err(unsupported(t)),
+        };
+        column.ok_or_else(|| unsupported(array.data_type()))
+    }
+
+    /// Returns the value at index `i` of the array.
+    fn get(&self, i: usize) -> Scalar {
+        match *self {
+            Column::Boolean(a) if a.is_valid(i) => Scalar::Boolean(a.value(i)),
+            Column::Int64(a) if a.is_valid(i) => Scalar::Int64(a.value(i)),
+            Column::UInt64(a) if a.is_valid(i) => Scalar::UInt64(a.value(i)),
+            Column::Float64(a) if a.is_valid(i) => Scalar::Float64(a.value(i)),
+            Column::Utf8(a) if a.is_valid(i) => Scalar::Utf8(a.value(i).to_string()),
+            _ => Scalar::Null,
+        }
+    }
+}
+
+/// Returns the error for a data type that cannot be exported or imported.
+fn unsupported(data_type: &DataType) -> ArrowError {
+    ArrowError::NotYetImplemented(format!("columns of type {} are not supported", data_type))
+}
+
+impl<T, R> Store<T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Export the rows matching all the given `Condition`s as an Arrow `RecordBatch` with the
+    /// given schema, using `convert` to turn each value into a `Scalar`. Rows are found as by
+    /// `find`, and appear in the batch in the order `find` yields them.
+    ///
+    /// The schema must have one field for every column of the `Store`, and every field must be of
+    /// one of the types supported by `Scalar`. Every value must convert to a `Scalar` of its
+    /// field's type, or to `Scalar::Null` if the field is nullable.
+    ///
+    /// The matching rows are collected before anything is converted, so that a builder with room
+    /// for every row can be allocated for each column up front. The values are then converted and
+    /// appended to the builders in a single pass over the rows.
+    pub fn to_record_batch<F>(&self,
+                              conds: &[Condition<T>],
+                              schema: SchemaRef,
+                              convert: F)
+                              -> Result<RecordBatch, ArrowError>
+        where F: Fn(&T) -> Scalar
+    {
+        if schema.fields().len() != self.cols {
+            return Err(ArrowError::InvalidArgumentError(format!("schema has {} fields, but \
+                                                                 the store has {} columns",
+                                                                schema.fields().len(),
+                                                                self.cols)));
+        }
+
+        let rows = self.find(conds).collect::<Vec<_>>();
+        let mut builders = schema.fields()
+            .iter()
+            .map(|field| Builder::new(field.data_type(), rows.len()))
+            .collect::<Result<Vec<_>, _>>()?;
+        for row in rows {
+            for (col, builder) in builders.iter_mut().enumerate() {
+                builder.append(convert(row.index(col)))?;
+            }
+        }
+        let columns = builders.iter_mut().map(Builder::finish).collect();
+        RecordBatch::try_new(schema, columns)
+    }
+}
+
+impl<T: Ord + Clone> Store<T> {
+    /// Construct a new `Store` from an Arrow `RecordBatch`, using `convert` to turn each value
+    /// into a value of the `Store`. The `Store` has one column for every column of the batch, and
+    /// one row for every row of it, in order. Null values are passed to `convert` as
+    /// `Scalar::Null`.
+    ///
+    /// Returns an error if any column of the batch is of a type not supported by `Scalar`.
+    pub fn from_record_batch<F>(batch: &RecordBatch, convert: F) -> Result<Store<T>, ArrowError>
+        where F: Fn(Scalar) -> T
+    {
+        let columns = batch.columns()
+            .iter()
+            .map(|array| Column::new(array.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let rows = (0..batch.num_rows())
+            .map(|i| columns.iter().map(|column| convert(column.get(i))).collect())
+            .collect();
+        Ok(Store::from_rows(columns.len(), rows))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_crate::datatypes::{Field, Schema};
+    use idx::BTreeIndex;
+
+    /// A value that can hold any of the supported Arrow types. Floats are kept as their bits, so
+    /// that values can be ordered.
+    #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    enum V {
+        Missing,
+        Bool(bool),
+        Int(i64),
+        Uint(u64),
+        Float(u64),
+        Text(String),
+    }
+
+    fn to_scalar(v: &V) -> Scalar {
+        match *v {
+            V::Missing => Scalar::Null,
+            V::Bool(b) => Scalar::Boolean(b),
+            V::Int(i) => Scalar::Int64(i),
+            V::Uint(u) => Scalar::UInt64(u),
+            V::Float(f) => Scalar::Float64(f64::from_bits(f)),
+            V::Text(ref s) => Scalar::Utf8(s.clone()),
+        }
+    }
+
+    fn from_scalar(s: Scalar) -> V {
+        match s {
+            Scalar::Null => V::Missing,
+            Scalar::Boolean(b) => V::Bool(b),
+            Scalar::Int64(i) => V::Int(i),
+            Scalar::UInt64(u) => V::Uint(u),
+            Scalar::Float64(f) => V::Float(f.to_bits()),
+            Scalar::Utf8(s) => V::Text(s),
+        }
+    }
+
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![Field::new("id", DataType::UInt64, false),
+                                  Field::new("name", DataType::Utf8, true),
+                                  Field::new("delta", DataType::Int64, false),
+                                  Field::new("score", DataType::Float64, true),
+                                  Field::new("ok", DataType::Boolean, false)]))
+    }
+
+    fn store() -> Store<V> {
+        let mut store = Store::new(5);
+        for i in 0..10u64 {
+            let name = if i % 4 == 0 { V::Missing } else { V::Text(format!("n{}", i)) };
+            let score = if i % 3 == 0 { V::Missing } else { V::Float((i as f64 / 2.0).to_bits()) };
+            store.insert(vec![V::Uint(i), name, V::Int(5 - i as i64), score, V::Bool(i % 2 == 0)]);
+        }
+        store
+    }
+
+    #[test]
+    fn roundtrip() {
+        let store = store();
+        let batch = store.to_record_batch(&[], schema(), to_scalar).unwrap();
+        assert_eq!(batch.num_rows(), 10);
+        assert_eq!(batch.num_columns(), 5);
+        assert_eq!(batch.column(1).null_count(), 3);
+        assert_eq!(batch.column(3).null_count(), 4);
+
+        let back = Store::from_record_batch(&batch, from_scalar).unwrap();
+        assert!(store.rows().eq(back.rows()));
+    }
+
+    #[test]
+    fn exports_matching_rows() {
+        let mut store = store();
+        store.index(4, BTreeIndex::new());
+        let conds = [Condition::eq(4, V::Bool(true)), Condition::gt(2, V::Int(0))];
+        let batch = store.to_record_batch(&conds, schema(), to_scalar).unwrap();
+        let ids = batch.column(0).as_any().downcast_ref::<UInt64Array>().unwrap();
+        assert_eq!(ids.values().to_vec(), vec![0, 2, 4]);
+
+        let back = Store::from_record_batch(&batch, from_scalar).unwrap();
+        assert!(back.rows().eq(store.find(&conds)));
+    }
+
+    #[test]
+    fn rejects_mismatches() {
+        let store = store();
+        let narrow = Arc::new(Schema::new(vec![Field::new("id", DataType::UInt64, false)]));
+        assert!(store.to_record_batch(&[], narrow, to_scalar).is_err());
+
+        // every value is converted according to its own type, not the field's
+        let mut fields = schema().fields().iter().cloned().collect::<Vec<_>>();
+        fields.swap(0, 2);
+        let swapped = Arc::new(Schema::new(fields));
+        assert!(store.to_record_batch(&[], swapped, to_scalar).is_err());
+
+        // nulls are only allowed in nullable fields
+        let mut fields = schema().fields().iter().cloned().collect::<Vec<_>>();
+        fields[1] = Arc::new(Field::new("name", DataType::Utf8, false));
+        let strict = Arc::new(Schema::new(fields));
+        assert!(store.to_record_batch(&[], strict, to_scalar).is_err());
+
+        let dates = Arc::new(Schema::new(vec![Field::new("d", DataType::Date32, false)]));
+        let batch = RecordBatch::new_empty(dates.clone());
+        assert!(Store::from_record_batch(&batch, from_scalar).is_err());
+        assert!(Store::<V>::new(1).to_record_batch(&[], dates, to_scalar).is_err());
+    }
+}
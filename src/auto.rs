@@ -0,0 +1,223 @@
+//! Adaptive indexing: indices that are built automatically for columns that are often scanned.
+//!
+//! Queries only borrow the `Store`, so an index built while a query runs cannot be added to
+//! `Store::indices` right away. It is instead kept in a per-column slot that the planner also
+//! consults, and is moved into `Store::indices` before the rows or indices are next modified.
+
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use EqualityIndex;
+use Index;
+use Plan;
+use Row;
+use Source;
+use Store;
+use cmp::{Comparison, Condition, Value};
+use next_generation;
+
+/// The state of the advisor enabled with `Store::enable_auto_index`.
+pub(crate) struct AutoIndex<T> {
+    threshold: usize,
+    factory: Box<dyn Fn() -> Index<T> + Send + Sync>,
+    /// Whether the indices made by `factory` can answer range queries.
+    range: bool,
+    /// For each column, the number of scans that could have used an index on it.
+    scans: Vec<AtomicUsize>,
+    /// For each column, the index built for it since the `Store` was last modified.
+    built: Vec<OnceLock<Index<T>>>,
+}
+
+impl<T, R> Store<T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Automatically build an index, using `factory`, on every column that `find` has to scan
+    /// `threshold` times.
+    ///
+    /// A scan only counts towards a column if one of the conditions could have used an index on
+    /// it had there been one: an equality comparison (or `In`) against constants, or, if
+    /// `factory` makes range indices, an ordering comparison or prefix match against a constant.
+    /// Columns that already have an index are never counted. `find_at` and `query` count just
+    /// like `find` does.
+    ///
+    /// The index is built by the query that crosses the threshold, which therefore takes as long
+    /// as adding the index with `index` would. That query still scans, but every later query
+    /// can use the new index. Calling this again replaces the advisor, and resets all counts.
+    pub fn enable_auto_index<F>(&mut self, threshold: usize, factory: F)
+        where F: Fn() -> Index<T> + Send + Sync + 'static
+    {
+        self.adopt_auto_indices();
+        let range = matches!(factory(), Index::Range(..));
+        self.auto_index = Some(AutoIndex {
+            threshold,
+            factory: Box::new(factory),
+            range,
+            scans: (0..self.cols).map(|_| AtomicUsize::new(0)).collect(),
+            built: (0..self.cols).map(|_| OnceLock::new()).collect(),
+        });
+    }
+
+    /// Stop building indices automatically. Indices that have already been built are kept.
+    pub fn disable_auto_index(&mut self) {
+        self.adopt_auto_indices();
+        self.auto_index = None;
+    }
+
+    /// Returns the index on the given column, including one that has just been built
+    /// automatically.
+    pub(crate) fn column_index(&self, column: usize) -> Option<&Index<T>> {
+        self.check_indexed();
+        self.indices.get(&column).or_else(|| {
+            self.auto_index.as_ref().and_then(|auto| auto.built.get(column)?.get())
+        })
+    }
+
+    /// Move all automatically built indices into `self.indices`. This must be called before the
+    /// rows or indices are modified, since the indices in the per-column slots are not kept up to
+    /// date.
+    pub(crate) fn adopt_auto_indices(&mut self) {
+        if let Some(ref mut auto) = self.auto_index {
+            for (column, slot) in auto.built.iter_mut().enumerate() {
+                if let Some(idx) = slot.take() {
+                    self.indices.insert(column, idx);
+                    self.generation = next_generation();
+                }
+            }
+        }
+    }
+
+    /// Start counting scans of the given column from zero again, so that an index that was built
+    /// automatically and then removed is not immediately built again.
+    pub(crate) fn reset_auto_index(&mut self, column: usize) {
+        if let Some(ref mut auto) = self.auto_index {
+            if let Some(scans) = auto.scans.get_mut(column) {
+                *scans.get_mut() = 0;
+            }
+        }
+    }
+
+    /// Count a query that is about to be answered using the given plan, and build an index on any
+    /// column that has now been scanned often enough.
+    pub(crate) fn count_scan(&self, conds: &[Condition<T>], plan: &Plan) {
+        let auto = match (&self.auto_index, &plan.source) {
+            (Some(auto), Source::Scan) => auto,
+            _ => return,
+        };
+        let mut columns = conds.iter()
+            .filter(|c| auto.could_use(&c.cmp))
+            .map(|c| c.column)
+            .collect::<Vec<_>>();
+        // a query with several conditions on the same column only counts once for that column
+        columns.sort_unstable();
+        columns.dedup();
+        for column in columns {
+            if self.column_index(column).is_some() {
+                continue;
+            }
+            if auto.scans[column].fetch_add(1, Ordering::Relaxed) + 1 < auto.threshold {
+                continue;
+            }
+            auto.built[column].get_or_init(|| {
+                let mut idx = (auto.factory)();
+                for (&rowid, row) in self.indexed_rows() {
+                    idx.index(row.index(column).clone(), rowid);
+                }
+                idx
+            });
+        }
+    }
+}
+
+impl<T: Ord + Clone> AutoIndex<T> {
+    /// Returns true if an index made by the factory could answer the given comparison.
+    fn could_use(&self, cmp: &Comparison<T>) -> bool {
+        match *cmp {
+            Comparison::Equal(Value::Const(..)) => true,
+            Comparison::In(ref vs) => vs.iter().all(|v| matches!(*v, Value::Const(..))),
+            Comparison::StartsWith(ref p) => {
+                self.range && matches!(*p.value(), Value::Const(..))
+            }
+            Comparison::InRanges(..) => self.range && cmp.ranges().is_some(),
+            ref cmp => self.range && cmp.range().is_some(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use idx::{BTreeIndex, HashIndex};
+    use query::Access;
+
+    fn store() -> Store<usize> {
+        let mut store = Store::new(3);
+        for i in 0..1000 {
+            store.insert(vec![i % 10, i, i % 7]);
+        }
+        store
+    }
+
+    #[test]
+    fn it_builds_index_after_scans() {
+        let mut store = store();
+        store.enable_auto_index(3, || HashIndex::new().into());
+        let conds = [Condition::eq(0, 3usize), Condition::ne(2, 0usize)];
+        let expected = store.find(&conds).cloned().collect::<Vec<_>>();
+        assert_eq!(store.explain(&conds).access, Access::Scan);
+        store.find(&conds).count();
+        store.find(&conds).count();
+        assert_eq!(store.explain(&conds).access, Access::Index(0));
+        assert_eq!(store.find(&conds).cloned().collect::<Vec<_>>(), expected);
+        // only the column with the indexable comparison got an index
+        assert!(store.column_index(2).is_none());
+
+        // the index is kept up to date once the store is modified
+        store.insert(vec![3, 1000, 1]);
+        assert!(store.indices.contains_key(&0));
+        store.delete(&[Condition::eq(1, 3usize)]);
+        store.update(&[Condition::eq(1, 13usize)], |r| r[0] = 4);
+        assert_eq!(store.find(&conds).count(), expected.len() - 1);
+        assert_eq!(store.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn it_ignores_unindexable_comparisons() {
+        let mut store = store();
+        store.enable_auto_index(1, || HashIndex::new().into());
+        let unindexable = [Condition::ne(0, 3usize),
+                           Condition::new(1, Comparison::Equal(Value::column(0))),
+                           Condition::lt(2, 3usize)];
+        for _ in 0..10 {
+            store.find(&unindexable).count();
+        }
+        assert!((0..3).all(|col| store.column_index(col).is_none()));
+
+        // a query that uses an index does not count either
+        store.index(1, HashIndex::new());
+        let conds = [Condition::eq(1, 3usize), Condition::eq(0, 3usize)];
+        store.find(&conds).count();
+        assert!(store.column_index(0).is_none());
+    }
+
+    #[test]
+    fn it_builds_range_index() {
+        let mut store = store();
+        store.enable_auto_index(2, || BTreeIndex::new().into());
+        let conds = [Condition::lt(2, 3usize)];
+        store.find(&conds).count();
+        assert_eq!(store.explain(&conds).access, Access::Scan);
+        store.find(&conds).count();
+        assert_eq!(store.explain(&conds).access, Access::Index(2));
+        assert_eq!(store.find(&conds).count(), 429);
+
+        // removing the index starts counting from zero again
+        store.unindex(2);
+        store.find(&conds).count();
+        assert_eq!(store.explain(&conds).access, Access::Scan);
+        store.disable_auto_index();
+        store.find(&conds).count();
+        store.find(&conds).count();
+        assert_eq!(store.explain(&conds).access, Access::Scan);
+    }
+}
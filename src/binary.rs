@@ -0,0 +1,334 @@
+//! Binary snapshots of a `Store`.
+//!
+//! A snapshot starts with a fixed header, followed by the width of the `Store`, its next row id,
+//! its key and expiry columns, the kinds of the built-in indices it maintains, and finally every
+//! row along with its row id. All numbers are written as little-endian `u64`s, and optional
+//! columns are preceded by a byte that says whether they are present. The values themselves are
+//! written and read by caller-provided closures. As with `serde`, the indices are not written, and
+//! are instead rebuilt from the rows when the snapshot is read.
+
+use std::convert::TryFrom;
+use std::hash::Hash;
+use std::io::{self, Read, Write};
+
+use idx::IndexKind;
+use CompositeKey;
+use EqualityIndex;
+use is_dead;
+use Row;
+use Store;
+
+/// The bytes every snapshot starts with, followed by the format version.
+const MAGIC: &[u8; 8] = b"shortcut";
+const VERSION: u8 = 1;
+
+impl<T, R> Store<T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Write a binary snapshot of this `Store` to `writer`, using `encode` to write each value.
+    /// The `Store` can later be re-created from the snapshot with `read_from`.
+    ///
+    /// Only the rows and a description of the built-in indices are written (see `IndexKind`),
+    /// along with the key and expiry columns. Rows keep their identifiers, but rows that have been
+    /// marked as deleted are left out. Custom, keyed, and sparse indices are not preserved, nor are
+    /// dictionaries. `encode` may write values in any format, as long as the `decode` passed to
+    /// `read_from` reads them back the same way. Small writes are issued for every row, so
+    /// `writer` should generally be buffered.
+    pub fn write_to<W, F>(&self, mut writer: W, encode: F) -> io::Result<()>
+        where W: Write,
+              F: Fn(&T, &mut W) -> io::Result<()>
+    {
+        let w = &mut writer;
+        w.write_all(MAGIC)?;
+        w.write_all(&[VERSION])?;
+        write_usize(w, self.cols)?;
+        write_usize(w, self.rowid)?;
+        write_column(w, self.key)?;
+        write_column(w, self.expiry)?;
+
+        let indices = self.indices
+            .iter()
+            .filter_map(|(&col, idx)| idx.kind().map(|k| (col, k)))
+            .collect::<Vec<_>>();
+        write_usize(w, indices.len())?;
+        for (col, kind) in indices {
+            write_usize(w, col)?;
+            write_kind(w, kind)?;
+        }
+
+        let composite = self.composite
+            .iter()
+            .filter_map(|(key, idx)| match *key {
+                CompositeKey::Columns(ref columns) => idx.kind().map(|k| (columns, k)),
+                CompositeKey::Computed(..) | CompositeKey::Sparse(..) => None,
+            })
+            .collect::<Vec<_>>();
+        write_usize(w, composite.len())?;
+        for (columns, kind) in composite {
+            write_usize(w, columns.len())?;
+            for &col in columns {
+                write_usize(w, col)?;
+            }
+            write_kind(w, kind)?;
+        }
+
+        write_usize(w, self.len())?;
+        for (&rowid, row) in &self.rows {
+            if is_dead(&self.tombstones, rowid) {
+                continue;
+            }
+            write_usize(w, rowid)?;
+            for col in 0..self.cols {
+                encode(row.index(col), w)?;
+            }
+        }
+        w.flush()
+    }
+}
+
+impl<T> Store<T>
+    where T: Ord + Hash + Clone + Send + Sync + 'static
+{
+    /// Re-create a `Store` from a binary snapshot written by `write_to`, using `decode` to read
+    /// each value. All built-in indices described by the snapshot are rebuilt as the rows are
+    /// read.
+    ///
+    /// An error is returned if the input ends early, or if it is not a valid snapshot: if it has
+    /// the wrong header, if it refers to columns the `Store` does not have, if its row identifiers
+    /// are out of order, or if two rows have the same value in a column with a unique index.
+    /// Errors returned by `decode` are passed on as they are. The input is never trusted to be
+    /// consistent, so corrupt input does not cause a panic.
+    pub fn read_from<Rd, F>(mut reader: Rd, decode: F) -> io::Result<Store<T>>
+        where Rd: Read,
+              F: Fn(&mut Rd) -> io::Result<T>
+    {
+        let r = &mut reader;
+        let mut magic = [0; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a shortcut snapshot".to_string()));
+        }
+        let mut version = [0];
+        r.read_exact(&mut version)?;
+        if version[0] != VERSION {
+            return Err(invalid(format!("unsupported snapshot version {}", version[0])));
+        }
+
+        let cols = read_usize(r)?;
+        let check = |col: usize| if col < cols {
+            Ok(col)
+        } else {
+            Err(invalid(format!("column {} is out of range for a store with {} columns",
+                                col,
+                                cols)))
+        };
+        let next = read_usize(r)?;
+        let key = read_column(r)?.map(check).transpose()?;
+        let expiry = read_column(r)?.map(check).transpose()?;
+
+        let mut store = Store::new(cols);
+        for _ in 0..read_usize(r)? {
+            let col = check(read_usize(r)?)?;
+            store.index(col, read_kind(r)?.build());
+        }
+        for _ in 0..read_usize(r)? {
+            let columns = (0..read_usize(r)?)
+                .map(|_| read_usize(r).and_then(check))
+                .collect::<io::Result<Vec<_>>>()?;
+            store.index_multi(&columns, read_kind(r)?.build());
+        }
+        store.key = key;
+        store.expiry = expiry;
+
+        for _ in 0..read_usize(r)? {
+            let rowid = read_usize(r)?;
+            if rowid >= next || store.rows.keys().next_back() >= Some(&rowid) {
+                return Err(invalid(format!("row id {} is out of order", rowid)));
+            }
+            let row = (0..cols).map(|_| decode(r)).collect::<io::Result<Vec<_>>>()?;
+            if let Some((column, existing)) = store.unique_conflict(&row, None) {
+                return Err(invalid(format!("row {} has the same value as row {} in unique \
+                                            column {}",
+                                           rowid,
+                                           existing,
+                                           column)));
+            }
+            store.index_row(rowid, &row);
+            store.rows.insert(rowid, row);
+        }
+        store.rowid = next;
+        Ok(store)
+    }
+}
+
+/// Returns an error for input that is not a valid snapshot.
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Write a number as a little-endian `u64`.
+fn write_usize<W: Write>(w: &mut W, n: usize) -> io::Result<()> {
+    w.write_all(&(n as u64).to_le_bytes())
+}
+
+/// Read a number written by `write_usize`.
+fn read_usize<Rd: Read>(r: &mut Rd) -> io::Result<usize> {
+    let mut bytes = [0; 8];
+    r.read_exact(&mut bytes)?;
+    let n = u64::from_le_bytes(bytes);
+    usize::try_from(n).map_err(|_| invalid(format!("{} does not fit in a usize", n)))
+}
+
+/// Write an optional column number, preceded by a byte that says whether it is present.
+fn write_column<W: Write>(w: &mut W, col: Option<usize>) -> io::Result<()> {
+    match col {
+        None => w.write_all(&[0]),
+        Some(col) => {
+            w.write_all(&[1])?;
+            write_usize(w, col)
+        }
+    }
+}
+
+/// Read an optional column number written by `write_column`.
+fn read_column<Rd: Read>(r: &mut Rd) -> io::Result<Option<usize>> {
+    let mut tag = [0];
+    r.read_exact(&mut tag)?;
+    match tag[0] {
+        0 => Ok(None),
+        1 => read_usize(r).map(Some),
+        tag => Err(invalid(format!("invalid column tag {}", tag))),
+    }
+}
+
+/// Write the kind of a built-in index as a single byte.
+fn write_kind<W: Write>(w: &mut W, kind: IndexKind) -> io::Result<()> {
+    w.write_all(&[match kind {
+                      IndexKind::Hash => 0,
+                      IndexKind::UniqueHash => 1,
+                      IndexKind::BTree => 2,
+                  }])
+}
+
+/// Read the kind of a built-in index written by `write_kind`.
+fn read_kind<Rd: Read>(r: &mut Rd) -> io::Result<IndexKind> {
+    let mut tag = [0];
+    r.read_exact(&mut tag)?;
+    match tag[0] {
+        0 => Ok(IndexKind::Hash),
+        1 => Ok(IndexKind::UniqueHash),
+        2 => Ok(IndexKind::BTree),
+        tag => Err(invalid(format!("invalid index kind {}", tag))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cmp;
+    use idx::{BTreeIndex, HashIndex};
+
+    fn encode<W: Write>(v: &String, w: &mut W) -> io::Result<()> {
+        write_usize(w, v.len())?;
+        w.write_all(v.as_bytes())
+    }
+
+    fn decode<Rd: Read>(r: &mut Rd) -> io::Result<String> {
+        // don't trust the length enough to allocate for it up front
+        let len = read_usize(r)?;
+        let mut bytes = Vec::new();
+        if r.by_ref().take(len as u64).read_to_end(&mut bytes)? < len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        String::from_utf8(bytes).map_err(|e| invalid(e.to_string()))
+    }
+
+    fn store() -> Store<String> {
+        let mut store = Store::with_key(3, 0);
+        store.index(1, HashIndex::new());
+        store.index(2, BTreeIndex::new());
+        store.index_multi(&[1, 2], HashIndex::new());
+        for i in 0..100 {
+            store.insert(vec![format!("k{}", i), format!("v{}", i % 7), format!("{:03}", i)]);
+        }
+        store.delete(&[cmp::Condition::eq(1, "v3".to_string())]);
+        store.mark_deleted(10);
+        store
+    }
+
+    fn write(store: &Store<String>) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        store.write_to(&mut bytes, encode).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn roundtrip() {
+        let store = store();
+        let bytes = write(&store);
+        let mut back = Store::read_from(&bytes[..], decode::<&[u8]>).unwrap();
+
+        assert_eq!(back.len(), store.len());
+        assert!(store.rows().eq(back.rows()));
+        assert_eq!(back.get(10), None);
+        assert_eq!(back.get(11), store.get(11));
+        assert_eq!(back.key_column(), Some(0));
+        assert_eq!(back.indices[&0].kind(), Some(IndexKind::UniqueHash));
+        assert_eq!(back.indices[&1].kind(), Some(IndexKind::Hash));
+        assert_eq!(back.indices[&2].kind(), Some(IndexKind::BTree));
+        assert_eq!(back.composite.len(), 1);
+        assert_eq!(back.check_integrity(), Ok(()));
+
+        let conds = [cmp::Condition::eq(1, "v4".to_string()), cmp::Condition::ge(2, "050")];
+        assert_eq!(back.find(&conds).collect::<Vec<_>>(),
+                   store.find(&conds).collect::<Vec<_>>());
+        assert_eq!(write(&back), bytes);
+
+        // row ids are not reused after a round-trip
+        assert_eq!(back.insert(vec!["k100".into(), "v0".into(), "100".into()]), 100);
+    }
+
+    #[test]
+    fn roundtrip_empty() {
+        let store = Store::<String>::new(0);
+        let back = Store::read_from(&write(&store)[..], decode::<&[u8]>).unwrap();
+        assert_eq!(back.cols, 0);
+        assert!(back.is_empty());
+    }
+
+    #[test]
+    fn rejects_truncated() {
+        let bytes = write(&store());
+        for len in 0..bytes.len() {
+            let err = Store::read_from(&bytes[..len], decode::<&[u8]>).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof, "truncated at {}", len);
+        }
+    }
+
+    #[test]
+    fn rejects_corrupt() {
+        let bytes = write(&store());
+        let read = |bytes: &[u8]| Store::read_from(bytes, decode::<&[u8]>).err().unwrap().kind();
+
+        let mut bad = bytes.clone();
+        bad[0] = b'S';
+        assert_eq!(read(&bad), io::ErrorKind::InvalidData);
+
+        // the first row's key is encoded as its length followed by "k0"
+        let first = bytes.windows(2).position(|w| w == b"k0").unwrap();
+        let mut bad = bytes.clone();
+        bad[first + 1] = b'1';
+        assert_eq!(read(&bad), io::ErrorKind::InvalidData);
+
+        // the first row id comes just before the first key's length
+        let mut bad = bytes.clone();
+        bad[first - 16] = 200;
+        assert_eq!(read(&bad), io::ErrorKind::InvalidData);
+
+        // the width comes right after the header
+        let mut bad = bytes.clone();
+        bad[9] = 2;
+        assert_eq!(read(&bad), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,164 @@
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+use Error;
+use Index;
+use Row;
+use Store;
+use idx::{BTreeIndex, HashIndex};
+
+/// A `StoreBuilder` sets up a `Store` along with its indices in a single expression:
+///
+/// ```
+/// use shortcut::{Condition, Store, StoreBuilder};
+///
+/// let mut store: Store<i32> = StoreBuilder::new(4)
+///     .capacity(1000)
+///     .hash_index(0)
+///     .btree_index(3)
+///     .build()
+///     .unwrap();
+/// store.insert_many((0..1000).map(|i| vec![i % 10, i, i % 7, i]));
+/// assert_eq!(store.find(&[Condition::eq(0, 3), Condition::lt(3, 100)]).count(), 10);
+/// ```
+///
+/// The indices are added before any rows exist, so that `build` does no more work than
+/// `Store::new` followed by a call to `Store::index` for every index. Declaring more than one
+/// index on the same column, or an index on a column that does not exist, is an error, which is
+/// returned by `build`.
+pub struct StoreBuilder<T, R = Vec<T>> {
+    cols: usize,
+    capacity: usize,
+    indices: Vec<(usize, Index<T>)>,
+    error: Option<Error>,
+    rows: PhantomData<R>,
+}
+
+impl<T, R> StoreBuilder<T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Start building a `Store` with the given number of columns.
+    pub fn new(cols: usize) -> Self {
+        StoreBuilder {
+            cols,
+            capacity: 0,
+            indices: Vec::new(),
+            error: None,
+            rows: PhantomData,
+        }
+    }
+
+    /// Reserve capacity in the indices for at least `capacity` rows (see `Store::reserve`).
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Add the given index on the given column. See `Store::index`.
+    pub fn index<I: Into<Index<T>>>(mut self, column: usize, indexer: I) -> Self {
+        if self.error.is_none() && self.indices.iter().any(|&(col, _)| col == column) {
+            self.error = Some(Error::DuplicateIndex { column });
+        }
+        self.indices.push((column, indexer.into()));
+        self
+    }
+
+    /// Add a `HashIndex` on the given column.
+    pub fn hash_index(self, column: usize) -> Self
+        where T: Hash + Send + Sync + 'static
+    {
+        self.index(column, HashIndex::new())
+    }
+
+    /// Add a unique `HashIndex` (see `HashIndex::unique`) on the given column.
+    pub fn unique_index(self, column: usize) -> Self
+        where T: Hash + Send + Sync + 'static
+    {
+        self.index(column, HashIndex::unique())
+    }
+
+    /// Add a `BTreeIndex` on the given column.
+    pub fn btree_index(self, column: usize) -> Self
+        where T: Send + Sync + 'static
+    {
+        self.index(column, BTreeIndex::new())
+    }
+
+    /// Build the `Store`, with all the declared indices. Returns an error if more than one index
+    /// was declared on the same column, or if an index was declared on a column that does not
+    /// exist.
+    pub fn build(self) -> Result<Store<T, R>, Error> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        let mut store = Store::new(self.cols);
+        for (column, idx) in self.indices {
+            store.try_index(column, idx)?;
+        }
+        store.reserve(self.capacity);
+        Ok(store)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cmp::Condition;
+    use query::Access;
+
+    fn rows() -> Vec<Vec<usize>> {
+        (0..500).map(|i| vec![i % 10, i, i % 7, i / 3]).collect()
+    }
+
+    #[test]
+    fn it_builds_like_index() {
+        let mut built = StoreBuilder::new(4)
+            .capacity(500)
+            .hash_index(0)
+            .btree_index(1)
+            .index(3, HashIndex::new())
+            .build()
+            .unwrap();
+        built.insert_many(rows());
+
+        let mut store = Store::new(4);
+        store.index(0, HashIndex::new());
+        store.index(1, BTreeIndex::new());
+        store.index(3, HashIndex::new());
+        store.insert_many(rows());
+
+        assert_eq!(built.indexed_columns().collect::<Vec<_>>(), vec![0, 1, 3]);
+        let queries = vec![vec![Condition::eq(0, 3usize)],
+                           vec![Condition::ge(1, 100usize), Condition::lt(1, 120usize)],
+                           vec![Condition::eq(0, 3usize), Condition::eq(3, 41usize)],
+                           vec![Condition::eq(2, 4usize), Condition::ne(0, 1usize)],
+                           vec![Condition::eq(0, 3usize), Condition::eq(0, 4usize)]];
+        for conds in &queries {
+            assert_eq!(built.explain(conds), store.explain(conds));
+            assert_eq!(built.find(conds).collect::<Vec<_>>(),
+                       store.find(conds).collect::<Vec<_>>());
+        }
+        assert_eq!(built.explain(&queries[1]).access, Access::Index(1));
+        assert_eq!(built.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn it_builds_unique_indices() {
+        let mut store: Store<usize> = StoreBuilder::new(2).unique_index(0).build().unwrap();
+        store.insert(vec![1, 1]);
+        assert!(store.try_insert(vec![1, 2]).is_err());
+    }
+
+    #[test]
+    fn it_rejects_duplicate_indices() {
+        let built = StoreBuilder::<usize>::new(2)
+            .hash_index(0)
+            .btree_index(1)
+            .btree_index(0)
+            .build();
+        assert_eq!(built.err(), Some(Error::DuplicateIndex { column: 0 }));
+        let built = StoreBuilder::<usize>::new(2).hash_index(2).build();
+        assert_eq!(built.err(), Some(Error::ColumnOutOfRange { column: 2, cols: 2 }));
+    }
+}
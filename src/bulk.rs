@@ -0,0 +1,240 @@
+use std::ops;
+
+use Row;
+use Store;
+
+/// A `BulkLoader` adds many rows to a `Store` without updating its indices for every row. It is
+/// constructed using `Store::begin_bulk_load`.
+///
+/// Rows inserted through a `BulkLoader` are added to the `Store` right away, but are only added
+/// to its indices and views when `finish` is called, in a single pass over the new rows for each
+/// index. Indices that can add many rows more efficiently than one at a time, such as a
+/// `BTreeIndex`, do so (see `EqualityIndex::index_many`). The loader borrows the `Store`
+/// mutably, so the `Store` cannot be queried while its indices are out of date.
+///
+/// If the `BulkLoader` is dropped without being finished, all the rows inserted through it are
+/// removed again, and the `Store` is left as it was.
+pub struct BulkLoader<'s, T: 's, R: 's = Vec<T>> {
+    store: &'s mut Store<T, R>,
+    start: usize,
+    finished: bool,
+    /// Whether index maintenance was lazy when the load started (see
+    /// `Store::set_index_maintenance`). The loaded rows are never queued, since they are added to
+    /// the indices by `finish` anyway.
+    lazy: bool,
+}
+
+impl<'s, T, R> BulkLoader<'s, T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Start a new bulk load into the given `Store`.
+    pub fn new(store: &'s mut Store<T, R>) -> Self {
+        store.adopt_auto_indices();
+        store.flush_indices();
+        let lazy = store.pending.take().is_some();
+        let start = store.rowid;
+        BulkLoader {
+            store,
+            start,
+            finished: false,
+            lazy,
+        }
+    }
+
+    /// Add the given row to the `Store`, without adding it to any index, and return its
+    /// identifier.
+    ///
+    /// The row **must** have the same number of columns as the `Store`. If it does not, the code
+    /// will panic, and all rows inserted through this loader are discarded. Conflicts in columns
+    /// with a unique index are only detected by `finish`.
+    pub fn insert(&mut self, row: R) -> usize {
+        assert!(row.columns() == self.store.cols,
+                "row has {} columns, but the store has {}",
+                row.columns(),
+                self.store.cols);
+        let rowid = self.store.rowid;
+        self.store.rows.insert(rowid, row);
+        self.store.rowid += 1;
+        rowid
+    }
+
+    /// Returns the number of rows inserted through this loader.
+    pub fn len(&self) -> usize {
+        self.store.rowid - self.start
+    }
+
+    /// Returns true if no rows have been inserted through this loader.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Add all the inserted rows to the indices and views of the `Store`, call the callbacks
+    /// registered with `Store::on_insert` for them, and return the range of identifiers assigned
+    /// to them.
+    ///
+    /// If any of the rows conflicts with an existing row (or another inserted row) in a column
+    /// with a unique index, this method panics, and all rows inserted through this loader are
+    /// discarded.
+    pub fn finish(mut self) -> ops::Range<usize> {
+        let start = self.start;
+        let store = &mut *self.store;
+        let new = store.rows.range(start..).map(|(_, row)| row);
+        store.check_batch(new);
+        for (_, row) in store.rows.range_mut(start..) {
+            for dictionary in store.dictionaries.values_mut() {
+                dictionary.intern(row);
+            }
+        }
+        store.index_rows_from(start);
+        if self.lazy {
+            store.pending = Some(store.rowid);
+        }
+        self.finished = true;
+        store.notify(start..store.rowid);
+        store.evict();
+        start..store.rowid
+    }
+}
+
+impl<'s, T, R> Drop for BulkLoader<'s, T, R> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.store.rows.split_off(&self.start);
+            self.store.rowid = self.start;
+            if self.lazy {
+                self.store.pending = Some(self.start);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Instant;
+
+    use cmp::Condition;
+    use idx::{BTreeIndex, HashIndex};
+    use query::Access;
+
+    /// A store with a hash index, a range index, and a composite index, holding `rows` rows.
+    fn indexed(rows: usize) -> Store<usize> {
+        let mut store = Store::new(3);
+        store.index(0, HashIndex::new());
+        store.index(1, BTreeIndex::new());
+        store.index_multi(&[0, 2], HashIndex::new());
+        for i in 0..rows {
+            store.insert(vec![i % 100, i % 1000, i]);
+        }
+        store
+    }
+
+    #[test]
+    fn it_bulk_loads() {
+        let mut store = indexed(500);
+        let inserted = Arc::new(AtomicUsize::new(0));
+        let counter = inserted.clone();
+        store.on_insert(Box::new(move |_, _| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }));
+
+        let mut loader = store.begin_bulk_load();
+        assert!(loader.is_empty());
+        for i in 500..2000 {
+            assert_eq!(loader.insert(vec![i % 100, i % 1000, i]), i);
+        }
+        assert_eq!(loader.len(), 1500);
+        assert_eq!(loader.finish(), 500..2000);
+        assert_eq!(inserted.load(Ordering::Relaxed), 1500);
+        assert_eq!(store.check_integrity(), Ok(()));
+
+        let mut expected = indexed(2000);
+        let queries = [vec![Condition::eq(0, 7usize)],
+                       vec![Condition::ge(1, 990usize), Condition::lt(1, 995usize)],
+                       vec![Condition::eq(0, 42usize), Condition::eq(2, 1742usize)]];
+        for conds in &queries {
+            assert_eq!(store.explain(conds), expected.explain(conds));
+            assert_ne!(store.explain(conds).access, Access::Scan);
+            let rows = store.find(conds).collect::<Vec<_>>();
+            assert_eq!(rows, expected.find(conds).collect::<Vec<_>>());
+            assert!(!rows.is_empty());
+        }
+
+        // rows inserted later are indexed as usual
+        store.insert(vec![7, 7, 7]);
+        expected.insert(vec![7, 7, 7]);
+        let conds = [Condition::eq(0, 7usize)];
+        assert_eq!(store.find(&conds).count(), expected.find(&conds).count());
+    }
+
+    #[test]
+    fn it_discards_unfinished_loads() {
+        let mut store = indexed(10);
+        let mut loader = store.begin_bulk_load();
+        loader.insert(vec![1, 2, 3]);
+        drop(loader);
+        assert_eq!(store.len(), 10);
+        assert_eq!(store.insert(vec![1, 2, 3]), 10);
+        assert_eq!(store.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn it_rejects_unique_conflicts() {
+        let mut store = Store::new(2);
+        store.index(0, HashIndex::unique());
+        store.insert(vec!["a", "x"]);
+
+        let bad = vec![vec![vec!["b", "x"], vec!["a", "y"]], vec![vec!["b", "x"], vec!["b", "y"]]];
+        for rows in bad {
+            let r = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                let mut loader = store.begin_bulk_load();
+                for row in rows {
+                    loader.insert(row);
+                }
+                loader.finish()
+            }));
+            assert!(r.is_err());
+            assert_eq!(store.len(), 1);
+        }
+        let mut loader = store.begin_bulk_load();
+        loader.insert(vec!["b", "x"]);
+        assert_eq!(loader.finish(), 1..2);
+        assert_eq!(store.find(&[Condition::eq(0, "b")]).count(), 1);
+    }
+
+    /// Compares inserting a million rows into a store with three indices one at a time against
+    /// loading them in bulk. Run with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_bulk_load() {
+        const ROWS: usize = 1_000_000;
+        let row = |i: usize| vec![i % 1000, i.wrapping_mul(2654435761) % ROWS, i];
+
+        let mut store = indexed(0);
+        let start = Instant::now();
+        for i in 0..ROWS {
+            store.insert(row(i));
+        }
+        let one_by_one = start.elapsed();
+
+        let mut store = indexed(0);
+        let start = Instant::now();
+        let mut loader = store.begin_bulk_load();
+        for i in 0..ROWS {
+            loader.insert(row(i));
+        }
+        loader.finish();
+        let bulk = start.elapsed();
+
+        println!("inserting {} rows one at a time took {:?}, loading them in bulk took {:?} \
+                  ({:.1}x faster)",
+                 ROWS,
+                 one_by_one,
+                 bulk,
+                 one_by_one.as_secs_f64() / bulk.as_secs_f64());
+    }
+}
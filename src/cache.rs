@@ -0,0 +1,367 @@
+//! Caching of the rows found by `find`, enabled with `Store::enable_query_cache`.
+//!
+//! The cache keeps its own copy of the conditions of every cached query, which requires the
+//! constants in them to be owned (and so `T: 'static`), and it hashes them (requiring `T: Hash`).
+//! `Store` requires neither, so, like a `Dictionary`, the cache is kept behind a trait object
+//! that can only be created where those bounds hold.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use Row;
+use Store;
+use cmp::Condition;
+use stats::CacheStats;
+
+/// The rows found for recently executed sets of conditions.
+pub(crate) trait Cache<T: Clone, R> {
+    /// Returns the identifiers of the rows found for the given conditions, if they are cached,
+    /// and were found while the `Store` was at the given generation.
+    fn get(&self, conds: &[Condition<T>], generation: usize) -> Option<Arc<[usize]>>;
+    /// Cache the identifiers of the rows found for the given conditions at the given generation,
+    /// evicting the least recently used entry if the cache is full.
+    fn put(&self, conds: &[Condition<T>], generation: usize, rowids: Arc<[usize]>);
+    /// Forget the rows found for every set of conditions that the given row matches. This must be
+    /// called for every row that is added to, changed in, or removed from the `Store`.
+    fn invalidate(&mut self, row: &R);
+    /// Forget the rows found for every set of conditions.
+    fn clear(&mut self);
+    /// Returns the counters of the cache.
+    fn stats(&self) -> CacheStats;
+    /// Returns an empty cache with the same capacity.
+    fn boxed_empty(&self) -> Box<dyn Cache<T, R> + Send + Sync>;
+}
+
+/// The built-in `Cache`, which evicts the least recently used entry when it is full. Entries are
+/// found by a linear search, which is fast for the small number of hot queries a cache is meant
+/// for.
+struct QueryCache<T: Clone + 'static> {
+    capacity: usize,
+    state: Mutex<State<T>>,
+}
+
+struct State<T: Clone + 'static> {
+    entries: Vec<Entry<T>>,
+    /// The generation of the `Store` the entries were found at. Entries from older generations
+    /// may have used indices that no longer exist, and so are not kept.
+    generation: usize,
+    /// Incremented on every lookup, to tell which entry was used least recently.
+    clock: usize,
+    hits: usize,
+    misses: usize,
+}
+
+struct Entry<T: Clone + 'static> {
+    hash: u64,
+    conds: Vec<Condition<'static, T>>,
+    rowids: Arc<[usize]>,
+    used: usize,
+}
+
+impl<T: Clone + 'static> QueryCache<T> {
+    fn new(capacity: usize) -> Self {
+        QueryCache {
+            capacity,
+            state: Mutex::new(State {
+                entries: Vec::new(),
+                generation: 0,
+                clock: 0,
+                hits: 0,
+                misses: 0,
+            }),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        // the state is consistent whenever the lock is released, even by a panic
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<T: Clone + 'static> State<T> {
+    /// Forget all entries if they were not found at the given generation.
+    fn at_generation(&mut self, generation: usize) {
+        if self.generation != generation {
+            self.entries.clear();
+            self.generation = generation;
+        }
+    }
+}
+
+/// Hash the given conditions for `Entry::hash`.
+fn hash<T: Hash + Clone>(conds: &[Condition<T>]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    conds.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl<T, R> Cache<T, R> for QueryCache<T>
+    where T: Ord + Hash + Clone + Send + Sync + 'static,
+          R: Row<T>
+{
+    fn get(&self, conds: &[Condition<T>], generation: usize) -> Option<Arc<[usize]>> {
+        let hash = hash(conds);
+        let mut state = self.lock();
+        state.at_generation(generation);
+        state.clock += 1;
+        let now = state.clock;
+        let found = state.entries
+            .iter_mut()
+            .find(|e| e.hash == hash && e.conds[..] == *conds)
+            .map(|e| {
+                e.used = now;
+                e.rowids.clone()
+            });
+        match found {
+            Some(..) => state.hits += 1,
+            None => state.misses += 1,
+        }
+        found
+    }
+
+    fn put(&self, conds: &[Condition<T>], generation: usize, rowids: Arc<[usize]>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let hash = hash(conds);
+        let mut state = self.lock();
+        state.at_generation(generation);
+        // another thread may have found the same rows in the meantime
+        if state.entries.iter().any(|e| e.hash == hash && e.conds[..] == *conds) {
+            return;
+        }
+        if state.entries.len() >= self.capacity {
+            let lru = (0..state.entries.len()).min_by_key(|&i| state.entries[i].used).unwrap();
+            state.entries.swap_remove(lru);
+        }
+        let used = state.clock;
+        state.entries.push(Entry {
+            hash,
+            conds: conds.iter().map(Condition::owned).collect(),
+            rowids,
+            used,
+        });
+    }
+
+    fn invalidate(&mut self, row: &R) {
+        let state = self.state.get_mut().unwrap_or_else(PoisonError::into_inner);
+        state.entries.retain(|e| !e.conds.iter().all(|c| c.matches(row)));
+    }
+
+    fn clear(&mut self) {
+        self.state.get_mut().unwrap_or_else(PoisonError::into_inner).entries.clear();
+    }
+
+    fn stats(&self) -> CacheStats {
+        let state = self.lock();
+        CacheStats {
+            hits: state.hits,
+            misses: state.misses,
+            entries: state.entries.len(),
+        }
+    }
+
+    fn boxed_empty(&self) -> Box<dyn Cache<T, R> + Send + Sync> {
+        Box::new(QueryCache::new(self.capacity))
+    }
+}
+
+impl<T, R> Store<T, R>
+    where T: Ord + Hash + Clone + Send + Sync + 'static,
+          R: Row<T>
+{
+    /// Cache the rows found by `find` for the `capacity` most recently used sets of conditions,
+    /// so that running the same query again does not have to look at any rows that do not
+    /// match. Calling this again replaces the cache with an empty one of the new capacity.
+    ///
+    /// Whenever a row is inserted, updated, or deleted, the cached results for every set of
+    /// conditions that the row matches (before or after the change) are discarded, while results
+    /// that cannot have changed are kept. Adding or removing an index discards all cached
+    /// results. Rows marked as deleted with `mark_deleted` are skipped when cached results are
+    /// read, just like they are by `find`.
+    ///
+    /// Cached results are yielded in the same order as `find` would yield them. The first
+    /// execution of a query finds all matching rows before it yields the first one, and later
+    /// ones take a lock shared with other threads querying the same `Store`. Only `find`, the
+    /// methods built on it (such as `find_one`), and `find_with_ids` use the cache.
+    pub fn enable_query_cache(&mut self, capacity: usize) {
+        self.cache = Some(Box::new(QueryCache::new(capacity)));
+    }
+}
+
+impl<T, R> Store<T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Stop caching the rows found by `find`, and discard all cached results.
+    pub fn disable_query_cache(&mut self) {
+        self.cache = None;
+    }
+
+    /// Returns how often `find` was answered from the cache enabled with `enable_query_cache`.
+    /// All counts are zero if there is no cache.
+    pub fn query_cache_stats(&self) -> CacheStats {
+        self.cache.as_ref().map(|cache| cache.stats()).unwrap_or_default()
+    }
+
+    /// Discard the cached results for all conditions that the given row matches.
+    pub(crate) fn invalidate_cached(&mut self, row: &R) {
+        if let Some(ref mut cache) = self.cache {
+            cache.invalidate(row);
+        }
+    }
+
+    /// Like `find_with_ids`, but uses the given cache.
+    pub(crate) fn find_cached<'s>(&'s self,
+                                  cache: &'s (dyn Cache<T, R> + Send + Sync),
+                                  conds: &[Condition<T>])
+                                  -> Box<dyn Iterator<Item = (usize, &'s R)> + 's> {
+        self.check_indexed();
+        let rowids = match cache.get(conds, self.generation) {
+            Some(rowids) => rowids,
+            None => {
+                let (rowids, filters) = self.candidates(conds);
+                let rowids: Arc<[usize]> = rowids.filter(|rowid| {
+                        let row = &self.rows[rowid];
+                        filters.iter().all(|c| c.matches(row))
+                    })
+                    .collect();
+                cache.put(conds, self.generation, rowids.clone());
+                rowids
+            }
+        };
+        let rows = (0..rowids.len())
+            .map(move |i| rowids[i])
+            .filter(move |&rowid| !self.is_dead(rowid))
+            .map(move |rowid| (rowid, &self.rows[&rowid]));
+        self.count_returned(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use idx::{BTreeIndex, HashIndex};
+
+    /// A store of `[i % 10, i, i % 3]` for every `i` in `0..100`, with a hash index on column 0
+    /// and a range index on column 1.
+    fn store() -> Store<usize> {
+        let mut store = Store::new(3);
+        for i in 0..100 {
+            store.insert(vec![i % 10, i, i % 3]);
+        }
+        store.index(0, HashIndex::new());
+        store.index(1, BTreeIndex::new());
+        store
+    }
+
+    fn rows(store: &Store<usize>, conds: &[Condition<usize>]) -> Vec<(usize, Vec<usize>)> {
+        store.find_with_ids(conds).map(|(rowid, row)| (rowid, row.clone())).collect()
+    }
+
+    #[test]
+    fn it_caches_queries() {
+        let uncached = store();
+        let mut store = store();
+        store.enable_query_cache(8);
+        let queries = [vec![Condition::eq(0, 3usize)],
+                       vec![Condition::gt(1, 90usize)],
+                       vec![Condition::lt(1, 50usize), Condition::eq(2, 1usize)],
+                       vec![Condition::eq(2, 2usize)]];
+        for _ in 0..3 {
+            for conds in &queries {
+                assert_eq!(rows(&store, conds), rows(&uncached, conds));
+            }
+        }
+        assert_eq!(store.query_cache_stats(),
+                   CacheStats {
+                       hits: 8,
+                       misses: 4,
+                       entries: 4,
+                   });
+
+        // a clone caches too, but starts out empty
+        let clone = store.clone();
+        assert_eq!(clone.find(&queries[0]).count(), 10);
+        assert_eq!(clone.query_cache_stats().misses, 1);
+
+        store.disable_query_cache();
+        assert_eq!(rows(&store, &queries[0]), rows(&uncached, &queries[0]));
+        assert_eq!(store.query_cache_stats(), CacheStats::default());
+    }
+
+    #[test]
+    fn it_invalidates_matching_entries() {
+        let mut store = store();
+        store.enable_query_cache(8);
+        let threes = [Condition::eq(0, 3usize)];
+        let fours = [Condition::eq(0, 4usize)];
+        assert_eq!(store.find(&threes).count(), 10);
+        assert_eq!(store.find(&fours).count(), 10);
+
+        // only the entry the new row matches is discarded
+        store.insert(vec![3, 100, 0]);
+        assert_eq!(store.query_cache_stats().entries, 1);
+        assert_eq!(store.find(&threes).count(), 11);
+        assert_eq!(store.find(&fours).count(), 10);
+        assert_eq!(store.query_cache_stats().hits, 1);
+
+        // an update invalidates both what the row matched before and what it matches after
+        store.update(&[Condition::eq(1, 100usize)], |r| r[0] = 4);
+        assert_eq!(store.query_cache_stats().entries, 0);
+        assert_eq!(store.find(&threes).count(), 10);
+        assert_eq!(store.find(&fours).count(), 11);
+
+        store.delete(&[Condition::eq(1, 4usize)]);
+        assert_eq!(store.find(&fours).count(), 10);
+        assert_eq!(store.find(&threes).count(), 10);
+        assert_eq!(store.query_cache_stats().entries, 2);
+
+        // rows marked as deleted are skipped even though the entry is kept
+        store.mark_deleted(3);
+        assert_eq!(store.find(&threes).count(), 9);
+        assert_eq!(store.query_cache_stats().entries, 2);
+
+        store.insert_many(vec![vec![3, 200, 0], vec![5, 201, 0]]);
+        assert_eq!(store.find(&threes).count(), 10);
+        assert_eq!(store.find(&fours).count(), 10);
+
+        // changing the indices discards everything
+        store.unindex(0);
+        assert_eq!(store.find(&fours).count(), 10);
+        assert_eq!(store.query_cache_stats().entries, 1);
+
+        store.clear();
+        assert_eq!(store.query_cache_stats().entries, 0);
+        assert_eq!(store.find(&fours).count(), 0);
+    }
+
+    #[test]
+    fn it_evicts_least_recently_used() {
+        let mut store = store();
+        store.enable_query_cache(2);
+        let conds = (0..3usize).map(|i| [Condition::eq(0, i)]).collect::<Vec<_>>();
+        store.find(&conds[0]).count();
+        store.find(&conds[1]).count();
+        store.find(&conds[0]).count();
+        store.find(&conds[2]).count();
+        assert_eq!(store.query_cache_stats().entries, 2);
+
+        // the entry for conds[1] was used least recently, and so was evicted
+        store.find(&conds[0]).count();
+        store.find(&conds[2]).count();
+        store.find(&conds[1]).count();
+        assert_eq!(store.query_cache_stats(),
+                   CacheStats {
+                       hits: 3,
+                       misses: 4,
+                       entries: 2,
+                   });
+
+        store.enable_query_cache(0);
+        store.find(&conds[0]).count();
+        store.find(&conds[0]).count();
+        assert_eq!(store.query_cache_stats().entries, 0);
+    }
+}
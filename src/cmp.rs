@@ -1,16 +1,24 @@
 use Row;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::borrow::Cow;
 use std::borrow::Borrow;
+use std::ops::Bound;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize, Serializer};
 
 /// A value represents something to compare against.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Value<'a, T: Clone + 'a> {
     /// A constant value literal.
     Const(Cow<'a, T>),
 
     /// A different column for the same row. Note that comparisons of this kind *cannot use an
-    /// index*, at least not in the current implementation.
+    /// index*, at least not in the current implementation, even if either column is indexed.
+    /// Other conditions in the same query may still be satisfied using an index, in which case
+    /// the column comparison is checked against each row the index yields.
     Column(usize),
 }
 
@@ -20,7 +28,7 @@ impl<'a, T: Clone + 'a> Value<'a, T> {
     /// to the value of that column in the given row.
     pub fn value<'b: 'a, R: Row<T> + ?Sized>(&'b self, row: &'b R) -> &'b T {
         match *self {
-            Value::Column(i) => &row.index(i),
+            Value::Column(i) => row.index(i),
             Value::Const(ref val) => val,
         }
     }
@@ -39,13 +47,445 @@ impl<'a, T: Clone + 'a> Value<'a, T> {
     pub fn column(c: usize) -> Self {
         Value::Column(c)
     }
+
+    /// If this is a constant, replace it with the next of the given values. References to other
+    /// columns are kept. This is used to bind the placeholders of a `PreparedQuery`.
+    pub(crate) fn bind<'b, I>(&self, values: &mut I) -> Value<'b, T>
+        where I: Iterator<Item = &'b T>
+    {
+        match *self {
+            Value::Const(..) => Value::Const(Cow::Borrowed(values.next().unwrap())),
+            Value::Column(c) => Value::Column(c),
+        }
+    }
+
+    /// Construct the same value, with a constant that is owned rather than borrowed.
+    pub(crate) fn owned(&self) -> Value<'static, T>
+        where T: 'static
+    {
+        match *self {
+            Value::Const(ref v) => Value::Const(Cow::Owned(v.clone().into_owned())),
+            Value::Column(c) => Value::Column(c),
+        }
+    }
 }
 
+/// A range of values, given by a lower and an upper bound, as used by `Comparison::InRanges`.
+pub type Range<'a, T> = (Bound<Value<'a, T>>, Bound<Value<'a, T>>);
+
 /// A comparison to perform for a literal value against a `Value`.
-#[derive(Clone, Debug)]
+///
+/// Comparisons can be compared and hashed whenever the values can, so that sets of conditions
+/// can be used as keys (see `Store::enable_query_cache`). A `KeyFn` is identified by its name, a
+/// `Pattern` by its regular expression, and a `Prefix` by its value.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Comparison<'a, T: Clone + 'a> {
     /// Is the value equal to the given `Value`?
     Equal(Value<'a, T>),
+
+    /// Is the value different from the given `Value`? Note that comparisons of this kind *cannot
+    /// use an index*, since they generally match most of the rows in the dataset.
+    NotEqual(Value<'a, T>),
+
+    /// Is the value equal to any of the given `Value`s? An empty list matches nothing.
+    In(Vec<Value<'a, T>>),
+
+    /// Is the value strictly less than the given `Value`?
+    Less(Value<'a, T>),
+
+    /// Is the value less than or equal to the given `Value`?
+    LessOrEqual(Value<'a, T>),
+
+    /// Is the value strictly greater than the given `Value`?
+    Greater(Value<'a, T>),
+
+    /// Is the value greater than or equal to the given `Value`?
+    GreaterOrEqual(Value<'a, T>),
+
+    /// Does the value start with the given prefix? See `Prefix` for how to construct one. If the
+    /// prefix is a constant, this can be satisfied with a bounded scan of an `Index::Range`.
+    #[cfg_attr(feature = "serde", serde(skip_deserializing))]
+    StartsWith(Prefix<'a, T>),
+
+    /// Does the value match the given regular expression? See `Pattern` for how to construct one.
+    /// Comparisons of this kind *cannot use an index*.
+    ///
+    /// A `Pattern` can only be constructed with the `regex` feature enabled. The variant exists
+    /// either way, so that whether a `match` on a `Comparison` is exhaustive does not depend on
+    /// which features other crates enable.
+    #[cfg_attr(feature = "serde", serde(skip_deserializing))]
+    Regex(Pattern<T>),
+
+    /// Is the key computed from the value by the given `KeyFn` equal to the key computed from the
+    /// given `Value`? This can be satisfied by an index added with `Store::index_keyed` using a
+    /// `KeyFn` with the same name.
+    #[cfg_attr(feature = "serde", serde(skip_deserializing))]
+    KeyEqual(KeyFn<T>, Value<'a, T>),
+
+    /// Is the value null? See `Nullable` for what that means, and `Null` for how to construct one.
+    /// Comparisons of this kind *cannot use an index*; in particular, a sparse index (see
+    /// `Store::index_sparse`) does not hold the rows with null values at all.
+    #[cfg_attr(feature = "serde", serde(skip_deserializing))]
+    IsNull(Null<T>),
+
+    /// Is the value not null? See `Nullable` for what that means, and `Null` for how to construct
+    /// one. Comparisons of this kind *cannot use an index*.
+    #[cfg_attr(feature = "serde", serde(skip_deserializing))]
+    IsNotNull(Null<T>),
+
+    /// Does the value lie between the given bounds? Either bound may be inclusive, exclusive, or
+    /// absent. If both bounds are constants, this can be satisfied with a single scan of an
+    /// `Index::Range`. If `min` is greater than `max`, the comparison matches nothing.
+    Between {
+        /// The lower bound.
+        min: Bound<Value<'a, T>>,
+        /// The upper bound.
+        max: Bound<Value<'a, T>>,
+    },
+
+    /// Does the value lie within any of the given ranges, each given by a lower and an upper bound
+    /// as for `Between`? An empty list matches nothing. If all bounds are constants, this can be
+    /// satisfied by scanning an `Index::Range` once for each range, after ranges that overlap or
+    /// touch have been merged, so that no row is found twice (see `Comparison::ranges`).
+    InRanges(Vec<Range<'a, T>>),
+}
+
+/// Types whose values can be checked for a prefix, for use with `Comparison::StartsWith`.
+///
+/// Implementors must ensure that all values that start with a given prefix sort immediately
+/// after that prefix, with no other values in between. This holds for the lexicographic
+/// ordering of strings and slices.
+pub trait HasPrefix {
+    /// Returns true if `self` starts with `prefix`.
+    fn has_prefix(&self, prefix: &Self) -> bool;
+}
+
+impl HasPrefix for String {
+    fn has_prefix(&self, prefix: &Self) -> bool {
+        self.starts_with(&prefix[..])
+    }
+}
+
+impl HasPrefix for &str {
+    fn has_prefix(&self, prefix: &Self) -> bool {
+        self.starts_with(*prefix)
+    }
+}
+
+impl<T: PartialEq> HasPrefix for Vec<T> {
+    fn has_prefix(&self, prefix: &Self) -> bool {
+        self.starts_with(prefix)
+    }
+}
+
+/// A named function that computes a key from a value, such as a lowercase version of a string, or
+/// the bucket a number falls into. See `Comparison::KeyEqual` and `Store::index_keyed`.
+///
+/// The name identifies the function: a query can only use a keyed index if the `KeyFn` in the
+/// query has the same name as the one the index was created with. Two `KeyFn`s with the same name
+/// must therefore compute the same keys.
+pub struct KeyFn<T> {
+    name: &'static str,
+    key: fn(&T) -> T,
+}
+
+impl<T> KeyFn<T> {
+    /// Construct a new key function with the given name.
+    pub fn new(name: &'static str, key: fn(&T) -> T) -> Self {
+        KeyFn { name, key }
+    }
+
+    /// The name of this key function.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Compute the key for the given value.
+    pub fn key(&self, value: &T) -> T {
+        (self.key)(value)
+    }
+}
+
+impl<T> Clone for KeyFn<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for KeyFn<T> {}
+
+/// Key functions are equal if they have the same name, just as they are for the purposes of
+/// `Store::index_keyed`.
+impl<T> PartialEq for KeyFn<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl<T> Eq for KeyFn<T> {}
+
+impl<T> Hash for KeyFn<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for KeyFn<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("KeyFn").field(&self.name).finish()
+    }
+}
+
+/// Key functions cannot be serialized, since the function itself cannot.
+#[cfg(feature = "serde")]
+impl<T> Serialize for KeyFn<T> {
+    fn serialize<S: Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+        unserializable::<S>("KeyEqual")
+    }
+}
+
+/// Types with values that represent a missing value, for use with `Comparison::IsNull` and
+/// `Store::index_sparse`. Typically, this is a variant of an enum like `None`.
+pub trait Nullable {
+    /// Returns true if this value represents a missing value.
+    fn is_null(&self) -> bool;
+}
+
+impl<T> Nullable for Option<T> {
+    fn is_null(&self) -> bool {
+        self.is_none()
+    }
+}
+
+/// How to check for null values in a `Comparison::IsNull` or `Comparison::IsNotNull`.
+///
+/// Like `Prefix`, this captures `Nullable::is_null` when it is constructed, so that `Comparison`
+/// does not need to require `Nullable` of every value type.
+pub struct Null<T> {
+    is_null: fn(&T) -> bool,
+}
+
+impl<T: Nullable> Null<T> {
+    /// Use the notion of nullness given by `Nullable`.
+    pub fn new() -> Self {
+        Null { is_null: T::is_null }
+    }
+}
+
+impl<T: Nullable> Default for Null<T> {
+    fn default() -> Self {
+        Null::new()
+    }
+}
+
+impl<T> Null<T> {
+    /// Returns true if the given value is null.
+    pub fn is_null(&self, value: &T) -> bool {
+        (self.is_null)(value)
+    }
+}
+
+impl<T> Clone for Null<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Null<T> {}
+
+/// A type has only one notion of nullness, so all `Null`s for the same type are equal.
+impl<T> PartialEq for Null<T> {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl<T> Eq for Null<T> {}
+
+impl<T> Hash for Null<T> {
+    fn hash<H: Hasher>(&self, _: &mut H) {}
+}
+
+impl<T> fmt::Debug for Null<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Null")
+    }
+}
+
+/// A `Null` cannot be serialized, since it holds the function that checks for nullness.
+#[cfg(feature = "serde")]
+impl<T> Serialize for Null<T> {
+    fn serialize<S: Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+        unserializable::<S>("IsNull and IsNotNull")
+    }
+}
+
+/// The prefix to compare against in a `Comparison::StartsWith`.
+///
+/// The prefix captures how to check for a prefix when it is constructed, so that `Comparison`
+/// does not need to require `HasPrefix` of every value type.
+#[derive(Clone, Debug)]
+pub struct Prefix<'a, T: Clone + 'a> {
+    value: Value<'a, T>,
+    pub(crate) has_prefix: fn(&T, &T) -> bool,
+}
+
+impl<'a, T: HasPrefix + Clone + 'a> Prefix<'a, T> {
+    /// Construct a new prefix to compare against.
+    pub fn new(value: Value<'a, T>) -> Self {
+        Prefix {
+            value,
+            has_prefix: T::has_prefix,
+        }
+    }
+}
+
+impl<'a, T: Clone + 'a> Prefix<'a, T> {
+    /// The `Value` that holds the prefix.
+    pub fn value(&self) -> &Value<'a, T> {
+        &self.value
+    }
+}
+
+/// Prefixes are equal if their values are.
+impl<'a, T: PartialEq + Clone + 'a> PartialEq for Prefix<'a, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl<'a, T: Eq + Clone + 'a> Eq for Prefix<'a, T> {}
+
+impl<'a, T: Hash + Clone + 'a> Hash for Prefix<'a, T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.value.hash(state);
+    }
+}
+
+/// A `Prefix` cannot be serialized, since it holds the function that checks for a prefix.
+#[cfg(feature = "serde")]
+impl<'a, T: Clone + 'a> Serialize for Prefix<'a, T> {
+    fn serialize<S: Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+        unserializable::<S>("StartsWith")
+    }
+}
+
+/// The regular expression to match against in a `Comparison::Regex`.
+///
+/// Like `Prefix`, the pattern captures how to get at the text of a value when it is constructed,
+/// so that `Comparison` does not need to require `AsRef<str>` of every value type. The regular
+/// expression is compiled once, when the `Pattern` is constructed, and is then reused for every
+/// row.
+///
+/// Without the `regex` feature, no `Pattern` can be constructed.
+#[derive(Clone, Debug)]
+pub struct Pattern<T> {
+    #[cfg(feature = "regex")]
+    regex: regex::Regex,
+    #[cfg(not(feature = "regex"))]
+    regex: NoRegex,
+    as_str: fn(&T) -> &str,
+}
+
+/// Stands in for the compiled regular expression of a `Pattern` without the `regex` feature. It
+/// is never constructed, so neither is a `Pattern`.
+#[cfg(not(feature = "regex"))]
+#[derive(Clone, Debug)]
+struct NoRegex(());
+
+#[cfg(not(feature = "regex"))]
+impl NoRegex {
+    fn as_str(&self) -> &str {
+        unreachable!("a Pattern cannot be constructed without the regex feature")
+    }
+
+    fn is_match(&self, _: &str) -> bool {
+        unreachable!("a Pattern cannot be constructed without the regex feature")
+    }
+}
+
+#[cfg(feature = "regex")]
+impl<T: AsRef<str>> Pattern<T> {
+    /// Construct a new pattern from an already compiled regular expression.
+    pub fn new(regex: regex::Regex) -> Self {
+        Pattern {
+            regex,
+            as_str: T::as_ref,
+        }
+    }
+
+    /// Compile the given regular expression, and construct a new pattern from it.
+    pub fn parse(regex: &str) -> Result<Self, regex::Error> {
+        regex::Regex::new(regex).map(Pattern::new)
+    }
+}
+
+impl<T> Pattern<T> {
+    /// The compiled regular expression.
+    #[cfg(feature = "regex")]
+    pub fn regex(&self) -> &regex::Regex {
+        &self.regex
+    }
+
+    /// Returns true if the regular expression matches anywhere in the given value. Like
+    /// `Regex::is_match`, the match is not anchored, so use `^` and `$` to match the whole value.
+    pub fn is_match(&self, value: &T) -> bool {
+        self.regex.is_match((self.as_str)(value))
+    }
+}
+
+/// Patterns are equal if their regular expressions were compiled from the same text.
+impl<T> PartialEq for Pattern<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.regex.as_str() == other.regex.as_str()
+    }
+}
+
+impl<T> Eq for Pattern<T> {}
+
+impl<T> Hash for Pattern<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.regex.as_str().hash(state);
+    }
+}
+
+/// A `Pattern` cannot be serialized, since it holds the function that gets at the text of a value.
+#[cfg(feature = "serde")]
+impl<T> Serialize for Pattern<T> {
+    fn serialize<S: Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+        unserializable::<S>("Regex")
+    }
+}
+
+/// Fail to serialize a comparison of the given kinds, which hold a function.
+#[cfg(feature = "serde")]
+fn unserializable<S: Serializer>(kinds: &str) -> Result<S::Ok, S::Error> {
+    use serde::ser::Error;
+    Err(S::Error::custom(format_args!("{} comparisons cannot be serialized, since they hold a \
+                                       function",
+                                      kinds)))
+}
+
+/// Returns the bound on the value of `b` if it is a constant or unbounded.
+fn const_bound<'b, 'a: 'b, T: Clone + 'a>(b: &'b Bound<Value<'a, T>>) -> Option<Bound<&'b T>> {
+    match *b {
+        Bound::Included(Value::Const(ref v)) => Some(Bound::Included(v)),
+        Bound::Excluded(Value::Const(ref v)) => Some(Bound::Excluded(v)),
+        Bound::Unbounded => Some(Bound::Unbounded),
+        _ => None,
+    }
+}
+
+/// Returns true if no value can lie between the given bounds.
+pub(crate) fn is_empty_range<T: Ord>(min: Bound<&T>, max: Bound<&T>) -> bool {
+    match (min, max) {
+        (Bound::Included(a), Bound::Included(b)) => a > b,
+        (Bound::Included(a), Bound::Excluded(b)) |
+        (Bound::Excluded(a), Bound::Included(b)) |
+        (Bound::Excluded(a), Bound::Excluded(b)) => a >= b,
+        _ => false,
+    }
 }
 
 impl<'a, T: Ord + Clone + 'a> Comparison<'a, T> {
@@ -54,12 +494,320 @@ impl<'a, T: Ord + Clone + 'a> Comparison<'a, T> {
     pub fn matches<R: Row<T> + ?Sized>(&self, value: &T, row: &R) -> bool {
         match *self {
             Comparison::Equal(ref v) => value == v.value(row),
+            Comparison::NotEqual(ref v) => value != v.value(row),
+            Comparison::In(ref vs) => vs.iter().any(|v| value == v.value(row)),
+            Comparison::Less(ref v) => value < v.value(row),
+            Comparison::LessOrEqual(ref v) => value <= v.value(row),
+            Comparison::Greater(ref v) => value > v.value(row),
+            Comparison::GreaterOrEqual(ref v) => value >= v.value(row),
+            Comparison::StartsWith(ref p) => (p.has_prefix)(value, p.value.value(row)),
+            Comparison::KeyEqual(ref k, ref v) => k.key(value) == k.key(v.value(row)),
+            Comparison::IsNull(ref n) => n.is_null(value),
+            Comparison::IsNotNull(ref n) => !n.is_null(value),
+            Comparison::Regex(ref p) => p.is_match(value),
+            Comparison::Between { ref min, ref max } => within(value, min, max, row),
+            Comparison::InRanges(ref ranges) => {
+                ranges.iter().any(|(min, max)| within(value, min, max, row))
+            }
+        }
+    }
+
+    /// Returns all the `Value`s this comparison compares against.
+    pub fn values(&self) -> Vec<&Value<'a, T>> {
+        self.iter_values().collect()
+    }
+
+    /// Like `values`, but yields the values rather than collecting them, so that it does not
+    /// allocate.
+    pub(crate) fn iter_values<'b>(&'b self) -> impl Iterator<Item = &'b Value<'a, T>> + 'b {
+        let (one, many, bounds): (_, &[_], _) = match *self {
+            Comparison::Equal(ref v) |
+            Comparison::NotEqual(ref v) |
+            Comparison::Less(ref v) |
+            Comparison::LessOrEqual(ref v) |
+            Comparison::Greater(ref v) |
+            Comparison::GreaterOrEqual(ref v) |
+            Comparison::KeyEqual(_, ref v) => (Some(v), &[], None),
+            Comparison::In(ref vs) => (None, vs, None),
+            Comparison::StartsWith(ref p) => (Some(&p.value), &[], None),
+            Comparison::IsNull(..) | Comparison::IsNotNull(..) => (None, &[], None),
+            Comparison::Regex(..) => (None, &[], None),
+            Comparison::Between { ref min, ref max } => (None, &[], Some((min, max))),
+            Comparison::InRanges(..) => (None, &[], None),
+        };
+        let ranges: &[_] = match *self {
+            Comparison::InRanges(ref ranges) => ranges,
+            _ => &[],
+        };
+        let bound = |b: &'b Bound<Value<'a, T>>| match *b {
+            Bound::Included(ref v) | Bound::Excluded(ref v) => Some(v),
+            Bound::Unbounded => None,
+        };
+        let (min, max) = bounds.map_or((None, None), |(min, max)| (bound(min), bound(max)));
+        one.into_iter()
+            .chain(many)
+            .chain(min)
+            .chain(max)
+            .chain(ranges.iter()
+                .flat_map(move |(min, max)| bound(min).into_iter().chain(bound(max))))
+    }
+
+    /// Construct the same comparison, but with every constant replaced by the next of the given
+    /// values, in the order they are returned by `values`. See `Value::bind`.
+    pub(crate) fn bind<'b, I>(&self, values: &mut I) -> Comparison<'b, T>
+        where I: Iterator<Item = &'b T>
+    {
+        let bind_bound = |b: &Bound<Value<'a, T>>, values: &mut I| match *b {
+            Bound::Included(ref v) => Bound::Included(v.bind(values)),
+            Bound::Excluded(ref v) => Bound::Excluded(v.bind(values)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        match *self {
+            Comparison::Equal(ref v) => Comparison::Equal(v.bind(values)),
+            Comparison::NotEqual(ref v) => Comparison::NotEqual(v.bind(values)),
+            Comparison::In(ref vs) => Comparison::In(vs.iter().map(|v| v.bind(values)).collect()),
+            Comparison::Less(ref v) => Comparison::Less(v.bind(values)),
+            Comparison::LessOrEqual(ref v) => Comparison::LessOrEqual(v.bind(values)),
+            Comparison::Greater(ref v) => Comparison::Greater(v.bind(values)),
+            Comparison::GreaterOrEqual(ref v) => Comparison::GreaterOrEqual(v.bind(values)),
+            Comparison::StartsWith(ref p) => {
+                Comparison::StartsWith(Prefix {
+                    value: p.value.bind(values),
+                    has_prefix: p.has_prefix,
+                })
+            }
+            Comparison::Regex(ref p) => Comparison::Regex(p.clone()),
+            Comparison::KeyEqual(kf, ref v) => Comparison::KeyEqual(kf, v.bind(values)),
+            Comparison::IsNull(n) => Comparison::IsNull(n),
+            Comparison::IsNotNull(n) => Comparison::IsNotNull(n),
+            Comparison::Between { ref min, ref max } => {
+                let min = bind_bound(min, values);
+                Comparison::Between {
+                    min,
+                    max: bind_bound(max, values),
+                }
+            }
+            Comparison::InRanges(ref ranges) => {
+                Comparison::InRanges(ranges.iter()
+                    .map(|(min, max)| {
+                        let min = bind_bound(min, values);
+                        (min, bind_bound(max, values))
+                    })
+                    .collect())
+            }
+        }
+    }
+
+    /// Construct the same comparison, with every constant owned rather than borrowed.
+    pub(crate) fn owned(&self) -> Comparison<'static, T>
+        where T: 'static
+    {
+        let owned_bound = |b: &Bound<Value<'a, T>>| match *b {
+            Bound::Included(ref v) => Bound::Included(v.owned()),
+            Bound::Excluded(ref v) => Bound::Excluded(v.owned()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        match *self {
+            Comparison::Equal(ref v) => Comparison::Equal(v.owned()),
+            Comparison::NotEqual(ref v) => Comparison::NotEqual(v.owned()),
+            Comparison::In(ref vs) => Comparison::In(vs.iter().map(Value::owned).collect()),
+            Comparison::Less(ref v) => Comparison::Less(v.owned()),
+            Comparison::LessOrEqual(ref v) => Comparison::LessOrEqual(v.owned()),
+            Comparison::Greater(ref v) => Comparison::Greater(v.owned()),
+            Comparison::GreaterOrEqual(ref v) => Comparison::GreaterOrEqual(v.owned()),
+            Comparison::StartsWith(ref p) => {
+                Comparison::StartsWith(Prefix {
+                    value: p.value.owned(),
+                    has_prefix: p.has_prefix,
+                })
+            }
+            Comparison::Regex(ref p) => Comparison::Regex(p.clone()),
+            Comparison::KeyEqual(kf, ref v) => Comparison::KeyEqual(kf, v.owned()),
+            Comparison::IsNull(n) => Comparison::IsNull(n),
+            Comparison::IsNotNull(n) => Comparison::IsNotNull(n),
+            Comparison::Between { ref min, ref max } => {
+                Comparison::Between {
+                    min: owned_bound(min),
+                    max: owned_bound(max),
+                }
+            }
+            Comparison::InRanges(ref ranges) => {
+                Comparison::InRanges(ranges.iter()
+                    .map(|(min, max)| (owned_bound(min), owned_bound(max)))
+                    .collect())
+            }
+        }
+    }
+
+    /// Returns true if this comparison is known to match no value at all, regardless of the row
+    /// it is evaluated against. This is the case for an empty `In`, for a `Between` whose
+    /// constant bounds describe an empty range, and for an `InRanges` whose ranges are all empty.
+    pub fn matches_nothing(&self) -> bool {
+        let empty = |min, max| match (const_bound(min), const_bound(max)) {
+            (Some(min), Some(max)) => is_empty_range(min, max),
+            _ => false,
+        };
+        match *self {
+            Comparison::In(ref vs) => vs.is_empty(),
+            Comparison::Between { ref min, ref max } => empty(min, max),
+            Comparison::InRanges(ref ranges) => {
+                ranges.iter().all(|(min, max)| empty(min, max))
+            }
+            _ => false,
         }
     }
+
+    /// If this is an ordering comparison against a constant, returns the range of values that
+    /// satisfy it as a pair of `Bound`s suitable for `RangeIndex::between`. Returns `None` for
+    /// comparisons that cannot be expressed as a single range, and for empty ranges (see
+    /// `matches_nothing`).
+    pub fn range(&self) -> Option<(Bound<&T>, Bound<&T>)> {
+        match *self {
+            Comparison::Less(Value::Const(ref v)) => Some((Bound::Unbounded, Bound::Excluded(v))),
+            Comparison::LessOrEqual(Value::Const(ref v)) => {
+                Some((Bound::Unbounded, Bound::Included(v)))
+            }
+            Comparison::Greater(Value::Const(ref v)) => {
+                Some((Bound::Excluded(v), Bound::Unbounded))
+            }
+            Comparison::GreaterOrEqual(Value::Const(ref v)) => {
+                Some((Bound::Included(v), Bound::Unbounded))
+            }
+            Comparison::Between { ref min, ref max } => {
+                match (const_bound(min), const_bound(max)) {
+                    (Some(min), Some(max)) if !is_empty_range(min, max) => Some((min, max)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// If this is an `InRanges` comparison whose bounds are all constants, returns its ranges in
+    /// ascending order, with empty ranges left out, and with ranges that overlap or touch merged
+    /// into one. Scanning a `RangeIndex` with `RangeIndex::between` for each of the returned
+    /// ranges in turn thus yields every matching row exactly once. Returns `None` for all other
+    /// comparisons.
+    pub fn ranges(&self) -> Option<Vec<(Bound<&T>, Bound<&T>)>> {
+        let ranges = match *self {
+            Comparison::InRanges(ref ranges) => ranges,
+            _ => return None,
+        };
+        let mut ranges = ranges.iter()
+            .map(|(min, max)| Some((const_bound(min)?, const_bound(max)?)))
+            .collect::<Option<Vec<_>>>()?;
+        ranges.retain(|&(min, max)| !is_empty_range(min, max));
+        ranges.sort_by(|a, b| cmp_lower(a.0, b.0));
+
+        let mut merged: Vec<(Bound<&T>, Bound<&T>)> = Vec::with_capacity(ranges.len());
+        for (min, max) in ranges {
+            if let Some(last) = merged.last_mut() {
+                if !is_empty_range(min, last.1) || touches(last.1, min) {
+                    last.1 = looser_upper(last.1, max);
+                    continue;
+                }
+            }
+            merged.push((min, max));
+        }
+        Some(merged)
+    }
+}
+
+/// Does `value` lie between the given bounds, when evaluated against the given row?
+fn within<T, R>(value: &T, min: &Bound<Value<T>>, max: &Bound<Value<T>>, row: &R) -> bool
+    where T: Ord + Clone,
+          R: Row<T> + ?Sized
+{
+    let above = match *min {
+        Bound::Included(ref v) => value >= v.value(row),
+        Bound::Excluded(ref v) => value > v.value(row),
+        Bound::Unbounded => true,
+    };
+    let below = match *max {
+        Bound::Included(ref v) => value <= v.value(row),
+        Bound::Excluded(ref v) => value < v.value(row),
+        Bound::Unbounded => true,
+    };
+    above && below
+}
+
+/// Order two lower bounds by the smallest value they allow.
+fn cmp_lower<T: Ord>(a: Bound<&T>, b: Bound<&T>) -> ::std::cmp::Ordering {
+    use std::cmp::Ordering;
+    match (a, b) {
+        (Bound::Unbounded, Bound::Unbounded) => Ordering::Equal,
+        (Bound::Unbounded, _) => Ordering::Less,
+        (_, Bound::Unbounded) => Ordering::Greater,
+        (Bound::Included(a), Bound::Excluded(b)) if a == b => Ordering::Less,
+        (Bound::Excluded(a), Bound::Included(b)) if a == b => Ordering::Greater,
+        (Bound::Included(a), Bound::Included(b)) |
+        (Bound::Included(a), Bound::Excluded(b)) |
+        (Bound::Excluded(a), Bound::Included(b)) |
+        (Bound::Excluded(a), Bound::Excluded(b)) => a.cmp(b),
+    }
+}
+
+/// Returns true if a range ending at the upper bound `max` and a range starting at the lower bound
+/// `min` leave no value out between them, as with `..=3` and `3..`, or `..3` and `3..`.
+fn touches<T: Ord>(max: Bound<&T>, min: Bound<&T>) -> bool {
+    match (max, min) {
+        (Bound::Included(a), Bound::Excluded(b)) |
+        (Bound::Excluded(a), Bound::Included(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Pick the less restrictive of two upper bounds.
+fn looser_upper<'b, T: Ord>(a: Bound<&'b T>, b: Bound<&'b T>) -> Bound<&'b T> {
+    if tighter(a, b, false) == a { b } else { a }
 }
 
 /// A single condition to evaluate for a row in the dataset.
-#[derive(Clone, Debug)]
+///
+/// Conditions that compare a column against a constant are most easily constructed using the
+/// functions named after their comparison, such as `Condition::eq` and `Condition::gt`:
+///
+/// ```
+/// use shortcut::{Comparison, Condition, Store, Value};
+///
+/// let mut store = Store::new(2);
+/// store.insert(vec![1, 10]);
+/// store.insert(vec![2, 20]);
+/// store.insert(vec![1, 30]);
+///
+/// // written out in full
+/// let conds = [Condition {
+///                  column: 0,
+///                  cmp: Comparison::Equal(Value::new(1)),
+///              },
+///              Condition {
+///                  column: 1,
+///                  cmp: Comparison::Greater(Value::new(15)),
+///              }];
+/// assert_eq!(store.find(&conds).count(), 1);
+///
+/// // using the constructors
+/// let conds = [Condition::eq(0, 1), Condition::gt(1, 15)];
+/// assert_eq!(store.find(&conds).count(), 1);
+/// ```
+///
+/// With the `serde` feature enabled, conditions can be serialized and deserialized, so that they
+/// can be sent to the process that holds the `Store`. `Condition` is serialized as a struct with
+/// the fields `column` and `cmp`, and `Comparison`, `Value`, and `Bound` as enums tagged with
+/// the name of their variant, so in JSON the conditions above are written as:
+///
+/// ```text
+/// [{"column":0,"cmp":{"Equal":{"Const":1}}},{"column":1,"cmp":{"Greater":{"Const":15}}}]
+/// ```
+///
+/// A comparison against another column is written as `{"Column":1}`, and a `Between` as
+/// `{"Between":{"min":{"Included":{"Const":1}},"max":"Unbounded"}}`. This representation will
+/// not change in a way that breaks existing serialized conditions. The comparisons that hold a
+/// function (`StartsWith`, `Regex`, `KeyEqual`, `IsNull`, and `IsNotNull`) cannot be
+/// serialized: trying to do so fails with an error that names the comparison, and they are
+/// rejected as unknown variants when deserializing.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Condition<'a, T: Clone + 'a> {
     /// The column of the row to use as the comparison value.
     pub column: usize,
@@ -68,11 +816,233 @@ pub struct Condition<'a, T: Clone + 'a> {
     pub cmp: Comparison<'a, T>,
 }
 
+impl<'a, T: Clone + 'a> Condition<'a, T> {
+    /// Construct a condition that performs the given comparison on the given column.
+    pub fn new(column: usize, cmp: Comparison<'a, T>) -> Self {
+        Condition { column, cmp }
+    }
+
+    /// Format all the given conditions on a single line, joined by `AND`, for example for logging
+    /// slow queries.
+    ///
+    /// ```
+    /// use shortcut::{Comparison, Condition, Value};
+    ///
+    /// let listed = Comparison::In(vec![Value::new("b"), Value::Column(2)]);
+    /// let conds: [Condition<&str>; 3] = [Condition::eq(0, "a"),
+    ///                                    Condition::gt(3, "17"),
+    ///                                    Condition::new(1, listed)];
+    /// assert_eq!(Condition::display_all(&conds).to_string(),
+    ///            "[0] = a AND [3] > 17 AND [1] IN (b, [2])");
+    /// assert_eq!(Condition::<&str>::display_all(&[]).to_string(), "TRUE");
+    /// ```
+    pub fn display_all<'c>(conds: &'c [Condition<'a, T>]) -> Conjunction<'c, 'a, T> {
+        Conjunction(conds)
+    }
+
+    /// Is the value in `column` equal to `value`? See `Comparison::Equal`.
+    pub fn eq<I: Into<T>>(column: usize, value: I) -> Self {
+        Condition::new(column, Comparison::Equal(Value::new(value)))
+    }
+
+    /// Is the value in `column` different from `value`? See `Comparison::NotEqual`.
+    pub fn ne<I: Into<T>>(column: usize, value: I) -> Self {
+        Condition::new(column, Comparison::NotEqual(Value::new(value)))
+    }
+
+    /// Is the value in `column` strictly less than `value`? See `Comparison::Less`.
+    pub fn lt<I: Into<T>>(column: usize, value: I) -> Self {
+        Condition::new(column, Comparison::Less(Value::new(value)))
+    }
+
+    /// Is the value in `column` less than or equal to `value`? See `Comparison::LessOrEqual`.
+    pub fn le<I: Into<T>>(column: usize, value: I) -> Self {
+        Condition::new(column, Comparison::LessOrEqual(Value::new(value)))
+    }
+
+    /// Is the value in `column` strictly greater than `value`? See `Comparison::Greater`.
+    pub fn gt<I: Into<T>>(column: usize, value: I) -> Self {
+        Condition::new(column, Comparison::Greater(Value::new(value)))
+    }
+
+    /// Is the value in `column` greater than or equal to `value`? See
+    /// `Comparison::GreaterOrEqual`.
+    pub fn ge<I: Into<T>>(column: usize, value: I) -> Self {
+        Condition::new(column, Comparison::GreaterOrEqual(Value::new(value)))
+    }
+
+    /// Is the value in `column` equal to any of `values`? See `Comparison::In`.
+    pub fn is_in<I, V>(column: usize, values: V) -> Self
+        where I: Into<T>,
+              V: IntoIterator<Item = I>
+    {
+        Condition::new(column,
+                       Comparison::In(values.into_iter().map(Value::new).collect()))
+    }
+
+    /// Does the value in `column` lie between `min` and `max`? See `Comparison::Between`.
+    pub fn between<I: Into<T>>(column: usize, min: Bound<I>, max: Bound<I>) -> Self {
+        Condition::new(column,
+                       Comparison::Between {
+                           min: min.map(Value::new),
+                           max: max.map(Value::new),
+                       })
+    }
+
+    /// Does the value in `column` lie within any of the given ranges? See
+    /// `Comparison::InRanges`.
+    pub fn in_ranges<I, V>(column: usize, ranges: V) -> Self
+        where I: Into<T>,
+              V: IntoIterator<Item = (Bound<I>, Bound<I>)>
+    {
+        Condition::new(column,
+                       Comparison::InRanges(ranges.into_iter()
+                           .map(|(min, max)| (min.map(Value::new), max.map(Value::new)))
+                           .collect()))
+    }
+}
+
+impl<'a, T: HasPrefix + Clone + 'a> Condition<'a, T> {
+    /// Does the value in `column` start with `prefix`? See `Comparison::StartsWith`.
+    pub fn starts_with<I: Into<T>>(column: usize, prefix: I) -> Self {
+        Condition::new(column,
+                       Comparison::StartsWith(Prefix::new(Value::new(prefix))))
+    }
+}
+
+impl<'a, T: Nullable + Clone + 'a> Condition<'a, T> {
+    /// Is the value in `column` null? See `Comparison::IsNull`.
+    pub fn is_null(column: usize) -> Self {
+        Condition::new(column, Comparison::IsNull(Null::new()))
+    }
+
+    /// Is the value in `column` not null? See `Comparison::IsNotNull`.
+    pub fn is_not_null(column: usize) -> Self {
+        Condition::new(column, Comparison::IsNotNull(Null::new()))
+    }
+}
+
 impl<'a, T: Ord + Clone + 'a> Condition<'a, T> {
     /// Returns true if this condition holds true for the given row. To determine if this is the
     /// case, `row[self.column]` is extracted, and is evaluated using the comparison in `self.cmp`.
     pub fn matches<R: Row<T> + ?Sized>(&self, row: &R) -> bool {
-        self.cmp.matches(&row.index(self.column), row)
+        self.cmp.matches(row.index(self.column), row)
+    }
+
+    /// Construct the same condition, but with every constant replaced by the next of the given
+    /// values. See `Comparison::bind`.
+    pub(crate) fn bind<'b, I>(&self, values: &mut I) -> Condition<'b, T>
+        where I: Iterator<Item = &'b T>
+    {
+        Condition {
+            column: self.column,
+            cmp: self.cmp.bind(values),
+        }
+    }
+
+    /// Construct the same condition, with every constant owned rather than borrowed.
+    pub(crate) fn owned(&self) -> Condition<'static, T>
+        where T: 'static
+    {
+        Condition {
+            column: self.column,
+            cmp: self.cmp.owned(),
+        }
+    }
+
+    /// Returns true if every row that satisfies this condition is known to also satisfy `other`,
+    /// regardless of the values in any other column. This is only ever the case if both are on
+    /// the same column, and this condition is an `Equal` or an ordering comparison against a
+    /// constant, so that the values it allows form a range (see `column_range`). That range must
+    /// then lie within the values `other` allows, if `other` is an `Equal`, `NotEqual`, `In`,
+    /// `InRanges`, or ordering comparison against constants. For any other pair of conditions,
+    /// false is returned.
+    pub(crate) fn implies(&self, other: &Condition<'a, T>) -> bool {
+        if self.column != other.column {
+            return false;
+        }
+        let (min, max) = match self.cmp {
+            Comparison::Equal(Value::Const(ref v)) => {
+                (Bound::Included(&**v), Bound::Included(&**v))
+            }
+            ref cmp => {
+                match cmp.range() {
+                    Some(range) => range,
+                    None => return false,
+                }
+            }
+        };
+        let within = |(omin, omax): (Bound<&T>, Bound<&T>)| {
+            tighter(min, omin, true) == min && tighter(max, omax, false) == max
+        };
+        match other.cmp {
+            Comparison::Equal(Value::Const(ref w)) => {
+                min == Bound::Included(&**w) && max == Bound::Included(&**w)
+            }
+            Comparison::NotEqual(Value::Const(ref w)) => {
+                let w = Bound::Included(&**w);
+                is_empty_range(tighter(min, w, true), tighter(max, w, false))
+            }
+            Comparison::In(ref ws) => {
+                ws.iter().any(|w| match *w {
+                    Value::Const(ref w) => {
+                        min == Bound::Included(&**w) && max == Bound::Included(&**w)
+                    }
+                    Value::Column(..) => false,
+                })
+            }
+            Comparison::InRanges(..) => {
+                other.cmp.ranges().is_some_and(|ranges| ranges.into_iter().any(within))
+            }
+            ref cmp => cmp.range().is_some_and(within),
+        }
+    }
+}
+
+/// Intersect the ranges of values allowed by every condition on `column` that compares against a
+/// constant using either `Equal` or an ordering comparison (see `Comparison::range`). Returns
+/// `None` if there are no such conditions. The returned range may be empty (see
+/// `is_empty_range`), in which case the conditions contradict each other.
+pub(crate) fn column_range<'b, 'a: 'b, T>(conds: &'b [Condition<'a, T>],
+                                          column: usize)
+                                          -> Option<(Bound<&'b T>, Bound<&'b T>)>
+    where T: Ord + Clone + 'a
+{
+    conds.iter()
+        .filter(|c| c.column == column)
+        .filter_map(|c| match c.cmp {
+            Comparison::Equal(Value::Const(ref v)) => {
+                Some((Bound::Included(&**v), Bound::Included(&**v)))
+            }
+            ref cmp => cmp.range(),
+        })
+        .fold(None, |acc, (min, max)| match acc {
+            None => Some((min, max)),
+            Some((amin, amax)) => Some((tighter(amin, min, true), tighter(amax, max, false))),
+        })
+}
+
+/// Pick the more restrictive of two lower (if `lower`) or upper bounds.
+fn tighter<'b, T: Ord>(a: Bound<&'b T>, b: Bound<&'b T>, lower: bool) -> Bound<&'b T> {
+    use std::cmp::Ordering;
+    let (av, bv) = match (a, b) {
+        (Bound::Unbounded, b) => return b,
+        (a, Bound::Unbounded) => return a,
+        (Bound::Included(av), Bound::Included(bv)) |
+        (Bound::Included(av), Bound::Excluded(bv)) |
+        (Bound::Excluded(av), Bound::Included(bv)) |
+        (Bound::Excluded(av), Bound::Excluded(bv)) => (av, bv),
+    };
+    match (av.cmp(bv), lower) {
+        (Ordering::Greater, true) | (Ordering::Less, false) => a,
+        (Ordering::Less, true) | (Ordering::Greater, false) => b,
+        (Ordering::Equal, _) => {
+            // for the same value, an exclusive bound is the more restrictive one
+            match a {
+                Bound::Excluded(_) => a,
+                _ => b,
+            }
+        }
     }
 }
 
@@ -89,16 +1059,93 @@ impl<'a, T: fmt::Display + Clone + 'a> fmt::Display for Comparison<'a, T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Comparison::Equal(ref v) => write!(f, "= {}", v),
+            Comparison::NotEqual(ref v) => write!(f, "!= {}", v),
+            Comparison::In(ref vs) => {
+                write!(f, "IN (")?;
+                for (i, v) in vs.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", v)?;
+                }
+                write!(f, ")")
+            }
+            Comparison::Less(ref v) => write!(f, "< {}", v),
+            Comparison::LessOrEqual(ref v) => write!(f, "<= {}", v),
+            Comparison::Greater(ref v) => write!(f, "> {}", v),
+            Comparison::GreaterOrEqual(ref v) => write!(f, ">= {}", v),
+            Comparison::StartsWith(ref p) => write!(f, "STARTS WITH {}", p.value),
+            Comparison::KeyEqual(ref k, ref v) => write!(f, "= {} BY {}", v, k.name),
+            Comparison::IsNull(..) => write!(f, "IS NULL"),
+            Comparison::IsNotNull(..) => write!(f, "IS NOT NULL"),
+            Comparison::Regex(ref p) => write!(f, "MATCHES /{}/", p.regex.as_str()),
+            Comparison::Between { ref min, ref max } => {
+                write!(f, "BETWEEN ")?;
+                write_range(f, min, max)
+            }
+            Comparison::InRanges(ref ranges) => {
+                write!(f, "IN RANGES (")?;
+                for (i, (min, max)) in ranges.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write_range(f, min, max)?;
+                }
+                write!(f, ")")
+            }
         }
     }
 }
 
+/// Write the range between the given bounds, as in `[1, 2)`.
+fn write_range<T>(f: &mut fmt::Formatter,
+                  min: &Bound<Value<T>>,
+                  max: &Bound<Value<T>>)
+                  -> fmt::Result
+    where T: fmt::Display + Clone
+{
+    match *min {
+        Bound::Included(ref v) => write!(f, "[{}", v)?,
+        Bound::Excluded(ref v) => write!(f, "({}", v)?,
+        Bound::Unbounded => write!(f, "(..")?,
+    }
+    match *max {
+        Bound::Included(ref v) => write!(f, ", {}]", v),
+        Bound::Excluded(ref v) => write!(f, ", {})", v),
+        Bound::Unbounded => write!(f, ", ..)"),
+    }
+}
+
+/// A condition is written as the column in brackets followed by the comparison, as in `[0] = a`.
+/// Columns that a comparison refers to are written the same way, so `[1] < [0]` matches rows whose
+/// value in column 1 is less than their value in column 0. Constants are written using their own
+/// `Display` implementation, without quotes.
 impl<'a, T: fmt::Display + Clone + 'a> fmt::Display for Condition<'a, T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "[{}] {}", self.column, self.cmp)
     }
 }
 
+/// A list of conditions that all have to hold, as formatted by `Condition::display_all`.
+pub struct Conjunction<'c, 'a: 'c, T: Clone + 'a>(&'c [Condition<'a, T>]);
+
+/// The conditions are written one after the other, separated by `AND`. An empty list of
+/// conditions, which matches every row, is written as `TRUE`.
+impl<'c, 'a: 'c, T: fmt::Display + Clone + 'a> fmt::Display for Conjunction<'c, 'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "TRUE");
+        }
+        for (i, c) in self.0.iter().enumerate() {
+            if i != 0 {
+                write!(f, " AND ")?;
+            }
+            write!(f, "{}", c)?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -121,6 +1168,195 @@ mod tests {
         assert!(!Comparison::Equal(Value::new("b")).matches(&"a", &a[..]));
     }
 
+    #[test]
+    fn cmp_neq() {
+        let a = &["a"];
+        let b = &["b"];
+        assert!(!Comparison::NotEqual(Value::column(0)).matches(&"a", &a[..]));
+        assert!(Comparison::NotEqual(Value::column(0)).matches(&"a", &b[..]));
+        assert!(Comparison::NotEqual(Value::new("a")).matches(&"b", &b[..]));
+        assert!(!Comparison::NotEqual(Value::new("b")).matches(&"b", &a[..]));
+    }
+
+    #[test]
+    fn cmp_in() {
+        let a = &["a"];
+        let b = &["b"];
+        assert!(Comparison::In(vec![Value::new("a"), Value::new("b")]).matches(&"a", &a[..]));
+        assert!(!Comparison::In(vec![Value::new("b")]).matches(&"a", &a[..]));
+        assert!(Comparison::In(vec![Value::new("c"), Value::column(0)]).matches(&"b", &b[..]));
+        assert!(!Comparison::In(vec![]).matches(&"a", &a[..]));
+    }
+
+    #[test]
+    fn cmp_ord() {
+        let a = &["a"];
+        assert!(Comparison::Less(Value::new("b")).matches(&"a", &a[..]));
+        assert!(!Comparison::Less(Value::new("a")).matches(&"a", &a[..]));
+        assert!(Comparison::LessOrEqual(Value::new("a")).matches(&"a", &a[..]));
+        assert!(Comparison::Greater(Value::new("a")).matches(&"b", &a[..]));
+        assert!(!Comparison::Greater(Value::column(0)).matches(&"a", &a[..]));
+        assert!(Comparison::GreaterOrEqual(Value::column(0)).matches(&"a", &a[..]));
+    }
+
+    #[test]
+    fn cmp_range() {
+        use std::ops::Bound::{Excluded, Included, Unbounded};
+        let a = "a";
+        assert_eq!(Comparison::Less(Value::new("a")).range(),
+                   Some((Unbounded, Excluded(&a))));
+        assert_eq!(Comparison::GreaterOrEqual(Value::new("a")).range(),
+                   Some((Included(&a), Unbounded)));
+        assert_eq!(Comparison::<&str>::Equal(Value::new("a")).range(), None);
+        assert_eq!(Comparison::<&str>::Less(Value::column(0)).range(), None);
+    }
+
+    #[test]
+    fn cmp_between() {
+        use std::ops::Bound::{Excluded, Included, Unbounded};
+        let a = &[3];
+        let between = |min, max| Comparison::Between { min, max };
+        let c = between(Included(Value::new(3)), Excluded(Value::new(10)));
+        assert!(c.matches(&3, &a[..]));
+        assert!(c.matches(&9, &a[..]));
+        assert!(!c.matches(&10, &a[..]));
+        assert!(!c.matches(&2, &a[..]));
+        assert_eq!(c.range(), Some((Included(&3), Excluded(&10))));
+
+        let c = between(Excluded(Value::column(0)), Unbounded);
+        assert!(c.matches(&4, &a[..]));
+        assert!(!c.matches(&3, &a[..]));
+        assert_eq!(c.range(), None);
+        assert!(!c.matches_nothing());
+
+        let c = between(Unbounded, Included(Value::new(3)));
+        assert!(c.matches(&3, &a[..]));
+        assert_eq!(c.range(), Some((Unbounded, Included(&3))));
+
+        for c in [between(Included(Value::new(4)), Included(Value::new(3))),
+                  between(Included(Value::new(3)), Excluded(Value::new(3))),
+                  between(Excluded(Value::new(3)), Excluded(Value::new(3)))] {
+            assert!(c.matches_nothing());
+            assert_eq!(c.range(), None);
+            assert!(!c.matches(&3, &a[..]));
+        }
+        assert!(!between(Included(Value::new(3)), Included(Value::new(3))).matches_nothing());
+        assert!(Comparison::<usize>::In(vec![]).matches_nothing());
+    }
+
+    #[test]
+    fn cmp_starts_with() {
+        let a = &["fo", "foo"];
+        let prefix = |v| Comparison::StartsWith(Prefix::new(v));
+        assert!(prefix(Value::new("foo")).matches(&"foo", &a[..]));
+        assert!(prefix(Value::new("foo")).matches(&"food", &a[..]));
+        assert!(!prefix(Value::new("foo")).matches(&"fo", &a[..]));
+        assert!(!prefix(Value::new("foo")).matches(&"afoo", &a[..]));
+        assert!(prefix(Value::new("")).matches(&"", &a[..]));
+        assert!(prefix(Value::column(0)).matches(&"fob", &a[..]));
+        assert!(!prefix(Value::column(1)).matches(&"fob", &a[..]));
+        assert_eq!(prefix(Value::new("foo")).range(), None);
+
+        let b = vec![vec![1u8, 255]];
+        let c = Comparison::StartsWith(Prefix::new(Value::column(0)));
+        assert!(c.matches(&vec![1, 255, 0], &b));
+        assert!(!c.matches(&vec![1, 254, 0], &b));
+    }
+
+    #[test]
+    fn cmp_null() {
+        let a = &[None, Some(1)];
+        let null = Comparison::IsNull(Null::new());
+        let not_null = Comparison::IsNotNull(Null::new());
+        assert!(null.matches(&None, &a[..]));
+        assert!(!null.matches(&Some(1), &a[..]));
+        assert!(!not_null.matches(&None, &a[..]));
+        assert!(not_null.matches(&Some(0), &a[..]));
+        assert!(null.values().is_empty());
+        assert_eq!(null.range(), None);
+        assert!(!null.matches_nothing());
+
+        assert_eq!(Condition::is_null(0), Condition::new(0, null));
+        assert_ne!(Condition::is_null(0), Condition::new(0, not_null));
+        assert!(Condition::is_not_null(1).matches(&a[..]));
+    }
+
+    #[test]
+    #[cfg(feature = "regex")]
+    fn cmp_regex() {
+        let a = &["a"];
+        let c = Comparison::Regex(Pattern::parse("b+c").unwrap());
+        assert!(c.matches(&"bbc", &a[..]));
+        assert!(c.matches(&"abcd", &a[..]));
+        assert!(!c.matches(&"bd", &a[..]));
+
+        // anchors match the whole value
+        let c = Comparison::Regex(Pattern::parse("^b+c$").unwrap());
+        assert!(c.matches(&"bbc", &a[..]));
+        assert!(!c.matches(&"abcd", &a[..]));
+        assert!(!c.matches(&"bbc\n", &a[..]));
+
+        let c = Comparison::Regex(Pattern::<String>::parse("^[0-9]+$").unwrap());
+        assert!(c.matches(&"123".to_string(), &vec!["x".to_string()]));
+        assert_eq!(format!("{}", c), "MATCHES /^[0-9]+$/");
+        assert!(c.values().is_empty());
+        assert!(Pattern::<String>::parse("(").is_err());
+    }
+
+    #[test]
+    fn cmp_key_eq() {
+        let a = &["Foo".to_string()];
+        let lower = KeyFn::new("lower", |s: &String| s.to_lowercase());
+        assert!(Comparison::KeyEqual(lower, Value::new("FOO".to_string()))
+            .matches(&"foo".to_string(), &a[..]));
+        assert!(!Comparison::KeyEqual(lower, Value::new("FOOD".to_string()))
+            .matches(&"foo".to_string(), &a[..]));
+        assert!(Comparison::KeyEqual(lower, Value::column(0)).matches(&"fOO".to_string(), &a[..]));
+        assert_eq!(format!("{}", Comparison::KeyEqual(lower, Value::column(0))),
+                   "= [0] BY lower");
+
+        let bucket = KeyFn::new("bucket", |v: &usize| v / 10);
+        assert!(Comparison::KeyEqual(bucket, Value::new(31usize)).matches(&39, &[0][..]));
+        assert!(!Comparison::KeyEqual(bucket, Value::new(31usize)).matches(&40, &[0][..]));
+    }
+
+    #[test]
+    fn merged_range() {
+        use std::ops::Bound::{Excluded, Included, Unbounded};
+        let cond = |column, cmp| Condition { column, cmp };
+        let conds = vec![cond(0, Comparison::Greater(Value::new(3))),
+                         cond(0, Comparison::GreaterOrEqual(Value::new(3))),
+                         cond(1, Comparison::Equal(Value::new(100))),
+                         cond(0, Comparison::Less(Value::new(10))),
+                         cond(0, Comparison::LessOrEqual(Value::new(12))),
+                         cond(0, Comparison::NotEqual(Value::new(5))),
+                         cond(2, Comparison::Less(Value::column(0)))];
+        assert_eq!(column_range(&conds, 0), Some((Excluded(&3), Excluded(&10))));
+        assert_eq!(column_range(&conds, 1), Some((Included(&100), Included(&100))));
+        assert_eq!(column_range(&conds, 2), None);
+        assert_eq!(column_range(&conds, 3), None);
+
+        let conds = vec![cond(0, Comparison::Equal(Value::new(1))),
+                         cond(0, Comparison::Equal(Value::new(2)))];
+        let (min, max) = column_range(&conds, 0).unwrap();
+        assert!(is_empty_range(min, max));
+
+        let conds = vec![cond(0, Comparison::Equal(Value::new(3))),
+                         cond(0, Comparison::Less(Value::new(3)))];
+        let (min, max) = column_range(&conds, 0).unwrap();
+        assert_eq!((min, max), (Included(&3), Excluded(&3)));
+        assert!(is_empty_range(min, max));
+
+        let conds = vec![cond(0, Comparison::Equal(Value::new(3))),
+                         cond(0, Comparison::Between {
+                             min: Unbounded,
+                             max: Included(Value::new(3)),
+                         })];
+        let (min, max) = column_range(&conds, 0).unwrap();
+        assert_eq!((min, max), (Included(&3), Included(&3)));
+        assert!(!is_empty_range(min, max));
+    }
+
     #[test]
     fn borrowed_values() {
         let a = vec!["a".to_string()];
@@ -172,6 +1408,89 @@ mod tests {
         assert!(!ccb.matches(&a[..]));
     }
 
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_roundtrip() {
+        extern crate serde_json;
+        let conds: Vec<Condition<String>> = vec![Condition {
+                             column: 0,
+                             cmp: Comparison::Equal(Value::new("a".to_string())),
+                         },
+                         Condition {
+                             column: 1,
+                             cmp: Comparison::In(vec![Value::new("b".to_string()),
+                                                      Value::Column(0)]),
+                         }];
+        let json = serde_json::to_string(&conds).unwrap();
+        let back: Vec<Condition<String>> = serde_json::from_str(&json).unwrap();
+        let a = vec!["a".to_string(), "a".to_string()];
+        let b = vec!["a".to_string(), "c".to_string()];
+        assert!(back.iter().all(|c| c.matches(&a)));
+        assert!(!back.iter().all(|c| c.matches(&b)));
+        assert_eq!(format!("{}", back[1]), "[1] IN (b, [0])");
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_executes_like_original() {
+        extern crate serde_json;
+        use std::ops::Bound;
+        use idx::{BTreeIndex, HashIndex};
+        use Store;
+
+        let mut store = Store::new(3);
+        store.index(0, HashIndex::new());
+        store.index(1, BTreeIndex::new());
+        for i in 0..500usize {
+            store.insert(vec![i % 13, i, i % 5]);
+        }
+
+        let queries: Vec<Vec<Condition<usize>>> =
+            vec![vec![Condition::eq(0, 3usize)],
+                 vec![Condition::eq(0, 3usize), Condition::gt(1, 100usize)],
+                 vec![Condition::ne(2, 0usize), Condition::lt(1, 50usize)],
+                 vec![Condition::between(1, Bound::Excluded(10usize), Bound::Unbounded),
+                      Condition::new(2, Comparison::In(vec![Value::new(1usize),
+                                                            Value::Column(0)]))],
+                 vec![Condition::in_ranges(1, vec![(Bound::Included(5usize),
+                                                    Bound::Excluded(20usize)),
+                                                   (Bound::Unbounded, Bound::Included(2usize))])],
+                 vec![Condition::new(0, Comparison::LessOrEqual(Value::Column(2)))]];
+        for conds in queries {
+            // the client serializes the conditions, and the server executes what it receives
+            let json = serde_json::to_string(&conds).unwrap();
+            let received: Vec<Condition<usize>> = serde_json::from_str(&json).unwrap();
+            assert_eq!(received, conds);
+            let expected = store.find(&conds).collect::<Vec<_>>();
+            assert_eq!(store.find(&received).collect::<Vec<_>>(), expected, "{}", json);
+        }
+
+        let conds: [Condition<usize>; 2] = [Condition::eq(0, 1usize), Condition::gt(1, 15usize)];
+        assert_eq!(serde_json::to_string(&conds).unwrap(),
+                   r#"[{"column":0,"cmp":{"Equal":{"Const":1}}},"#.to_string() +
+                   r#"{"column":1,"cmp":{"Greater":{"Const":15}}}]"#);
+        let between = Condition::<usize>::between(1, Bound::Included(1usize), Bound::Unbounded);
+        assert_eq!(serde_json::to_string(&between.cmp).unwrap(),
+                   r#"{"Between":{"min":{"Included":{"Const":1}},"max":"Unbounded"}}"#);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_rejects_functions() {
+        extern crate serde_json;
+        let null = Condition::<Option<usize>>::new(0, Comparison::IsNull(Null::new()));
+        let err = serde_json::to_string(&null).unwrap_err().to_string();
+        assert!(err.contains("IsNull and IsNotNull comparisons cannot be serialized"), "{}", err);
+
+        let prefix = Condition::<String>::starts_with(0, "a".to_string());
+        let err = serde_json::to_string(&prefix).unwrap_err().to_string();
+        assert!(err.contains("StartsWith comparisons cannot be serialized"), "{}", err);
+
+        let json = r#"{"column":0,"cmp":{"StartsWith":{"Const":"a"}}}"#;
+        let err = serde_json::from_str::<Condition<String>>(json).unwrap_err().to_string();
+        assert!(err.contains("unknown variant `StartsWith`"), "{}", err);
+    }
+
     #[test]
     fn display() {
         let cf01: Condition<String> = Condition {
@@ -185,6 +1504,164 @@ mod tests {
         };
 
         assert_eq!(format!("{}", cf01), "[0] = [1]");
-        assert_eq!(format!("{}", cca), "[0] = a")
+        assert_eq!(format!("{}", cca), "[0] = a");
+
+        let cna = Condition {
+            column: 0,
+            cmp: Comparison::NotEqual::<&str>(Value::new("a")),
+        };
+        assert_eq!(format!("{}", cna), "[0] != a");
+
+        let cin = Condition {
+            column: 0,
+            cmp: Comparison::In::<&str>(vec![Value::new("a"), Value::Column(1)]),
+        };
+        assert_eq!(format!("{}", cin), "[0] IN (a, [1])");
+
+        let cbt = Condition {
+            column: 0,
+            cmp: Comparison::Between::<&str> {
+                min: Bound::Included(Value::new("a")),
+                max: Bound::Excluded(Value::Column(1)),
+            },
+        };
+        assert_eq!(format!("{}", cbt), "[0] BETWEEN [a, [1])");
+        let cbt = Condition {
+            column: 0,
+            cmp: Comparison::Between::<&str> {
+                min: Bound::Unbounded,
+                max: Bound::Included(Value::new("b")),
+            },
+        };
+        assert_eq!(format!("{}", cbt), "[0] BETWEEN (.., b]");
+
+        let csw = Condition {
+            column: 0,
+            cmp: Comparison::StartsWith(Prefix::new(Value::<&str>::new("ab"))),
+        };
+        assert_eq!(format!("{}", csw), "[0] STARTS WITH ab");
+    }
+
+    #[test]
+    fn condition_constructors() {
+        let show = |c: Condition<&str>| format!("{}", c);
+        assert_eq!(show(Condition::eq(0, "a")), "[0] = a");
+        assert_eq!(show(Condition::ne(1, "a")), "[1] != a");
+        assert_eq!(show(Condition::lt(0, "a")), "[0] < a");
+        assert_eq!(show(Condition::le(0, "a")), "[0] <= a");
+        assert_eq!(show(Condition::gt(0, "a")), "[0] > a");
+        assert_eq!(show(Condition::ge(0, "a")), "[0] >= a");
+        assert_eq!(show(Condition::is_in(0, vec!["a", "b"])), "[0] IN (a, b)");
+        assert_eq!(show(Condition::between(0, Bound::Included("a"), Bound::Unbounded)),
+                   "[0] BETWEEN [a, ..)");
+        assert_eq!(show(Condition::starts_with(0, "ab")), "[0] STARTS WITH ab");
+        assert_eq!(show(Condition::new(0, Comparison::IsNull(Null { is_null: |_| false }))),
+                   "[0] IS NULL");
+        assert_eq!(show(Condition::new(0, Comparison::IsNotNull(Null { is_null: |_| false }))),
+                   "[0] IS NOT NULL");
+        assert_eq!(show(Condition::new(0, Comparison::Equal(Value::column(1)))),
+                   "[0] = [1]");
+
+        let a = &["a", "b"];
+        assert!(Condition::eq(1, "b").matches(&a[..]));
+        assert!(!Condition::gt(0, "a").matches(&a[..]));
+    }
+
+    #[test]
+    fn in_ranges() {
+        let given: Vec<(Bound<usize>, _)> = vec![(Bound::Included(1), Bound::Excluded(3)),
+                                                 (Bound::Excluded(7), Bound::Unbounded)];
+        let c = Condition::<usize>::in_ranges(0, given);
+        assert_eq!(format!("{}", c), "[0] IN RANGES ([1, 3), (7, ..))");
+        let matching = (0..10usize).filter(|&i| c.matches(&[i][..])).collect::<Vec<_>>();
+        assert_eq!(matching, vec![1, 2, 8, 9]);
+        assert_eq!(c.cmp.ranges().unwrap(),
+                   vec![(Bound::Included(&1), Bound::Excluded(&3)),
+                        (Bound::Excluded(&7), Bound::Unbounded)]);
+
+        // overlapping and touching ranges are merged, in ascending order
+        let ranges = |ranges: Vec<(Bound<usize>, Bound<usize>)>| {
+            Condition::<usize>::in_ranges(0, ranges)
+                .cmp
+                .ranges()
+                .unwrap()
+                .into_iter()
+                .map(|(min, max)| (min.cloned(), max.cloned()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ranges(vec![(Bound::Included(5usize), Bound::Included(9usize)),
+                               (Bound::Included(1usize), Bound::Excluded(6usize)),
+                               (Bound::Included(12usize), Bound::Excluded(14usize))]),
+                   vec![(Bound::Included(1usize), Bound::Included(9usize)),
+                        (Bound::Included(12usize), Bound::Excluded(14usize))]);
+        assert_eq!(ranges(vec![(Bound::Included(3usize), Bound::Unbounded),
+                               (Bound::Unbounded, Bound::Excluded(3usize))]),
+                   vec![(Bound::Unbounded, Bound::Unbounded)]);
+        assert_eq!(ranges(vec![(Bound::Included(1usize), Bound::Included(3usize)),
+                               (Bound::Excluded(3usize), Bound::Included(5usize)),
+                               (Bound::Included(2usize), Bound::Included(3usize))]),
+                   vec![(Bound::Included(1usize), Bound::Included(5usize))]);
+        // but ranges that both leave out the value between them are not
+        assert_eq!(ranges(vec![(Bound::Excluded(3usize), Bound::Included(5usize)),
+                               (Bound::Included(1usize), Bound::Excluded(3usize))]),
+                   vec![(Bound::Included(1usize), Bound::Excluded(3usize)),
+                        (Bound::Excluded(3usize), Bound::Included(5usize))]);
+        // and empty ranges are left out
+        assert_eq!(ranges(vec![(Bound::Included(4usize), Bound::Excluded(4usize)),
+                               (Bound::Included(6usize), Bound::Included(6usize))]),
+                   vec![(Bound::Included(6usize), Bound::Included(6usize))]);
+
+        assert!(Condition::<usize>::in_ranges::<usize, _>(0, vec![]).cmp.matches_nothing());
+        let empty = vec![(Bound::Included(4usize), Bound::Excluded(4usize))];
+        assert!(Condition::<usize>::in_ranges(0, empty).cmp.matches_nothing());
+        assert!(!c.cmp.matches_nothing());
+        let unbound = Comparison::InRanges(vec![(Bound::Included(Value::column(1)),
+                                                 Bound::Unbounded)]);
+        assert!(unbound.ranges().is_none());
+        assert!(unbound.matches(&2, &[0usize, 1][..]));
+        assert!(!unbound.matches(&0, &[0usize, 1][..]));
+    }
+
+    #[test]
+    fn condition_eq() {
+        type C<'a> = Condition<'a, &'static str>;
+        assert_eq!(Value::<&str>::new("a"), Value::using(&"a"));
+        assert_ne!(Value::<&str>::new("a"), Value::column(0));
+        assert_eq!(C::eq(0, "a"), C::eq(0, "a"));
+        assert_ne!(C::eq(0, "a"), C::eq(1, "a"));
+        assert_ne!(C::eq(0, "a"), C::ne(0, "a"));
+        assert_eq!(C::starts_with(0, "a"), C::starts_with(0, "a").clone());
+        assert_ne!(C::starts_with(0, "a"), C::starts_with(0, "b"));
+
+        let lower = |v: &String| v.to_lowercase();
+        let upper = |v: &String| v.to_uppercase();
+        let keyed = |kf| Comparison::KeyEqual(kf, Value::new("a"));
+        assert_eq!(keyed(KeyFn::new("lower", lower)), keyed(KeyFn::new("lower", upper)));
+        assert_ne!(keyed(KeyFn::new("lower", lower)), keyed(KeyFn::new("upper", upper)));
+    }
+
+    #[test]
+    fn cond_implies() {
+        use std::ops::Bound::{Excluded, Included, Unbounded};
+        let eq = Condition::<usize>::eq(0, 5usize);
+        assert!(eq.implies(&Condition::eq(0, 5usize)));
+        assert!(eq.implies(&Condition::ne(0, 4usize)));
+        assert!(eq.implies(&Condition::is_in(0, vec![4usize, 5])));
+        assert!(eq.implies(&Condition::in_ranges(0, vec![(Unbounded, Excluded(2usize)),
+                                                         (Included(5), Included(5))])));
+        assert!(eq.implies(&Condition::le(0, 5usize)));
+        assert!(!eq.implies(&Condition::lt(0, 5usize)));
+        assert!(!eq.implies(&Condition::eq(1, 5usize)));
+        assert!(!eq.implies(&Condition::new(0, Comparison::Equal(Value::column(1)))));
+
+        let lt = Condition::<usize>::lt(0, 5usize);
+        assert!(lt.implies(&Condition::le(0, 5usize)));
+        assert!(lt.implies(&Condition::ne(0, 5usize)));
+        assert!(lt.implies(&Condition::between(0, Unbounded, Excluded(5usize))));
+        assert!(!Condition::<usize>::le(0, 5usize).implies(&lt));
+        assert!(!lt.implies(&Condition::ne(0, 4usize)));
+        assert!(!lt.implies(&Condition::gt(0, 0usize)));
+        assert!(!lt.implies(&Condition::is_in(0, vec![4usize])));
+        assert!(!Condition::<usize>::ne(0, 5usize).implies(&Condition::ne(0, 5usize)));
     }
 }
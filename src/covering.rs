@@ -0,0 +1,352 @@
+//! Covering indices, added with `Store::index_covering`, which keep a copy of every row grouped by
+//! its value in the indexed column.
+//!
+//! The rows of a `Store` are kept in insertion order, so the rows found by looking up a value in
+//! an index are generally scattered across memory. A covering index instead keeps the rows for
+//! each value next to each other, so that `find` can walk them in order. `Store` does not require
+//! its rows to be `Clone`, so the function that copies them is captured when the index is added.
+
+use std::collections::BTreeMap;
+
+use Index;
+use IntegrityError;
+use Row;
+use Store;
+
+/// Copies of the rows of a `Store`, grouped by their value in the covered column. Within each
+/// group, the rows are ordered by identifier, which is the order in which they were inserted.
+pub(crate) struct Covering<T, R> {
+    copy: fn(&R) -> R,
+    groups: BTreeMap<T, Vec<(usize, R)>>,
+}
+
+impl<T: Ord + Clone, R> Clone for Covering<T, R> {
+    fn clone(&self) -> Self {
+        let copy = self.copy;
+        Covering {
+            copy,
+            groups: self.groups
+                .iter()
+                .map(|(value, rows)| {
+                    (value.clone(), rows.iter().map(|(rowid, row)| (*rowid, copy(row))).collect())
+                })
+                .collect(),
+        }
+    }
+}
+
+impl<T: Ord, R> Covering<T, R> {
+    /// Returns the copies of the rows with the given value, in the order they were inserted.
+    pub(crate) fn lookup(&self, value: &T) -> &[(usize, R)] {
+        self.groups.get(value).map_or(&[], |rows| &rows[..])
+    }
+
+    /// Add a copy of the given row, which has the given value in the covered column.
+    pub(crate) fn add(&mut self, value: T, rowid: usize, row: &R) {
+        let copy = (self.copy)(row);
+        let rows = self.groups.entry(value).or_default();
+        // rows are almost always added in order, except by `upsert`, which re-uses an identifier
+        match rows.last() {
+            Some(&(last, _)) if last > rowid => {
+                let at = rows.binary_search_by_key(&rowid, |&(rowid, _)| rowid).unwrap_err();
+                rows.insert(at, (rowid, copy));
+            }
+            _ => rows.push((rowid, copy)),
+        }
+    }
+
+    /// Remove the copy of the given row, which had the given value in the covered column.
+    pub(crate) fn remove(&mut self, value: &T, rowid: usize) {
+        let now_empty = match self.groups.get_mut(value) {
+            Some(rows) => {
+                if let Ok(at) = rows.binary_search_by_key(&rowid, |&(rowid, _)| rowid) {
+                    rows.remove(at);
+                }
+                rows.is_empty()
+            }
+            None => false,
+        };
+        if now_empty {
+            self.groups.remove(value);
+        }
+    }
+
+    /// Remove the copies of all rows.
+    pub(crate) fn clear(&mut self) {
+        self.groups.clear();
+    }
+
+    /// Returns the number of rows copied.
+    pub(crate) fn len(&self) -> usize {
+        self.groups.values().map(Vec::len).sum()
+    }
+}
+
+impl<T, R> Store<T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Like `index`, but the index also keeps a copy of every row, grouped by the row's value in
+    /// the given column. A query with an equality condition against a constant on the column
+    /// that is answered using its index then walks those copies, which lie next to each other in
+    /// memory, rather than looking up each row it finds. This is much friendlier to the CPU cache
+    /// when many rows share a value, since the rows of a `Store` are otherwise kept in the order
+    /// they were inserted.
+    ///
+    /// The copies are kept up to date as rows are inserted, updated, and deleted, so adding a
+    /// covering index roughly doubles the memory used by the rows, and the cost of every
+    /// modification. Rows that have been marked as deleted keep their copies until `compact` is
+    /// called. Removing the index on the column with `unindex` also drops the copies. Covering
+    /// indices are not preserved when a `Store` is serialized.
+    ///
+    /// Panics if the column does not exist, or if the index is unique and the rows already
+    /// conflict in the column.
+    pub fn index_covering<I: Into<Index<T>>>(&mut self, column: usize, indexer: I)
+        where R: Clone
+    {
+        self.index(column, indexer);
+        let mut covering = Covering {
+            copy: R::clone,
+            groups: BTreeMap::new(),
+        };
+        for (&rowid, row) in self.indexed_rows() {
+            covering.add(row.index(column).clone(), rowid, row);
+        }
+        self.covering.insert(column, covering);
+    }
+
+    /// Returns the copies of the rows with the given value in the given column, if the column has
+    /// a covering index. See `index_covering`.
+    pub(crate) fn covered(&self, column: usize, value: &T) -> Option<&[(usize, R)]> {
+        self.covering.get(&column).map(|covering| covering.lookup(value))
+    }
+
+    /// Add a copy of the given row to every covering index.
+    pub(crate) fn cover_row(&mut self, rowid: usize, row: &R) {
+        for (&column, covering) in self.covering.iter_mut() {
+            covering.add(row.index(column).clone(), rowid, row);
+        }
+    }
+
+    /// Remove the copy of the given row from every covering index.
+    pub(crate) fn uncover_row(&mut self, rowid: usize, row: &R) {
+        for (&column, covering) in self.covering.iter_mut() {
+            covering.remove(row.index(column), rowid);
+        }
+    }
+
+    /// Check that every covering index holds an identical copy of every row under the row's value
+    /// in the covered column, and nothing else.
+    pub(crate) fn check_covering(&self) -> Result<(), IntegrityError<T>> {
+        for (&column, covering) in &self.covering {
+            for (value, rows) in &covering.groups {
+                for (i, &(rowid, ref copy)) in rows.iter().enumerate() {
+                    // a row copied twice shows up as an out of order identifier
+                    let repeated = i != 0 && rows[i - 1].0 >= rowid;
+                    let matches = self.rows.get(&rowid).is_some_and(|row| {
+                        row.index(column) == value &&
+                        (0..self.cols).all(|col| row.index(col) == copy.index(col))
+                    });
+                    if repeated || !matches {
+                        return Err(IntegrityError::Stale {
+                            column,
+                            value: value.clone(),
+                            row: rowid,
+                        });
+                    }
+                }
+            }
+            if covering.len() != self.indexed_rows().count() {
+                let (&rowid, row) = self.indexed_rows()
+                    .find(|&(&rowid, row)| {
+                        covering.lookup(row.index(column))
+                            .binary_search_by_key(&rowid, |&(rowid, _)| rowid)
+                            .is_err()
+                    })
+                    .unwrap();
+                return Err(IntegrityError::Missing {
+                    column,
+                    value: row.index(column).clone(),
+                    row: rowid,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use idx::{BTreeIndex, HashIndex};
+    use cmp::Condition;
+    use query::Access;
+
+    /// A store of `[i % 10, i, i % 3]` for every `i` in `0..100`, along with an identical store
+    /// in which the first column has a covering index rather than a plain one.
+    fn stores() -> (Store<usize>, Store<usize>) {
+        let mut plain = Store::new(3);
+        for i in 0..100 {
+            plain.insert(vec![i % 10, i, i % 3]);
+        }
+        let mut covered = plain.clone();
+        plain.index(0, HashIndex::new());
+        covered.index_covering(0, HashIndex::new());
+        (plain, covered)
+    }
+
+    /// Check that both stores find the same rows for the given conditions. A covering index
+    /// yields rows in the order they were inserted, which a `HashIndex` does not always do.
+    fn same(plain: &Store<usize>, covered: &Store<usize>, conds: &[Condition<usize>]) {
+        let mut expected = plain.find_with_ids(conds).collect::<Vec<_>>();
+        expected.sort_unstable_by_key(|&(rowid, _)| rowid);
+        let expected = expected.into_iter().map(|(_, row)| row).collect::<Vec<_>>();
+        assert_eq!(covered.find(conds).size_hint(), plain.find(conds).size_hint());
+        assert_eq!(covered.find(conds).collect::<Vec<_>>(), expected);
+        assert_eq!(covered.count(conds), expected.len());
+    }
+
+    /// Check that the copies kept by the covering index on column 0 agree with the rows.
+    fn consistent(store: &Store<usize>) {
+        assert_eq!(store.check_integrity(), Ok(()));
+        assert_eq!(store.covering[&0].len(), store.rows.len());
+    }
+
+    #[test]
+    fn it_finds_covered_rows() {
+        let (plain, covered) = stores();
+        consistent(&covered);
+        let eq = [Condition::eq(0, 3usize)];
+        assert_eq!(covered.explain(&eq).access, Access::Index(0));
+        assert!(covered.covered(0, &3).is_some());
+        assert!(plain.covered(0, &3).is_none());
+        same(&plain, &covered, &eq);
+        same(&plain, &covered, &[Condition::eq(0, 3usize), Condition::eq(2, 1usize)]);
+        same(&plain, &covered, &[Condition::eq(0, 3usize), Condition::gt(1, 50usize)]);
+        same(&plain, &covered, &[Condition::eq(0, 42usize)]);
+        same(&plain, &covered, &[Condition::is_in(0, vec![3usize, 4])]);
+        same(&plain, &covered, &[Condition::eq(2, 1usize)]);
+
+        // the rows found are the copies, not the rows themselves
+        let row = covered.find(&eq).next().unwrap();
+        assert!(!std::ptr::eq(row, covered.get(3).unwrap()));
+        assert_eq!(row, covered.get(3).unwrap());
+    }
+
+    #[test]
+    fn it_keeps_copies_up_to_date() {
+        let (mut plain, mut covered) = stores();
+        let check = |plain: &Store<usize>, covered: &Store<usize>| {
+            consistent(covered);
+            for value in 0..12usize {
+                same(plain, covered, &[Condition::eq(0, value)]);
+            }
+        };
+
+        for store in [&mut plain, &mut covered] {
+            store.insert(vec![3, 100, 1]);
+            store.insert_many(vec![vec![11, 101, 2], vec![3, 102, 0]]);
+            {
+                let mut loader = store.begin_bulk_load();
+                loader.insert(vec![4, 103, 1]);
+                loader.finish();
+            }
+        }
+        check(&plain, &covered);
+
+        for store in [&mut plain, &mut covered] {
+            assert_eq!(store.delete(&[Condition::eq(0, 5usize)]), 10);
+            store.delete(&[Condition::eq(1, 33usize)]);
+            store.drain_where(&[Condition::eq(2, 2usize), Condition::eq(0, 4usize)]);
+        }
+        check(&plain, &covered);
+
+        // both moving a row to another value and changing another column replace its copy
+        for store in [&mut plain, &mut covered] {
+            store.update(&[Condition::eq(0, 1usize)], |r| r[0] = 11);
+            store.update(&[Condition::eq(0, 2usize)], |r| r[2] = 7);
+        }
+        check(&plain, &covered);
+        assert!(covered.find(&[Condition::eq(0, 2usize)]).all(|r| r[2] == 7));
+
+        // rows marked as deleted keep their copies, but are not found, until compaction
+        for store in [&mut plain, &mut covered] {
+            assert!(store.mark_deleted(3));
+            assert!(store.mark_deleted(13));
+        }
+        check(&plain, &covered);
+        for store in [&mut plain, &mut covered] {
+            store.compact();
+        }
+        check(&plain, &covered);
+
+        for store in [&mut plain, &mut covered] {
+            for row in store.rows_mut_unindexed() {
+                row[0] = (row[0] + 1) % 12;
+            }
+            store.reindex();
+        }
+        check(&plain, &covered);
+
+        for store in [&mut plain, &mut covered] {
+            store.truncate(40);
+        }
+        check(&plain, &covered);
+        for store in [&mut plain, &mut covered] {
+            store.clear();
+            store.insert(vec![3, 0, 0]);
+        }
+        check(&plain, &covered);
+        assert_eq!(covered.find(&[Condition::eq(0, 3usize)]).count(), 1);
+    }
+
+    #[test]
+    fn it_replaces_copies_on_upsert() {
+        let mut store = Store::with_key(2, 0);
+        store.index_covering(1, BTreeIndex::new());
+        store.insert(vec![1usize, 10usize]);
+        store.insert(vec![2, 10]);
+        store.insert(vec![3, 10]);
+        store.upsert(vec![1, 10]);
+        store.upsert(vec![2, 20]);
+        assert_eq!(store.check_integrity(), Ok(()));
+        let rows = store.find(&[Condition::eq(1, 10usize)]).map(|r| r[0]).collect::<Vec<_>>();
+        assert_eq!(rows, vec![1, 3]);
+        assert_eq!(store.find(&[Condition::eq(1, 20usize)]).count(), 1);
+    }
+
+    #[test]
+    fn it_drops_copies_with_index() {
+        let (_, mut covered) = stores();
+        let clone = covered.clone();
+        consistent(&clone);
+        assert!(clone.covered(0, &3).is_some());
+
+        assert!(covered.unindex(0).is_some());
+        assert!(covered.covering.is_empty());
+        assert_eq!(covered.explain(&[Condition::eq(0, 3usize)]).access, Access::Scan);
+        covered.insert(vec![3, 100, 1]);
+        assert_eq!(covered.find(&[Condition::eq(0, 3usize)]).count(), 11);
+    }
+
+    #[test]
+    fn it_detects_stale_copies() {
+        let (_, mut covered) = stores();
+        covered.rows.get_mut(&7).unwrap()[2] = 9;
+        assert_eq!(covered.check_integrity(),
+                   Err(IntegrityError::Stale {
+                       column: 0,
+                       value: 7,
+                       row: 7,
+                   }));
+
+        let (_, mut covered) = stores();
+        covered.covering.get_mut(&0).unwrap().remove(&7, 17);
+        assert_eq!(covered.check_integrity(),
+                   Err(IntegrityError::Missing {
+                       column: 0,
+                       value: 7,
+                       row: 17,
+                   }));
+    }
+}
@@ -0,0 +1,288 @@
+use std::error;
+use std::fmt;
+
+/// The error returned by the fallible operations of a `Store` that have no more specific error
+/// type, such as `Store::try_index`.
+///
+/// The more specific errors convert into an `Error`, so that functions that perform several
+/// fallible operations can return a single error type using `?`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// A row does not have the same number of columns as the `Store`.
+    WrongWidth {
+        /// The number of columns in the `Store`.
+        expected: usize,
+        /// The number of columns in the row.
+        got: usize,
+    },
+
+    /// A column that does not exist in the `Store` was referred to.
+    ColumnOutOfRange {
+        /// The offending column.
+        column: usize,
+        /// The number of columns in the `Store`.
+        cols: usize,
+    },
+
+    /// Two rows hold the same value in a column with a unique index.
+    DuplicateKey {
+        /// The column where the conflict was found.
+        column: usize,
+        /// The identifier of the row that already held the value.
+        existing: usize,
+    },
+
+    /// More than one index was declared on the same column of a `StoreBuilder`.
+    DuplicateIndex {
+        /// The column with more than one index.
+        column: usize,
+    },
+
+    /// A query could not be planned for a reason other than a column being out of range; see
+    /// `QueryError`.
+    Query(QueryError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::WrongWidth { expected, got } => {
+                write!(f, "row has {} columns, but the store has {}", got, expected)
+            }
+            Error::ColumnOutOfRange { column, cols } => {
+                write!(f,
+                       "column {} is out of range for a store with {} columns",
+                       column,
+                       cols)
+            }
+            Error::DuplicateKey { column, existing } => {
+                write!(f,
+                       "row conflicts with row {} in unique column {}",
+                       existing,
+                       column)
+            }
+            Error::DuplicateIndex { column } => {
+                write!(f, "more than one index was declared on column {}", column)
+            }
+            Error::Query(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl<R> From<InsertError<R>> for Error {
+    fn from(e: InsertError<R>) -> Self {
+        match e {
+            InsertError::WrongWidth { expected, got, .. } => Error::WrongWidth { expected, got },
+            InsertError::Duplicate { column, existing, .. } => {
+                Error::DuplicateKey { column, existing }
+            }
+        }
+    }
+}
+
+impl From<QueryError> for Error {
+    fn from(e: QueryError) -> Self {
+        match e {
+            QueryError::ColumnOutOfRange { column, cols } => {
+                Error::ColumnOutOfRange { column, cols }
+            }
+            e => Error::Query(e),
+        }
+    }
+}
+
+/// The error returned when a row cannot be inserted into a `Store`. The rejected row is handed
+/// back unmodified as part of the error.
+#[derive(Debug)]
+pub enum InsertError<R> {
+    /// The row does not have the same number of columns as the `Store`.
+    WrongWidth {
+        /// The number of columns in the `Store`.
+        expected: usize,
+        /// The number of columns in the rejected row.
+        got: usize,
+        /// The rejected row.
+        row: R,
+    },
+
+    /// The row has the same value as an existing row in a column with a unique index.
+    Duplicate {
+        /// The column where the conflict was found.
+        column: usize,
+        /// The identifier of the existing row.
+        existing: usize,
+        /// The rejected row.
+        row: R,
+    },
+}
+
+impl<R> InsertError<R> {
+    /// Recover the rejected row.
+    pub fn into_row(self) -> R {
+        match self {
+            InsertError::WrongWidth { row, .. } |
+            InsertError::Duplicate { row, .. } => row,
+        }
+    }
+}
+
+impl<R> fmt::Display for InsertError<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InsertError::WrongWidth { expected, got, .. } => {
+                write!(f, "row has {} columns, but the store has {}", got, expected)
+            }
+            InsertError::Duplicate { column, existing, .. } => {
+                write!(f,
+                       "row conflicts with row {} in unique column {}",
+                       existing,
+                       column)
+            }
+        }
+    }
+}
+
+impl<R: fmt::Debug> error::Error for InsertError<R> {}
+
+/// The error returned when a set of conditions cannot be evaluated against a `Store`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryError {
+    /// A condition refers to a column that does not exist in the `Store`.
+    ColumnOutOfRange {
+        /// The offending column.
+        column: usize,
+        /// The number of columns in the `Store`.
+        cols: usize,
+    },
+
+    /// A query hint names a column that has no index (see `Query::use_index`).
+    NoIndex {
+        /// The offending column.
+        column: usize,
+    },
+
+    /// A query is forced to use the index on a column, but that index cannot answer any of the
+    /// conditions.
+    UnusableIndex {
+        /// The offending column.
+        column: usize,
+    },
+
+    /// A query is forced to use more than one index, or is forced to use an index that it is also
+    /// forbidden from using.
+    ConflictingHints,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            QueryError::ColumnOutOfRange { column, cols } => {
+                write!(f,
+                       "condition refers to column {}, but the store only has {} columns",
+                       column,
+                       cols)
+            }
+            QueryError::NoIndex { column } => {
+                write!(f, "query hint refers to column {}, which has no index", column)
+            }
+            QueryError::UnusableIndex { column } => {
+                write!(f,
+                       "query is forced to use the index on column {}, which cannot answer any \
+                        of its conditions",
+                       column)
+            }
+            QueryError::ConflictingHints => write!(f, "query hints conflict with each other"),
+        }
+    }
+}
+
+impl error::Error for QueryError {}
+
+/// The error returned by `Store::check_integrity` when an index disagrees with the rows of the
+/// `Store`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IntegrityError<T> {
+    /// Looking up a row's value in the index on its column does not yield the row.
+    Missing {
+        /// The indexed column.
+        column: usize,
+        /// The row's value in the column.
+        value: T,
+        /// The identifier of the row.
+        row: usize,
+    },
+
+    /// The index on a column yields a row for a value, but the row does not exist, or does not
+    /// hold that value in the column.
+    Stale {
+        /// The indexed column.
+        column: usize,
+        /// The value the row was yielded for.
+        value: T,
+        /// The identifier of the row.
+        row: usize,
+    },
+
+    /// Looking up the key computed from a row in an index added with `Store::index_multi`,
+    /// `Store::index_keyed`, or `Store::index_sparse` does not yield the row.
+    MissingKey {
+        /// The columns the key is computed from.
+        columns: Vec<usize>,
+        /// The key computed from the row.
+        key: Vec<T>,
+        /// The identifier of the row.
+        row: usize,
+    },
+
+    /// An index added with `Store::index_multi`, `Store::index_keyed`, or `Store::index_sparse`
+    /// yields a row for a key, but the row does not exist, or the key is not computed from it.
+    StaleKey {
+        /// The columns the key is computed from.
+        columns: Vec<usize>,
+        /// The key the row was yielded for.
+        key: Vec<T>,
+        /// The identifier of the row.
+        row: usize,
+    },
+}
+
+impl<T: fmt::Debug> fmt::Display for IntegrityError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            IntegrityError::Missing { column, ref value, row } => {
+                write!(f,
+                       "index on column {} does not yield row {} for its value {:?}",
+                       column,
+                       row,
+                       value)
+            }
+            IntegrityError::Stale { column, ref value, row } => {
+                write!(f,
+                       "index on column {} yields row {} for value {:?}, which the row does not \
+                        hold",
+                       column,
+                       row,
+                       value)
+            }
+            IntegrityError::MissingKey { ref columns, ref key, row } => {
+                write!(f,
+                       "index on columns {:?} does not yield row {} for its key {:?}",
+                       columns,
+                       row,
+                       key)
+            }
+            IntegrityError::StaleKey { ref columns, ref key, row } => {
+                write!(f,
+                       "index on columns {:?} yields row {} for key {:?}, which is not computed \
+                        from the row",
+                       columns,
+                       row,
+                       key)
+            }
+        }
+    }
+}
+
+impl<T: fmt::Debug> error::Error for IntegrityError<T> {}
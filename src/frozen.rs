@@ -0,0 +1,573 @@
+//! Read-only stores whose rows are memory-mapped from a file.
+//!
+//! A frozen store is written once by `Store::write_frozen`, and can then be opened any number of
+//! times with `FrozenStore::open` without reading its rows into memory: the operating system pages
+//! rows in as queries touch them. The file starts with a fixed header, followed by the indexed
+//! columns, every row laid out flat, one value after the other, and finally, for each indexed
+//! column, the numbers of all rows sorted by their value in that column. All numbers are written
+//! as native-endian `u64`s, and the values as their in-memory representation, so a file can only
+//! be opened on a machine with the same byte order, using the same value type.
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, Write};
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::{Bound, Range};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+
+use libc;
+
+use QueryError;
+use Row;
+use Store;
+use cmp::{self, Condition};
+use contradicts;
+
+/// The bytes every frozen store starts with, followed by the format version.
+const MAGIC: &[u8; 8] = b"shortfrz";
+const VERSION: u64 = 1;
+/// Written in native byte order, so that a file written with another byte order is recognized.
+const BYTE_ORDER: u64 = 0x0102_0304_0506_0708;
+/// The number of `u64`s in the header after the magic bytes: the version, the byte order, the
+/// size of a value, the number of columns, the number of rows, and the number of indices.
+const HEADER: usize = 6;
+/// The alignment of the rows within the file, which is the largest alignment a value may have.
+const ALIGN: usize = 16;
+
+/// Types whose values can be read straight from the bytes of a file.
+///
+/// This is implemented for all the primitive integer types.
+///
+/// # Safety
+///
+/// Values are read from the file without any checks, so every pattern of bytes must be a valid
+/// value, the type must not contain any padding or pointers, and its alignment must be at most
+/// 16 bytes.
+pub unsafe trait Pod: Copy + 'static {}
+
+unsafe impl Pod for u8 {}
+unsafe impl Pod for u16 {}
+unsafe impl Pod for u32 {}
+unsafe impl Pod for u64 {}
+unsafe impl Pod for u128 {}
+unsafe impl Pod for usize {}
+unsafe impl Pod for i8 {}
+unsafe impl Pod for i16 {}
+unsafe impl Pod for i32 {}
+unsafe impl Pod for i64 {}
+unsafe impl Pod for i128 {}
+unsafe impl Pod for isize {}
+
+/// A read-only mapping of a whole file into memory.
+struct Map {
+    ptr: *const u8,
+    len: usize,
+}
+
+// the mapping is never written to, and is only unmapped when dropped
+unsafe impl Send for Map {}
+unsafe impl Sync for Map {}
+
+impl Map {
+    fn bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for Map {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+/// Where the parts of a frozen store lie within its file, in bytes.
+struct Layout {
+    rows: usize,
+    indices: usize,
+    len: usize,
+}
+
+impl Layout {
+    /// Returns the layout of a frozen store of the given shape, or `None` if it would not fit in
+    /// memory.
+    fn new<T>(cols: usize, rows: usize, indices: usize) -> Option<Layout> {
+        let header = (MAGIC.len() + 8 * HEADER).checked_add(indices.checked_mul(8)?)?;
+        let start = header.checked_next_multiple_of(ALIGN)?;
+        let values = rows.checked_mul(cols)?.checked_mul(mem::size_of::<T>())?;
+        let end = start.checked_add(values)?.checked_next_multiple_of(8)?;
+        let sorted = rows.checked_mul(8)?.checked_mul(indices)?;
+        Some(Layout {
+            rows: start,
+            indices: end,
+            len: end.checked_add(sorted)?,
+        })
+    }
+}
+
+/// A read-only `Store` whose rows are memory-mapped from a file written by
+/// `Store::write_frozen`, so that opening it takes the same short time no matter how many rows it
+/// holds, and rows are only read from disk as queries need them.
+///
+/// Rows are identified by their position in the file, from 0 up to `len()`. Indexed columns are
+/// indexed by the numbers of all rows, sorted by their value in the column, which is searched
+/// with a binary search much like a `BTreeIndex`, and so answers both equality and range
+/// conditions.
+pub struct FrozenStore<T: Pod> {
+    map: Map,
+    cols: usize,
+    len: usize,
+    layout: Layout,
+    /// The indexed columns, in the order their sorted row numbers appear in the file.
+    indexed: Vec<usize>,
+    values: PhantomData<T>,
+}
+
+impl<T: Pod + Ord> FrozenStore<T> {
+    /// Open the frozen store in the file at the given path by mapping it into memory. Only the
+    /// header is read; rows and indices are read from the file as they are used.
+    ///
+    /// An error is returned if the file cannot be mapped, or if it is not a frozen store of
+    /// values of type `T` written on a machine with the same byte order: if it has the wrong
+    /// header, if its values have a different size, if it refers to columns it does not have, or
+    /// if it has the wrong length. The sorted row numbers of the indices are not checked, but
+    /// corrupt ones can only lead to rows being missed by `find`, never to rows that do not match
+    /// being found.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified, by this or any other process, while the returned store (or
+    /// any row borrowed from it) is in use. Rows are read straight from the mapped file, so a
+    /// modification would change the rows under the store's feet, and truncating the file makes
+    /// reading the rows that were cut off crash the process.
+    pub unsafe fn open<P: AsRef<Path>>(path: P) -> io::Result<FrozenStore<T>> {
+        let file = File::open(path)?;
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| invalid("frozen store is too large to map".to_string()))?;
+        if len < MAGIC.len() + 8 * HEADER {
+            return Err(invalid("not a frozen store".to_string()));
+        }
+        let ptr = libc::mmap(ptr::null_mut(),
+                             len,
+                             libc::PROT_READ,
+                             libc::MAP_PRIVATE,
+                             file.as_raw_fd(),
+                             0);
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        FrozenStore::parse(Map {
+            ptr: ptr as *const u8,
+            len,
+        })
+    }
+
+    /// Check the header of the given mapping, and find where the rows and indices lie.
+    fn parse(map: Map) -> io::Result<FrozenStore<T>> {
+        let bytes = map.bytes();
+        if &bytes[..MAGIC.len()] != MAGIC {
+            return Err(invalid("not a frozen store".to_string()));
+        }
+        let number = |i: usize| {
+            let at = MAGIC.len() + 8 * i;
+            bytes.get(at..at + 8).map(|b| u64::from_ne_bytes(<[u8; 8]>::try_from(b).unwrap()))
+        };
+        let size = |i: usize| {
+            number(i)
+                .and_then(|n| usize::try_from(n).ok())
+                .ok_or_else(|| invalid("frozen store ends early".to_string()))
+        };
+        if number(1) != Some(BYTE_ORDER) {
+            return Err(invalid("frozen store was written with another byte order".to_string()));
+        }
+        if number(0) != Some(VERSION) {
+            return Err(invalid(format!("unsupported frozen store version {}", size(0)?)));
+        }
+        if size(2)? != mem::size_of::<T>() {
+            return Err(invalid(format!("frozen store holds values of {} bytes, not {}",
+                                       size(2)?,
+                                       mem::size_of::<T>())));
+        }
+        let cols = size(3)?;
+        let len = size(4)?;
+        let indices = size(5)?;
+        let layout = Layout::new::<T>(cols, len, indices)
+            .ok_or_else(|| invalid("frozen store is too large to map".to_string()))?;
+        if layout.len != bytes.len() {
+            return Err(invalid(format!("frozen store should be {} bytes long, but is {}",
+                                       layout.len,
+                                       bytes.len())));
+        }
+        let indexed = (0..indices)
+            .map(|i| {
+                size(HEADER + i).and_then(|col| if col < cols {
+                    Ok(col)
+                } else {
+                    Err(invalid(format!("frozen store has no column {}", col)))
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(FrozenStore {
+            map,
+            cols,
+            len,
+            layout,
+            indexed,
+            values: PhantomData,
+        })
+    }
+
+    /// Returns the number of columns of every row.
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    /// Returns the number of rows.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if there are no rows.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the indexed columns, in ascending order.
+    pub fn indexed_columns(&self) -> impl Iterator<Item = usize> + '_ {
+        let mut columns = self.indexed.clone();
+        columns.sort_unstable();
+        columns.into_iter()
+    }
+
+    /// Returns the row with the given number, if there is one.
+    pub fn get(&self, row: usize) -> Option<&[T]> {
+        if row >= self.len {
+            return None;
+        }
+        // the rows are aligned for any `Pod` type, and lie entirely within the mapping, which
+        // `parse` checked was exactly as long as the layout requires
+        unsafe {
+            let at = self.layout.rows + row * self.cols * mem::size_of::<T>();
+            Some(slice::from_raw_parts(self.map.ptr.add(at) as *const T, self.cols))
+        }
+    }
+
+    /// Returns all rows, in the order they were written.
+    pub fn rows(&self) -> impl Iterator<Item = &[T]> + '_ {
+        (0..self.len).filter_map(move |row| self.get(row))
+    }
+
+    /// Returns the numbers of all rows, sorted by their value in the `i`th indexed column.
+    fn sorted(&self, i: usize) -> &[u64] {
+        // like the rows, the sorted row numbers are aligned, and checked to lie within the mapping
+        unsafe {
+            let at = self.layout.indices + i * self.len * 8;
+            slice::from_raw_parts(self.map.ptr.add(at) as *const u64, self.len)
+        }
+    }
+
+    /// Check that all the given conditions only refer to columns that exist in this store.
+    pub fn validate(&self, conds: &[Condition<T>]) -> Result<(), QueryError> {
+        for c in conds {
+            let columns = c.cmp
+                .iter_values()
+                .filter_map(|v| match *v {
+                    cmp::Value::Column(col) => Some(col),
+                    cmp::Value::Const(..) => None,
+                });
+            for column in Some(c.column).into_iter().chain(columns) {
+                if column >= self.cols {
+                    return Err(QueryError::ColumnOutOfRange {
+                        column,
+                        cols: self.cols,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns an iterator that yields all rows matching all the given `Condition`s, just like
+    /// `Store::find`.
+    ///
+    /// If any of the conditions compare an indexed column for equality against a constant, or
+    /// are range conditions on an indexed column, the index that narrows the candidates down
+    /// the most is searched, and only the rows it yields are read. All range conditions on the
+    /// column are combined into a single search. The rows are then yielded in the order of their
+    /// values in that column, and rows with the same value in the order they were written.
+    /// Otherwise, every row is read, in the order they were written.
+    ///
+    /// Panics if any of the conditions refer to a column that does not exist.
+    pub fn find<'c, 's: 'c>(&'s self,
+                            conds: &'c [Condition<'c, T>])
+                            -> impl Iterator<Item = &'s [T]> + 'c {
+        if let Err(e) = self.validate(conds) {
+            panic!("{}", e);
+        }
+        let (sorted, range) = self.candidates(conds);
+        range.filter_map(move |i| match sorted {
+                Some(sorted) => usize::try_from(sorted[i]).ok().and_then(|row| self.get(row)),
+                None => self.get(i),
+            })
+            .filter(move |row| conds.iter().all(|c| c.matches(*row)))
+    }
+
+    /// Returns the number of rows matching all the given `Condition`s.
+    pub fn count(&self, conds: &[Condition<T>]) -> usize {
+        self.find(conds).count()
+    }
+
+    /// Decide which rows to look at for the given conditions: either the given positions in the
+    /// sorted row numbers of an index, or, if there are none, the given row numbers.
+    fn candidates(&self, conds: &[Condition<T>]) -> (Option<&[u64]>, Range<usize>) {
+        if contradicts(conds) {
+            return (None, 0..0);
+        }
+        let value = |row: u64, column: usize| {
+            usize::try_from(row).ok().and_then(|row| self.get(row)).map(|row| &row[column])
+        };
+        let mut best: Option<(&[u64], Range<usize>)> = None;
+        for c in conds {
+            let i = match self.indexed.iter().position(|&col| col == c.column) {
+                Some(i) => i,
+                None => continue,
+            };
+            let (min, max) = match cmp::column_range(conds, c.column) {
+                Some(range) => range,
+                None => continue,
+            };
+            let sorted = self.sorted(i);
+            let start = sorted.partition_point(|&row| {
+                value(row, c.column).is_some_and(|v| match min {
+                    Bound::Included(min) => v < min,
+                    Bound::Excluded(min) => v <= min,
+                    Bound::Unbounded => false,
+                })
+            });
+            let end = sorted.partition_point(|&row| {
+                value(row, c.column).is_some_and(|v| match max {
+                    Bound::Included(max) => v <= max,
+                    Bound::Excluded(max) => v < max,
+                    Bound::Unbounded => true,
+                })
+            });
+            let range = start..end.max(start);
+            // ties go to the earliest condition, as with `Store::find`
+            if best.as_ref().is_none_or(|(_, b)| range.len() < b.len()) {
+                best = Some((sorted, range));
+            }
+        }
+        match best {
+            Some((sorted, range)) => (Some(sorted), range),
+            None => (None, 0..self.len),
+        }
+    }
+}
+
+impl<T, R> Store<T, R>
+    where T: Pod + Ord,
+          R: Row<T>
+{
+    /// Write the rows of this `Store` to `writer` as a frozen store, which can then be opened
+    /// with `FrozenStore::open` without reading its rows into memory. The given columns are
+    /// indexed in the frozen store, no matter which columns are indexed in this `Store`.
+    ///
+    /// Rows are numbered from 0 in the frozen store, in the order they were inserted. Rows that
+    /// have been marked as deleted are left out. Since the file holds the in-memory
+    /// representation of the values, it can only be opened on a machine with the same byte
+    /// order, using the same value type. Small writes are issued for every value, so `writer`
+    /// should generally be buffered.
+    ///
+    /// Panics if any of the columns to index does not exist.
+    pub fn write_frozen<W: Write>(&self, mut writer: W, indexed: &[usize]) -> io::Result<()> {
+        for &column in indexed {
+            assert!(column < self.cols,
+                    "column {} is out of range for a store with {} columns",
+                    column,
+                    self.cols);
+        }
+        let rows = self.rows().collect::<Vec<_>>();
+        let layout = Layout::new::<T>(self.cols, rows.len(), indexed.len())
+            .ok_or_else(|| invalid("store is too large to freeze".to_string()))?;
+
+        let w = &mut writer;
+        w.write_all(MAGIC)?;
+        let header = [VERSION,
+                      BYTE_ORDER,
+                      mem::size_of::<T>() as u64,
+                      self.cols as u64,
+                      rows.len() as u64,
+                      indexed.len() as u64];
+        for n in header.iter().copied().chain(indexed.iter().map(|&col| col as u64)) {
+            w.write_all(&n.to_ne_bytes())?;
+        }
+        let mut written = MAGIC.len() + 8 * (HEADER + indexed.len());
+        w.write_all(&[0; ALIGN][..layout.rows - written])?;
+
+        for row in &rows {
+            for col in 0..self.cols {
+                let value = row.index(col);
+                // `Pod` types have no padding, so all their bytes are initialized
+                let bytes = unsafe {
+                    slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>())
+                };
+                w.write_all(bytes)?;
+            }
+        }
+        written = layout.rows + rows.len() * self.cols * mem::size_of::<T>();
+        w.write_all(&[0; 8][..layout.indices - written])?;
+
+        for &column in indexed {
+            let mut sorted = (0..rows.len() as u64).collect::<Vec<_>>();
+            // a stable sort keeps rows with the same value in the order they were inserted
+            sorted.sort_by_key(|&row| rows[row as usize].index(column));
+            for row in sorted {
+                w.write_all(&row.to_ne_bytes())?;
+            }
+        }
+        w.flush()
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+
+    /// A file in the temporary directory that is removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            TempFile(std::env::temp_dir()
+                .join(format!("shortcut-frozen-{}-{}", process::id(), name)))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    /// A store of `[i % 10, i, i % 7]` for every `i` in `0..1000`, with row 13 marked as deleted,
+    /// written to a frozen store with indices on columns 1 and 0.
+    fn frozen(name: &str) -> (Store<u64>, TempFile) {
+        let mut store = Store::new(3);
+        for i in 0..1000 {
+            store.insert(vec![i % 10, i, i % 7]);
+        }
+        store.mark_deleted(13);
+        let file = TempFile::new(name);
+        store.write_frozen(io::BufWriter::new(File::create(&file.0).unwrap()), &[1, 0]).unwrap();
+        (store, file)
+    }
+
+    #[test]
+    fn it_finds_like_a_store() {
+        let (store, file) = frozen("find");
+        let frozen = unsafe { FrozenStore::<u64>::open(&file.0) }.unwrap();
+        assert_eq!(frozen.cols(), 3);
+        assert_eq!(frozen.len(), 999);
+        assert_eq!(frozen.indexed_columns().collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(frozen.get(13), Some(&[4, 14, 0][..]));
+        assert_eq!(frozen.get(999), None);
+        assert!(frozen.rows().eq(store.rows().map(|r| &r[..])));
+
+        let col = cmp::Value::column;
+        let queries: Vec<Vec<Condition<u64>>> =
+            vec![vec![],
+                 vec![Condition::eq(0, 3u64)],
+                 vec![Condition::eq(0, 3u64), Condition::eq(2, 1u64)],
+                 vec![Condition::eq(0, 3u64), Condition::lt(1, 200u64)],
+                 vec![Condition::eq(0, 42u64)],
+                 vec![Condition::eq(2, 6u64)],
+                 vec![Condition::ne(0, 3u64), Condition::gt(1, 990u64)],
+                 vec![Condition::between(1, Bound::Excluded(10u64), Bound::Included(20u64))],
+                 vec![Condition::ge(1, 500u64), Condition::lt(1, 505u64), Condition::eq(0, 2u64)],
+                 vec![Condition::le(1, 5u64)],
+                 vec![Condition::is_in(0, vec![1u64, 2])],
+                 vec![Condition::in_ranges(1,
+                                           vec![(Bound::Included(3u64), Bound::Excluded(9)),
+                                                (Bound::Included(7), Bound::Included(11))])],
+                 vec![Condition::new(0, cmp::Comparison::Equal(col(2)))],
+                 vec![Condition::eq(0, 1u64), Condition::eq(0, 2u64)],
+                 vec![Condition::gt(1, 10u64), Condition::lt(1, 5u64)]];
+        for conds in &queries {
+            let mut expected = store.find(conds).map(|r| &r[..]).collect::<Vec<_>>();
+            let mut found = frozen.find(conds).collect::<Vec<_>>();
+            assert_eq!(frozen.count(conds), expected.len());
+            expected.sort_unstable();
+            found.sort_unstable();
+            assert_eq!(found, expected, "{:?}", conds);
+        }
+
+        // rows found through an index come in the order of their values
+        let rows = frozen.find(&queries[7]).map(|r| r[1]).collect::<Vec<_>>();
+        assert_eq!(rows, (11..=20).filter(|&i| i != 13).collect::<Vec<_>>());
+        let rows = frozen.find(&[Condition::lt(0, 2u64), Condition::lt(1, 30u64)])
+            .map(|r| r[1])
+            .collect::<Vec<_>>();
+        assert_eq!(rows, vec![0, 1, 10, 11, 20, 21]);
+        let rows = frozen.find(&[Condition::eq(0, 4u64), Condition::lt(1, 40u64)])
+            .map(|r| r[1])
+            .collect::<Vec<_>>();
+        assert_eq!(rows, vec![4, 14, 24, 34]);
+    }
+
+    #[test]
+    #[should_panic(expected = "condition refers to column 3")]
+    fn it_rejects_bad_conditions() {
+        let (_, file) = frozen("bad-conditions");
+        let frozen = unsafe { FrozenStore::<u64>::open(&file.0) }.unwrap();
+        assert!(frozen.validate(&[Condition::eq(3, 1u64)]).is_err());
+        frozen.find(&[Condition::eq(3, 1u64)]).count();
+    }
+
+    #[test]
+    fn it_freezes_empty_stores() {
+        let store = Store::<i32>::new(2);
+        let file = TempFile::new("empty");
+        store.write_frozen(File::create(&file.0).unwrap(), &[1]).unwrap();
+        let frozen = unsafe { FrozenStore::<i32>::open(&file.0) }.unwrap();
+        assert!(frozen.is_empty());
+        assert_eq!(frozen.find(&[Condition::eq(1, 0)]).count(), 0);
+        assert_eq!(frozen.rows().count(), 0);
+    }
+
+    #[test]
+    fn it_rejects_invalid_files() {
+        let (_, file) = frozen("invalid");
+        let error = |file: &TempFile| unsafe { FrozenStore::<u64>::open(&file.0) }.err().unwrap();
+
+        // the wrong value type
+        let e = unsafe { FrozenStore::<u32>::open(&file.0) }.err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(e.to_string(), "frozen store holds values of 8 bytes, not 4");
+
+        // a truncated file
+        let mut bytes = fs::read(&file.0).unwrap();
+        bytes.pop();
+        fs::write(&file.0, &bytes).unwrap();
+        assert_eq!(error(&file).kind(), io::ErrorKind::InvalidData);
+        fs::write(&file.0, &bytes[..20]).unwrap();
+        assert_eq!(error(&file).kind(), io::ErrorKind::InvalidData);
+
+        // something else entirely
+        fs::write(&file.0, vec![b'x'; 1000]).unwrap();
+        assert_eq!(error(&file).to_string(), "not a frozen store");
+        let missing = TempFile::new("missing");
+        assert_eq!(error(&missing).kind(), io::ErrorKind::NotFound);
+    }
+}
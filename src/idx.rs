@@ -1,67 +1,340 @@
 use std::collections::HashMap;
-use std::hash::Hash;
+use std::collections::hash_map::{Entry, RandomState};
+use std::hash::{BuildHasher, Hash};
 
 use std::collections::BTreeMap;
+use std::iter;
+use std::mem;
 use std::ops::Bound;
+use std::slice;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 /// An `EqualityIndex` is an index that can perform *efficient* equality lookups.
 pub trait EqualityIndex<T> {
     /// Return an iterator that yields the indices of all rows that match the given value.
-    fn lookup<'a>(&'a self, &T) -> Box<Iterator<Item = usize> + 'a>;
+    fn lookup<'a>(&'a self, key: &T) -> Box<dyn Iterator<Item = usize> + 'a>;
 
     /// Add the given row index to the index under the given value.
-    fn index(&mut self, T, usize);
+    fn index(&mut self, key: T, row: usize);
 
     /// Remove the given row index under the given value from the index.
-    fn undex(&mut self, &T, usize);
+    fn undex(&mut self, key: &T, row: usize);
 
     /// Give the expected number of rows returned for a key.
     /// This method may be called often, and in rapid succession, and so should return quickly.
     fn estimate(&self) -> usize;
+
+    /// Give the expected number of rows returned for the given key. Like `estimate`, this may be
+    /// called often, and should return quickly.
+    ///
+    /// The default implementation returns `estimate`, but implementors that can cheaply tell how
+    /// many rows a particular key has should override it, since that lets the planner avoid
+    /// indices where the queried value is far more common than average.
+    fn estimate_for(&self, key: &T) -> usize {
+        let _ = key;
+        self.estimate()
+    }
+
+    /// Return an iterator that yields every distinct value that has rows in the index, or `None`
+    /// if the index cannot enumerate its values.
+    ///
+    /// The default implementation returns `None`.
+    fn keys<'a>(&'a self) -> Option<Box<dyn Iterator<Item = &'a T> + 'a>> {
+        None
+    }
+
+    /// Return the number of rows indexed under the given value.
+    ///
+    /// The default implementation counts the rows yielded by `lookup`, but implementors that know
+    /// the number of rows for a key should override it.
+    fn count(&self, key: &T) -> usize {
+        self.lookup(key).count()
+    }
+
+    /// Return the indices of all rows that match the given value as a slice, or `None` if the
+    /// index does not keep them in one. `Store::find` uses this to walk the rows of a lookup
+    /// without allocating.
+    ///
+    /// The default implementation returns `None`, in which case `lookup` is used instead.
+    fn lookup_slice(&self, key: &T) -> Option<&[usize]> {
+        let _ = key;
+        None
+    }
+
+    /// Remove all rows from the index, and return true. Returns false if the index does not
+    /// support being cleared in one go, in which case all rows must be removed individually using
+    /// `undex`.
+    ///
+    /// The default implementation returns false.
+    fn clear(&mut self) -> bool {
+        false
+    }
+
+    /// Hint that at least `additional` more rows are about to be indexed, so that the index can
+    /// allocate space for them up front.
+    ///
+    /// The default implementation does nothing.
+    fn reserve(&mut self, additional: usize) {
+        let _ = additional;
+    }
+
+    /// Add every given row to the index under the value it is given with. This is used when many
+    /// rows are added at once, such as when a bulk load is finished (see `Store::begin_bulk_load`).
+    ///
+    /// The default implementation calls `reserve`, and then `index` for every row in turn, but
+    /// implementors that can add many rows more efficiently than one at a time should override it.
+    fn index_many(&mut self, rows: Vec<(T, usize)>) {
+        self.reserve(rows.len());
+        for (key, row) in rows {
+            self.index(key, row);
+        }
+    }
+
+    /// Hint that the index should release any memory it is not currently using.
+    ///
+    /// The default implementation does nothing.
+    fn shrink_to_fit(&mut self) {}
+
+    /// Returns the kind of built-in index this is, or `None` if this is not one of the built-in
+    /// indices. This is used to re-create the index when needed (e.g., when deserializing a
+    /// `Store`), and so custom indices that return `None` are simply not re-created.
+    fn kind(&self) -> Option<IndexKind> {
+        None
+    }
+
+    /// Returns true if this index allows at most one row for any given value. A `Store` will
+    /// refuse to insert a row whose value in the column of a unique index is already present.
+    fn is_unique(&self) -> bool {
+        false
+    }
+
+    /// Returns statistics about the contents of the index.
+    ///
+    /// The default implementation counts the values and rows by going through `keys` and `count`
+    /// if the index can enumerate its values, and leaves everything unknown otherwise. It never
+    /// estimates the memory used by the index.
+    fn stats(&self) -> IndexStats {
+        match self.keys() {
+            Some(keys) => {
+                let (keys, entries) = keys.fold((0, 0), |(n, e), key| (n + 1, e + self.count(key)));
+                IndexStats {
+                    keys: Some(keys),
+                    entries: Some(entries),
+                    bytes: None,
+                }
+            }
+            None => IndexStats::default(),
+        }
+    }
+}
+
+/// Statistics about the contents of an index, as returned by `EqualityIndex::stats`. Statistics
+/// the index cannot give are `None`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IndexStats {
+    /// The number of distinct values that have rows in the index.
+    pub keys: Option<usize>,
+    /// The number of rows in the index. A row is counted once for every value it is indexed
+    /// under.
+    pub entries: Option<usize>,
+    /// A rough estimate of the number of bytes of memory used by the index. Memory owned by the
+    /// values themselves (such as the contents of a `String`) is not included.
+    pub bytes: Option<usize>,
+}
+
+/// The different kinds of indices built into shortcut.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum IndexKind {
+    /// A `HashIndex`.
+    Hash,
+    /// A `HashIndex` created with `HashIndex::unique`.
+    UniqueHash,
+    /// A `BTreeIndex`.
+    BTree,
+}
+
+impl IndexKind {
+    /// Allocate a new, empty index of this kind.
+    pub fn build<T: Ord + Hash + Send + Sync + 'static>(self) -> Index<T> {
+        match self {
+            IndexKind::Hash => HashIndex::new().into(),
+            IndexKind::UniqueHash => HashIndex::unique().into(),
+            IndexKind::BTree => BTreeIndex::new().into(),
+        }
+    }
 }
 
 /// An implementation of `EqualityIndex` that uses a `HashMap`.
+///
+/// Values are hashed using `S`, which defaults to the same hasher as `HashMap` uses. A faster
+/// hasher can be given with `with_hasher`.
 #[derive(Clone)]
-pub struct HashIndex<K: Eq + Hash> {
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde",
+           serde(bound(serialize = "K: Serialize",
+                       deserialize = "K: Deserialize<'de>, S: BuildHasher + Default")))]
+pub struct HashIndex<K: Eq + Hash, S = RandomState> {
     num: usize,
-    map: HashMap<K, Vec<usize>>,
+    map: HashMap<K, Rows, S>,
+    unique: bool,
+}
+
+/// The rows indexed under a single value in a `HashIndex`. Most values in a typical indexed column
+/// only have a single row, which is then kept inline rather than in a separately allocated `Vec`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "Vec<usize>", into = "Vec<usize>"))]
+enum Rows {
+    One(usize),
+    Many(Vec<usize>),
+}
+
+impl Rows {
+    fn as_slice(&self) -> &[usize] {
+        match *self {
+            Rows::One(ref row) => slice::from_ref(row),
+            Rows::Many(ref rows) => rows,
+        }
+    }
+
+    fn push(&mut self, row: usize) {
+        match *self {
+            Rows::One(first) => *self = Rows::Many(vec![first, row]),
+            Rows::Many(ref mut rows) => rows.push(row),
+        }
+    }
+
+    /// Remove the given row, which must be present, and return true if no rows are left.
+    fn remove(&mut self, row: usize) -> bool {
+        match *self {
+            Rows::One(r) => {
+                assert_eq!(r, row);
+                true
+            }
+            Rows::Many(ref mut rows) => {
+                match rows.iter().position(|&r| r == row) {
+                    Some(i) => {
+                        rows.swap_remove(i);
+                    }
+                    None => unreachable!(),
+                }
+                if rows.len() == 1 {
+                    *self = Rows::One(rows[0]);
+                }
+                false
+            }
+        }
+    }
+
+    /// The number of rows that fit in the memory allocated for this list.
+    fn capacity(&self) -> usize {
+        match *self {
+            Rows::One(_) => 0,
+            Rows::Many(ref rows) => rows.capacity(),
+        }
+    }
+}
+
+impl From<Vec<usize>> for Rows {
+    fn from(mut rows: Vec<usize>) -> Rows {
+        if rows.len() == 1 {
+            Rows::One(rows[0])
+        } else {
+            rows.shrink_to_fit();
+            Rows::Many(rows)
+        }
+    }
+}
+
+impl From<Rows> for Vec<usize> {
+    fn from(rows: Rows) -> Vec<usize> {
+        match rows {
+            Rows::One(row) => vec![row],
+            Rows::Many(rows) => rows,
+        }
+    }
+}
+
+impl<K: Eq + Hash, S: BuildHasher + Default> Default for HashIndex<K, S> {
+    fn default() -> Self {
+        HashIndex::with_hasher(S::default())
+    }
 }
 
 impl<K: Eq + Hash> HashIndex<K> {
     /// Allocate a new `HashIndex`.
     pub fn new() -> HashIndex<K> {
+        HashIndex::with_hasher(RandomState::new())
+    }
+
+    /// Allocate a new `HashIndex` that enforces that every value maps to at most one row.
+    ///
+    /// Indexing a second row under a value that is already present will panic.
+    pub fn unique() -> HashIndex<K> {
         HashIndex {
-            map: HashMap::new(),
+            unique: true,
+            ..HashIndex::new()
+        }
+    }
+}
+
+impl<K: Eq + Hash, S: BuildHasher> HashIndex<K, S> {
+    /// Allocate a new `HashIndex` that hashes values using the given hasher.
+    ///
+    /// Note that if the index is re-created from its `IndexKind` (e.g., when a `Store` is
+    /// deserialized), it uses the default hasher instead.
+    pub fn with_hasher(hasher: S) -> HashIndex<K, S> {
+        HashIndex::with_capacity_and_hasher(0, hasher)
+    }
+
+    /// Allocate a new `HashIndex` with room for at least `capacity` distinct values, that hashes
+    /// values using the given hasher. See `with_hasher`.
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> HashIndex<K, S> {
+        HashIndex {
+            map: HashMap::with_capacity_and_hasher(capacity, hasher),
             num: 0,
+            unique: false,
         }
     }
 }
 
-impl<T: Eq + Hash> EqualityIndex<T> for HashIndex<T> {
-    fn lookup<'a>(&'a self, key: &T) -> Box<Iterator<Item = usize> + 'a> {
+impl<T: Eq + Hash, S: BuildHasher> EqualityIndex<T> for HashIndex<T, S> {
+    fn lookup<'a>(&'a self, key: &T) -> Box<dyn Iterator<Item = usize> + 'a> {
         match self.map.get(key) {
-            Some(ref v) => Box::new(v.iter().map(|row| *row)),
+            Some(v) => Box::new(v.as_slice().iter().copied()),
             None => Box::new(None.into_iter()),
         }
     }
 
+    fn lookup_slice(&self, key: &T) -> Option<&[usize]> {
+        Some(self.map.get(key).map_or(&[][..], Rows::as_slice))
+    }
+
     fn index(&mut self, key: T, row: usize) {
-        self.map.entry(key).or_insert_with(Vec::new).push(row);
+        match self.map.entry(key) {
+            Entry::Vacant(e) => {
+                e.insert(Rows::One(row));
+            }
+            Entry::Occupied(mut e) => {
+                if self.unique {
+                    panic!("row {} has the same value as row {} in a unique index",
+                           row,
+                           e.get().as_slice()[0]);
+                }
+                e.get_mut().push(row);
+            }
+        }
         self.num += 1;
     }
 
     fn undex(&mut self, key: &T, row: usize) {
         let mut empty = false;
-        if let Some(mut l) = self.map.get_mut(key) {
-            empty = {
-                match l.iter().position(|&r| r == row) {
-                    Some(i) => {
-                        l.swap_remove(i);
-                    }
-                    None => unreachable!(),
-                }
-                l.is_empty()
-            };
+        if let Some(l) = self.map.get_mut(key) {
+            empty = l.remove(row);
+            self.num -= 1;
         }
         if empty {
             self.map.remove(key);
@@ -69,28 +342,233 @@ impl<T: Eq + Hash> EqualityIndex<T> for HashIndex<T> {
     }
 
     fn estimate(&self) -> usize {
-        let len = self.map.len();
-        if len > 0 {
-            self.num / self.map.len()
+        self.num.checked_div(self.map.len()).unwrap_or(0)
+    }
+
+    fn estimate_for(&self, key: &T) -> usize {
+        self.count(key)
+    }
+
+    fn keys<'a>(&'a self) -> Option<Box<dyn Iterator<Item = &'a T> + 'a>> {
+        Some(Box::new(self.map.keys()))
+    }
+
+    fn count(&self, key: &T) -> usize {
+        self.map.get(key).map_or(0, |rows| rows.as_slice().len())
+    }
+
+    fn clear(&mut self) -> bool {
+        self.map.clear();
+        self.num = 0;
+        true
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.map.reserve(additional);
+    }
+
+    fn shrink_to_fit(&mut self) {
+        self.map.shrink_to_fit();
+        for rows in self.map.values_mut() {
+            if let Rows::Many(ref mut rows) = *rows {
+                rows.shrink_to_fit();
+            }
+        }
+    }
+
+    fn kind(&self) -> Option<IndexKind> {
+        if self.unique {
+            Some(IndexKind::UniqueHash)
         } else {
-            0
+            Some(IndexKind::Hash)
+        }
+    }
+
+    fn is_unique(&self) -> bool {
+        self.unique
+    }
+
+    fn stats(&self) -> IndexStats {
+        // every bucket holds a value and its rows, plus a byte of control data
+        let buckets = self.map.capacity() * (mem::size_of::<(T, Rows)>() + 1);
+        let rows = rows_bytes(self.map.values().map(Rows::capacity));
+        IndexStats {
+            keys: Some(self.map.len()),
+            entries: Some(self.num),
+            bytes: Some(mem::size_of::<Self>() + buckets + rows),
         }
     }
 }
 
+/// The number of bytes allocated for lists of rows with the given capacities.
+fn rows_bytes<I: Iterator<Item = usize>>(capacities: I) -> usize {
+    capacities.sum::<usize>() * mem::size_of::<usize>()
+}
+
 /// A `RangeIndex` is an index that, in addition to performing efficient equality lookups, can
-/// *also* perform efficient range queries.
+/// *also* perform efficient range queries. If a `RangeIndex` can enumerate its values (see
+/// `EqualityIndex::keys`), it should yield them in ascending order.
 pub trait RangeIndex<T>: EqualityIndex<T> {
     /// Return an iterator that yields the indices of all rows whose value (in the column this
     /// index is assigned to) lies within the given `Bound`s.
-    fn between<'a>(&'a self, Bound<&T>, Bound<&T>) -> Box<Iterator<Item = usize> + 'a>;
+    ///
+    /// Rows should be yielded in ascending order of value, and rows with the same value should be
+    /// yielded in ascending order of row index.
+    fn between<'a>(&'a self, min: Bound<&T>, max: Bound<&T>) -> Box<dyn Iterator<Item = usize> + 'a>;
+
+    /// Like `between`, but yields rows in *descending* order of value. Rows with the same value
+    /// should still be yielded in ascending order of row index.
+    fn between_rev<'a>(&'a self,
+                       min: Bound<&T>,
+                       max: Bound<&T>)
+                       -> Box<dyn Iterator<Item = usize> + 'a>;
+
+    /// Give the expected number of rows returned by `between` for the given `Bound`s. Like
+    /// `estimate`, this may be called often, and should return quickly.
+    ///
+    /// The default implementation uses the histogram returned by `histogram` if there is one, and
+    /// otherwise returns `estimate`, which is a poor guess for any range that covers more than a
+    /// single value.
+    fn estimate_range(&self, min: Bound<&T>, max: Bound<&T>) -> usize
+        where T: Ord
+    {
+        match self.histogram() {
+            Some(histogram) => histogram.estimate(min, max),
+            None => self.estimate(),
+        }
+    }
+
+    /// Keep the given histogram of the values in the index (see `Store::analyze`), replacing any
+    /// histogram kept before, and return true. Returns false if the index cannot keep a
+    /// histogram.
+    ///
+    /// The default implementation returns false.
+    fn set_histogram(&mut self, histogram: Histogram<T>) -> bool {
+        let _ = histogram;
+        false
+    }
+
+    /// Returns the histogram last given to `set_histogram`, if the index kept it.
+    ///
+    /// The default implementation returns `None`.
+    fn histogram(&self) -> Option<&Histogram<T>> {
+        None
+    }
+}
+
+/// An equi-depth histogram of the values in a column, as built by `Store::analyze`: the values
+/// are split into buckets of consecutive values that hold roughly the same number of rows, so
+/// that the number of rows with values in a range can be estimated without looking at any rows.
+/// A value held by so many rows that it would fill a bucket on its own is always given a bucket
+/// of its own, so that frequent values do not distort the estimates for their neighbours.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Histogram<T> {
+    buckets: Vec<Bucket<T>>,
+}
+
+/// The values from `min` to `max` (inclusive), and the number of rows that hold them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Bucket<T> {
+    min: T,
+    max: T,
+    rows: usize,
+}
+
+impl<T: Ord + Clone> Histogram<T> {
+    /// Build a histogram with about `buckets` buckets from the number of rows that hold each
+    /// value, in ascending order of value. There are at most twice as many buckets as asked for.
+    pub(crate) fn new<'a, I>(counts: I, buckets: usize) -> Self
+        where I: IntoIterator<Item = (&'a T, usize)>,
+              T: 'a
+    {
+        let counts = counts.into_iter().filter(|&(_, n)| n != 0).collect::<Vec<_>>();
+        let rows = counts.iter().map(|&(_, n)| n).sum::<usize>();
+        let depth = rows.div_ceil(buckets.max(1)).max(1);
+
+        let mut histogram = Histogram { buckets: Vec::new() };
+        let mut current: Option<Bucket<T>> = None;
+        for (value, n) in counts {
+            if n >= depth {
+                histogram.buckets.extend(current.take());
+            }
+            let bucket = current.get_or_insert_with(|| {
+                Bucket {
+                    min: value.clone(),
+                    max: value.clone(),
+                    rows: 0,
+                }
+            });
+            bucket.max = value.clone();
+            bucket.rows += n;
+            if bucket.rows >= depth {
+                histogram.buckets.extend(current.take());
+            }
+        }
+        histogram.buckets.extend(current);
+        histogram
+    }
+}
+
+impl<T: Ord> Histogram<T> {
+    /// Returns the estimated number of rows with values between the given `Bound`s.
+    ///
+    /// Buckets that lie entirely within the bounds count in full, and buckets that lie partly
+    /// within them count for half their rows, since nothing is known about how their rows are
+    /// spread across their values.
+    pub fn estimate(&self, min: Bound<&T>, max: Bound<&T>) -> usize {
+        let above_min = |v: &T| match min {
+            Bound::Included(min) => v >= min,
+            Bound::Excluded(min) => v > min,
+            Bound::Unbounded => true,
+        };
+        let below_max = |v: &T| match max {
+            Bound::Included(max) => v <= max,
+            Bound::Excluded(max) => v < max,
+            Bound::Unbounded => true,
+        };
+        self.buckets
+            .iter()
+            .map(|b| if above_min(&b.min) && below_max(&b.max) {
+                b.rows
+            } else if above_min(&b.max) && below_max(&b.min) {
+                b.rows.div_ceil(2)
+            } else {
+                0
+            })
+            .sum()
+    }
+
+    /// Returns the number of buckets in the histogram.
+    pub fn len(&self) -> usize {
+        self.buckets.len()
+    }
+
+    /// Returns true if the histogram has no buckets, which is the case if it was built while
+    /// there were no rows.
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    /// Returns the number of rows the histogram was built from.
+    pub fn rows(&self) -> usize {
+        self.buckets.iter().map(|b| b.rows).sum()
+    }
 }
 
 /// An implementation of `RangeIndex` using a `BTreeMap`.
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct BTreeIndex<K: Ord + Eq> {
     num: usize,
     map: BTreeMap<K, Vec<usize>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    histogram: Option<Histogram<K>>,
+}
+
+impl<K: Ord + Eq> Default for BTreeIndex<K> {
+    fn default() -> Self {
+        BTreeIndex::new()
+    }
 }
 
 impl<K: Ord + Eq> BTreeIndex<K> {
@@ -99,38 +577,146 @@ impl<K: Ord + Eq> BTreeIndex<K> {
         BTreeIndex {
             map: BTreeMap::new(),
             num: 0,
+            histogram: None,
         }
     }
 }
 
 impl<T: Ord + Eq> EqualityIndex<T> for BTreeIndex<T> {
-    fn lookup<'a>(&'a self, key: &T) -> Box<Iterator<Item = usize> + 'a> {
+    fn lookup<'a>(&'a self, key: &T) -> Box<dyn Iterator<Item = usize> + 'a> {
         match self.map.get(key) {
-            Some(ref v) => Box::new(v.iter().map(|row| *row)),
+            Some(v) => Box::new(v.iter().copied()),
             None => Box::new(None.into_iter()),
         }
     }
 
+    fn lookup_slice(&self, key: &T) -> Option<&[usize]> {
+        Some(self.map.get(key).map_or(&[][..], Vec::as_slice))
+    }
+
     fn index(&mut self, key: T, row: usize) {
-        self.map.entry(key).or_insert_with(Vec::new).push(row);
+        // keep rows sorted, so that rows with the same value are yielded in order
+        let rows = self.map.entry(key).or_default();
+        let at = rows.partition_point(|&r| r < row);
+        rows.insert(at, row);
         self.num += 1;
     }
 
     fn undex(&mut self, key: &T, row: usize) {
-        if let Some(ref mut l) = self.map.get_mut(key) {
+        let mut empty = false;
+        if let Some(l) = self.map.get_mut(key) {
             self.num -= l.len();
             l.retain(|&i| i != row);
             self.num += l.len();
+            empty = l.is_empty();
+        }
+        if empty {
+            self.map.remove(key);
         }
     }
 
     fn estimate(&self) -> usize {
-        self.num / self.map.len()
+        self.num.checked_div(self.map.len()).unwrap_or(0)
+    }
+
+    fn estimate_for(&self, key: &T) -> usize {
+        self.count(key)
+    }
+
+    fn keys<'a>(&'a self) -> Option<Box<dyn Iterator<Item = &'a T> + 'a>> {
+        Some(Box::new(self.map.keys()))
+    }
+
+    fn count(&self, key: &T) -> usize {
+        self.map.get(key).map(Vec::len).unwrap_or(0)
+    }
+
+    fn index_many(&mut self, mut rows: Vec<(T, usize)>) {
+        // with the rows sorted by value, every value is only looked up once, and the tree is
+        // walked in order
+        rows.sort_unstable();
+        self.num += rows.len();
+        let mut rows = rows.into_iter().peekable();
+        while let Some((key, row)) = rows.next() {
+            let entry = self.map.entry(key);
+            let mut new = vec![row];
+            while let Some((_, row)) = rows.next_if(|(k, _)| k == entry.key()) {
+                new.push(row);
+            }
+            let existing = entry.or_default();
+            let sorted = existing.last() < new.first();
+            existing.extend(new);
+            if !sorted {
+                existing.sort_unstable();
+            }
+        }
+    }
+
+    fn clear(&mut self) -> bool {
+        self.map.clear();
+        self.num = 0;
+        self.histogram = None;
+        true
+    }
+
+    fn shrink_to_fit(&mut self) {
+        for rows in self.map.values_mut() {
+            rows.shrink_to_fit();
+        }
+    }
+
+    fn kind(&self) -> Option<IndexKind> {
+        Some(IndexKind::BTree)
+    }
+
+    fn stats(&self) -> IndexStats {
+        // ignoring the spare room in the tree's nodes
+        let entries = self.map.len() * mem::size_of::<(T, Vec<usize>)>();
+        IndexStats {
+            keys: Some(self.map.len()),
+            entries: Some(self.num),
+            bytes: Some(mem::size_of::<Self>() + entries +
+                        rows_bytes(self.map.values().map(Vec::capacity))),
+        }
     }
 }
 impl<T: Ord + Eq> RangeIndex<T> for BTreeIndex<T> {
-    fn between<'a>(&'a self, min: Bound<&T>, max: Bound<&T>) -> Box<Iterator<Item = usize> + 'a> {
-        Box::new(self.map.range((min, max)).flat_map(|rows| rows.1.iter().map(|row| *row)))
+    fn between<'a>(&'a self, min: Bound<&T>, max: Bound<&T>) -> Box<dyn Iterator<Item = usize> + 'a> {
+        if is_empty_range(min, max) {
+            return Box::new(iter::empty());
+        }
+        Box::new(self.map.range((min, max)).flat_map(|rows| rows.1.iter().copied()))
+    }
+
+    fn between_rev<'a>(&'a self,
+                       min: Bound<&T>,
+                       max: Bound<&T>)
+                       -> Box<dyn Iterator<Item = usize> + 'a> {
+        if is_empty_range(min, max) {
+            return Box::new(iter::empty());
+        }
+        Box::new(self.map.range((min, max)).rev().flat_map(|rows| rows.1.iter().copied()))
+    }
+
+    fn set_histogram(&mut self, histogram: Histogram<T>) -> bool {
+        self.histogram = Some(histogram);
+        true
+    }
+
+    fn histogram(&self) -> Option<&Histogram<T>> {
+        self.histogram.as_ref()
+    }
+}
+
+/// Returns true if no key can lie between the given bounds. `BTreeMap::range` panics if given
+/// such bounds, rather than yielding nothing.
+fn is_empty_range<T: Ord>(min: Bound<&T>, max: Bound<&T>) -> bool {
+    match (min, max) {
+        (Bound::Included(min), Bound::Included(max)) => min > max,
+        (Bound::Included(min), Bound::Excluded(max)) |
+        (Bound::Excluded(min), Bound::Included(max)) |
+        (Bound::Excluded(min), Bound::Excluded(max)) => min >= max,
+        _ => false,
     }
 }
 
@@ -139,13 +725,13 @@ impl<T: Ord + Eq> RangeIndex<T> for BTreeIndex<T> {
 /// that trait to the underlying index for convenience.
 pub enum Index<T> {
     /// A `RangeIndex` trait object.
-    Range(Box<RangeIndex<T> + Send + Sync>),
+    Range(Box<dyn RangeIndex<T> + Send + Sync>),
     /// An `EqualityIndex` trait object.
-    Equality(Box<EqualityIndex<T> + Send + Sync>),
+    Equality(Box<dyn EqualityIndex<T> + Send + Sync>),
 }
 
 impl<T> EqualityIndex<T> for Index<T> {
-    fn lookup<'a>(&'a self, key: &T) -> Box<Iterator<Item = usize> + 'a> {
+    fn lookup<'a>(&'a self, key: &T) -> Box<dyn Iterator<Item = usize> + 'a> {
         match *self {
             Index::Range(ref ri) => ri.lookup(key),
             Index::Equality(ref ei) => ei.lookup(key),
@@ -169,10 +755,79 @@ impl<T> EqualityIndex<T> for Index<T> {
             Index::Equality(ref ei) => ei.estimate(),
         }
     }
+    fn estimate_for(&self, key: &T) -> usize {
+        match *self {
+            Index::Range(ref ri) => ri.estimate_for(key),
+            Index::Equality(ref ei) => ei.estimate_for(key),
+        }
+    }
+    fn keys<'a>(&'a self) -> Option<Box<dyn Iterator<Item = &'a T> + 'a>> {
+        match *self {
+            Index::Range(ref ri) => ri.keys(),
+            Index::Equality(ref ei) => ei.keys(),
+        }
+    }
+    fn count(&self, key: &T) -> usize {
+        match *self {
+            Index::Range(ref ri) => ri.count(key),
+            Index::Equality(ref ei) => ei.count(key),
+        }
+    }
+    fn lookup_slice(&self, key: &T) -> Option<&[usize]> {
+        match *self {
+            Index::Range(ref ri) => ri.lookup_slice(key),
+            Index::Equality(ref ei) => ei.lookup_slice(key),
+        }
+    }
+    fn clear(&mut self) -> bool {
+        match *self {
+            Index::Range(ref mut ri) => ri.clear(),
+            Index::Equality(ref mut ei) => ei.clear(),
+        }
+    }
+    fn reserve(&mut self, additional: usize) {
+        match *self {
+            Index::Range(ref mut ri) => ri.reserve(additional),
+            Index::Equality(ref mut ei) => ei.reserve(additional),
+        }
+    }
+    fn index_many(&mut self, rows: Vec<(T, usize)>) {
+        match *self {
+            Index::Range(ref mut ri) => ri.index_many(rows),
+            Index::Equality(ref mut ei) => ei.index_many(rows),
+        }
+    }
+    fn shrink_to_fit(&mut self) {
+        match *self {
+            Index::Range(ref mut ri) => ri.shrink_to_fit(),
+            Index::Equality(ref mut ei) => ei.shrink_to_fit(),
+        }
+    }
+    fn kind(&self) -> Option<IndexKind> {
+        match *self {
+            Index::Range(ref ri) => ri.kind(),
+            Index::Equality(ref ei) => ei.kind(),
+        }
+    }
+    fn is_unique(&self) -> bool {
+        match *self {
+            Index::Range(ref ri) => ri.is_unique(),
+            Index::Equality(ref ei) => ei.is_unique(),
+        }
+    }
+    fn stats(&self) -> IndexStats {
+        match *self {
+            Index::Range(ref ri) => ri.stats(),
+            Index::Equality(ref ei) => ei.stats(),
+        }
+    }
 }
 
-impl<T: Eq + Hash + 'static + Send + Sync> From<HashIndex<T>> for Index<T> {
-    fn from(x: HashIndex<T>) -> Index<T> {
+impl<T, S> From<HashIndex<T, S>> for Index<T>
+    where T: Eq + Hash + 'static + Send + Sync,
+          S: BuildHasher + 'static + Send + Sync
+{
+    fn from(x: HashIndex<T, S>) -> Index<T> {
         Index::Equality(Box::new(x))
     }
 }
@@ -187,17 +842,186 @@ impl<T: Ord + Eq + 'static + Send + Sync> From<BTreeIndex<T>> for Index<T> {
 mod tests {
     use super::*;
 
+    /// A simple, deterministic FNV-1a hasher.
+    struct FnvHasher(u64);
+
+    impl Default for FnvHasher {
+        fn default() -> Self {
+            FnvHasher(0xcbf29ce484222325)
+        }
+    }
+
+    impl ::std::hash::Hasher for FnvHasher {
+        fn finish(&self) -> u64 {
+            self.0
+        }
+        fn write(&mut self, bytes: &[u8]) {
+            for &b in bytes {
+                self.0 = (self.0 ^ u64::from(b)).wrapping_mul(0x100000001b3);
+            }
+        }
+    }
+
+    type FnvBuildHasher = ::std::hash::BuildHasherDefault<FnvHasher>;
+
     #[test]
     fn hashmap_eq_index() {
+        check_hashmap_eq_index(HashIndex::new());
+        check_hashmap_eq_index(HashIndex::with_hasher(FnvBuildHasher::default()));
+        check_hashmap_eq_index(HashIndex::<_, FnvBuildHasher>::default());
+        check_hashmap_eq_index(HashIndex::with_capacity_and_hasher(16, FnvBuildHasher::default()));
+    }
+
+    #[test]
+    fn hashmap_index_with_hasher_into_index() {
+        let mut idx: Index<usize> = HashIndex::with_hasher(FnvBuildHasher::default()).into();
+        for row in 0..100 {
+            idx.index(row % 10, row);
+        }
+        assert_eq!(idx.lookup(&3).collect::<Vec<_>>(), vec![3, 13, 23, 33, 43, 53, 63, 73, 83, 93]);
+        assert_eq!(idx.kind(), Some(IndexKind::Hash));
+    }
+
+    fn check_hashmap_eq_index<S: BuildHasher>(mut eqidx: HashIndex<&'static str, S>) {
         use super::EqualityIndex;
-        let mut eqidx = HashIndex::new();
         assert_eq!(eqidx.lookup(&"a").count(), 0);
         eqidx.index("a", 0);
         assert_eq!(eqidx.lookup(&"a").count(), 1);
         eqidx.index("a", 1);
         assert_eq!(eqidx.lookup(&"a").count(), 2);
+        assert_eq!(eqidx.count(&"a"), 2);
+        assert_eq!(eqidx.count(&"b"), 0);
+        assert_eq!(eqidx.estimate_for(&"a"), 2);
+        assert_eq!(eqidx.estimate_for(&"b"), 0);
         eqidx.undex(&"a", 0);
         assert_eq!(eqidx.lookup(&"a").count(), 1);
+        assert_eq!(eqidx.estimate(), 1);
+        eqidx.undex(&"a", 1);
+        assert_eq!(eqidx.lookup(&"a").count(), 0);
+        assert_eq!(eqidx.estimate(), 0);
+    }
+
+    #[test]
+    fn hashmap_index_inlines_single_rows() {
+        use super::EqualityIndex;
+        let mut unique = HashIndex::unique();
+        for row in 0..100 {
+            unique.index(row, row);
+        }
+        assert!(unique.map.values().all(|rows| *rows == Rows::One(rows.as_slice()[0])));
+        assert_eq!(unique.lookup(&42).collect::<Vec<_>>(), vec![42]);
+        assert_eq!(unique.stats().entries, Some(100));
+
+        // a second row for a value moves the rows out of line, and back once it is removed
+        let mut eqidx = HashIndex::new();
+        eqidx.index("a", 0);
+        assert_eq!(eqidx.map[&"a"], Rows::One(0));
+        eqidx.index("a", 1);
+        eqidx.index("a", 2);
+        assert_eq!(eqidx.map[&"a"], Rows::Many(vec![0, 1, 2]));
+        assert_eq!(eqidx.lookup(&"a").collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert_eq!(eqidx.estimate(), 3);
+        eqidx.undex(&"a", 0);
+        assert_eq!(eqidx.count(&"a"), 2);
+        eqidx.undex(&"a", 2);
+        assert_eq!(eqidx.map[&"a"], Rows::One(1));
+        assert_eq!(eqidx.lookup(&"a").collect::<Vec<_>>(), vec![1]);
+        eqidx.index("a", 3);
+        assert_eq!(eqidx.lookup(&"a").collect::<Vec<_>>(), vec![1, 3]);
+        eqidx.undex(&"a", 1);
+        eqidx.undex(&"a", 3);
+        assert!(eqidx.map.is_empty());
+        assert_eq!(eqidx.estimate(), 0);
+    }
+
+    #[test]
+    fn hashmap_unique_index() {
+        use super::EqualityIndex;
+        let mut eqidx = HashIndex::unique();
+        assert!(eqidx.is_unique());
+        eqidx.index("a", 0);
+        eqidx.index("b", 1);
+        assert_eq!(eqidx.estimate(), 1);
+        eqidx.undex(&"a", 0);
+        eqidx.index("a", 2);
+        assert_eq!(eqidx.lookup(&"a").collect::<Vec<_>>(), vec![2]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn hashmap_unique_index_rejects_duplicates() {
+        use super::EqualityIndex;
+        let mut eqidx = HashIndex::unique();
+        eqidx.index("a", 0);
+        eqidx.index("a", 1);
+    }
+
+    #[test]
+    fn hashmap_index_reserve() {
+        use super::EqualityIndex;
+        let mut eqidx = HashIndex::new();
+        eqidx.reserve(1000);
+        let cap = eqidx.map.capacity();
+        assert!(cap >= 1000);
+        for i in 0..1000 {
+            eqidx.index(i, i);
+        }
+        assert_eq!(eqidx.map.capacity(), cap);
+
+        for i in 0..990 {
+            eqidx.undex(&i, i);
+        }
+        eqidx.shrink_to_fit();
+        assert!(eqidx.map.capacity() < cap);
+        assert_eq!(eqidx.lookup(&995).collect::<Vec<_>>(), vec![995]);
+    }
+
+    #[test]
+    fn index_keys() {
+        use super::EqualityIndex;
+        let mut eqidx = HashIndex::new();
+        let mut idx = BTreeIndex::new();
+        for (row, &key) in ["c", "a", "c", "b"].iter().enumerate() {
+            eqidx.index(key, row);
+            idx.index(key, row);
+        }
+        eqidx.undex(&"b", 3);
+        idx.undex(&"b", 3);
+
+        let mut keys = eqidx.keys().unwrap().cloned().collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, vec!["a", "c"]);
+        assert_eq!(idx.keys().unwrap().cloned().collect::<Vec<_>>(), vec!["a", "c"]);
+    }
+
+    #[test]
+    fn index_stats() {
+        use super::EqualityIndex;
+        let mut eqidx = HashIndex::new();
+        let mut idx = BTreeIndex::new();
+        assert_eq!(eqidx.stats().keys, Some(0));
+        assert_eq!(idx.stats().entries, Some(0));
+        for row in 0..100 {
+            eqidx.index(row % 10, row);
+            idx.index(row % 10, row);
+        }
+        eqidx.undex(&3, 3);
+        idx.undex(&3, 3);
+
+        for stats in &[eqidx.stats(), idx.stats()] {
+            assert_eq!(stats.keys, Some(10));
+            assert_eq!(stats.entries, Some(99));
+            // at least the values and rows themselves must be stored somewhere
+            assert!(stats.bytes.unwrap() >= (10 + 99) * mem::size_of::<usize>());
+        }
+        let before = eqidx.stats().bytes.unwrap();
+        for row in 100..1000 {
+            eqidx.index(row, row);
+        }
+        assert!(eqidx.stats().bytes.unwrap() > before);
+
+        let idx: Index<usize> = idx.into();
+        assert_eq!(idx.stats().keys, Some(10));
     }
 
     #[test]
@@ -209,8 +1033,47 @@ mod tests {
         assert_eq!(idx.lookup(&"a").count(), 1);
         idx.index("a", 1);
         assert_eq!(idx.lookup(&"a").count(), 2);
+        assert_eq!(idx.count(&"a"), 2);
+        assert_eq!(idx.count(&"b"), 0);
+        assert_eq!(idx.estimate_for(&"a"), 2);
         idx.undex(&"a", 0);
         assert_eq!(idx.lookup(&"a").count(), 1);
+        assert_eq!(idx.estimate(), 1);
+        idx.undex(&"a", 1);
+        assert_eq!(idx.lookup(&"a").count(), 0);
+        assert_eq!(idx.estimate(), 0);
+    }
+
+    #[test]
+    fn lookup_slice() {
+        let mut hash = HashIndex::new();
+        let mut btree = BTreeIndex::new();
+        for row in 0..10 {
+            hash.index(row % 3, row);
+            btree.index(row % 3, row);
+        }
+        hash.index(7, 10);
+        let expected: &[usize] = &[1, 4, 7];
+        assert_eq!(hash.lookup_slice(&1), Some(expected));
+        assert_eq!(btree.lookup_slice(&1), Some(expected));
+        // a single row is kept inline, but is still a slice
+        assert_eq!(hash.lookup_slice(&7), Some(&[10][..]));
+        assert_eq!(hash.lookup_slice(&8), Some(&[][..]));
+        assert_eq!(btree.lookup_slice(&8), Some(&[][..]));
+        let idx: Index<usize> = btree.into();
+        assert_eq!(idx.lookup_slice(&2), Some(&[2, 5, 8][..]));
+    }
+
+    #[test]
+    fn btree_index_many() {
+        use super::EqualityIndex;
+        let mut idx = BTreeIndex::new();
+        idx.index("b", 4);
+        idx.index_many(vec![("b", 7), ("a", 5), ("c", 6), ("b", 1), ("a", 2)]);
+        assert_eq!(idx.lookup(&"a").collect::<Vec<_>>(), vec![2, 5]);
+        assert_eq!(idx.lookup(&"b").collect::<Vec<_>>(), vec![1, 4, 7]);
+        assert_eq!(idx.lookup(&"c").collect::<Vec<_>>(), vec![6]);
+        assert_eq!(idx.stats().entries, Some(6));
     }
 
     #[test]
@@ -227,4 +1090,104 @@ mod tests {
         idx.undex(&"b", 1);
         assert_eq!(idx.between(Included(&"a"), Included(&"b")).count(), 1);
     }
+
+    #[test]
+    fn btree_range_index_bounds() {
+        use super::RangeIndex;
+        use std::ops::Bound::{Excluded, Included, Unbounded};
+
+        let mut idx = BTreeIndex::new();
+        for (row, &key) in [1, 2, 3, 4, 5].iter().enumerate() {
+            idx.index(key, row);
+        }
+        let keys = |min, max| idx.between(min, max).map(|row| row + 1).collect::<Vec<_>>();
+        assert_eq!(keys(Included(&2), Included(&4)), vec![2, 3, 4]);
+        assert_eq!(keys(Included(&2), Excluded(&4)), vec![2, 3]);
+        assert_eq!(keys(Excluded(&2), Included(&4)), vec![3, 4]);
+        assert_eq!(keys(Excluded(&2), Excluded(&4)), vec![3]);
+        assert_eq!(keys(Unbounded, Excluded(&3)), vec![1, 2]);
+        assert_eq!(keys(Included(&4), Unbounded), vec![4, 5]);
+        assert_eq!(keys(Unbounded, Unbounded), vec![1, 2, 3, 4, 5]);
+        assert_eq!(keys(Included(&3), Included(&3)), vec![3]);
+
+        // empty and inverted ranges yield nothing
+        assert!(keys(Excluded(&3), Excluded(&3)).is_empty());
+        assert!(keys(Included(&3), Excluded(&3)).is_empty());
+        assert!(keys(Included(&4), Included(&2)).is_empty());
+        assert_eq!(idx.between_rev(Excluded(&3), Excluded(&3)).count(), 0);
+        assert_eq!(idx.between_rev(Included(&4), Included(&2)).count(), 0);
+        assert_eq!(idx.between_rev(Excluded(&2), Included(&4)).collect::<Vec<_>>(),
+                   vec![3, 2]);
+    }
+
+    #[test]
+    fn btree_range_index_rev() {
+        use super::RangeIndex;
+        use std::ops::Bound::{Excluded, Included, Unbounded};
+
+        // three rows for each of the values 0 through 9, added out of order
+        let mut idx = BTreeIndex::new();
+        for row in (0..30).rev() {
+            idx.index(row / 3, row);
+        }
+        let rows = |min, max| idx.between_rev(min, max).collect::<Vec<_>>();
+        // values come out largest first, but the rows for each value in ascending order
+        assert_eq!(rows(Excluded(&2), Excluded(&5)), vec![12, 13, 14, 9, 10, 11]);
+        assert_eq!(rows(Included(&2), Excluded(&4)), vec![9, 10, 11, 6, 7, 8]);
+        assert_eq!(rows(Excluded(&7), Unbounded), vec![27, 28, 29, 24, 25, 26]);
+        assert_eq!(rows(Unbounded, Excluded(&1)), vec![0, 1, 2]);
+        assert!(rows(Excluded(&9), Unbounded).is_empty());
+        assert_eq!(rows(Unbounded, Unbounded).len(), 30);
+        // walking backwards stops wherever the caller stops
+        assert_eq!(idx.between_rev(Unbounded, Excluded(&6)).take(4).collect::<Vec<_>>(),
+                   vec![15, 16, 17, 12]);
+    }
+
+    #[test]
+    fn btree_range_index_histogram() {
+        use super::RangeIndex;
+        use std::ops::Bound::{Excluded, Included, Unbounded};
+
+        // 50 rows with the value 0, and one row for each of 1 through 50
+        let mut idx = BTreeIndex::new();
+        for row in 0..100usize {
+            idx.index(row.saturating_sub(49), row);
+        }
+        assert!(idx.histogram().is_none());
+        assert_eq!(idx.estimate_range(Unbounded, Included(&0)), idx.estimate());
+
+        let counts = idx.map.iter().map(|(key, rows)| (key, rows.len()));
+        assert!(idx.set_histogram(Histogram::new(counts, 10)));
+        let histogram = idx.histogram().unwrap();
+        assert_eq!(histogram.rows(), 100);
+        // the frequent value gets a bucket of its own
+        assert_eq!(histogram.len(), 6);
+        assert_eq!(idx.estimate_range(Unbounded, Unbounded), 100);
+        assert_eq!(idx.estimate_range(Included(&0), Included(&0)), 50);
+        assert_eq!(idx.estimate_range(Excluded(&0), Included(&50)), 50);
+        assert_eq!(idx.estimate_range(Included(&41), Unbounded), 10);
+        // a bucket partly within the range counts for half its rows
+        assert_eq!(idx.estimate_range(Included(&45), Unbounded), 5);
+        assert_eq!(idx.estimate_range(Excluded(&50), Unbounded), 0);
+
+        idx.clear();
+        assert!(idx.histogram().is_none());
+        assert!(Histogram::<usize>::new(None, 10).is_empty());
+    }
+
+    #[test]
+    fn btree_range_index_order() {
+        use super::RangeIndex;
+        use std::ops::Bound::Unbounded;
+
+        let mut idx = BTreeIndex::new();
+        idx.index("b", 3);
+        idx.index("a", 0);
+        idx.index("b", 1);
+        idx.index("c", 2);
+        assert_eq!(idx.between(Unbounded, Unbounded).collect::<Vec<_>>(),
+                   vec![0, 1, 3, 2]);
+        assert_eq!(idx.between_rev(Unbounded, Unbounded).collect::<Vec<_>>(),
+                   vec![2, 1, 3, 0]);
+    }
 }
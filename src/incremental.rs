@@ -0,0 +1,233 @@
+use EqualityIndex;
+use Index;
+use IndexKind;
+use Row;
+use Store;
+use next_generation;
+
+/// An index that is being built a few rows at a time, as started by `Store::index_incremental`.
+pub(crate) struct Build<T> {
+    id: usize,
+    column: usize,
+    index: Index<T>,
+    chunk: usize,
+    /// Every row with an identifier below this has been added to `index`, and no other row has.
+    next: usize,
+}
+
+impl<T: Ord + Clone> Build<T> {
+    /// Returns the column the index is being built on.
+    pub(crate) fn column(&self) -> usize {
+        self.column
+    }
+
+    /// Returns the kind of the index being built, if it is a built-in one.
+    pub(crate) fn kind(&self) -> Option<IndexKind> {
+        self.index.kind()
+    }
+
+    /// Add the given row to the index, if the build has already passed it.
+    pub(crate) fn add<R: Row<T>>(&mut self, rowid: usize, row: &R) {
+        if rowid < self.next {
+            self.index.index(row.index(self.column).clone(), rowid);
+        }
+    }
+
+    /// Remove the given row from the index, if the build has already passed it.
+    pub(crate) fn remove<R: Row<T>>(&mut self, rowid: usize, row: &R) {
+        if rowid < self.next {
+            self.index.undex(row.index(self.column), rowid);
+        }
+    }
+}
+
+/// A handle to an index that is being built a few rows at a time. See
+/// `Store::index_incremental`.
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct IndexBuild {
+    id: usize,
+    column: usize,
+}
+
+impl IndexBuild {
+    /// Returns the column the index is being built on.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    /// Add the next chunk of rows to the index, and return true if that completed it, in which
+    /// case the index has been added to the given `Store` just as with `Store::index`. Returns
+    /// true without doing anything if the build has already completed, or has been cancelled.
+    ///
+    /// Rows inserted since the build started are added in later chunks, so the build completes
+    /// once it has caught up with the rows in the `Store`.
+    pub fn step<T, R>(&self, store: &mut Store<T, R>) -> bool
+        where T: Ord + Clone,
+              R: Row<T>
+    {
+        // rows queued by lazy index maintenance are added to the index when they are flushed
+        let end = store.pending.unwrap_or(store.rowid);
+        let build = match store.builds.iter_mut().find(|build| build.id == self.id) {
+            Some(build) => build,
+            None => return true,
+        };
+        let rows = store.rows
+            .range(build.next..end)
+            .take(build.chunk)
+            .map(|(&rowid, row)| (row.index(build.column).clone(), rowid))
+            .collect::<Vec<_>>();
+        build.next = match rows.last() {
+            Some(&(_, rowid)) if rows.len() == build.chunk => rowid + 1,
+            _ => end,
+        };
+        build.index.index_many(rows);
+        if build.next < end {
+            return false;
+        }
+
+        let i = store.builds.iter().position(|build| build.id == self.id).unwrap();
+        let build = store.builds.remove(i);
+        store.adopt_auto_indices();
+        store.indices.insert(build.column, build.index);
+        store.generation = next_generation();
+        true
+    }
+
+    /// Add all remaining rows to the index, and add the index to the given `Store`. Does nothing
+    /// if the build has already completed, or has been cancelled.
+    pub fn finish<T, R>(self, store: &mut Store<T, R>)
+        where T: Ord + Clone,
+              R: Row<T>
+    {
+        while !self.step(store) {}
+    }
+
+    /// Stop building the index, and return it, holding only the rows added to it so far. Returns
+    /// `None` if the build has already completed, or has been cancelled.
+    pub fn cancel<T, R>(self, store: &mut Store<T, R>) -> Option<Index<T>> {
+        let i = store.builds.iter().position(|build| build.id == self.id)?;
+        Some(store.builds.remove(i).index)
+    }
+}
+
+impl<T, R> Store<T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Start building an index on the given column without blocking for as long as `index`
+    /// would on a `Store` with many rows. The returned handle adds `chunk` rows to the index every
+    /// time its `step` is called, so that a caller can interleave building the index with other
+    /// work, including modifying the `Store`.
+    ///
+    /// The planner ignores the index until the build completes, at which point it is added to the
+    /// `Store` just as with `index`. Until then, the rows that have already been added to the
+    /// index are kept up to date as they are updated or deleted, and rows inserted while the
+    /// index is being built are added by later steps. Methods that rebuild the indices or remove
+    /// every row (`clear`, `compact`, `rows_mut_unindexed`, and `reindex`) first complete every
+    /// build. A build is not carried over to a clone of the `Store`, which instead gets the whole
+    /// index right away if it is a built-in one.
+    ///
+    /// Panics if the column does not exist, if `chunk` is zero, or if the index is unique, since
+    /// rows inserted while the index is being built could not be checked against it.
+    pub fn index_incremental<I>(&mut self, column: usize, indexer: I, chunk: usize) -> IndexBuild
+        where I: Into<Index<T>>
+    {
+        assert!(column < self.cols,
+                "column {} is out of range for a store with {} columns",
+                column,
+                self.cols);
+        assert!(chunk > 0, "an index must be built at least one row at a time");
+        let index = indexer.into();
+        assert!(!index.is_unique(), "a unique index cannot be built incrementally");
+
+        let id = self.next_build;
+        self.next_build += 1;
+        self.builds.push(Build {
+            id,
+            column,
+            index,
+            chunk,
+            next: 0,
+        });
+        IndexBuild { id, column }
+    }
+
+    /// Returns the columns of the indices that are still being built with `index_incremental`,
+    /// in the order the builds were started.
+    pub fn building_columns(&self) -> impl Iterator<Item = usize> + '_ {
+        self.builds.iter().map(|build| build.column)
+    }
+
+    /// Complete every index started with `index_incremental`.
+    pub(crate) fn complete_builds(&mut self) {
+        while let Some(&Build { id, column, .. }) = self.builds.first() {
+            IndexBuild { id, column }.finish(self);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cmp::Condition;
+    use idx::{BTreeIndex, HashIndex};
+    use query::Access;
+
+    #[test]
+    fn it_builds_incrementally() {
+        let mut store = Store::new(2);
+        store.insert_many((0..100usize).map(|i| vec![i % 10, i]));
+        let build = store.index_incremental(0, HashIndex::new(), 30);
+        assert_eq!(build.column(), 0);
+        let conds = [Condition::eq(0, 3usize)];
+
+        // the planner ignores the index until it is complete
+        assert!(!build.step(&mut store));
+        assert_eq!(store.explain(&conds).access, Access::Scan);
+        assert_eq!(store.building_columns().collect::<Vec<_>>(), vec![0]);
+
+        // rows inserted, updated, and deleted during the build are all accounted for
+        store.insert_many((100..150usize).map(|i| vec![i % 10, i]));
+        assert_eq!(store.update(&[Condition::eq(1, 13usize)], |row| row[0] = 4), 1);
+        assert_eq!(store.delete(&[Condition::eq(1, 23usize)]), 1);
+        assert!(!build.step(&mut store));
+        assert_eq!(store.update(&[Condition::eq(1, 3usize)], |row| row[0] = 5), 1);
+        store.insert(vec![3, 150]);
+        let mut steps = 2;
+        while !build.step(&mut store) {
+            steps += 1;
+            store.insert(vec![3, 150 + steps]);
+        }
+        assert_eq!(steps, 5);
+        assert!(build.step(&mut store));
+
+        assert_eq!(store.explain(&conds).access, Access::Index(0));
+        assert_eq!(store.building_columns().count(), 0);
+        assert_eq!(store.count(&conds), 16);
+        assert_eq!(store.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn it_completes_builds_before_rebuilding() {
+        let mut store = Store::new(2);
+        store.insert_many((0..50usize).map(|i| vec![i % 5, i]));
+        let first = store.index_incremental(0, HashIndex::new(), 10);
+        let second = store.index_incremental(1, BTreeIndex::new(), 10);
+        assert!(!first.step(&mut store));
+        store.mark_deleted(3);
+        store.compact();
+        assert_eq!(store.building_columns().count(), 0);
+        assert!(first.step(&mut store));
+        assert!(second.cancel(&mut store).is_none());
+        assert_eq!(store.explain(&[Condition::eq(1, 4usize)]).access, Access::Index(1));
+        assert_eq!(store.count(&[Condition::eq(0, 3usize)]), 9);
+        assert_eq!(store.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot be built incrementally")]
+    fn it_rejects_unique_indices() {
+        let mut store = Store::<usize>::new(2);
+        store.index_incremental(0, HashIndex::unique(), 10);
+    }
+}
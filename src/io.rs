@@ -0,0 +1,213 @@
+use csv;
+
+use std::error::Error;
+use std::fmt;
+use std::io::{Read, Write};
+
+use Row;
+use Store;
+
+/// The error returned when a `Store` cannot be read from CSV.
+#[derive(Debug)]
+pub enum CsvError<E> {
+    /// The underlying CSV reader failed, either because of an I/O error or because the input is
+    /// not valid CSV.
+    Csv(csv::Error),
+
+    /// A record does not have the same number of fields as the first one.
+    Ragged {
+        /// The line the offending record starts on.
+        line: u64,
+        /// The number of fields in the first record (or header).
+        expected: usize,
+        /// The number of fields in the offending record.
+        got: usize,
+    },
+
+    /// A field could not be parsed into a value.
+    Parse {
+        /// The line the offending record starts on.
+        line: u64,
+        /// The column of the offending field.
+        column: usize,
+        /// The error returned by the parse function.
+        error: E,
+    },
+}
+
+impl<E> From<csv::Error> for CsvError<E> {
+    fn from(e: csv::Error) -> Self {
+        CsvError::Csv(e)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for CsvError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CsvError::Csv(ref e) => write!(f, "{}", e),
+            CsvError::Ragged { line, expected, got } => {
+                write!(f,
+                       "record on line {} has {} fields, but the first record has {}",
+                       line,
+                       got,
+                       expected)
+            }
+            CsvError::Parse { line, column, ref error } => {
+                write!(f,
+                       "could not parse column {} on line {}: {}",
+                       column,
+                       line,
+                       error)
+            }
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> Error for CsvError<E> {}
+
+impl<T: Ord + Clone> Store<T> {
+    /// Construct a new `Store` from CSV data, using `parse` to turn each field into a value.
+    ///
+    /// The input is not expected to have a header; the number of columns is taken from the first
+    /// record. Every later record must have the same number of fields, or `CsvError::Ragged` is
+    /// returned. Use `from_csv_reader` to read input with a header or a different delimiter.
+    pub fn from_csv<Rd, E, F>(reader: Rd, parse: F) -> Result<Store<T>, CsvError<E>>
+        where Rd: Read,
+              F: Fn(&str) -> Result<T, E>
+    {
+        let reader = csv::ReaderBuilder::new()
+                         .has_headers(false)
+                         .flexible(true)
+                         .from_reader(reader);
+        Store::from_csv_reader(reader, parse)
+    }
+
+    /// Construct a new `Store` from a configured CSV reader, using `parse` to turn each field into
+    /// a value.
+    ///
+    /// If the reader expects a header, the number of columns is taken from the header, and the
+    /// header itself is not stored. Otherwise, the number of columns is taken from the first
+    /// record. Note that ragged records are only reported as `CsvError::Ragged` if the reader is
+    /// `flexible`; otherwise the reader itself will reject them.
+    pub fn from_csv_reader<Rd, E, F>(mut reader: csv::Reader<Rd>,
+                                     parse: F)
+                                     -> Result<Store<T>, CsvError<E>>
+        where Rd: Read,
+              F: Fn(&str) -> Result<T, E>
+    {
+        let mut store = if reader.has_headers() {
+            Some(Store::new(reader.headers()?.len()))
+        } else {
+            None
+        };
+
+        let mut record = csv::StringRecord::new();
+        while reader.read_record(&mut record)? {
+            let line = record.position().map(|p| p.line()).unwrap_or(0);
+            let store = store.get_or_insert_with(|| Store::new(record.len()));
+            if record.len() != store.cols {
+                return Err(CsvError::Ragged {
+                    line,
+                    expected: store.cols,
+                    got: record.len(),
+                });
+            }
+
+            let row = record.iter()
+                            .enumerate()
+                            .map(|(column, field)| {
+                                parse(field).map_err(|error| {
+                                    CsvError::Parse {
+                                        line,
+                                        column,
+                                        error,
+                                    }
+                                })
+                            })
+                            .collect::<Result<Vec<_>, _>>()?;
+            store.insert(row);
+        }
+
+        Ok(store.unwrap_or_else(|| Store::new(0)))
+    }
+}
+
+impl<T, R: Row<T>> Store<T, R> {
+    /// Write all rows in this `Store` as CSV, using `format` to turn each value into a field.
+    ///
+    /// Rows are written in the order they were inserted, with one record per row and no header.
+    /// Fields are quoted as necessary, so values may freely contain delimiters, quotes, and
+    /// newlines.
+    pub fn to_csv<W, F>(&self, writer: W, format: F) -> Result<(), csv::Error>
+        where W: Write,
+              F: Fn(&T) -> String
+    {
+        let mut writer = csv::Writer::from_writer(writer);
+        for row in self {
+            writer.write_record((0..self.cols).map(|col| format(row.index(col))))?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cmp;
+
+    fn parse(s: &str) -> Result<String, ()> {
+        Ok(s.to_owned())
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut store = Store::new(3);
+        store.insert(vec!["a".to_string(), "b, c".to_string(), "plain".to_string()]);
+        store.insert(vec!["\"quoted\"".to_string(), "".to_string(), "multi\nline".to_string()]);
+
+        let mut out = Vec::new();
+        store.to_csv(&mut out, String::clone).unwrap();
+        let back = Store::from_csv(&out[..], parse).unwrap();
+
+        assert_eq!(back.find(&[]).collect::<Vec<_>>(),
+                   store.find(&[]).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn parse_values() {
+        let store = Store::from_csv(&b"1,2\n3,4\n"[..], |s| s.parse::<usize>()).unwrap();
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(3usize)),
+                   }];
+        assert_eq!(store.find(&cmp).collect::<Vec<_>>(), vec![&vec![3, 4]]);
+
+        match Store::from_csv(&b"1,2\n3,x\n"[..], |s| s.parse::<usize>()) {
+            Err(CsvError::Parse { line: 2, column: 1, .. }) => {}
+            r => panic!("expected a parse error, got {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn header() {
+        let reader = csv::Reader::from_reader(&b"x,y\n1,2\n"[..]);
+        let store = Store::from_csv_reader(reader, parse).unwrap();
+        assert_eq!(store.find(&[]).collect::<Vec<_>>(),
+                   vec![&vec!["1".to_string(), "2".to_string()]]);
+    }
+
+    #[test]
+    fn ragged() {
+        match Store::from_csv(&b"a,b\nc,d\ne\n"[..], parse) {
+            Err(CsvError::Ragged { line: 3, expected: 2, got: 1 }) => {}
+            r => panic!("expected a ragged error, got {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn empty() {
+        let store = Store::from_csv(&b""[..], parse).unwrap();
+        assert_eq!(store.find(&[]).count(), 0);
+    }
+}
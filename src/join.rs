@@ -0,0 +1,115 @@
+use std::collections::BTreeMap;
+
+use EqualityIndex;
+use Row;
+use Store;
+
+/// How to find the rows in one side of a join that have a given value in the joined column.
+enum Probe<'a, T: 'a, R: 'a> {
+    /// Look the value up in the index on the column.
+    Index(&'a Store<T, R>, usize),
+    /// Look the value up in a map from every value in the column to the rows that have it.
+    Map(BTreeMap<&'a T, Vec<&'a R>>),
+}
+
+impl<'a, T, R> Probe<'a, T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Use the index on the given column if there is one, and build a map of the column
+    /// otherwise.
+    fn new(store: &'a Store<T, R>, col: usize) -> Self {
+        if store.complete_index(col).is_some() {
+            return Probe::Index(store, col);
+        }
+        let mut map: BTreeMap<_, Vec<_>> = BTreeMap::new();
+        for row in store.rows() {
+            map.entry(row.index(col)).or_default().push(row);
+        }
+        Probe::Map(map)
+    }
+
+    /// Returns all the rows with the given value in the joined column.
+    fn lookup(&self, value: &T) -> Vec<&'a R> {
+        match *self {
+            Probe::Index(store, col) => {
+                store.live(store.indices[&col].lookup(value))
+                    .map(|rowid| &store.rows[&rowid])
+                    .collect()
+            }
+            Probe::Map(ref map) => map.get(value).cloned().unwrap_or_default(),
+        }
+    }
+}
+
+/// Returns an iterator that yields every pair of a row in `left` and a row in `right` where the
+/// value in column `left_col` of the left row is equal to the value in column `right_col` of the
+/// right row. Rows with no partner in the other `Store` are not yielded (this is an inner join;
+/// see `left_join` for an alternative).
+///
+/// If `right_col` has an index, every left row is looked up in that index. Otherwise, if
+/// `left_col` has an index, every right row is looked up in that index instead. If neither column
+/// has an index, a temporary map of the values in `right_col` is built before the first pair is
+/// yielded, and every left row is looked up in it.
+///
+/// Pairs are yielded in the order of the rows that are looked up, so the order depends on which
+/// index is used. Panics if either column is out of range for its `Store`.
+pub fn join<'a, T, L, R>(left: &'a Store<T, L>,
+                         left_col: usize,
+                         right: &'a Store<T, R>,
+                         right_col: usize)
+                         -> Box<dyn Iterator<Item = (&'a L, &'a R)> + 'a>
+    where T: Ord + Clone,
+          L: Row<T>,
+          R: Row<T>
+{
+    check(left, left_col, right, right_col);
+    if right.complete_index(right_col).is_some() || left.complete_index(left_col).is_none() {
+        let probe = Probe::new(right, right_col);
+        Box::new(left.rows().flat_map(move |l| {
+            probe.lookup(l.index(left_col)).into_iter().map(move |r| (l, r))
+        }))
+    } else {
+        let probe = Probe::new(left, left_col);
+        Box::new(right.rows().flat_map(move |r| {
+            probe.lookup(r.index(right_col)).into_iter().map(move |l| (l, r))
+        }))
+    }
+}
+
+/// Like `join`, but also yields every row in `left` that has no partner in `right`, paired with
+/// `None`. Pairs are always yielded in the order of the left rows.
+///
+/// If `right_col` has an index, every left row is looked up in that index. Otherwise, a
+/// temporary map of the values in `right_col` is built before the first pair is yielded. Panics
+/// if either column is out of range for its `Store`.
+pub fn left_join<'a, T, L, R>(left: &'a Store<T, L>,
+                              left_col: usize,
+                              right: &'a Store<T, R>,
+                              right_col: usize)
+                              -> Box<dyn Iterator<Item = (&'a L, Option<&'a R>)> + 'a>
+    where T: Ord + Clone,
+          L: Row<T>,
+          R: Row<T>
+{
+    check(left, left_col, right, right_col);
+    let probe = Probe::new(right, right_col);
+    Box::new(left.rows().flat_map(move |l| {
+        let partners = probe.lookup(l.index(left_col));
+        if partners.is_empty() {
+            vec![(l, None)]
+        } else {
+            partners.into_iter().map(|r| (l, Some(r))).collect()
+        }
+    }))
+}
+
+/// Panic with a helpful message if either joined column is out of range for its `Store`.
+fn check<T, L, R>(left: &Store<T, L>, left_col: usize, right: &Store<T, R>, right_col: usize) {
+    for &(col, cols) in &[(left_col, left.cols), (right_col, right.cols)] {
+        assert!(col < cols,
+                "joined column {} is out of range for a store with {} columns",
+                col,
+                cols);
+    }
+}
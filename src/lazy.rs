@@ -0,0 +1,221 @@
+use std::collections::btree_map;
+
+use Index;
+use Row;
+use Store;
+use next_generation;
+
+/// How a `Store` keeps its indices up to date as rows are inserted. See
+/// `Store::set_index_maintenance`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum IndexMaintenance {
+    /// Every inserted row is added to all indices right away. This is the default.
+    #[default]
+    Eager,
+    /// Inserted rows are only added to the indices once the indices are needed for something
+    /// other than a query, or when `Store::flush_indices` is called.
+    Lazy,
+}
+
+impl<T, R> Store<T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Choose how the indices are kept up to date as rows are inserted.
+    ///
+    /// With `IndexMaintenance::Lazy`, `insert` and `insert_many` only add rows to the `Store`, and
+    /// queue them to be added to the indices and views later. The queued rows are added to every
+    /// index in a single pass by `flush_indices`, which is also called by every method that
+    /// modifies the indices in some other way, such as `delete`, `update`, `upsert`, and adding an
+    /// index. This suits workloads of bursts of insertions followed by bursts of queries, since
+    /// each index is then updated once per burst rather than once per row.
+    ///
+    /// Queries only borrow the `Store`, and so cannot flush the queue themselves, but still find
+    /// the queued rows: whichever index a query uses, every queued row is also checked against
+    /// all of its conditions, and `count`, `exists`, and the aggregates do not answer from an
+    /// index alone. Since every query thus also scans the queue, `flush_indices` should be called
+    /// at the end of every burst of insertions. A row inserted while rows are queued is also
+    /// checked against each of them in every column with a unique index.
+    ///
+    /// Switching back to `IndexMaintenance::Eager` flushes the queue.
+    pub fn set_index_maintenance(&mut self, maintenance: IndexMaintenance) {
+        match maintenance {
+            IndexMaintenance::Eager => {
+                self.flush_indices();
+                self.pending = None;
+            }
+            IndexMaintenance::Lazy => {
+                self.pending.get_or_insert(self.rowid);
+            }
+        }
+    }
+
+    /// Returns how the indices are kept up to date as rows are inserted. See
+    /// `set_index_maintenance`.
+    pub fn index_maintenance(&self) -> IndexMaintenance {
+        match self.pending {
+            Some(_) => IndexMaintenance::Lazy,
+            None => IndexMaintenance::Eager,
+        }
+    }
+
+    /// Add every row queued by an insertion (see `set_index_maintenance`) to all indices and
+    /// views, and empty the queue. Does nothing if the queue is empty, so this can be called as
+    /// often as is convenient.
+    pub fn flush_indices(&mut self) {
+        if let Some(start) = self.pending {
+            if start < self.rowid {
+                self.index_rows_from(start);
+                self.mark_indexed();
+                self.generation = next_generation();
+            }
+        }
+    }
+
+    /// Returns the number of rows queued to be added to the indices by `flush_indices`.
+    pub fn pending_rows(&self) -> usize {
+        self.unindexed().count()
+    }
+
+    /// Prepare for the given row to be inserted without being added to the indices, so that it is
+    /// instead added by `flush_indices`. Index maintenance must be lazy.
+    pub(crate) fn queue(&mut self, row: &R) {
+        if self.unindexed().next().is_none() {
+            // plans made while every row was indexed rely on the indices holding every row
+            self.generation = next_generation();
+        }
+        self.invalidate_cached(row);
+    }
+
+    /// Returns true if the row with the given identifier is queued to be added to the indices.
+    pub(crate) fn is_queued(&self, rowid: usize) -> bool {
+        self.pending.is_some_and(|start| rowid >= start)
+    }
+
+    /// Record that every row in the `Store` has been added to the indices, so that only rows
+    /// inserted from now on are queued.
+    pub(crate) fn mark_indexed(&mut self) {
+        if let Some(ref mut start) = self.pending {
+            *start = self.rowid;
+        }
+    }
+
+    /// Returns the rows that are queued to be added to the indices, in the order they were
+    /// inserted. Rows that have been marked as deleted are included.
+    pub(crate) fn unindexed(&self) -> btree_map::Range<'_, usize, R> {
+        self.rows.range(self.pending.unwrap_or(self.rowid)..)
+    }
+
+    /// Returns the rows that have been added to the indices, in the order they were inserted.
+    /// Rows that have been marked as deleted are included.
+    pub(crate) fn indexed_rows(&self) -> btree_map::Range<'_, usize, R> {
+        self.rows.range(..self.pending.unwrap_or(self.rowid))
+    }
+
+    /// Returns the index on the given column, unless rows are queued to be added to the indices,
+    /// in which case no index holds every row, and so none can answer a query on its own.
+    ///
+    /// Panics if the indices are out of date (see `rows_mut_unindexed`).
+    pub(crate) fn complete_index(&self, column: usize) -> Option<&Index<T>> {
+        self.check_indexed();
+        match self.unindexed().next() {
+            Some(_) => None,
+            None => self.indices.get(&column),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cmp::Condition;
+    use idx::{BTreeIndex, HashIndex};
+    use query::Access;
+
+    fn store() -> Store<usize> {
+        let mut store = Store::new(3);
+        store.index(0, HashIndex::new());
+        store.index(1, BTreeIndex::new());
+        store.set_index_maintenance(IndexMaintenance::Lazy);
+        store
+    }
+
+    #[test]
+    fn it_finds_queued_rows() {
+        let mut store = store();
+        for i in 0..100usize {
+            store.insert(vec![i % 10, i, 0]);
+        }
+        store.flush_indices();
+        assert_eq!(store.pending_rows(), 0);
+        let view = store.materialize(vec![Condition::eq(0, 7usize)]);
+
+        // a burst of insertions is queued, but can be found through the indices all the same
+        for i in 100..150usize {
+            store.insert(vec![i % 10, i, 1]);
+        }
+        assert_eq!(store.pending_rows(), 50);
+        let conds = [Condition::eq(0, 3usize)];
+        assert_eq!(store.explain(&conds).access, Access::Index(0));
+        assert_eq!(store.explain(&conds).filters, vec![0]);
+        assert_eq!(store.find(&conds).count(), 15);
+        assert_eq!(store.count(&conds), 15);
+        assert!(store.exists(&[Condition::eq(0, 3usize), Condition::eq(2, 1usize)]));
+        let range = [Condition::gt(1, 95usize), Condition::lt(1, 105usize)];
+        assert_eq!(store.find(&range).map(|row| row[1]).collect::<Vec<_>>(),
+                   vec![96, 97, 98, 99, 100, 101, 102, 103, 104]);
+        assert_eq!(store.aggregate(&[], 1).max, Some(&149));
+        assert_eq!(store.view(view).count(), 15);
+        assert_eq!(store.check_integrity(), Ok(()));
+
+        // flushing adds the queued rows to the indices, and is idempotent
+        store.flush_indices();
+        store.flush_indices();
+        assert_eq!(store.pending_rows(), 0);
+        assert_eq!(store.explain(&conds).filters, Vec::<usize>::new());
+        assert_eq!(store.find(&conds).count(), 15);
+        assert_eq!(store.count(&conds), 15);
+        assert_eq!(store.find(&range).count(), 9);
+        assert_eq!(store.view(view).count(), 15);
+        assert_eq!(store.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn it_flushes_before_modifying() {
+        let mut store = store();
+        store.insert_many((0..20usize).map(|i| vec![i % 4, i, 0]));
+        assert_eq!(store.pending_rows(), 20);
+
+        // deleting queued rows first adds them to the indices, so they can be removed again
+        assert_eq!(store.delete(&[Condition::eq(0, 1usize)]), 5);
+        assert_eq!(store.pending_rows(), 0);
+        store.insert(vec![1, 20, 0]);
+        assert_eq!(store.update(&[Condition::eq(1, 20usize)], |row| row[0] = 2), 1);
+        assert_eq!(store.count(&[Condition::eq(0, 2usize)]), 6);
+        assert_eq!(store.check_integrity(), Ok(()));
+
+        // an index added while rows are queued holds them, without them being added twice
+        store.insert(vec![3, 21, 0]);
+        store.index(2, HashIndex::new());
+        store.flush_indices();
+        assert_eq!(store.count(&[Condition::eq(2, 0usize)]), 17);
+        assert_eq!(store.check_integrity(), Ok(()));
+
+        store.insert(vec![3, 22, 0]);
+        store.set_index_maintenance(IndexMaintenance::Eager);
+        assert_eq!(store.pending_rows(), 0);
+        assert_eq!(store.index_maintenance(), IndexMaintenance::Eager);
+        assert_eq!(store.count(&[Condition::eq(0, 3usize)]), 7);
+        assert_eq!(store.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    #[should_panic(expected = "conflicts with row 1")]
+    fn it_checks_queued_rows_for_conflicts() {
+        let mut store = Store::with_key(2, 0);
+        store.set_index_maintenance(IndexMaintenance::Lazy);
+        store.insert(vec![1usize, 1]);
+        store.insert(vec![2, 2]);
+        store.insert(vec![2, 3]);
+    }
+}
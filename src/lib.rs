@@ -15,7 +15,8 @@
 //! speeding up exact lookups, whereas the latter can also perform efficient range queries.
 //!
 //! Queries are performed over the dataset by calling `find` with a set of `Condition`s that will
-//! be `AND`ed together. `OR` is currently not supported --- issue multiple quieries instead. Each
+//! be `AND`ed together. To express `OR`, use `find_any`, which takes several such sets and
+//! returns the rows matching any one of them. Each
 //! `Condition` represents a value comparison against the value in a single column. The system
 //! automatically picks what index to use to satisfy the query, using a heuristic based on the
 //! expected number of rows returned for that column for each index.
@@ -23,12 +24,36 @@
 //! # Known limitations
 //!
 //!  - The set of match operations is currently fairly limited.
-//!  - The system currently provides an add/remove-only abstraction (i.e., no edit).
 
 #![deny(missing_docs)]
 
-use std::collections::HashMap;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "csv")]
+extern crate csv;
+#[cfg(feature = "regex")]
+extern crate regex;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "arrow")]
+extern crate arrow as arrow_crate;
+#[cfg(feature = "rand")]
+extern crate rand;
+#[cfg(all(feature = "libc", unix))]
+extern crate libc;
+
+use std::borrow::Borrow;
+use std::borrow::Cow;
 use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::btree_map;
+use std::fmt;
+use std::hash::Hash;
+use std::iter;
+use std::mem;
+use std::ops;
+use std::ops::Bound;
 
 /// The `cmp` module holds the mechanisms needed to compare values and express conditionals.
 pub mod cmp;
@@ -36,27 +61,398 @@ pub use cmp::Comparison;
 pub use cmp::Condition;
 pub use cmp::Value;
 
+/// The `query` module holds `Query`, which allows additional options to be given for a `find`,
+/// `Find`, which holds the rows found by `find`, `Collected`, which holds the rows of a `Query`
+/// that may have been cut short, `PreparedQuery`,
+/// which is executed many times with different constants, `Snapshot`, which gives a stable view
+/// of a `Store` while rows are inserted, and `QueryPlan`, which describes how a `find` is
+/// executed.
+pub mod query;
+pub use query::Collected;
+pub use query::Find;
+pub use query::PreparedQuery;
+pub use query::Query;
+pub use query::QueryPlan;
+pub use query::Snapshot;
+
+/// The `error` module holds the errors that may be returned by fallible `Store` operations.
+pub mod error;
+pub use error::Error;
+pub use error::InsertError;
+pub use error::IntegrityError;
+pub use error::QueryError;
+
 /// The `idx` module described the traits indexers must adhere to, and implements sensible default
 /// indexers.
 pub mod idx;
 pub use idx::EqualityIndex;
+pub use idx::IndexKind;
+pub use idx::Histogram;
+pub use idx::IndexStats;
 pub use idx::RangeIndex;
 pub use idx::Index;
 
+/// The `agg` module holds `Aggregate`, which summarizes the values in a column of the rows
+/// matching a set of conditions, along with the other ways of summarizing rows by column.
+pub mod agg;
+pub use agg::Aggregate;
+pub use agg::ColumnSummary;
+
+/// The `stats` module holds `QueryStats`, which counts how the queries made against a `Store` were
+/// answered, so that unused indices and often scanned columns can be found, `CacheStats`,
+/// which counts how often the query cache was used, and `QuerySummary`, which describes a single
+/// query to the observer set with `Store::set_query_observer`.
+pub mod stats;
+pub use stats::QueryStats;
+pub use stats::QuerySummary;
+
+/// The `join` module allows the rows of two `Store`s to be paired up by the values in one column
+/// of each.
+pub mod join;
+pub use join::join;
+pub use join::left_join;
+
+/// The `txn` module holds `Txn`, which groups insertions so that either all or none of them
+/// take effect.
+pub mod txn;
+pub use txn::Txn;
+
+/// The `bulk` module holds `BulkLoader`, which adds many rows to a `Store` while deferring index
+/// maintenance until all of them have been added.
+pub mod bulk;
+pub use bulk::BulkLoader;
+
+/// The `lazy` module holds `IndexMaintenance`, which chooses whether inserted rows are added to the
+/// indices right away, or queued until the indices are next needed (see
+/// `Store::set_index_maintenance`).
+pub mod lazy;
+pub use lazy::IndexMaintenance;
+
+/// The `incremental` module holds `IndexBuild`, a handle to an index that is built a few rows at a
+/// time (see `Store::index_incremental`).
+pub mod incremental;
+pub use incremental::IndexBuild;
+
+/// The `shared` module holds `SyncStore`, which allows a `Store` to be queried by many threads
+/// while another thread inserts rows.
+pub mod shared;
+pub use shared::SyncStore;
+
+/// The `builder` module holds `StoreBuilder`, which sets up a `Store` along with its indices in a
+/// single expression.
+pub mod builder;
+pub use builder::StoreBuilder;
+
+/// The `sharded` module holds `ShardedStore`, which spreads its rows over several `Store`s by the
+/// hash of a designated column, so that many threads can insert rows at the same time.
+pub mod sharded;
+pub use sharded::ShardedStore;
+
+/// The `oplog` module holds `Op`, which describes a single modification of a `Store`, so that the
+/// modifications made to one `Store` can be replayed on another (see `Store::attach_log`).
+pub mod oplog;
+pub use oplog::Op;
+
+/// The `io` module allows a `Store` to be read from and written to CSV. Values are converted to
+/// and from text by caller-provided closures.
+#[cfg(feature = "csv")]
+pub mod io;
+#[cfg(feature = "csv")]
+pub use io::CsvError;
+
+/// The `arrow` module allows the rows of a `Store` to be exported to and imported from Apache
+/// Arrow record batches. Values are converted to and from Arrow by caller-provided closures.
+#[cfg(feature = "arrow")]
+pub mod arrow;
+
+/// The `frozen` module holds `FrozenStore`, a read-only store whose rows are memory-mapped from a
+/// file written by `Store::write_frozen`, so that large datasets can be queried without first
+/// being read into memory.
+#[cfg(all(feature = "libc", unix))]
+pub mod frozen;
+#[cfg(all(feature = "libc", unix))]
+pub use frozen::FrozenStore;
+
+/// The `packed` module holds `PackedStore`, a read-only store whose indices are sorted arrays,
+/// which is made from a `Store` that will no longer be modified with `Store::freeze`.
+pub mod packed;
+pub use packed::PackedStore;
+
+#[cfg(feature = "serde")]
+mod ser;
+
+mod ttl;
+
+mod auto;
+
+mod cache;
+
+mod covering;
+
+mod ring;
+
+mod binary;
+
+#[cfg(feature = "rayon")]
+mod par;
+
+#[cfg(feature = "rand")]
+mod sample;
+
 /// A `Store` is the main storage unit in shortcut. It keeps track of all the rows of data, as well
 /// as what indices are available. You will generally be accessing the `Store` either through the
 /// `find` method (which lets you find rows that match a certain condition), or through the
 /// `insert` method, which lets you add another row.
 ///
+/// With the `serde` feature enabled, a `Store` can be serialized and deserialized. Only the rows
+/// and a description of the built-in indices (see `IndexKind`) are serialized; the indices are
+/// re-created from the rows when the `Store` is deserialized. Custom indices are not preserved.
+///
+/// A `Store` is `Send` and `Sync` whenever `T` and the row type are. Indices, observers, and views
+/// are all required to be `Send + Sync`, so they never prevent a `Store` from being shared
+/// between threads. To query a `Store` from several threads while another inserts into it, wrap
+/// it in a `SyncStore`.
+///
 /// Note that the type used for the rows needs to be `Clone`. This is because the value is also
 /// given to the index, which (currently) take a full value, not just a borrow. This *might* change
 /// down the line, but it's tricky to get the lifetimes to work out, because the indices would then
-/// be scoped by the lifetime of the `Store`.
+/// be scoped by the lifetime of the `Store`. If values are expensive to clone, use a shared
+/// pointer such as `Arc<T>` as the value type instead (for example `Store<Arc<String>>`): values
+/// are compared, hashed, and ordered just as before, but the row and every index that holds a
+/// value then share a single copy of it, and cloning it only increments a reference count.
+///
+/// Rows are `Vec<T>` by default, but any type that implements `Row` can be used. Since rows never
+/// change width, `Box<[T]>` is a good choice for large stores: it holds the same values, but is a
+/// word smaller than a `Vec<T>` (16 rather than 24 bytes on 64-bit platforms), as it has no
+/// capacity. For a million rows of two `usize` columns, with no indices, a `Store` of `Box<[T]>`
+/// rows allocates 65 MB, where one of `Vec<T>` rows allocates 80 MB. A `Vec<T>` is converted into
+/// a boxed row by `insert_from`, or with `into_boxed_slice` (or `into`) before calling `insert`.
+/// If the number of columns is known at compile time, a fixed-size array `[T; N]` holds the
+/// values inline, with no separate allocation for each row. Values that are at hand as a slice
+/// can be inserted into a `Store` of `Vec<T>` or `Box<[T]>` rows with `insert_row`, and anything
+/// that converts into the row type, such as an array, with `insert_from`.
 pub struct Store<T, C = Vec<T>> {
     cols: usize,
     rowid: usize,
     rows: BTreeMap<usize, C>,
-    indices: HashMap<usize, Index<T>>,
+    indices: BTreeMap<usize, Index<T>>,
+    composite: Vec<(CompositeKey<T>, Index<Vec<T>>)>,
+    covering: BTreeMap<usize, covering::Covering<T, C>>,
+    key: Option<usize>,
+    expiry: Option<usize>,
+    tombstones: Vec<bool>,
+    dead: usize,
+    max_rows: Option<usize>,
+    observers: Vec<(ObserverId, Observer<C>)>,
+    next_observer: usize,
+    log: Option<oplog::Log<T>>,
+    views: Vec<(ViewId, View<C>)>,
+    next_view: usize,
+    dictionaries: BTreeMap<usize, Box<dyn Intern<C> + Send + Sync>>,
+    auto_index: Option<auto::AutoIndex<T>>,
+    stats: Option<stats::Collector>,
+    query_observer: Option<stats::QueryObserver>,
+    cache: Option<Box<dyn cache::Cache<T, C> + Send + Sync>>,
+    generation: usize,
+    dirty: bool,
+    pending: Option<usize>,
+    builds: Vec<incremental::Build<T>>,
+    next_build: usize,
+}
+
+/// A callback registered with `Store::on_insert`.
+type Observer<R> = Box<dyn FnMut(usize, &R) + Send + Sync>;
+
+/// Identifies a callback registered with `Store::on_insert`, so that it can later be removed with
+/// `Store::remove_observer`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ObserverId(usize);
+
+/// Identifies a view registered with `Store::materialize`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ViewId(usize);
+
+/// A view registered with `Store::materialize`: a check of whether a row matches the view's
+/// conditions, along with the identifiers of all rows that currently match them.
+struct View<R> {
+    matches: sync::Arc<dyn Fn(&R) -> bool + Send + Sync>,
+    rows: BTreeSet<usize>,
+}
+
+impl<R> Clone for View<R> {
+    fn clone(&self) -> Self {
+        View {
+            matches: self.matches.clone(),
+            rows: self.rows.clone(),
+        }
+    }
+}
+
+impl<R> View<R> {
+    /// Add the given row to the view if it matches the view's conditions, and remove it otherwise.
+    fn refresh(&mut self, rowid: usize, row: &R) {
+        if (self.matches)(row) {
+            self.rows.insert(rowid);
+        } else {
+            self.rows.remove(&rowid);
+        }
+    }
+}
+
+/// The distinct values of a column registered with `Store::dictionary_encode`.
+#[derive(Clone)]
+struct Dictionary<T> {
+    column: usize,
+    values: BTreeSet<T>,
+}
+
+/// Replaces a value in a row with an equal value held elsewhere. This is implemented by
+/// `Dictionary` for every row type whose values can be replaced, so that `Store` does not itself
+/// need to be able to modify its rows.
+trait Intern<R> {
+    /// Replace the value of the row with the equal value held in the dictionary, adding the value
+    /// to the dictionary if there is none.
+    fn intern(&mut self, row: &mut R);
+    /// Returns the number of distinct values in the dictionary.
+    fn len(&self) -> usize;
+    /// Returns a copy of the dictionary.
+    fn boxed_clone(&self) -> Box<dyn Intern<R> + Send + Sync>;
+}
+
+impl<T, R> Intern<R> for Dictionary<T>
+    where T: Ord + Clone + Send + Sync + 'static,
+          R: AsMut<[T]>
+{
+    fn intern(&mut self, row: &mut R) {
+        let value = &mut row.as_mut()[self.column];
+        match self.values.get(value) {
+            Some(interned) => *value = interned.clone(),
+            None => {
+                self.values.insert(value.clone());
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    fn boxed_clone(&self) -> Box<dyn Intern<R> + Send + Sync> {
+        Box::new(self.clone())
+    }
+}
+
+/// The source of `Store::generation`, which changes whenever an index is added or removed.
+/// Generations are unique across all stores, so that a `PreparedQuery` can also tell when it is
+/// executed against a different `Store` than the one it was last planned for.
+static GENERATION: sync::atomic::AtomicUsize = sync::atomic::AtomicUsize::new(0);
+
+/// Returns a generation that has never been used before.
+fn next_generation() -> usize {
+    GENERATION.fetch_add(1, sync::atomic::Ordering::Relaxed)
+}
+
+/// Indices expected to yield at most this many rows for a key are never intersected with other
+/// indices, since checking that many rows directly is cheap.
+const INTERSECT_MIN_ESTIMATE: usize = 8;
+
+/// Indices expected to yield more than this many times as many rows as the most selective index
+/// are not worth intersecting with it.
+const INTERSECT_MAX_RATIO: usize = 16;
+
+/// How `Store::plan` decided to find the candidate rows for a set of conditions. Conditions are
+/// referred to by their position, so that a `Source` chosen for one set of conditions can be
+/// reused for another set of the same shape (see `query::PreparedQuery`).
+#[derive(Clone)]
+enum Source {
+    /// The conditions contradict each other, so no rows can match.
+    Empty,
+    /// Every row has to be checked.
+    Scan,
+    /// Use the index on the column of the given condition.
+    Index(usize),
+    /// Intersect the rows found by looking up the constant of each of the given equality
+    /// conditions in the index on its column.
+    Intersect(Vec<usize>),
+    /// Look up a key in the index at the given position in `Store::composite`. The key is made up
+    /// of the constants of the given conditions.
+    Composite(usize, Vec<usize>),
+}
+
+/// The decision made by `Store::plan`.
+#[derive(Clone)]
+struct Plan {
+    source: Source,
+    /// The number of rows `source` is expected to yield, if it uses an index.
+    estimate: Option<usize>,
+    /// Which conditions are known to hold for every row yielded by `source`.
+    exact: Known,
+}
+
+/// Which of the conditions of a query are known to hold for every row yielded by the source of a
+/// `Plan`. Plans for a scan or a single lookup are described without allocating.
+#[derive(Clone)]
+enum Known {
+    /// None of the conditions.
+    Nothing,
+    /// Every condition.
+    Everything,
+    /// Only the condition at the given position.
+    Only(usize),
+    /// For each condition, whether it is known to hold.
+    Each(Vec<bool>),
+}
+
+impl Known {
+    /// Returns true if the condition at the given position is known to hold.
+    fn holds(&self, i: usize) -> bool {
+        match *self {
+            Known::Nothing => false,
+            Known::Everything => true,
+            Known::Only(j) => i == j,
+            Known::Each(ref exact) => exact[i],
+        }
+    }
+
+    /// Returns true if all of the given number of conditions are known to hold.
+    fn all(&self, conds: usize) -> bool {
+        match *self {
+            Known::Nothing => conds == 0,
+            Known::Everything => true,
+            Known::Only(..) => conds == 1,
+            Known::Each(ref exact) => exact.iter().all(|&exact| exact),
+        }
+    }
+}
+
+/// What the keys of an index in `Store::composite` are computed from.
+#[derive(Clone, Debug)]
+enum CompositeKey<T> {
+    /// The values of the given columns, in order (see `Store::index_multi`).
+    Columns(Vec<usize>),
+    /// The key computed from the value of a single column (see `Store::index_keyed`).
+    Computed(usize, cmp::KeyFn<T>),
+    /// The value of a single column, for rows where it is not null (see `Store::index_sparse`).
+    Sparse(usize, cmp::Null<T>),
+}
+
+impl<T> CompositeKey<T> {
+    /// Returns the columns the key is computed from, in order.
+    fn columns(&self) -> Vec<usize> {
+        match *self {
+            CompositeKey::Columns(ref columns) => columns.clone(),
+            CompositeKey::Computed(column, _) | CompositeKey::Sparse(column, _) => vec![column],
+        }
+    }
+
+    /// Returns the column reported for conflicts in a unique index with this key: the first of
+    /// the columns it is computed from.
+    fn column(&self) -> usize {
+        match *self {
+            CompositeKey::Columns(ref columns) => columns.first().cloned().unwrap_or(0),
+            CompositeKey::Computed(column, _) | CompositeKey::Sparse(column, _) => column,
+        }
+    }
 }
 
 /// Implementors of `Row` can be used to store the individual rows of a `Store`.
@@ -79,10 +475,80 @@ impl<T, R> Store<T, R>
     /// `insert` at runtime (bleh).
     pub fn new(cols: usize) -> Store<T, R> {
         Store {
-            cols: cols,
+            cols,
             rowid: 0,
             rows: BTreeMap::new(),
-            indices: HashMap::new(),
+            indices: BTreeMap::new(),
+            composite: Vec::new(),
+            covering: BTreeMap::new(),
+            key: None,
+            expiry: None,
+            tombstones: Vec::new(),
+            dead: 0,
+            max_rows: None,
+            observers: Vec::new(),
+            next_observer: 0,
+            log: None,
+            views: Vec::new(),
+            next_view: 0,
+            dictionaries: BTreeMap::new(),
+            auto_index: None,
+            stats: None,
+            query_observer: None,
+            cache: None,
+            generation: next_generation(),
+            dirty: false,
+            pending: None,
+            builds: Vec::new(),
+            next_build: 0,
+        }
+    }
+
+    /// Construct a new `Store` with the given number of columns that holds the given rows. The
+    /// rows are assigned identifiers in order, starting from 0, just as if they had been inserted
+    /// one at a time with `insert`.
+    ///
+    /// This is cheaper than calling `insert` in a loop, since the backing storage for the rows
+    /// can be built in bulk. Every row **must** have `cols` columns; if any row does not, the code
+    /// will panic.
+    pub fn from_rows(cols: usize, rows: Vec<R>) -> Store<T, R> {
+        let rows: BTreeMap<_, _> = rows.into_iter()
+                                       .enumerate()
+                                       .inspect(|&(rowid, ref row)| {
+                                           assert!(row.columns() == cols,
+                                                   "row {} has {} columns, but the store has {}",
+                                                   rowid,
+                                                   row.columns(),
+                                                   cols);
+                                       })
+                                       .collect();
+        Store {
+            cols,
+            rowid: rows.len(),
+            rows,
+            indices: BTreeMap::new(),
+            composite: Vec::new(),
+            covering: BTreeMap::new(),
+            key: None,
+            expiry: None,
+            tombstones: Vec::new(),
+            dead: 0,
+            max_rows: None,
+            observers: Vec::new(),
+            next_observer: 0,
+            log: None,
+            views: Vec::new(),
+            next_view: 0,
+            dictionaries: BTreeMap::new(),
+            auto_index: None,
+            stats: None,
+            query_observer: None,
+            cache: None,
+            generation: next_generation(),
+            dirty: false,
+            pending: None,
+            builds: Vec::new(),
+            next_build: 0,
         }
     }
 
@@ -97,336 +563,7051 @@ impl<T, R> Store<T, R>
     /// of scope.
     fn using_index<'c, 's: 'c>(&'s self,
                                conds: &'c [cmp::Condition<'c, T>])
-                               -> Box<Iterator<Item = usize> + 's> {
+                               -> Box<dyn Iterator<Item = usize> + 's> {
+        let plan = self.plan(conds);
+        self.rowids(conds, &plan.source)
+    }
 
-        use EqualityIndex;
-        let best_idx = conds.iter()
+    /// Like `using_index`, but returns `None` if no index can be used to satisfy the given
+    /// conditions (i.e., if all rows would have to be scanned).
+    fn best_index<'c, 's: 'c>(&'s self,
+                              conds: &'c [cmp::Condition<'c, T>])
+                              -> Option<Box<dyn Iterator<Item = usize> + 's>> {
+        match self.plan(conds).source {
+            Source::Scan => None,
+            source => Some(self.rowids(conds, &source)),
+        }
+    }
+
+    /// Like `using_index`, but also returns the conditions that the yielded rows still have to be
+    /// checked against. Every yielded row is known to match all the other conditions.
+    fn candidates<'c, 's: 'c>(&'s self,
+                              conds: &'c [cmp::Condition<'c, T>])
+                              -> (Box<dyn Iterator<Item = usize> + 's>,
+                                  Vec<&'c cmp::Condition<'c, T>>) {
+        self.candidates_for(conds, self.plan(conds))
+    }
+
+    /// Like `candidates`, but uses the given plan for the conditions, as made by `plan` or
+    /// `plan_hinted`.
+    fn candidates_for<'c, 's: 'c>(&'s self,
+                                  conds: &'c [cmp::Condition<'c, T>],
+                                  plan: Plan)
+                                  -> (Box<dyn Iterator<Item = usize> + 's>,
+                                      Vec<&'c cmp::Condition<'c, T>>) {
+        self.count_scan(conds, &plan);
+        self.count_query(conds, &plan);
+        let filters = conds.iter()
             .enumerate()
-            .filter_map(|(ci, c)| self.indices.get(&c.column).and_then(|idx| Some((ci, idx))))
-            .filter(|&(ci, _)| {
-                // does this index work for the operation in question?
-                match conds[ci].cmp {
-                    cmp::Comparison::Equal(cmp::Value::Const(..)) => true,
-                    _ => false,
-                }
-            })
-            .min_by_key(|&(_, idx)| idx.estimate());
+            .filter(|&(i, _)| !plan.exact.holds(i))
+            .map(|(_, c)| c)
+            .collect();
+        (self.count_examined(self.rowids(conds, &plan.source)), filters)
+    }
 
-        best_idx.and_then(|(ci, idx)| match conds[ci].cmp {
-                cmp::Comparison::Equal(cmp::Value::Const(ref v)) => Some(idx.lookup(v)),
-                _ => unreachable!(),
-            })
-            .unwrap_or_else(|| Box::new(self.rows.keys().map(|k| *k)))
+    /// Decide how to find the rows matching the given conditions most efficiently. This is the
+    /// only place where that decision is made, so that `find` and `explain` always agree.
+    fn plan(&self, conds: &[cmp::Condition<T>]) -> Plan {
+        self.check(conds);
+        self.check_indexed();
+        if contradicts(conds) {
+            return Plan {
+                source: Source::Empty,
+                estimate: Some(0),
+                exact: Known::Everything,
+            };
+        }
+        self.choose(conds)
     }
 
-    /// Returns an iterator that yields all rows matching all the given `Condition`s.
+    /// Like `plan`, but follows the given hints (see `Query::use_index` and friends), and returns
+    /// an error rather than panicking if the conditions or the hints are invalid.
     ///
-    /// This method will automatically determine what index to use to satisfy this query. It
-    /// currently uses a fairly simple heuristic: it picks the index that: a) is over one of
-    /// columns being filtered on; b) supports the operation for that filter; and c) has the lowest
-    /// expected number of rows for a single value. This latter metric is generally the total
-    /// number of rows divided by the number of entries in the index. See `EqualityIndex::estimate`
-    /// for details.
-    pub fn find<'c, 's: 'c>(&'s self,
-                            conds: &'c [cmp::Condition<'c, T>])
-                            -> Box<Iterator<Item = &'s R> + 'c> {
-        let is_a_match = move |r: &&'s _| conds.iter().all(|c| c.matches(*r));
-        Box::new(self.using_index(conds)
-            .map(move |rowi| &self.rows[&rowi])
-            .filter(is_a_match))
+    /// A hint may only name a column that has an index, a query may only be forced to use a
+    /// single index, and that index must be able to answer one of the conditions.
+    fn plan_hinted(&self,
+                   conds: &[cmp::Condition<T>],
+                   hints: &[query::Hint])
+                   -> Result<Plan, QueryError> {
+        use query::Hint;
+        self.validate(conds)?;
+        let mut forced = None;
+        for hint in hints {
+            match *hint {
+                Hint::Use(column) |
+                Hint::Ignore(column) if self.column_index(column).is_none() => {
+                    return Err(QueryError::NoIndex { column });
+                }
+                Hint::Use(column) => {
+                    if matches!(forced, Some(f) if f != column) {
+                        return Err(QueryError::ConflictingHints);
+                    }
+                    forced = Some(column);
+                }
+                Hint::Ignore(..) | Hint::Scan => {}
+            }
+        }
+        let scan = hints.contains(&Hint::Scan);
+        if let Some(column) = forced {
+            if scan || hints.contains(&Hint::Ignore(column)) {
+                return Err(QueryError::ConflictingHints);
+            }
+        }
+
+        if hints.is_empty() || contradicts(conds) {
+            return Ok(self.plan(conds));
+        }
+        if scan {
+            return Ok(Plan {
+                source: Source::Scan,
+                estimate: None,
+                exact: Known::Nothing,
+            });
+        }
+        let plan = self.choose_hinted(conds, hints);
+        match (forced, &plan.source) {
+            (Some(column), &Source::Index(i)) if conds[i].column != column => {
+                Err(QueryError::UnusableIndex { column })
+            }
+            (Some(column), &Source::Scan) => Err(QueryError::UnusableIndex { column }),
+            _ => Ok(plan),
+        }
     }
 
-    /// Delete all rows that match the given conditions.
-    pub fn delete(&mut self, conds: &[cmp::Condition<T>]) {
-        self.delete_filter(conds, |_| true);
+    /// Like `plan`, but assumes that the conditions are valid and do not contradict each other.
+    /// The choice only depends on the constants in the conditions through the estimates of the
+    /// indices.
+    fn choose(&self, conds: &[cmp::Condition<T>]) -> Plan {
+        self.choose_hinted(conds, &[])
     }
 
-    /// Delete all rows that match the given conditions *and* where the given filter function
-    /// returns true.
-    ///
-    /// This requires that `R` implements `IntoIterator`, and that the columns are yielded *in
-    /// column order*.
-    pub fn delete_filter<F>(&mut self, conds: &[cmp::Condition<T>], mut f: F)
-        where F: FnMut(&R) -> bool
-    {
-        // find the rows we should delete
-        let rowids = self.using_index(conds)
-            .map(|rowi| (rowi, &self.rows[&rowi]))
-            .filter(move |&(_, row)| conds.iter().all(|c| c.matches(row)))
-            .filter(|&(_, row)| f(row))
-            .map(|(rowid, _)| rowid)
-            .collect::<Vec<_>>();
+    /// Like `choose`, but only considers the indices that the given hints allow. If the hints
+    /// force the use of an index, no other index is considered, and `Source::Index` is chosen if
+    /// that index can be used at all.
+    fn choose_hinted(&self, conds: &[cmp::Condition<T>], hints: &[query::Hint]) -> Plan {
+        let mut plan = self.choose_source(conds, hints);
+        // rows queued to be added to the indices (see `set_index_maintenance`) are yielded along
+        // with the rows found through an index, and so may not match any of the conditions
+        if self.unindexed().next().is_some() {
+            plan.exact = Known::Nothing;
+        }
+        plan
+    }
 
-        let deleted = rowids.into_iter()
-            .map(|rowid| (rowid, self.rows.remove(&rowid).unwrap()))
-            .collect::<Vec<_>>();
+    /// Like `choose_hinted`, but ignores rows queued to be added to the indices.
+    fn choose_source(&self, conds: &[cmp::Condition<T>], hints: &[query::Hint]) -> Plan {
+        use EqualityIndex;
+        use query::Hint;
+
+        let forced = hints.iter().find_map(|hint| match *hint {
+            Hint::Use(column) => Some(column),
+            _ => None,
+        });
+        let allowed = |column: usize| {
+            forced.unwrap_or(column) == column && !hints.contains(&Hint::Ignore(column))
+        };
 
-        for (rowid, row) in deleted.into_iter() {
-            for (col, idx) in self.indices.iter_mut() {
-                idx.undex(row.index(*col), rowid);
+        let candidates = conds.iter()
+            .enumerate()
+            .filter(|&(_, c)| allowed(c.column))
+            .filter_map(|(i, c)| self.column_index(c.column).map(|idx| (i, c, idx)))
+            .filter_map(|(i, c, idx)| {
+                // does this index work for the operation in question?
+                // if so, how many rows do we expect it to give us?
+                match (&c.cmp, idx) {
+                    (cmp::Comparison::Equal(cmp::Value::Const(v)), _) => Some(idx.estimate_for(v)),
+                    (cmp::Comparison::In(vs), _) => {
+                        vs.iter()
+                            .map(|v| match *v {
+                                cmp::Value::Const(ref v) => Some(idx.estimate_for(v)),
+                                cmp::Value::Column(..) => None,
+                            })
+                            .sum()
+                    }
+                    (cmp @ cmp::Comparison::InRanges(..), Index::Range(ri)) => {
+                        // each of the merged ranges is answered by a scan of its own
+                        let ranges = cmp.ranges()?;
+                        Some(ranges.into_iter().map(|(min, max)| ri.estimate_range(min, max)).sum())
+                    }
+                    (cmp, Index::Range(ri)) if cmp.range().is_some() => {
+                        // all range conditions on this column are answered by a single scan
+                        let (min, max) = cmp::column_range(conds, c.column).unwrap();
+                        Some(ri.estimate_range(min, max))
+                    }
+                    (cmp::Comparison::StartsWith(p), Index::Range(..)) => {
+                        match *p.value() {
+                            cmp::Value::Const(..) => Some(idx.estimate()),
+                            cmp::Value::Column(..) => None,
+                        }
+                    }
+                    _ => None,
+                }
+                .map(|estimate| (i, c, idx, estimate))
+            });
+        let is_lookup = |c: &cmp::Condition<T>| {
+            matches!(c.cmp, cmp::Comparison::Equal(cmp::Value::Const(..)))
+        };
+        // find the best index and count the lookups in a single pass, so that the common case of
+        // a single usable index needs neither a second round of estimates nor an allocation. ties
+        // go to the earliest condition, so the plan only depends on the conditions and indices.
+        let mut best_idx = None;
+        let mut lookups = 0;
+        for candidate in candidates.clone() {
+            if is_lookup(candidate.1) {
+                lookups += 1;
+            }
+            if best_idx.is_none_or(|(_, _, _, estimate)| candidate.3 < estimate) {
+                best_idx = Some(candidate);
             }
         }
-    }
 
-    /// Insert a new data row into the `Store`. The row **must** have the same number of columns as
-    /// specified when the `Store` was created. If it does not, the code will panic with an
-    /// assertion failure.
-    ///
-    /// Inserting a row has similar complexity to `BTreeMap::insert`, and *may* need to re-allocate
-    /// the backing memory for the `Store`. The insertion also updates all maintained indices,
-    /// which may also re-allocate.
-    pub fn insert(&mut self, row: R) {
-        debug_assert_eq!(row.columns(), self.cols);
-        let rowid = self.rowid;
-        for (column, idx) in self.indices.iter_mut() {
-            use EqualityIndex;
-            idx.index(row.index(*column).clone(), rowid);
+        // a composite index can be used if all its columns are compared against constants, a
+        // keyed index if its column is compared against a constant using the same key function,
+        // and a sparse index if its column is compared against a constant that is not null
+        let best_composite = self.composite
+            .iter()
+            .enumerate()
+            .filter(|_| forced.is_none())
+            .filter_map(|(j, (composite, idx))| {
+                match *composite {
+                    CompositeKey::Columns(ref columns) => {
+                        columns.iter()
+                            .map(|&col| {
+                                conds.iter().position(|c| {
+                                    c.column == col &&
+                                    matches!(c.cmp,
+                                             cmp::Comparison::Equal(cmp::Value::Const(..)))
+                                })
+                            })
+                            .collect::<Option<Vec<_>>>()
+                    }
+                    CompositeKey::Computed(col, ref kf) => {
+                        conds.iter()
+                            .position(|c| match c.cmp {
+                                cmp::Comparison::KeyEqual(ref k, cmp::Value::Const(..)) => {
+                                    c.column == col && k.name() == kf.name()
+                                }
+                                _ => false,
+                            })
+                            .map(|i| vec![i])
+                    }
+                    CompositeKey::Sparse(col, ref null) => {
+                        conds.iter()
+                            .position(|c| match c.cmp {
+                                cmp::Comparison::Equal(cmp::Value::Const(ref v)) => {
+                                    c.column == col && !null.is_null(v)
+                                }
+                                _ => false,
+                            })
+                            .map(|i| vec![i])
+                    }
+                }
+                .map(|used| {
+                    let estimate = idx.estimate_for(&lookup_key(composite, conds, &used));
+                    (j, used, estimate)
+                })
+            })
+            .min_by_key(|&(_, _, estimate)| estimate);
+
+        if let Some((j, used, estimate)) = best_composite {
+            if best_idx.as_ref().map(|&(_, _, _, e)| estimate <= e).unwrap_or(true) {
+                let mut exact = vec![false; conds.len()];
+                for &i in &used {
+                    exact[i] = true;
+                }
+                return Plan {
+                    source: Source::Composite(j, used),
+                    estimate: Some(estimate),
+                    exact: Known::Each(exact),
+                };
+            }
+        }
+
+        if forced.is_none() && lookups > 1 {
+            let lookups = candidates.filter(|&(_, c, _, _)| is_lookup(c))
+                .map(|(i, _, _, estimate)| (i, estimate));
+            if let Some(plan) = self.intersect(conds, lookups) {
+                return plan;
+            }
+        }
+
+        match best_idx {
+            Some((i, c, idx, estimate)) => {
+                let exact = match (&c.cmp, idx) {
+                    (cmp::Comparison::Equal(..), _) |
+                    (cmp::Comparison::In(..), _) |
+                    (cmp::Comparison::InRanges(..), _) |
+                    (cmp::Comparison::StartsWith(..), _) => Known::Only(i),
+                    // all range conditions on this column are answered by a single scan
+                    _ => {
+                        Known::Each(conds.iter()
+                            .map(|d| {
+                                d.column == c.column &&
+                                match d.cmp {
+                                    cmp::Comparison::Equal(cmp::Value::Const(..)) => true,
+                                    ref cmp => cmp.range().is_some(),
+                                }
+                            })
+                            .collect())
+                    }
+                };
+                Plan {
+                    source: Source::Index(i),
+                    estimate: Some(estimate),
+                    exact,
+                }
+            }
+            None => {
+                Plan {
+                    source: Source::Scan,
+                    estimate: None,
+                    exact: Known::Nothing,
+                }
+            }
         }
-        self.rows.insert(self.rowid, row);
-        self.rowid += 1;
     }
 
-    /// Add an index on the given colum using the given indexer. The indexer *must*, at the very
-    /// least, implement `EqualityIndex`. It *may* also implement other, more sophisticated,
-    /// indexing strategies outlined in `Index`.
+    /// If there are several equality conditions against constants that can each be answered by an
+    /// index, and intersecting the rows from those indices is likely to be cheaper than checking
+    /// every row yielded by the best of them, plan to use the intersection.
     ///
-    /// When an index is added, it is immediately fed all rows in the current dataset. Thus, adding
-    /// an index to a `Store` with many rows can be fairly costly. Keep this in mind!
-    pub fn index<I: Into<Index<T>>>(&mut self, column: usize, indexer: I) {
-        use EqualityIndex;
-        let mut idx = indexer.into();
+    /// Intersection is not worth it if the best index is expected to yield only a few rows, or if
+    /// all other indices are expected to yield far more rows than the best one.
+    ///
+    /// `lookups` gives the position and estimate of each of the equality conditions.
+    fn intersect<I>(&self, conds: &[cmp::Condition<T>], lookups: I) -> Option<Plan>
+        where I: Iterator<Item = (usize, usize)>
+    {
+        let mut lookups = lookups.collect::<Vec<_>>();
+        lookups.sort_by_key(|&(_, estimate)| estimate);
 
-        // populate the new index
-        for (rowid, row) in self.rows.iter() {
-            idx.index(row.index(column).clone(), *rowid);
+        let smallest = match lookups.first() {
+            Some(&(_, estimate)) if estimate > INTERSECT_MIN_ESTIMATE => estimate,
+            _ => return None,
+        };
+        lookups.retain(|&(_, estimate)| estimate <= smallest * INTERSECT_MAX_RATIO);
+        if lookups.len() < 2 {
+            return None;
         }
 
-        self.indices.insert(column, idx);
+        let mut exact = vec![false; conds.len()];
+        for &(i, _) in &lookups {
+            exact[i] = true;
+        }
+        Some(Plan {
+            source: Source::Intersect(lookups.into_iter().map(|(i, _)| i).collect()),
+            estimate: Some(smallest),
+            exact: Known::Each(exact),
+        })
     }
-}
 
-impl<'a, T> Row<T> for &'a [T] {
-    fn index(&self, i: usize) -> &T {
-        &self[i]
+    /// Return the identifiers of the candidate rows from the given source, as chosen by `plan`.
+    /// Rows that have been marked as deleted are never returned.
+    fn rowids<'c, 's: 'c>(&'s self,
+                          conds: &'c [cmp::Condition<'c, T>],
+                          source: &Source)
+                          -> Box<dyn Iterator<Item = usize> + 's> {
+        self.live(self.source_rowids(conds, source))
     }
-    fn columns(&self) -> usize {
-        self.len()
+
+    /// Remove the identifiers of rows that have been marked as deleted from the given identifiers.
+    fn live<'s>(&'s self,
+                rowids: Box<dyn Iterator<Item = usize> + 's>)
+                -> Box<dyn Iterator<Item = usize> + 's> {
+        if self.dead == 0 {
+            rowids
+        } else {
+            Box::new(rowids.filter(move |&rowid| !self.is_dead(rowid)))
+        }
     }
-}
 
-impl<T> Row<T> for [T] {
-    fn index(&self, i: usize) -> &T {
-        &self[i]
+    /// Returns true if the row with the given identifier has been marked as deleted.
+    fn is_dead(&self, rowid: usize) -> bool {
+        is_dead(&self.tombstones, rowid)
     }
-    fn columns(&self) -> usize {
-        self.len()
+
+    /// Like `rowids`, but may also return rows that have been marked as deleted.
+    fn source_rowids<'c, 's: 'c>(&'s self,
+                                 conds: &'c [cmp::Condition<'c, T>],
+                                 source: &Source)
+                                 -> Box<dyn Iterator<Item = usize> + 's> {
+        let rowids = self.indexed_rowids(conds, source);
+        match *source {
+            Source::Empty | Source::Scan => rowids,
+            _ if self.unindexed().next().is_none() => rowids,
+            _ => Box::new(rowids.chain(self.unindexed().map(|(&rowid, _)| rowid))),
+        }
     }
-}
 
-impl<T> Row<T> for Vec<T> {
-    fn index(&self, i: usize) -> &T {
-        &self[i]
+    /// Like `source_rowids`, but leaves out rows queued to be added to the indices (see
+    /// `set_index_maintenance`) unless the source is a scan.
+    fn indexed_rowids<'c, 's: 'c>(&'s self,
+                                  conds: &'c [cmp::Condition<'c, T>],
+                                  source: &Source)
+                                  -> Box<dyn Iterator<Item = usize> + 's> {
+        use EqualityIndex;
+        let lookup = |i: usize| {
+            let c = &conds[i];
+            match c.cmp {
+                cmp::Comparison::Equal(cmp::Value::Const(ref v)) => {
+                    self.column_index(c.column).unwrap().lookup(v)
+                }
+                _ => unreachable!(),
+            }
+        };
+
+        match *source {
+            Source::Empty => Box::new(iter::empty()),
+            Source::Scan => Box::new(self.rows.keys().copied()),
+            Source::Composite(j, ref used) => {
+                let (ref composite, ref idx) = self.composite[j];
+                idx.lookup(&lookup_key(composite, conds, used))
+            }
+            Source::Intersect(ref used) => {
+                // start with the smallest list, and only keep rows that appear in every other list
+                let mut lookups = used.iter().map(|&i| lookup(i));
+                let mut matching: BTreeSet<_> = lookups.next().unwrap().collect();
+                for rows in lookups {
+                    if matching.is_empty() {
+                        break;
+                    }
+                    matching = rows.filter(|rowid| matching.contains(rowid)).collect();
+                }
+                Box::new(matching.into_iter())
+            }
+            Source::Index(i) => {
+                let c = &conds[i];
+                let idx = self.column_index(c.column).unwrap();
+                match (&c.cmp, idx) {
+                    (cmp::Comparison::Equal(..), _) => lookup(i),
+                    (cmp::Comparison::In(vs), _) => {
+                        // the same row may be yielded for several values, so we need to dedup
+                        let rows: BTreeSet<_> = vs.iter()
+                            .flat_map(|v| match *v {
+                                cmp::Value::Const(ref v) => idx.lookup(v),
+                                cmp::Value::Column(..) => unreachable!(),
+                            })
+                            .collect();
+                        Box::new(rows.into_iter())
+                    }
+                    (cmp::Comparison::StartsWith(p), Index::Range(ri)) => {
+                        // all values with the prefix sort immediately after the prefix itself, so
+                        // scan from the prefix until we reach a value that doesn't start with it
+                        let prefix = match *p.value() {
+                            cmp::Value::Const(ref v) => v.clone().into_owned(),
+                            cmp::Value::Column(..) => unreachable!(),
+                        };
+                        let has_prefix = p.has_prefix;
+                        let column = c.column;
+                        Box::new(ri.between(Bound::Included(&prefix), Bound::Unbounded)
+                            .take_while(move |rowid| {
+                                has_prefix(self.rows[rowid].index(column), &prefix)
+                            }))
+                    }
+                    (cmp @ cmp::Comparison::InRanges(..), Index::Range(ri)) => {
+                        // the ranges are merged so that they neither overlap nor touch, and so
+                        // no row is yielded by more than one scan
+                        let scans = cmp.ranges()
+                            .unwrap()
+                            .into_iter()
+                            .map(|(min, max)| ri.between(min, max))
+                            .collect::<Vec<_>>();
+                        Box::new(scans.into_iter().flatten())
+                    }
+                    (_, Index::Range(ri)) => {
+                        // all range conditions on this column can be answered by a single scan
+                        let (min, max) = cmp::column_range(conds, c.column).unwrap();
+                        ri.between(min, max)
+                    }
+                    _ => unreachable!(),
+                }
+            }
+        }
     }
-    fn columns(&self) -> usize {
-        self.len()
+
+    /// Describe how `find` would find the rows matching the given conditions, without looking at
+    /// any rows. The returned `QueryPlan` is produced by the same code that `find` uses to choose
+    /// an index, so it always reflects what `find` actually does.
+    ///
+    /// Just as `find` does, this leaves out redundant conditions before planning, and so never
+    /// lists them among the conditions that are checked.
+    pub fn explain(&self, conds: &[cmp::Condition<T>]) -> QueryPlan {
+        let kept = (0..conds.len()).filter(|&i| !redundant(conds, i)).collect::<Vec<_>>();
+        if kept.len() == conds.len() {
+            return self.describe(conds, self.plan(conds));
+        }
+        let conds = kept.iter().map(|&i| conds[i].clone()).collect::<Vec<_>>();
+        let mut plan = self.describe(&conds, self.plan(&conds));
+        plan.filters = plan.filters.into_iter().map(|i| kept[i]).collect();
+        plan
     }
-}
 
-use std::sync;
-impl<T> Row<T> for sync::Arc<Vec<T>> {
-    fn index(&self, i: usize) -> &T {
-        &self[i]
+    /// Describe the given plan for the given conditions, for `explain`.
+    fn describe(&self, conds: &[cmp::Condition<T>], plan: Plan) -> QueryPlan {
+        QueryPlan {
+            access: self.access(conds, &plan.source),
+            estimate: plan.estimate,
+            filters: (0..conds.len()).filter(|&i| !plan.exact.holds(i)).collect(),
+        }
     }
-    fn columns(&self) -> usize {
-        self.len()
+
+    /// Describe how the given source finds the candidate rows for the given conditions.
+    fn access(&self, conds: &[cmp::Condition<T>], source: &Source) -> query::Access {
+        match *source {
+            Source::Empty => query::Access::Nothing,
+            Source::Scan => query::Access::Scan,
+            Source::Index(i) => query::Access::Index(conds[i].column),
+            Source::Intersect(ref used) => {
+                query::Access::Intersect(used.iter().map(|&i| conds[i].column).collect())
+            }
+            Source::Composite(j, _) => {
+                match self.composite[j].0 {
+                    CompositeKey::Columns(ref columns) => query::Access::Composite(columns.clone()),
+                    CompositeKey::Computed(column, ref kf) => {
+                        query::Access::Keyed(column, kf.name())
+                    }
+                    CompositeKey::Sparse(column, _) => query::Access::Sparse(column),
+                }
+            }
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Check that all the given conditions only refer to columns that exist in this `Store`.
+    pub fn validate(&self, conds: &[cmp::Condition<T>]) -> Result<(), QueryError> {
+        for c in conds {
+            let columns = c.cmp
+                .iter_values()
+                .filter_map(|v| match *v {
+                    cmp::Value::Column(col) => Some(col),
+                    cmp::Value::Const(..) => None,
+                });
+            for column in Some(c.column).into_iter().chain(columns) {
+                if column >= self.cols {
+                    return Err(QueryError::ColumnOutOfRange {
+                        column,
+                        cols: self.cols,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
 
-    #[test]
-    fn it_works() {
-        let mut store = Store::new(2);
-        store.insert(vec!["a1", "a2"]);
-        store.insert(vec!["b1", "b2"]);
-        store.insert(vec!["c1", "c2"]);
-        assert_eq!(store.find(&[]).count(), 3);
+    /// Panic with a helpful message if the given conditions are not valid for this `Store`.
+    fn check(&self, conds: &[cmp::Condition<T>]) {
+        if let Err(e) = self.validate(conds) {
+            panic!("{}", e);
+        }
     }
 
-    #[test]
-    fn it_works_w_non_vec() {
-        use std::sync;
-        let mut store = Store::new(2);
-        store.insert(sync::Arc::new(vec!["a1", "a2"]));
-        store.insert(sync::Arc::new(vec!["b1", "b2"]));
-        store.insert(sync::Arc::new(vec!["c1", "c2"]));
-        assert_eq!(store.find(&[]).count(), 3);
+    /// Like `find`, but returns an error if any of the conditions refer to a column that does not
+    /// exist, rather than panicking.
+    pub fn try_find<'c, 's: 'c>(&'s self,
+                                conds: &'c [cmp::Condition<'c, T>])
+                                -> Result<Find<'s, 'c, T, R>, QueryError> {
+        self.validate(conds)?;
+        Ok(self.find(conds))
     }
 
-    #[test]
-    fn it_works_with_indices() {
+    /// Returns an iterator that yields all rows matching all the given `Condition`s.
+    ///
+    /// This method will automatically determine what index to use to satisfy this query. It
+    /// currently uses a fairly simple heuristic: it picks the index that: a) is over one of
+    /// columns being filtered on; b) supports the operation for that filter; and c) has the lowest
+    /// expected number of rows for the value being filtered on. For equality comparisons, this is
+    /// the number of rows with that value, if the index can tell (see
+    /// `EqualityIndex::estimate_for`). Otherwise, it is generally the total number of rows divided
+    /// by the number of entries in the index. See `EqualityIndex::estimate` for details.
+    ///
+    /// Any index can be used for equality comparisons against a constant, whereas ordering
+    /// comparisons (such as `Less` or `Between`) can only be satisfied by an `Index::Range`. If all
+    /// the columns of a composite index (see `index_multi`) are compared for equality against
+    /// constants, that index is also considered. When a range index is used, all the range
+    /// conditions on its column are combined into a single scan of the index, except for an
+    /// `InRanges` condition, which takes one scan for each of its ranges. If several
+    /// equality conditions have indices, none of which is very selective, the rows from those
+    /// indices may be intersected before any rows are examined.
+    ///
+    /// If the conditions contradict each other, such as `col = 1` and `col = 2`, or if any of them
+    /// can never match (see `Comparison::matches_nothing`), no rows are examined at all, and no
+    /// index is consulted. Before a plan is made, redundant conditions are left out: a condition
+    /// that repeats an earlier one, and a condition that another condition on the same column
+    /// implies, such as `col > 1` alongside `col = 3`, or `col < 10` alongside `col < 5`. Only
+    /// conditions that compare against constants are ever taken to imply another.
+    ///
+    /// Rows found through an index are only checked against the conditions that the index does
+    /// not already guarantee. Use `explain` to see which index is chosen, and which conditions
+    /// are checked. If no conditions have to be checked, and the rows are found by looking up a
+    /// single value, the number of matching rows is known up front (see `Find`).
+    ///
+    /// The choice of index is deterministic: if several indices are expected to yield the same
+    /// number of rows, the one for the condition that comes first in `conds` is used. Running the
+    /// same query against a `Store` that was built by the same sequence of operations therefore
+    /// always yields the same rows in the same order. That order depends on the plan:
+    ///
+    ///  - a scan, an intersection of several indices, and a lookup of several values (`In`) yield
+    ///    rows in the order they were inserted;
+    ///  - a range scan of a `BTreeIndex` yields rows in the order of their values, and rows with
+    ///    the same value in the order they were inserted, as does a lookup in a `BTreeIndex`;
+    ///  - a lookup in a `HashIndex` yields rows in the order they were added, except that removing
+    ///    a row from the index may move another row into its place;
+    ///  - a lookup in a covering index (see `index_covering`) yields rows in the order they were
+    ///    inserted;
+    ///  - any other index yields rows in whatever order it returns them.
+    ///
+    /// Use `Query::order_by` if the rows must come in a particular order regardless of the plan.
+    pub fn find<'c, 's: 'c>(&'s self, conds: &'c [cmp::Condition<'c, T>]) -> Find<'s, 'c, T, R> {
+        self.find_conds(Cow::Borrowed(conds))
+    }
+
+    /// Returns an iterator that yields all rows whose value in the given column is equal to
+    /// `value`. This is the same as `find` with a single `Condition::eq`, but does not require the
+    /// condition to be built (or kept alive) by the caller, and does not clone `value`.
+    ///
+    /// This, along with `find_range`, is the recommended way to look up rows by the value in a
+    /// single column. The query goes through the same planner as `find`, and so uses an index on
+    /// the column if there is one.
+    ///
+    /// ```
+    /// use shortcut::Store;
+    /// use shortcut::idx::HashIndex;
+    ///
+    /// let mut store = Store::new(2);
+    /// store.index(0, HashIndex::new());
+    /// store.insert(vec!["a", "x"]);
+    /// store.insert(vec!["b", "y"]);
+    /// assert_eq!(store.find_eq(0, &"a").collect::<Vec<_>>(), vec![&vec!["a", "x"]]);
+    /// assert_eq!(store.find_eq(1, &"y").count(), 1);
+    /// ```
+    ///
+    /// Panics if the column does not exist.
+    pub fn find_eq<'v, 's: 'v>(&'s self, column: usize, value: &'v T) -> Find<'s, 'v, T, R> {
+        let cmp = cmp::Comparison::Equal(cmp::Value::using(value));
+        self.find_conds(Cow::Owned(vec![cmp::Condition::new(column, cmp)]))
+    }
+
+    /// Returns an iterator that yields all rows whose value in the given column lies within the
+    /// given range, such as `3..10` or `..=5`. This is the same as `find` with a single
+    /// `Condition::between`; see `find_eq`. The bounds of the range are cloned.
+    ///
+    /// ```
+    /// use shortcut::Store;
+    /// use shortcut::idx::BTreeIndex;
+    ///
+    /// let mut store = Store::new(2);
+    /// store.index(0, BTreeIndex::new());
+    /// for i in 0..10 {
+    ///     store.insert(vec![i, i * i]);
+    /// }
+    /// let squares = store.find_range(0, 3..6).map(|row| row[1]).collect::<Vec<_>>();
+    /// assert_eq!(squares, vec![9, 16, 25]);
+    /// assert_eq!(store.find_range(1, 50..).count(), 2);
+    /// ```
+    ///
+    /// Panics if the column does not exist.
+    pub fn find_range<'s, B>(&'s self, column: usize, range: B) -> Find<'s, 's, T, R>
+        where B: ops::RangeBounds<T>
+    {
+        let bound = |b: Bound<&T>| match b {
+            Bound::Included(v) => Bound::Included(cmp::Value::new(v.clone())),
+            Bound::Excluded(v) => Bound::Excluded(cmp::Value::new(v.clone())),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let cmp = cmp::Comparison::Between {
+            min: bound(range.start_bound()),
+            max: bound(range.end_bound()),
+        };
+        self.find_conds(Cow::Owned(vec![cmp::Condition::new(column, cmp)]))
+    }
+
+    /// Returns an iterator that yields every row whose value in the given column is one of the
+    /// given keys, along with the position in `keys` of the key it holds. Keys that no row holds
+    /// are skipped, and a key that appears more than once in `keys` yields its rows once for every
+    /// position it appears at.
+    ///
+    /// This is cheaper than calling `find_eq` for every key. If the column has an index, the
+    /// decision to use it is made once, and every key is then looked up directly; the rows are
+    /// yielded grouped by key, in the order of `keys`. Otherwise, the rows are scanned just once,
+    /// checking each against the set of keys, and are yielded in the order they were inserted.
+    ///
+    /// ```
+    /// use shortcut::Store;
+    /// use shortcut::idx::HashIndex;
+    ///
+    /// let mut store = Store::new(2);
+    /// store.index(0, HashIndex::new());
+    /// store.insert(vec!["a", "x"]);
+    /// store.insert(vec!["b", "y"]);
+    /// store.insert(vec!["a", "z"]);
+    /// let found = store.find_many(0, &["b", "c", "a"])
+    ///     .map(|(i, row)| (i, row[1]))
+    ///     .collect::<Vec<_>>();
+    /// assert_eq!(found, vec![(0, "y"), (2, "x"), (2, "z")]);
+    /// ```
+    ///
+    /// Panics if the column does not exist.
+    pub fn find_many<'a>(&'a self,
+                         column: usize,
+                         keys: &'a [T])
+                         -> impl Iterator<Item = (usize, &'a R)> + 'a {
+        use EqualityIndex;
+        assert!(column < self.cols,
+                "column {} is out of range for a store with {} columns",
+                column,
+                self.cols);
+        // rows queued to be added to the indices (see `set_index_maintenance`) are only scanned
+        let idx = self.column_index(column).filter(|_| self.unindexed().next().is_none());
+        let found: Box<dyn Iterator<Item = (usize, &'a R)> + 'a> = match idx {
+            Some(idx) => {
+                Box::new(keys.iter().enumerate().flat_map(move |(i, key)| {
+                    self.live(idx.lookup(key)).map(move |rowid| (i, &self.rows[&rowid]))
+                }))
+            }
+            None => {
+                let mut positions: BTreeMap<&T, Vec<usize>> = BTreeMap::new();
+                for (i, key) in keys.iter().enumerate() {
+                    positions.entry(key).or_default().push(i);
+                }
+                let mut found = Vec::new();
+                for row in self.rows() {
+                    if let Some(positions) = positions.get(row.index(column)) {
+                        found.extend(positions.iter().map(|&i| (i, row)));
+                    }
+                }
+                Box::new(found.into_iter())
+            }
+        };
+        found
+    }
+
+    /// Like `find`, but with conditions that may be owned by the returned iterator.
+    fn find_conds<'c, 's: 'c>(&'s self,
+                              conds: Cow<'c, [cmp::Condition<'c, T>]>)
+                              -> Find<'s, 'c, T, R> {
+        let conds = if (0..conds.len()).any(|i| redundant(&conds, i)) {
+            Cow::Owned((0..conds.len())
+                .filter(|&i| !redundant(&conds, i))
+                .map(|i| conds[i].clone())
+                .collect())
+        } else {
+            conds
+        };
+        if let Some(ref cache) = self.cache {
+            let rows = Box::new(self.find_cached(&**cache, &conds).map(|(_, row)| row));
+            return Find::cached(rows, self.len()).observed(self.report(None));
+        }
+        let plan = self.plan(&conds);
+        self.count_scan(&conds, &plan);
+        self.count_query(&conds, &plan);
+        let report = self.report(Some((&conds, &plan)));
+        let known = self.lookup_len(&conds, &plan);
+        let upper = known.map_or(self.len(), |len| len.min(self.len()));
+        let candidates = self.find_candidates(&conds, &plan.source);
+        if known.is_some() && self.dead == 0 && plan.exact.all(conds.len()) {
+            return Find::exact(self, candidates, upper).observed(report);
+        }
+        Find::filtered(self, candidates, conds, plan.exact, upper).observed(report)
+    }
+
+    /// Like `source_rowids`, but walks lookups of a single value in the built-in indices and
+    /// scans directly, rather than through a boxed iterator. Lookups in a column with a covering
+    /// index walk the copies of the rows held by that index (see `index_covering`).
+    fn find_candidates<'c, 's: 'c>(&'s self,
+                                   conds: &'c [cmp::Condition<'c, T>],
+                                   source: &Source)
+                                   -> query::Candidates<'s, R> {
+        use EqualityIndex;
+        match *source {
+            Source::Empty => return query::Candidates::Slice([].iter()),
+            Source::Scan => return query::Candidates::Scan(self.rows.iter()),
+            Source::Index(i) if self.unindexed().next().is_none() => {
+                if let cmp::Comparison::Equal(cmp::Value::Const(ref key)) = conds[i].cmp {
+                    if let Some(rows) = self.covered(conds[i].column, key) {
+                        return query::Candidates::Covered(rows.iter());
+                    }
+                    let idx = self.column_index(conds[i].column).unwrap();
+                    if let Some(rowids) = idx.lookup_slice(key) {
+                        return query::Candidates::Slice(rowids.iter());
+                    }
+                }
+            }
+            _ => {}
+        }
+        query::Candidates::Boxed(self.source_rowids(conds, source))
+    }
+
+    /// Returns the number of candidate rows the given plan yields, if the plan looks up a single
+    /// value in one of the built-in indices (or finds no rows at all). Rows that have been marked
+    /// as deleted are included.
+    fn lookup_len(&self, conds: &[cmp::Condition<T>], plan: &Plan) -> Option<usize> {
+        use EqualityIndex;
+        let i = match plan.source {
+            Source::Empty => return Some(0),
+            Source::Index(i) if self.unindexed().next().is_none() => i,
+            _ => return None,
+        };
+        let idx = self.column_index(conds[i].column)?;
+        match conds[i].cmp {
+            // other indices may have to look at every row for the key to count them
+            cmp::Comparison::Equal(cmp::Value::Const(ref key)) if idx.kind().is_some() => {
+                Some(idx.count(key))
+            }
+            _ => None,
+        }
+    }
+
+    /// Like `find`, but also yields the identifier of each matching row. These are the same
+    /// identifiers that `insert` returns, and that `get` and `mark_deleted` accept.
+    pub fn find_with_ids<'c, 's: 'c>(&'s self,
+                                     conds: &'c [cmp::Condition<'c, T>])
+                                     -> Box<dyn Iterator<Item = (usize, &'s R)> + 'c> {
+        if let Some(ref cache) = self.cache {
+            return self.find_cached(&**cache, conds);
+        }
+        let (rowids, filters) = self.candidates(conds);
+        self.count_returned(rowids.map(move |rowi| (rowi, &self.rows[&rowi]))
+            .filter(move |&(_, row)| filters.iter().all(|c| c.matches(row))))
+    }
+
+    /// Take a snapshot of the rows currently in the `Store`. See `find_at`.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::new(self.rowid)
+    }
+
+    /// Like `find`, but ignores all rows inserted after the given snapshot was taken, including
+    /// rows found through an index. Repeating the same query against the same snapshot thus
+    /// yields the same rows, no matter how many rows have been inserted since.
+    ///
+    /// Only insertions are hidden: rows that are deleted or updated after the snapshot was taken
+    /// are seen as they are now. Since `compact` re-assigns row identifiers, snapshots taken
+    /// before a call to `compact` should not be used after it.
+    pub fn find_at<'c, 's: 'c>(&'s self,
+                               snapshot: &Snapshot,
+                               conds: &'c [cmp::Condition<'c, T>])
+                               -> Box<dyn Iterator<Item = &'s R> + 'c> {
+        let (rowids, filters) = self.candidates(conds);
+        let end = snapshot.end();
+        let is_a_match = move |r: &&'s _| filters.iter().all(|c| c.matches(*r));
+        self.count_returned(rowids.filter(move |&rowi| rowi < end)
+            .map(move |rowi| &self.rows[&rowi])
+            .filter(is_a_match))
+    }
+
+    /// Like `find`, but additionally filters the matching rows using an arbitrary predicate. This
+    /// is useful for conditions that cannot be expressed as a `Comparison`.
+    ///
+    /// The predicate cannot use an index. It is only evaluated for rows that match all the given
+    /// `Condition`s, so those conditions are still used to select an index and prune candidates
+    /// before the predicate is consulted.
+    pub fn find_where<'c, 's: 'c, F>(&'s self,
+                                     conds: &'c [cmp::Condition<'c, T>],
+                                     predicate: F)
+                                     -> Box<dyn Iterator<Item = &'s R> + 'c>
+        where F: Fn(&R) -> bool + 'c
+    {
+        Box::new(self.find(conds).filter(move |r| predicate(r)))
+    }
+
+    /// If `order_col` has an `Index::Range`, return the identifiers of all candidate rows for the
+    /// given conditions in the order of their values in that column. All range conditions on
+    /// `order_col` are used to narrow the range of the index that is walked. Rows that have been
+    /// marked as deleted are never returned.
+    fn ordered_rowids<'c, 's: 'c>(&'s self,
+                                  conds: &'c [cmp::Condition<'c, T>],
+                                  order_col: usize,
+                                  descending: bool)
+                                  -> Option<Box<dyn Iterator<Item = usize> + 's>> {
+        self.check(conds);
+        match self.complete_index(order_col) {
+            Some(Index::Range(..)) if contradicts(conds) => Some(Box::new(iter::empty())),
+            Some(Index::Range(ri)) => {
+                let (min, max) = cmp::column_range(conds, order_col)
+                    .unwrap_or((Bound::Unbounded, Bound::Unbounded));
+                if descending {
+                    Some(self.live(ri.between_rev(min, max)))
+                } else {
+                    Some(self.live(ri.between(min, max)))
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Prepare the given conditions for repeated execution with different constants. See
+    /// `PreparedQuery` for how the constants are replaced.
+    ///
+    /// Panics if any of the conditions refer to a column that does not exist.
+    pub fn prepare<'c>(&self, conds: &[cmp::Condition<'c, T>]) -> PreparedQuery<'c, T> {
+        PreparedQuery::new(self, conds)
+    }
+
+    /// Start building a query for the rows matching all the given `Condition`s. The returned
+    /// `Query` can be used to limit, offset, or order the results before iterating over them.
+    pub fn query<'c, 's: 'c>(&'s self, conds: &'c [cmp::Condition<'c, T>]) -> Query<'s, 'c, T, R> {
+        Query::new(self, conds)
+    }
+
+    /// Returns an iterator that yields all rows matching all the given `Condition`s, ordered by
+    /// the value in the column `order_col`. If `descending` is true, rows with larger values are
+    /// yielded first. Rows with equal values in `order_col` are always yielded in insertion order.
+    ///
+    /// If `order_col` has an `Index::Range`, rows are yielded by walking the index in order, and
+    /// no sorting is necessary. Any range conditions on `order_col` narrow that walk. Otherwise,
+    /// all matching rows are collected and sorted before the first row is yielded.
+    pub fn find_ordered<'c, 's: 'c>(&'s self,
+                                    conds: &'c [cmp::Condition<'c, T>],
+                                    order_col: usize,
+                                    descending: bool)
+                                    -> Box<dyn Iterator<Item = &'s R> + 'c> {
+        let is_a_match = move |r: &&'s R| conds.iter().all(|c| c.matches(*r));
+
+        if let Some(rowids) = self.ordered_rowids(conds, order_col, descending) {
+            return Box::new(rowids.map(move |rowi| &self.rows[&rowi]).filter(is_a_match));
+        }
+
+        let rowids = self.using_index(conds).filter(|rowi| is_a_match(&&self.rows[rowi]));
+        Box::new(self.sort_rows(rowids, order_col, descending).into_iter())
+    }
+
+    /// Returns the rows with the given identifiers, ordered by their value in column `order_col`
+    /// as described for `find_ordered`.
+    fn sort_rows<I>(&self, rowids: I, order_col: usize, descending: bool) -> Vec<&R>
+        where I: Iterator<Item = usize>
+    {
+        let mut rowids = rowids.collect::<Vec<_>>();
+        rowids.sort_unstable();
+        let mut rows = rowids.into_iter().map(|rowi| &self.rows[&rowi]).collect::<Vec<_>>();
+        if descending {
+            rows.sort_by(|a, b| b.index(order_col).cmp(a.index(order_col)));
+        } else {
+            rows.sort_by(|a, b| a.index(order_col).cmp(b.index(order_col)));
+        }
+        rows
+    }
+
+    /// Returns an iterator that yields the given columns of all rows matching all the given
+    /// `Condition`s. Each item holds the values of the requested columns, in the requested order.
+    /// The same column may be requested more than once.
+    ///
+    /// Panics if any of the requested columns is out of range for this `Store`.
+    pub fn find_project<'c, 's: 'c>(&'s self,
+                                    conds: &'c [cmp::Condition<'c, T>],
+                                    cols: &'c [usize])
+                                    -> Box<dyn Iterator<Item = Vec<&'s T>> + 'c> {
+        for &col in cols {
+            assert!(col < self.cols,
+                    "projected column {} is out of range for a store with {} columns",
+                    col,
+                    self.cols);
+        }
+        Box::new(self.find(conds).map(move |row| cols.iter().map(|&col| row.index(col)).collect()))
+    }
+
+    /// Returns the number of rows matching all the given `Condition`s.
+    ///
+    /// If there are no conditions, or if there is a single condition that can be answered
+    /// directly by an index, the count is computed without looking at any rows. Otherwise, this is
+    /// equivalent to `find(conds).count()`.
+    pub fn count(&self, conds: &[cmp::Condition<T>]) -> usize {
+        use EqualityIndex;
+        self.check(conds);
+        match *conds {
+            [] => return self.len(),
+            // the indices cannot tell which of their rows have been marked as deleted
+            [ref c] if self.dead == 0 => {
+                match (&c.cmp, self.complete_index(c.column)) {
+                    (cmp::Comparison::Equal(cmp::Value::Const(v)), Some(idx)) => {
+                        return idx.count(v);
+                    }
+                    (cmp, Some(Index::Range(ri))) if cmp.range().is_some() => {
+                        let (min, max) = cmp.range().unwrap();
+                        return ri.between(min, max).count();
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        self.find(conds).count()
+    }
+
+    /// Returns true if any row matches all the given `Condition`s.
+    ///
+    /// If there is a single equality condition that can be answered by an index, and the index
+    /// has no rows for the value, this returns false without looking at any rows. Otherwise, the
+    /// candidate rows are found as by `find`, and this stops at the first row that matches. Unlike
+    /// `find_one`, the rows are not boxed into an iterator first; only the index lookup itself
+    /// goes through `EqualityIndex::lookup`.
+    pub fn exists(&self, conds: &[cmp::Condition<T>]) -> bool {
+        use EqualityIndex;
+        self.check(conds);
+        if let [ref c] = *conds {
+            if let (cmp::Comparison::Equal(cmp::Value::Const(v)), Some(idx)) =
+                (&c.cmp, self.complete_index(c.column)) {
+                match idx.count(v) {
+                    0 => return false,
+                    // the index cannot tell which of its rows have been marked as deleted
+                    _ if self.dead == 0 => return true,
+                    _ => {}
+                }
+            }
+        }
+
+        let plan = self.plan(conds);
+        let matches = |rowid: &usize| {
+            !self.is_dead(*rowid) &&
+            conds.iter()
+                .enumerate()
+                .all(|(i, c)| plan.exact.holds(i) || c.matches(&self.rows[rowid]))
+        };
+        match plan.source {
+            Source::Empty => false,
+            Source::Scan => self.rows.keys().any(matches),
+            ref source => self.source_rowids(conds, source).any(|rowid| matches(&rowid)),
+        }
+    }
+
+    /// Returns the first row matching all the given `Condition`s, if any.
+    ///
+    /// This uses the same index selection as `find`, and stops as soon as a matching row is found.
+    pub fn find_one<'c, 's: 'c>(&'s self, conds: &'c [cmp::Condition<'c, T>]) -> Option<&'s R> {
+        self.find(conds).next()
+    }
+
+    /// Like `find_one`, but intended for conditions that should match at most one row (such as a
+    /// lookup by key). In debug builds, this method panics if more than one row matches.
+    pub fn find_single<'c, 's: 'c>(&'s self,
+                                   conds: &'c [cmp::Condition<'c, T>])
+                                   -> Option<&'s R> {
+        let mut rows = self.find(conds);
+        let row = rows.next();
+        debug_assert!(row.is_none() || rows.next().is_none(),
+                      "more than one row matched in find_single");
+        row
+    }
+
+    /// Returns an iterator that yields all rows matching all the `Condition`s of *any* of the
+    /// given branches. In other words, the conditions within each branch are `AND`ed together, and
+    /// the branches are then `OR`ed. Each matching row is yielded exactly once, in row order.
+    ///
+    /// Index selection is performed separately for each branch. If every branch can be satisfied
+    /// using an index, the union of the candidate rows from each index is filtered. If any branch
+    /// requires a full scan, all rows are scanned instead.
+    pub fn find_any<'c, 's: 'c>(&'s self,
+                                branches: &'c [Vec<cmp::Condition<'c, T>>])
+                                -> Box<dyn Iterator<Item = &'s R> + 'c> {
+        let is_a_match = move |r: &&'s R| {
+            branches.iter().any(|conds| conds.iter().all(|c| c.matches(*r)))
+        };
+
+        let candidates = branches.iter()
+            .map(|conds| self.best_index(conds))
+            .collect::<Option<Vec<_>>>();
+
+        match candidates {
+            Some(candidates) => {
+                let rows: BTreeSet<_> = candidates.into_iter().flatten().collect();
+                Box::new(rows.into_iter()
+                    .map(move |rowi| &self.rows[&rowi])
+                    .filter(is_a_match))
+            }
+            None => Box::new(self.rows().filter(is_a_match)),
+        }
+    }
+
+    /// Delete all rows that match the given conditions, and return the number of rows deleted.
+    ///
+    /// The deleted rows are also removed from all indices, so subsequent calls to `find` will
+    /// never yield them.
+    pub fn delete(&mut self, conds: &[cmp::Condition<T>]) -> usize {
+        self.delete_filter(conds, |_| true)
+    }
+
+    /// Delete all rows that match the given conditions *and* where the given filter function
+    /// returns true, and return the number of rows deleted.
+    pub fn delete_filter<F>(&mut self, conds: &[cmp::Condition<T>], f: F) -> usize
+        where F: FnMut(&R) -> bool
+    {
+        self.drain_filter(conds, f).len()
+    }
+
+    /// Remove all rows that match the given conditions from the `Store`, and return them in the
+    /// order they were inserted. The removed rows are also removed from all indices, just as with
+    /// `delete`.
+    ///
+    /// The remaining rows keep their identifiers, so row identifiers held onto across a call to
+    /// `drain_where` remain valid for the rows that were not removed.
+    pub fn drain_where(&mut self, conds: &[cmp::Condition<T>]) -> Vec<R> {
+        self.drain_filter(conds, |_| true)
+    }
+
+    /// Remove and return all rows that match the given conditions *and* where the given filter
+    /// function returns true, in the order they were inserted.
+    fn drain_filter<F>(&mut self, conds: &[cmp::Condition<T>], mut f: F) -> Vec<R>
+        where F: FnMut(&R) -> bool
+    {
+        self.flush_indices();
+
+        // find the rows we should delete
+        let mut rowids = self.using_index(conds)
+            .map(|rowi| (rowi, &self.rows[&rowi]))
+            .filter(move |&(_, row)| conds.iter().all(|c| c.matches(row)))
+            .filter(|&(_, row)| f(row))
+            .map(|(rowid, _)| rowid)
+            .collect::<Vec<_>>();
+        rowids.sort_unstable();
+
+        let deleted = rowids.into_iter()
+            .map(|rowid| (rowid, self.rows.remove(&rowid).unwrap()))
+            .collect::<Vec<_>>();
+
+        deleted.into_iter()
+            .map(|(rowid, row)| {
+                self.undex_row(rowid, &row);
+                self.log(oplog::Op::Delete { rowid });
+                row
+            })
+            .collect()
+    }
+
+    /// Remove all rows from the `Store`. All indices are kept, but are emptied.
+    ///
+    /// Row identifiers are not reset, so rows inserted after a `clear` are given identifiers that
+    /// were never used before it.
+    pub fn clear(&mut self) {
+        self.flush_indices();
+        self.complete_builds();
+        let rows = mem::take(&mut self.rows);
+        self.empty_indices(&rows);
+        self.tombstones.clear();
+        self.dead = 0;
+        self.log(oplog::Op::Clear);
+    }
+
+    /// Remove all the given rows, which must be all the rows that were in `self.rows`, from all
+    /// indices and views.
+    fn empty_indices(&mut self, rows: &BTreeMap<usize, R>) {
+        self.adopt_auto_indices();
+        if let Some(ref mut cache) = self.cache {
+            cache.clear();
+        }
+        for (_, view) in self.views.iter_mut() {
+            view.rows.clear();
+        }
+        for covering in self.covering.values_mut() {
+            covering.clear();
+        }
+        use EqualityIndex;
+        for (col, idx) in self.indices.iter_mut() {
+            if !idx.clear() {
+                for (rowid, row) in rows {
+                    idx.undex(row.index(*col), *rowid);
+                }
+            }
+        }
+        for (columns, idx) in self.composite.iter_mut() {
+            if !idx.clear() {
+                for (rowid, row) in rows {
+                    if let Some(key) = composite_key(columns, row) {
+                        idx.undex(&key, *rowid);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Mark the row with the given identifier as deleted, and return true. Returns false if no
+    /// such row exists, or if it has already been deleted.
+    ///
+    /// Unlike `delete`, this does not touch any index, and so is cheap regardless of how many
+    /// indices there are. The row is skipped by `find` and all other ways of reading rows from the
+    /// `Store`, but keeps taking up space, both in the `Store` and in its indices, until `compact`
+    /// is called. Until then, its value in any column with a unique index also remains taken.
+    pub fn mark_deleted(&mut self, rowid: usize) -> bool {
+        if !self.rows.contains_key(&rowid) || self.is_dead(rowid) {
+            return false;
+        }
+        if self.tombstones.len() <= rowid {
+            self.tombstones.resize(rowid + 1, false);
+        }
+        self.tombstones[rowid] = true;
+        self.dead += 1;
+        for (_, view) in self.views.iter_mut() {
+            view.rows.remove(&rowid);
+        }
+        self.log(oplog::Op::MarkDeleted { rowid });
+        true
+    }
+
+    /// Physically remove all rows that have been marked as deleted (see `mark_deleted`), and
+    /// rebuild all indices. Returns a map from the old identifier of every remaining row to its
+    /// new one.
+    ///
+    /// The remaining rows are given the identifiers `0..len()`, in the order they were inserted,
+    /// and rows inserted after compaction are given identifiers from `len()` onwards. This means
+    /// that, unlike otherwise, identifiers used before compaction may be handed out again, so any
+    /// identifiers held onto across a call to `compact` must be translated using the returned map.
+    pub fn compact(&mut self) -> BTreeMap<usize, usize> {
+        self.flush_indices();
+        self.complete_builds();
+        let rows = mem::take(&mut self.rows);
+        self.empty_indices(&rows);
+
+        let mut moved = BTreeMap::new();
+        self.rowid = 0;
+        for (rowid, row) in rows {
+            if self.is_dead(rowid) {
+                continue;
+            }
+            moved.insert(rowid, self.rowid);
+            self.index_row(self.rowid, &row);
+            self.rows.insert(self.rowid, row);
+            self.rowid += 1;
+        }
+        self.tombstones = Vec::new();
+        self.dead = 0;
+        self.mark_indexed();
+        self.log(oplog::Op::Compact);
+        moved
+    }
+
+    /// Keep only the first `len` rows of the `Store` (in the order they were inserted), and
+    /// delete the rest. The deleted rows are also removed from all indices. If the `Store` holds
+    /// `len` rows or fewer, this has no effect.
+    pub fn truncate(&mut self, len: usize) {
+        self.flush_indices();
+        let first = match self.rows.keys().filter(|&&rowid| !self.is_dead(rowid)).nth(len) {
+            Some(&rowid) => rowid,
+            None => return,
+        };
+        let removed = self.rows.split_off(&first);
+        for (rowid, row) in removed {
+            if self.is_dead(rowid) {
+                self.tombstones[rowid] = false;
+                self.dead -= 1;
+            }
+            self.undex_row(rowid, &row);
+            self.log(oplog::Op::Delete { rowid });
+        }
+    }
+
+    /// Reserve capacity for at least `additional` more rows to be inserted.
+    ///
+    /// The rows themselves are kept in a `BTreeMap`, which allocates as it grows and so has no
+    /// notion of capacity. The hint is instead forwarded to every index (see
+    /// `EqualityIndex::reserve`), which lets a `HashIndex` avoid re-hashing during a large batch of
+    /// insertions.
+    pub fn reserve(&mut self, additional: usize) {
+        use EqualityIndex;
+        for idx in self.indices.values_mut() {
+            idx.reserve(additional);
+        }
+        for (_, idx) in self.composite.iter_mut() {
+            idx.reserve(additional);
+        }
+    }
+
+    /// Release memory that is no longer in use by the indices, such as after many rows have been
+    /// deleted (see `EqualityIndex::shrink_to_fit`).
+    pub fn shrink_to_fit(&mut self) {
+        use EqualityIndex;
+        for idx in self.indices.values_mut() {
+            idx.shrink_to_fit();
+        }
+        for (_, idx) in self.composite.iter_mut() {
+            idx.shrink_to_fit();
+        }
+    }
+
+    /// Add the given row, which is not yet in `self.rows`, to all indices, and to every view it
+    /// matches. If this panics part of the way through, the row is first removed from the indices
+    /// and views it was already added to (see `IndexGuard`).
+    fn index_row(&mut self, rowid: usize, row: &R) {
+        use EqualityIndex;
+        self.adopt_auto_indices();
+        self.invalidate_cached(row);
+        // copy out every key first, so that a panicking `clone` leaves all indices untouched
+        let keys = self.indices.keys().map(|&col| row.index(col).clone()).collect::<Vec<_>>();
+        let composite = self.composite
+            .iter()
+            .map(|(columns, _)| composite_key(columns, row))
+            .collect::<Vec<_>>();
+
+        let mut guard = IndexGuard {
+            store: self,
+            rowid,
+            row,
+            indexed: 0,
+            composite: Vec::new(),
+            done: false,
+        };
+        for ((_, idx), key) in guard.store.indices.iter_mut().zip(keys) {
+            idx.index(key, rowid);
+            guard.indexed += 1;
+        }
+        for ((_, idx), key) in guard.store.composite.iter_mut().zip(composite) {
+            if let Some(ref key) = key {
+                idx.index(key.clone(), rowid);
+            }
+            guard.composite.push(key);
+        }
+        for (_, view) in guard.store.views.iter_mut() {
+            view.refresh(rowid, row);
+        }
+        guard.store.cover_row(rowid, row);
+        guard.done = true;
+    }
+
+    /// Replace the values of the given row in every dictionary-encoded column with the values held
+    /// by the dictionaries (see `dictionary_encode`).
+    fn intern(&mut self, row: &mut R) {
+        for dictionary in self.dictionaries.values_mut() {
+            dictionary.intern(row);
+        }
+    }
+
+    /// Remove the given row, which must no longer be in `self.rows`, from all indices and views.
+    fn undex_row(&mut self, rowid: usize, row: &R) {
+        use EqualityIndex;
+        self.adopt_auto_indices();
+        self.invalidate_cached(row);
+        for (col, idx) in self.indices.iter_mut() {
+            idx.undex(row.index(*col), rowid);
+        }
+        for (columns, idx) in self.composite.iter_mut() {
+            if let Some(key) = composite_key(columns, row) {
+                idx.undex(&key, rowid);
+            }
+        }
+        for (_, view) in self.views.iter_mut() {
+            view.rows.remove(&rowid);
+        }
+        for build in self.builds.iter_mut() {
+            build.remove(rowid, row);
+        }
+        self.uncover_row(rowid, row);
+    }
+
+    /// Run the given closure on every row matching the given conditions, and return the number of
+    /// rows that were updated.
+    ///
+    /// After the closure has modified a row, every index whose column changed value is updated
+    /// accordingly: the entry for the old value is removed, and one for the new value is added.
+    /// Indices over columns that did not change are not touched. The closure should not change the
+    /// number of columns in the row.
+    ///
+    /// The closure is given a copy of each row, which only replaces the row once it is known not
+    /// to conflict with any other row. If the closure gives a row the same value as another row in
+    /// a column with a unique index, this method panics, and that row is left as it was; the rows
+    /// updated before it keep their new values.
+    pub fn update<F>(&mut self, conds: &[cmp::Condition<T>], mut f: F) -> usize
+        where F: FnMut(&mut R),
+              R: Clone
+    {
+        self.adopt_auto_indices();
+        self.flush_indices();
+
+        // find the rows we should update
+        let rowids = self.using_index(conds)
+            .filter(|rowi| conds.iter().all(|c| c.matches(&self.rows[rowi])))
+            .collect::<Vec<_>>();
+
+        for &rowid in &rowids {
+            let mut row = self.rows[&rowid].clone();
+            f(&mut row);
+            self.update_row(rowid, row);
+            // logged right away, so that the log stays in step if a later row conflicts
+            if let Some(row) = self.logged(rowid) {
+                self.log(oplog::Op::Update { rowid, row });
+            }
+        }
+        rowids.len()
+    }
+
+    /// Replace the row with the given identifier, which must exist, with the given row, and update
+    /// every index whose column changed value. See `update`.
+    ///
+    /// Panics without modifying the `Store` if the new row conflicts with another row in a column
+    /// with a unique index.
+    fn update_row(&mut self, rowid: usize, mut new: R) {
+        debug_assert_eq!(new.columns(), self.cols);
+        if let Some((column, existing)) = self.unique_conflict(&new, Some(rowid)) {
+            panic!("row {} conflicts with row {} in unique column {} once updated",
+                   rowid,
+                   existing,
+                   column);
+        }
+        self.intern(&mut new);
+
+        let row = self.rows.get_mut(&rowid).unwrap();
+        let old = self.indices
+            .keys()
+            .map(|&col| (col, row.index(col).clone()))
+            .collect::<Vec<_>>();
+        let old_composite = self.composite
+            .iter()
+            .map(|(columns, _)| composite_key(columns, &*row))
+            .collect::<Vec<_>>();
+
+        if let Some(ref mut cache) = self.cache {
+            cache.invalidate(&*row);
+        }
+        for (&col, covering) in self.covering.iter_mut() {
+            covering.remove(row.index(col), rowid);
+        }
+        for build in self.builds.iter_mut() {
+            build.remove(rowid, &*row);
+        }
+        *row = new;
+
+        let row = &self.rows[&rowid];
+        for (col, old) in old {
+            let new = row.index(col);
+            if *new != old {
+                let idx = self.indices.get_mut(&col).unwrap();
+                idx.undex(&old, rowid);
+                idx.index(new.clone(), rowid);
+            }
+        }
+        for ((columns, idx), old) in self.composite.iter_mut().zip(old_composite) {
+            let new = composite_key(columns, row);
+            if new != old {
+                if let Some(old) = old {
+                    idx.undex(&old, rowid);
+                }
+                if let Some(new) = new {
+                    idx.index(new, rowid);
+                }
+            }
+        }
+        for (_, view) in self.views.iter_mut() {
+            view.refresh(rowid, row);
+        }
+        for (&col, covering) in self.covering.iter_mut() {
+            covering.add(row.index(col).clone(), rowid, row);
+        }
+        for build in self.builds.iter_mut() {
+            build.add(rowid, row);
+        }
+        if let Some(ref mut cache) = self.cache {
+            cache.invalidate(row);
+        }
+    }
+
+    /// Insert a new data row into the `Store`. The row **must** have the same number of columns as
+    /// specified when the `Store` was created. If it does not, the code will panic.
+    ///
+    /// Inserting a row has similar complexity to `BTreeMap::insert`, and *may* need to re-allocate
+    /// the backing memory for the `Store`. The insertion also updates all maintained indices,
+    /// which may also re-allocate.
+    ///
+    /// Returns the identifier of the newly inserted row, which can later be passed to `get`. Row
+    /// identifiers are never re-used, even if the row is later deleted. If the `Store` has a row
+    /// limit (see `set_max_rows`), inserting a row may evict the oldest row.
+    ///
+    /// If the row has the same value as an existing row in a column with a unique index (see
+    /// `EqualityIndex::is_unique`), this method panics without modifying the `Store`. Use
+    /// `try_insert` to handle these cases gracefully. Similarly, if cloning one of the row's values
+    /// or adding the row to one of the indices panics, the row is not added to any of the indices,
+    /// and the `Store` is left as it was.
+    pub fn insert(&mut self, row: R) -> usize {
+        match self.try_insert(row) {
+            Ok(rowid) => rowid,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Insert a new row holding a clone of each of the given values, and return its identifier.
+    /// This avoids building a row of the `Store`'s row type just to insert it: for a `Store` of
+    /// `Box<[T]>` rows, for example, the values are cloned straight into the new row, without
+    /// first being collected into a `Vec<T>`. A fixed-size array can be passed by reference.
+    ///
+    /// Panics in the same cases as `insert`, including if the slice does not have the same number
+    /// of columns as the `Store`. Use `try_insert_row` to handle these cases gracefully.
+    pub fn insert_row(&mut self, row: &[T]) -> usize
+        where R: for<'r> From<&'r [T]>
+    {
+        self.insert(R::from(row))
+    }
+
+    /// Like `insert_row`, but returns an error instead of panicking if the row cannot be
+    /// inserted. The error holds the row the values were cloned into.
+    pub fn try_insert_row(&mut self, row: &[T]) -> Result<usize, InsertError<R>>
+        where R: for<'r> From<&'r [T]>
+    {
+        self.try_insert(R::from(row))
+    }
+
+    /// Insert a new row converted from `row`, and return its identifier. This accepts anything
+    /// that converts into the `Store`'s row type, so a fixed-size array can be inserted into a
+    /// `Store` of `Vec<T>` or `Box<[T]>` rows as is, and a `Vec<T>` into a `Store` of `Box<[T]>`
+    /// rows. `insert` takes the row type itself, which lets the compiler infer it from the first
+    /// row inserted into a new `Store`.
+    ///
+    /// Panics in the same cases as `insert`. Use `try_insert_from` to handle these cases
+    /// gracefully.
+    pub fn insert_from<I: Into<R>>(&mut self, row: I) -> usize {
+        self.insert(row.into())
+    }
+
+    /// Like `insert_from`, but returns an error instead of panicking if the row cannot be
+    /// inserted. The error holds the converted row.
+    pub fn try_insert_from<I: Into<R>>(&mut self, row: I) -> Result<usize, InsertError<R>> {
+        self.try_insert(row.into())
+    }
+
+    /// Like `insert`, but returns an error instead of panicking if the row cannot be inserted. If
+    /// an error is returned, the `Store` is left unmodified, and the rejected row can be
+    /// recovered from the error using `InsertError::into_row`.
+    pub fn try_insert(&mut self, mut row: R) -> Result<usize, InsertError<R>> {
+        if row.columns() != self.cols {
+            return Err(InsertError::WrongWidth {
+                expected: self.cols,
+                got: row.columns(),
+                row,
+            });
+        }
+        if let Some((column, existing)) = self.unique_conflict(&row, None) {
+            return Err(InsertError::Duplicate {
+                column,
+                existing,
+                row,
+            });
+        }
+
+        let rowid = self.rowid;
+        self.intern(&mut row);
+        if self.pending.is_some() {
+            self.queue(&row);
+        } else {
+            self.index_row(rowid, &row);
+        }
+        self.rows.insert(self.rowid, row);
+        self.rowid += 1;
+        self.notify(rowid..self.rowid);
+        self.evict();
+        Ok(rowid)
+    }
+
+    /// Register a callback to be called for every row inserted into the `Store` from now on, with
+    /// the identifier of the new row and the row itself. Returns an identifier that can be given
+    /// to `remove_observer` to stop the callback from being called.
+    ///
+    /// Callbacks are called after the row has been added to the `Store` and all its indices, in
+    /// the order they were registered. Rows inserted using `insert_many` (and so also rows added by
+    /// committing a `Txn`) are reported one at a time, in order. Rows that replace an existing row
+    /// through `upsert`, or that are changed by `update`, are not reported.
+    pub fn on_insert(&mut self, callback: Observer<R>) -> ObserverId {
+        let id = ObserverId(self.next_observer);
+        self.next_observer += 1;
+        self.observers.push((id, callback));
+        id
+    }
+
+    /// Stop calling the callback with the given identifier (see `on_insert`), and return it.
+    /// Returns `None` if the callback has already been removed.
+    pub fn remove_observer(&mut self, id: ObserverId) -> Option<Observer<R>> {
+        let i = self.observers.iter().position(|&(oid, _)| oid == id)?;
+        Some(self.observers.remove(i).1)
+    }
+
+    /// Call every callback registered with `on_insert` for each of the given rows, which must all
+    /// have just been inserted, and pass their insertion to the log attached with `attach_log`.
+    fn notify(&mut self, rowids: ops::Range<usize>) {
+        if self.observers.is_empty() && self.log.is_none() {
+            return;
+        }
+        for (&rowid, row) in self.rows.range(rowids) {
+            for (_, observer) in self.observers.iter_mut() {
+                observer(rowid, row);
+            }
+            if let Some(ref mut log) = self.log {
+                let row = (0..self.cols).map(|col| row.index(col).clone()).collect();
+                log(oplog::Op::Insert { rowid, row });
+            }
+        }
+    }
+
+    /// Insert the given row, or replace the existing row with the same value in the key column
+    /// (see `with_key`). Returns the identifier of the inserted or replaced row; a replaced row
+    /// keeps its identifier.
+    ///
+    /// When a row is replaced, the old row is removed from every index before the new row is
+    /// added, so indices over columns that changed value no longer yield the row for its old
+    /// value.
+    ///
+    /// Panics if the `Store` has no key column, if the row has the wrong number of columns, or if
+    /// the row has the same value as some *other* row in a column with a unique index. In all
+    /// these cases, the `Store` is left unmodified.
+    pub fn upsert(&mut self, row: R) -> usize {
+        let key = self.key.expect("upsert requires a store with a key column");
+        assert!(row.columns() == self.cols,
+                "row has {} columns, but the store has {}",
+                row.columns(),
+                self.cols);
+        self.flush_indices();
+
+        let existing = {
+            let cond = [cmp::Condition {
+                            column: key,
+                            cmp: cmp::Comparison::Equal(cmp::Value::using(row.index(key))),
+                        }];
+            self.find_rowid(&cond)
+        };
+        let rowid = match existing {
+            Some(rowid) => rowid,
+            None => return self.insert(row),
+        };
+        if let Some((column, existing)) = self.unique_conflict(&row, Some(rowid)) {
+            panic!("row conflicts with row {} in unique column {}",
+                   existing,
+                   column);
+        }
+
+        self.replace_row(rowid, row);
+        if let Some(row) = self.logged(rowid) {
+            self.log(oplog::Op::Replace { rowid, row });
+        }
+        rowid
+    }
+
+    /// Replace the row with the given identifier, which must exist, with the given row, removing
+    /// the old row from every index before adding the new one.
+    fn replace_row(&mut self, rowid: usize, mut row: R) {
+        let old = self.rows.remove(&rowid).unwrap();
+        self.undex_row(rowid, &old);
+        self.intern(&mut row);
+        self.index_row(rowid, &row);
+        for build in self.builds.iter_mut() {
+            build.add(rowid, &row);
+        }
+        self.rows.insert(rowid, row);
+    }
+
+    /// Insert all the given rows into the `Store`, and return the range of identifiers assigned to
+    /// them.
+    ///
+    /// This is equivalent to calling `insert` for each row, but is faster for large batches: all
+    /// the rows are added first, and each index is then fed all the new rows in one pass, rather
+    /// than every index being updated for every row in turn.
+    ///
+    /// Every row is checked before any of them are inserted. If any row has the wrong number of
+    /// columns, or conflicts with an existing row (or another row in the batch) in a column with
+    /// a unique index, this method panics without modifying the `Store`.
+    pub fn insert_many<I: IntoIterator<Item = R>>(&mut self, rows: I) -> ops::Range<usize> {
+        let mut rows: Vec<_> = rows.into_iter().collect();
+
+        for row in &rows {
+            assert!(row.columns() == self.cols,
+                    "row has {} columns, but the store has {}",
+                    row.columns(),
+                    self.cols);
+        }
+        self.check_batch(&rows);
+
+        for row in &mut rows {
+            self.intern(row);
+        }
+        let start = self.rowid;
+        self.rowid += rows.len();
+        let lazy = self.pending.is_some();
+        if lazy {
+            for row in &rows {
+                self.queue(row);
+            }
+        } else {
+            self.reserve(rows.len());
+        }
+        self.rows.extend((start..).zip(rows));
+        if !lazy {
+            self.index_rows_from(start);
+        }
+        self.notify(start..self.rowid);
+        self.evict();
+        start..self.rowid
+    }
+
+    /// Panic if any of the given rows conflicts with an indexed row, or with another of the given
+    /// rows, in a column with a unique index or in a unique composite index.
+    fn check_batch<'a, I>(&self, rows: I)
+        where I: IntoIterator<Item = &'a R> + Clone,
+              R: 'a
+    {
+        use EqualityIndex;
+        for (&column, idx) in self.indices.iter().filter(|&(_, idx)| idx.is_unique()) {
+            let mut seen = BTreeSet::new();
+            // rows queued to be added to the indices are not yet in the index
+            let queued = self.unindexed()
+                .map(|(&rowid, row)| (row.index(column), rowid))
+                .collect::<BTreeMap<_, _>>();
+            for row in rows.clone() {
+                let value = row.index(column);
+                if let Some(existing) = idx.lookup(value).next().or(queued.get(value).copied()) {
+                    panic!("row conflicts with row {} in unique column {}",
+                           existing,
+                           column);
+                }
+                assert!(seen.insert(value),
+                        "rows in batch conflict in unique column {}",
+                        column);
+            }
+        }
+        for (key, idx) in self.composite.iter().filter(|(_, idx)| idx.is_unique()) {
+            let mut seen = BTreeSet::new();
+            let queued = self.unindexed()
+                .filter_map(|(&rowid, row)| composite_key(key, row).map(|k| (k, rowid)))
+                .collect::<BTreeMap<_, _>>();
+            for value in rows.clone().into_iter().filter_map(|row| composite_key(key, row)) {
+                if let Some(existing) = idx.lookup(&value).next().or(queued.get(&value).copied()) {
+                    panic!("row conflicts with row {} in unique column {}",
+                           existing,
+                           key.column());
+                }
+                assert!(seen.insert(value),
+                        "rows in batch conflict in unique column {}",
+                        key.column());
+            }
+        }
+    }
+
+    /// Add every row with an identifier of at least `start` to all indices and views, in a single
+    /// pass over those rows for each of them. None of the rows may have been indexed already.
+    fn index_rows_from(&mut self, start: usize) {
+        use EqualityIndex;
+        self.adopt_auto_indices();
+        let new = self.rows.range(start..);
+        for (column, idx) in self.indices.iter_mut() {
+            idx.index_many(new.clone()
+                .map(|(&rowid, row)| (row.index(*column).clone(), rowid))
+                .collect());
+        }
+        for (columns, idx) in self.composite.iter_mut() {
+            idx.index_many(new.clone()
+                .filter_map(|(&rowid, row)| composite_key(columns, row).map(|key| (key, rowid)))
+                .collect());
+        }
+        for (_, view) in self.views.iter_mut() {
+            for (rowid, row) in new.clone() {
+                view.refresh(*rowid, row);
+            }
+        }
+        for (&column, covering) in self.covering.iter_mut() {
+            for (&rowid, row) in new.clone() {
+                covering.add(row.index(column).clone(), rowid, row);
+            }
+        }
+        if let Some(ref mut cache) = self.cache {
+            for (_, row) in new {
+                cache.invalidate(row);
+            }
+        }
+    }
+
+    /// Move all rows from `other` into this `Store`, and return the range of identifiers assigned
+    /// to them. The rows are given new identifiers in the order they were inserted into `other`,
+    /// just as if they had been inserted with `insert_many`, and so all indices of this `Store`
+    /// are updated for the new rows in a single pass. The indices of `other` are discarded.
+    ///
+    /// Panics without modifying this `Store` if `other` has a different number of columns, or if
+    /// any of its rows conflicts with a row in this `Store` in a column with a unique index.
+    pub fn append(&mut self, other: Store<T, R>) -> ops::Range<usize> {
+        assert!(other.cols == self.cols,
+                "cannot append a store with {} columns to a store with {} columns",
+                other.cols,
+                self.cols);
+        self.insert_many(other.into_rows())
+    }
+
+    /// Start a transaction against this `Store`. Rows inserted through the returned `Txn` are only
+    /// added to the `Store` once it is committed, and are discarded if it is dropped.
+    pub fn transaction(&mut self) -> Txn<'_, T, R> {
+        Txn::new(self)
+    }
+
+    /// Start loading many rows into this `Store` at once. Rows inserted through the returned
+    /// `BulkLoader` are only added to the indices once it is finished, and are discarded if it is
+    /// dropped.
+    pub fn begin_bulk_load(&mut self) -> BulkLoader<'_, T, R> {
+        BulkLoader::new(self)
+    }
+
+    /// Returns an iterator that yields all rows in the given view (see `materialize`), in the
+    /// order they were inserted. No conditions are evaluated.
+    ///
+    /// Panics if the view has been dropped.
+    pub fn view(&self, id: ViewId) -> impl Iterator<Item = &R> + '_ {
+        let (_, view) = self.views
+            .iter()
+            .find(|&&(vid, _)| vid == id)
+            .expect("no such view");
+        self.check_indexed();
+        let queued = self.unindexed()
+            .filter(move |&(&rowid, row)| !self.is_dead(rowid) && (view.matches)(row))
+            .map(|(_, row)| row);
+        view.rows.iter().map(move |rowid| &self.rows[rowid]).chain(queued)
+    }
+
+    /// Stop maintaining the given view (see `materialize`), and return true. Returns false if the
+    /// view has already been dropped.
+    pub fn drop_view(&mut self, id: ViewId) -> bool {
+        let before = self.views.len();
+        self.views.retain(|&(vid, _)| vid != id);
+        self.views.len() != before
+    }
+
+    /// Find a row that has the same value as the given row in a column with a unique index, or the
+    /// same key in a unique composite index, and return that column (for a composite index, the
+    /// first of its columns) and the identifier of the existing row. The row being `replacing`
+    /// (if any) is not considered a conflict.
+    fn unique_conflict(&self, row: &R, replacing: Option<usize>) -> Option<(usize, usize)> {
+        use EqualityIndex;
+        let composite = self.composite
+            .iter()
+            .filter(|(_, idx)| idx.is_unique())
+            .filter_map(move |(key, idx)| {
+                let value = composite_key(key, row)?;
+                let queued = self.unindexed()
+                    .filter(|&(_, r)| composite_key(key, r).as_ref() == Some(&value))
+                    .map(|(&rowid, _)| rowid);
+                idx.lookup(&value)
+                    .chain(queued)
+                    .find(|&r| Some(r) != replacing)
+                    .map(|r| (key.column(), r))
+            });
+        self.indices
+            .iter()
+            .filter(|&(_, idx)| idx.is_unique())
+            .filter_map(|(&column, idx)| {
+                let value = row.index(column);
+                // rows queued to be added to the indices are not yet in the index
+                let queued = self.unindexed()
+                    .filter(move |&(_, r)| r.index(column) == value)
+                    .map(|(&rowid, _)| rowid);
+                idx.lookup(value)
+                    .chain(queued)
+                    .find(|&r| Some(r) != replacing)
+                    .map(|r| (column, r))
+            })
+            .chain(composite)
+            .next()
+    }
+
+    /// Returns the identifiers of the first row that holds the same value in the given column as
+    /// an earlier row, and of that earlier row, if there is such a row.
+    fn duplicate(&self, column: usize) -> Option<(usize, usize)> {
+        let mut seen = BTreeMap::new();
+        self.rows
+            .iter()
+            .find_map(|(&rowid, row)| seen.insert(row.index(column), rowid).map(|e| (rowid, e)))
+    }
+
+    /// Like `duplicate`, but for the keys of the given composite index. Rows that the index does
+    /// not hold, such as rows that are null in a sparse index, are skipped.
+    fn duplicate_key(&self, key: &CompositeKey<T>) -> Option<(usize, usize)> {
+        let mut seen = BTreeMap::new();
+        self.rows.iter().find_map(|(&rowid, row)| {
+            composite_key(key, row).and_then(|k| seen.insert(k, rowid)).map(|e| (rowid, e))
+        })
+    }
+
+    /// Returns the identifier of the first row matching all the given conditions, if any.
+    fn find_rowid(&self, conds: &[cmp::Condition<T>]) -> Option<usize> {
+        self.using_index(conds).find(|rowi| conds.iter().all(|c| c.matches(&self.rows[rowi])))
+    }
+
+    /// Look up the row with the given identifier (as returned by `insert`). Returns `None` if no
+    /// such row exists, or if it has since been deleted.
+    pub fn get(&self, row: usize) -> Option<&R> {
+        self.rows.get(&row).filter(|_| !self.is_dead(row))
+    }
+
+    /// Look up the row whose value in the key column (see `with_key`) is equal to the given key.
+    /// Returns `None` if there is no such row.
+    ///
+    /// Panics if the `Store` has no key column.
+    pub fn get_by_key(&self, key: &T) -> Option<&R> {
+        let column = self.key.expect("get_by_key requires a store with a key column");
+        let cond = [cmp::Condition {
+                        column,
+                        cmp: cmp::Comparison::Equal(cmp::Value::using(key)),
+                    }];
+        self.find_rowid(&cond).map(|rowid| &self.rows[&rowid])
+    }
+
+    /// Returns the key column of the `Store`, if it has one (see `with_key`).
+    pub fn key_column(&self) -> Option<usize> {
+        self.key
+    }
+
+    /// Returns the number of distinct values in the dictionary of the given column, or `None` if
+    /// the column is not dictionary-encoded (see `dictionary_encode`).
+    pub fn dictionary_len(&self, column: usize) -> Option<usize> {
+        self.dictionaries.get(&column).map(|dictionary| dictionary.len())
+    }
+
+    /// Returns the number of rows in the `Store`. Rows that have been marked as deleted are not
+    /// counted.
+    pub fn len(&self) -> usize {
+        self.rows.len() - self.dead
+    }
+
+    /// Returns true if the `Store` holds no rows.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of columns in the `Store`, as given when it was created.
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    /// Iterate over the columns that have a single-column index (see `index`), in ascending
+    /// column order. Columns that are only covered by a composite index (see `index_multi`) are
+    /// not included.
+    pub fn indexed_columns(&self) -> impl Iterator<Item = usize> + '_ {
+        self.indices.keys().cloned()
+    }
+
+    /// Iterate over all rows in the `Store`, in the order they were inserted.
+    ///
+    /// Unlike `find(&[])`, this does not evaluate any conditions or box the iterator, and so is
+    /// the cheapest way to walk the entire dataset. Iterating over `&Store` does the same.
+    pub fn rows(&self) -> Rows<'_, R> {
+        Rows {
+            rows: self.rows.iter(),
+            tombstones: &self.tombstones,
+        }
+    }
+
+    /// Iterate mutably over all rows in the `Store`, in the order they were inserted, *without*
+    /// keeping the indices up to date. This is meant for modifying many rows in place, such as
+    /// when normalizing a column, where `update` would have to look up and fix every index entry
+    /// one row at a time.
+    ///
+    /// Once this has been called, the indices and views are considered out of date, and every
+    /// query (including `find`, `count`, `delete`, `update`, and reading a view) panics until
+    /// `reindex` is called to rebuild them from the modified rows. So does every other method
+    /// that may read an index, such as `exists`, `find_ordered`, `find_many`, the aggregates
+    /// (`aggregate`, `group_by`, `distinct`, `column_stats`, and `top_k`), `join`, `analyze`, and
+    /// `histogram`. Rows must not be modified to have a different number of columns.
+    ///
+    /// Panics, before any row can be modified, if any index is not one of the built-in indices
+    /// (see `EqualityIndex::kind`), since `reindex` could then not be sure to rebuild it.
+    pub fn rows_mut_unindexed(&mut self) -> impl Iterator<Item = &mut R> + '_ {
+        self.adopt_auto_indices();
+        self.complete_builds();
+        self.check_rebuildable();
+        self.dirty = true;
+        let tombstones = &self.tombstones;
+        self.rows
+            .iter_mut()
+            .filter(move |&(&rowid, _)| !is_dead(tombstones, rowid))
+            .map(|(_, row)| row)
+    }
+
+    /// Panic if any index is not one of the built-in indices (see `EqualityIndex::kind`), and so
+    /// cannot be rebuilt by `reindex`: built-in indices can all be cleared, while other indices
+    /// may have to be told which rows to remove under which values.
+    fn check_rebuildable(&self) {
+        use EqualityIndex;
+        for (&column, idx) in &self.indices {
+            assert!(idx.kind().is_some(),
+                    "the index on column {} is not a built-in index, and cannot be rebuilt",
+                    column);
+        }
+        for (key, idx) in &self.composite {
+            assert!(idx.kind().is_some(),
+                    "the composite index on columns {:?} is not a built-in index, and cannot be \
+                     rebuilt",
+                    key.columns());
+        }
+    }
+
+    /// Rebuild every index and view from the rows as they are now, so that the `Store` can be
+    /// queried again after its rows were modified through `rows_mut_unindexed`. Histograms built
+    /// by `analyze` are discarded, and the query cache is emptied.
+    ///
+    /// This is about as costly as removing every index and adding it again with `index`, and so
+    /// is only worthwhile if many rows were modified. Calling this when no rows were modified
+    /// rebuilds the indices all the same.
+    ///
+    /// Panics without modifying any index if a row no longer has the number of columns the
+    /// `Store` was created with, or if the rows now conflict in a column with a unique index.
+    /// Also panics without modifying any index if an index that is not one of the built-in ones
+    /// (see `EqualityIndex::kind`) was added since `rows_mut_unindexed` was called, since there is
+    /// then no way of knowing which values it holds rows under.
+    pub fn reindex(&mut self) {
+        use EqualityIndex;
+        self.adopt_auto_indices();
+        self.complete_builds();
+        self.check_rebuildable();
+        for (&rowid, row) in &self.rows {
+            assert!(row.columns() == self.cols,
+                    "row {} has {} columns, but the store has {}",
+                    rowid,
+                    row.columns(),
+                    self.cols);
+        }
+        for (&column, _) in self.indices.iter().filter(|&(_, idx)| idx.is_unique()) {
+            if let Some((rowid, existing)) = self.duplicate(column) {
+                panic!("row {} conflicts with row {} in unique column {}",
+                       rowid,
+                       existing,
+                       column);
+            }
+        }
+        for (key, _) in self.composite.iter().filter(|(_, idx)| idx.is_unique()) {
+            if let Some((rowid, existing)) = self.duplicate_key(key) {
+                panic!("row {} conflicts with row {} in unique column {}",
+                       rowid,
+                       existing,
+                       key.column());
+            }
+        }
+
+        let mut rows = mem::take(&mut self.rows);
+        for row in rows.values_mut() {
+            self.intern(row);
+        }
+        self.rows = rows;
+        for (&column, idx) in self.indices.iter_mut() {
+            assert!(idx.clear(), "the index on column {} cannot be cleared", column);
+        }
+        for (_, idx) in self.composite.iter_mut() {
+            assert!(idx.clear(), "a composite index cannot be cleared");
+        }
+        for (_, view) in self.views.iter_mut() {
+            view.rows.clear();
+        }
+        for covering in self.covering.values_mut() {
+            covering.clear();
+        }
+        if let Some(ref mut cache) = self.cache {
+            cache.clear();
+        }
+        self.index_rows_from(0);
+        self.mark_indexed();
+        self.generation = next_generation();
+        self.dirty = false;
+
+        // the log cannot tell which rows were modified, so every row that could have been is sent
+        if self.log.is_some() {
+            let live = self.rows
+                .keys()
+                .copied()
+                .filter(|&rowid| !self.is_dead(rowid))
+                .collect::<Vec<_>>();
+            for rowid in live {
+                if let Some(row) = self.logged(rowid) {
+                    self.log(oplog::Op::Update { rowid, row });
+                }
+            }
+            self.log(oplog::Op::Reindex);
+        }
+    }
+
+    /// Panic if rows have been modified through `rows_mut_unindexed` since the indices were last
+    /// rebuilt with `reindex`.
+    fn check_indexed(&self) {
+        assert!(!self.dirty,
+                "rows were modified through rows_mut_unindexed; call reindex before querying");
+    }
+
+    /// Consume the `Store` and return all its rows, in the order they were inserted.
+    pub fn into_rows(self) -> Vec<R> {
+        let tombstones = self.tombstones;
+        self.rows
+            .into_iter()
+            .filter(|&(rowid, _)| !is_dead(&tombstones, rowid))
+            .map(|(_, row)| row)
+            .collect()
+    }
+
+    /// Add an index on the given colum using the given indexer. The indexer *must*, at the very
+    /// least, implement `EqualityIndex`. It *may* also implement other, more sophisticated,
+    /// indexing strategies outlined in `Index`.
+    ///
+    /// When an index is added, it is immediately fed all rows in the current dataset. Thus, adding
+    /// an index to a `Store` with many rows can be fairly costly. Keep this in mind!
+    ///
+    /// If the column already has an index, that index is replaced by the new one.
+    ///
+    /// Panics if the column does not exist, or if the index is unique (see
+    /// `EqualityIndex::is_unique`) and two rows hold the same value in the column. Use
+    /// `try_index` to handle these cases gracefully.
+    pub fn index<I: Into<Index<T>>>(&mut self, column: usize, indexer: I) {
+        if let Err(e) = self.try_index(column, indexer) {
+            panic!("{}", e);
+        }
+    }
+
+    /// Like `index`, but returns an error instead of panicking if the index cannot be added. If
+    /// an error is returned, the `Store` is left unmodified.
+    pub fn try_index<I: Into<Index<T>>>(&mut self, column: usize, indexer: I) -> Result<(), Error> {
+        use EqualityIndex;
+        if column >= self.cols {
+            return Err(Error::ColumnOutOfRange {
+                column,
+                cols: self.cols,
+            });
+        }
+        let mut idx = indexer.into();
+        if idx.is_unique() {
+            if let Some((_, existing)) = self.duplicate(column) {
+                return Err(Error::DuplicateKey { column, existing });
+            }
+        }
+        self.adopt_auto_indices();
+
+        // populate the new index; rows queued to be added to the indices are added to it later
+        for (rowid, row) in self.indexed_rows() {
+            idx.index(row.index(column).clone(), *rowid);
+        }
+
+        self.indices.insert(column, idx);
+        self.generation = next_generation();
+        Ok(())
+    }
+
+    /// Add a composite index over the given columns. The index is keyed by the values of all the
+    /// given columns (in the given order), and can be used to satisfy queries that compare *all*
+    /// of those columns for equality against constants with a single lookup. Queries that only
+    /// constrain some of the columns cannot use a composite index.
+    ///
+    /// If a composite index already exists for the exact same columns, it is replaced. Like
+    /// `index`, the new index is immediately fed all rows in the current dataset.
+    ///
+    /// A unique index (see `EqualityIndex::is_unique`) rejects rows that have the same values as
+    /// another row in all of the given columns, just as a unique index on a single column does;
+    /// conflicts are reported for the first of the columns. Panics if the index is unique and two
+    /// rows already have the same values in the columns.
+    pub fn index_multi<I: Into<Index<Vec<T>>>>(&mut self, columns: &[usize], indexer: I) {
+        self.composite.retain(|(key, _)| match *key {
+            CompositeKey::Columns(ref cs) => &cs[..] != columns,
+            CompositeKey::Computed(..) | CompositeKey::Sparse(..) => true,
+        });
+        self.add_composite(CompositeKey::Columns(columns.to_vec()), indexer.into());
+    }
+
+    /// Add an index over the keys computed by `key` from the values in the given column. For
+    /// example, a key function that lowercases strings gives an index for case-insensitive
+    /// lookups. The index is keyed by single-element vectors holding the computed keys.
+    ///
+    /// The index can be used to satisfy `Comparison::KeyEqual` conditions on the column against a
+    /// constant, provided that the condition's `KeyFn` has the same name as `key`. A column may
+    /// have several keyed indices, as long as their key functions have different names. If a
+    /// keyed index already exists for the same column and name, it is replaced. Like `index`, the
+    /// new index is immediately fed all rows in the current dataset.
+    ///
+    /// Panics if the index is unique and two rows already have the same key.
+    pub fn index_keyed<I>(&mut self, column: usize, key: cmp::KeyFn<T>, indexer: I)
+        where I: Into<Index<Vec<T>>>
+    {
+        self.composite.retain(|(k, _)| match *k {
+            CompositeKey::Computed(col, ref kf) => col != column || kf.name() != key.name(),
+            CompositeKey::Columns(..) | CompositeKey::Sparse(..) => true,
+        });
+        self.add_composite(CompositeKey::Computed(column, key), indexer.into());
+    }
+
+    /// Add an index over the values in the given column that are not null (see
+    /// `cmp::Nullable`). Rows that are null in the column are not added to the index at all, so
+    /// a column where most values are missing takes up little space in the index, and a single
+    /// very common null value does not skew the index's estimates. The index is keyed by
+    /// single-element vectors holding the values.
+    ///
+    /// The index can be used to satisfy `Comparison::Equal` conditions on the column against a
+    /// constant that is not null. Conditions that look for null values, including
+    /// `Comparison::IsNull`, must scan all rows (or use another index). If a sparse index
+    /// already exists for the column, it is replaced. Like `index`, the new index is immediately
+    /// fed all rows in the current dataset.
+    ///
+    /// Panics if the index is unique and two rows already have the same value that is not null.
+    pub fn index_sparse<I>(&mut self, column: usize, indexer: I)
+        where I: Into<Index<Vec<T>>>,
+              T: cmp::Nullable
+    {
+        self.composite.retain(|(k, _)| match *k {
+            CompositeKey::Sparse(col, _) => col != column,
+            CompositeKey::Columns(..) | CompositeKey::Computed(..) => true,
+        });
+        self.add_composite(CompositeKey::Sparse(column, cmp::Null::new()), indexer.into());
+    }
+
+    /// Feed all rows in the current dataset to the given index, and add it to `self.composite`.
+    ///
+    /// Panics if the index is unique and two rows have the same key.
+    fn add_composite(&mut self, key: CompositeKey<T>, mut idx: Index<Vec<T>>) {
+        use EqualityIndex;
+        if idx.is_unique() {
+            if let Some((_, existing)) = self.duplicate_key(&key) {
+                panic!("{}",
+                       Error::DuplicateKey {
+                           column: key.column(),
+                           existing,
+                       });
+            }
+        }
+
+        // populate the new index; rows queued to be added to the indices are added to it later
+        for (rowid, row) in self.indexed_rows() {
+            if let Some(k) = composite_key(&key, row) {
+                idx.index(k, *rowid);
+            }
+        }
+
+        self.composite.push((key, idx));
+        self.generation = next_generation();
+    }
+
+    /// Remove the index on the given column, and return it. Returns `None` if the column was not
+    /// indexed. Subsequent queries on the column will fall back to scanning all rows. If the index
+    /// was a covering index (see `index_covering`), its copies of the rows are dropped.
+    pub fn unindex(&mut self, column: usize) -> Option<Index<T>> {
+        self.adopt_auto_indices();
+        self.reset_auto_index(column);
+        self.generation = next_generation();
+        self.covering.remove(&column);
+        self.indices.remove(&column)
+    }
+
+    /// Returns statistics about the index on every indexed column, by column (see
+    /// `EqualityIndex::stats`). Composite indices are not included.
+    pub fn index_stats(&self) -> HashMap<usize, IndexStats> {
+        use EqualityIndex;
+        self.indices.iter().map(|(&col, idx)| (col, idx.stats())).collect()
+    }
+
+    /// Build a histogram with about `buckets` buckets of the values in the given column (see
+    /// `Histogram`), and keep it in the range index on that column, so that the number of rows
+    /// matching a range condition on the column can be estimated (see
+    /// `RangeIndex::estimate_range`). Returns false, and does nothing, if the column has no
+    /// `Index::Range`, or if that index cannot keep a histogram.
+    ///
+    /// Without a histogram, a range condition is expected to match as many rows as a single
+    /// value does, so `find` tends to prefer a range index even when an equality condition on
+    /// another column is far more selective. With one, the expected number of rows for each
+    /// condition is compared on equal footing.
+    ///
+    /// The histogram is not kept up to date as rows are inserted, updated, or deleted, so this
+    /// should be called again once the values in the column have changed substantially. Counts
+    /// are taken from the index if it can enumerate its values (see `EqualityIndex::keys`), and
+    /// from the rows otherwise; either way, rows marked as deleted are included until `compact`
+    /// is called, since the index still yields them.
+    pub fn analyze(&mut self, column: usize, buckets: usize) -> bool {
+        self.check_indexed();
+        self.adopt_auto_indices();
+        self.flush_indices();
+        let histogram = match self.indices.get(&column) {
+            Some(Index::Range(ri)) => {
+                match ri.keys() {
+                    Some(keys) => Histogram::new(keys.map(|key| (key, ri.count(key))), buckets),
+                    None => {
+                        let mut counts = BTreeMap::new();
+                        for row in self.rows.values() {
+                            *counts.entry(row.index(column)).or_insert(0) += 1;
+                        }
+                        Histogram::new(counts, buckets)
+                    }
+                }
+            }
+            _ => return false,
+        };
+        let kept = match self.indices.get_mut(&column) {
+            Some(Index::Range(ri)) => ri.set_histogram(histogram),
+            _ => unreachable!(),
+        };
+        // the estimates, and so the plans chosen, may have changed
+        self.generation = next_generation();
+        kept
+    }
+
+    /// Returns the histogram kept in the range index on the given column (see `analyze`), if
+    /// there is one.
+    pub fn histogram(&self, column: usize) -> Option<&Histogram<T>> {
+        self.check_indexed();
+        match self.indices.get(&column) {
+            Some(Index::Range(ri)) => ri.histogram(),
+            _ => None,
+        }
+    }
+
+    /// Check that the index on every indexed column agrees with the rows of the `Store`, and
+    /// return the first disagreement found. This is mostly useful when testing a custom index.
+    ///
+    /// Looking up each value in an indexed column must yield exactly the rows that hold that
+    /// value. If the index can enumerate its values (see `EqualityIndex::keys`), it must also not
+    /// yield any rows for values that no row holds. Rows that have been marked as deleted are
+    /// still expected to be in the indices. The same goes for the indices added with
+    /// `index_multi`, `index_keyed`, and `index_sparse`, for the key each of them computes from a
+    /// row, except that a sparse index must not hold the rows whose value is null. Covering
+    /// indices (see `index_covering`) must also hold an identical copy of every row, under its
+    /// value in the covered column.
+    pub fn check_integrity(&self) -> Result<(), IntegrityError<T>> {
+        for (&column, idx) in &self.indices {
+            let mut expected: BTreeMap<&T, Vec<usize>> = BTreeMap::new();
+            for (&rowid, row) in self.indexed_rows() {
+                expected.entry(row.index(column)).or_default().push(rowid);
+            }
+            check_index(column, idx, &expected)?;
+        }
+        for (key, idx) in &self.composite {
+            let columns = key.columns();
+            let mut expected: BTreeMap<Vec<T>, Vec<usize>> = BTreeMap::new();
+            for (&rowid, row) in self.indexed_rows() {
+                if let Some(key) = composite_key(key, row) {
+                    expected.entry(key).or_default().push(rowid);
+                }
+            }
+            check_index(0, idx, &expected).map_err(|e| match e {
+                    IntegrityError::Missing { value, row, .. } => {
+                        IntegrityError::MissingKey {
+                            columns,
+                            key: value,
+                            row,
+                        }
+                    }
+                    IntegrityError::Stale { value, row, .. } => {
+                        IntegrityError::StaleKey {
+                            columns,
+                            key: value,
+                            row,
+                        }
+                    }
+                    // check_index only ever reports a disagreement about a single value
+                    IntegrityError::MissingKey { .. } | IntegrityError::StaleKey { .. } => {
+                        unreachable!()
+                    }
+                })?;
+        }
+        self.check_covering()
+    }
+}
+
+impl<T, R> Store<T, R>
+    where T: Ord + Clone + Send + Sync + 'static,
+          R: Row<T>
+{
+    /// Register a view of the rows that match all the given conditions, and return its identifier.
+    /// The rows in the view can then be read using `view` without evaluating any conditions.
+    ///
+    /// The view is filled with the matching rows already in the `Store` when it is registered, and
+    /// is kept up to date from then on: every inserted row is checked against the conditions, and
+    /// rows that are deleted or no longer match after an `update` are removed. This makes every
+    /// modification of the `Store` somewhat more expensive, so views that are no longer needed
+    /// should be dropped using `drop_view`.
+    ///
+    /// Views are not preserved when a `Store` is serialized. Panics if any of the conditions refer
+    /// to a column that does not exist.
+    pub fn materialize(&mut self, conds: Vec<cmp::Condition<'static, T>>) -> ViewId {
+        self.check(&conds);
+        // rows queued to be added to the indices are added to the view along with them
+        let rows = self.using_index(&conds)
+            .filter(|&rowid| !self.is_queued(rowid))
+            .filter(|rowi| conds.iter().all(|c| c.matches(&self.rows[rowi])))
+            .collect();
+        let id = ViewId(self.next_view);
+        self.next_view += 1;
+        self.views.push((id,
+                         View {
+                             matches: sync::Arc::new(move |row| {
+                                 conds.iter().all(|c| c.matches(row))
+                             }),
+                             rows,
+                         }));
+        id
+    }
+}
+
+impl<T, R> Store<T, R>
+    where T: Ord + Clone + Send + Sync + 'static,
+          R: Row<T> + AsMut<[T]>
+{
+    /// Dictionary-encode the given column: keep a dictionary of the distinct values in the
+    /// column, and replace every inserted row's value in the column with the equal value from the
+    /// dictionary.
+    ///
+    /// This saves memory for columns that repeat a small set of large values, as long as cloning a
+    /// value shares it rather than copying it, as with `Arc<V>` (see `Store`). Each distinct value
+    /// is then stored once, and each row (and each index over the column) only holds a pointer to
+    /// it. Queries are unaffected, and still yield references to the values.
+    ///
+    /// Values stay in the dictionary after the rows that hold them are deleted. Dictionaries are
+    /// not preserved when a `Store` is serialized. Panics if the column does not exist, or if the
+    /// `Store` already has rows.
+    pub fn dictionary_encode(&mut self, column: usize) {
+        assert!(column < self.cols,
+                "column {} is out of range for a store with {} columns",
+                column,
+                self.cols);
+        assert!(self.rows.is_empty(),
+                "columns must be dictionary-encoded before any rows are inserted");
+        self.dictionaries.insert(column,
+                                 Box::new(Dictionary {
+                                     column,
+                                     values: BTreeSet::new(),
+                                 }));
+    }
+}
+
+impl<T, R> Store<T, R>
+    where T: Ord + Hash + Clone + Send + Sync + 'static,
+          R: Row<T>
+{
+    /// Allocate a new `Store` with the given number of columns, where rows are identified by
+    /// their value in the column `key`. The key column is given a unique `HashIndex`, so no two
+    /// rows can have the same key, and rows can be looked up with `get_by_key` and replaced with
+    /// `upsert`.
+    ///
+    /// Note that replacing or removing the index on the key column also removes the uniqueness
+    /// guarantee for rows added with `insert`.
+    pub fn with_key(cols: usize, key: usize) -> Store<T, R> {
+        assert!(key < cols,
+                "key column {} is out of range for a store with {} columns",
+                key,
+                cols);
+        let mut store = Store::new(cols);
+        store.index(key, idx::HashIndex::unique());
+        store.key = Some(key);
+        store
+    }
+}
+
+/// Cloning a `Store` copies its rows, views, and dictionaries. Its indices cannot be copied, since
+/// they are trait objects, so every built-in index is instead rebuilt from the copied rows (see
+/// `EqualityIndex::kind`), as is every built-in index that is still being built with
+/// `index_incremental`. Custom indices are not rebuilt, and so the clone has no index on their
+/// columns. Callbacks registered with `on_insert` are not carried over to the clone either. If
+/// this `Store` counts query statistics or caches query results, so does the clone, but its counts
+/// start from zero, and its cache starts out empty.
+impl<T, R> Clone for Store<T, R>
+    where T: Ord + Hash + Clone + Send + Sync + 'static,
+          R: Row<T> + Clone
+{
+    fn clone(&self) -> Self {
+        use EqualityIndex;
+        let mut store = Store {
+            cols: self.cols,
+            rowid: self.rowid,
+            rows: self.rows.clone(),
+            indices: BTreeMap::new(),
+            composite: Vec::new(),
+            covering: BTreeMap::new(),
+            key: self.key,
+            expiry: self.expiry,
+            tombstones: self.tombstones.clone(),
+            dead: self.dead,
+            max_rows: self.max_rows,
+            observers: Vec::new(),
+            next_observer: self.next_observer,
+            log: None,
+            views: self.views.clone(),
+            next_view: self.next_view,
+            dictionaries: self.dictionaries
+                .iter()
+                .map(|(&col, dictionary)| (col, dictionary.boxed_clone()))
+                .collect(),
+            auto_index: None,
+            stats: self.stats.as_ref().map(|_| stats::Collector::new(self.cols)),
+            query_observer: None,
+            cache: self.cache.as_ref().map(|cache| cache.boxed_empty()),
+            generation: next_generation(),
+            dirty: self.dirty,
+            pending: self.pending,
+            builds: Vec::new(),
+            next_build: self.next_build,
+        };
+        for (&col, idx) in &self.indices {
+            if let Some(kind) = idx.kind() {
+                store.index(col, kind.build());
+            }
+        }
+        // an index that is still being built (see `index_incremental`) is built in full
+        for build in &self.builds {
+            if let Some(kind) = build.kind() {
+                store.index(build.column(), kind.build());
+            }
+        }
+        for (key, idx) in &self.composite {
+            if let Some(kind) = idx.kind() {
+                store.add_composite(key.clone(), kind.build());
+            }
+        }
+        // copies of the rows are only used through the index on their column
+        for (&col, covering) in &self.covering {
+            if store.indices.contains_key(&col) {
+                store.covering.insert(col, covering.clone());
+            }
+        }
+        store
+    }
+}
+
+/// A `Store` is shown as its rows, by identifier, along with the kind of index on every indexed
+/// column (see `EqualityIndex::kind`; custom indices are shown as `None`). Rows that have been
+/// marked as deleted are not shown.
+impl<T, R: fmt::Debug> fmt::Debug for Store<T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use EqualityIndex;
+        struct LiveRows<'a, R: 'a>(&'a BTreeMap<usize, R>, &'a [bool]);
+        impl<'a, R: fmt::Debug> fmt::Debug for LiveRows<'a, R> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.debug_map()
+                    .entries(self.0.iter().filter(|&(&rowid, _)| !is_dead(self.1, rowid)))
+                    .finish()
+            }
+        }
+        let indices = self.indices
+            .iter()
+            .map(|(&col, idx)| (col, idx.kind()))
+            .collect::<BTreeMap<_, _>>();
+        f.debug_struct("Store")
+            .field("cols", &self.cols)
+            .field("rows", &LiveRows(&self.rows, &self.tombstones))
+            .field("indices", &indices)
+            .field("key", &self.key)
+            .finish()
+    }
+}
+
+/// An iterator over the rows of a `Store`, in the order they were inserted, as returned by
+/// `Store::rows`. Rows that have been marked as deleted are skipped.
+pub struct Rows<'a, R: 'a> {
+    rows: btree_map::Iter<'a, usize, R>,
+    tombstones: &'a [bool],
+}
+
+impl<'a, R> Iterator for Rows<'a, R> {
+    type Item = &'a R;
+
+    fn next(&mut self) -> Option<&'a R> {
+        let tombstones = self.tombstones;
+        self.rows.find(|&(&rowid, _)| !is_dead(tombstones, rowid)).map(|(_, row)| row)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.tombstones.is_empty() {
+            self.rows.size_hint()
+        } else {
+            (0, self.rows.size_hint().1)
+        }
+    }
+}
+
+impl<'a, R> DoubleEndedIterator for Rows<'a, R> {
+    fn next_back(&mut self) -> Option<&'a R> {
+        let tombstones = self.tombstones;
+        self.rows.rfind(|&(&rowid, _)| !is_dead(tombstones, rowid)).map(|(_, row)| row)
+    }
+}
+
+impl<'a, T, R> IntoIterator for &'a Store<T, R> {
+    type Item = &'a R;
+    type IntoIter = Rows<'a, R>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Rows {
+            rows: self.rows.iter(),
+            tombstones: &self.tombstones,
+        }
+    }
+}
+
+impl<T, R> Extend<R> for Store<T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Insert every row yielded by the iterator, as if by calling `insert_many`, so that every
+    /// index makes room for all the rows up front.
+    fn extend<I: IntoIterator<Item = R>>(&mut self, rows: I) {
+        self.insert_many(rows);
+    }
+}
+
+impl<T, R> iter::FromIterator<R> for Store<T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Build a `Store` that holds every row yielded by the iterator, as if by calling
+    /// `from_rows`. The number of columns is taken from the first row, and a `Store` built from
+    /// no rows at all has no columns. Panics if any other row has a different number of columns.
+    fn from_iter<I: IntoIterator<Item = R>>(rows: I) -> Self {
+        let rows = rows.into_iter().collect::<Vec<_>>();
+        let cols = rows.first().map_or(0, Row::columns);
+        Store::from_rows(cols, rows)
+    }
+}
+
+/// Returns true if the given conditions can never all hold for the same row, either because one
+/// of them matches nothing on its own, because the constant equality and range conditions on
+/// some column do not overlap (as in `col = 1 AND col = 2`), or because some column must be both
+/// null and not null.
+fn contradicts<T: Ord + Clone>(conds: &[cmp::Condition<T>]) -> bool {
+    conds.iter().any(|c| {
+        c.cmp.matches_nothing() ||
+        cmp::column_range(conds, c.column)
+            .map(|(min, max)| cmp::is_empty_range(min, max))
+            .unwrap_or(false) ||
+        matches!(c.cmp, cmp::Comparison::IsNull(..)) &&
+        conds.iter()
+            .any(|o| o.column == c.column && matches!(o.cmp, cmp::Comparison::IsNotNull(..)))
+    })
+}
+
+/// Returns true if the condition at position `i` of the given conditions need not be checked,
+/// since an identical condition comes before it, or since another condition implies it (see
+/// `Condition::implies`). Of two conditions that imply each other, only the later one is
+/// redundant.
+fn redundant<T: Ord + Clone>(conds: &[cmp::Condition<T>], i: usize) -> bool {
+    let c = &conds[i];
+    conds.iter().enumerate().any(|(j, o)| {
+        j < i && (o == c || o.implies(c)) || j > i && o.implies(c) && !c.implies(o)
+    })
+}
+
+/// Check that looking up each of the given values in the given index yields exactly the rows
+/// expected for it, and that the index yields no rows for any other value it can enumerate (see
+/// `EqualityIndex::keys`). A disagreement is reported as being in the given column. See
+/// `Store::check_integrity`.
+fn check_index<K, Q>(column: usize,
+                     idx: &Index<K>,
+                     expected: &BTreeMap<Q, Vec<usize>>)
+                     -> Result<(), IntegrityError<K>>
+    where K: Ord + Clone,
+          Q: Ord + Borrow<K>
+{
+    use EqualityIndex;
+    for (value, rows) in expected {
+        let value = value.borrow();
+        let mut found = idx.lookup(value).collect::<Vec<_>>();
+        found.sort_unstable();
+        if let Some(&row) = found.iter().find(|row| rows.binary_search(row).is_err()) {
+            return Err(IntegrityError::Stale {
+                column,
+                value: value.clone(),
+                row,
+            });
+        }
+        if let Some(&row) = rows.iter().find(|row| found.binary_search(row).is_err()) {
+            return Err(IntegrityError::Missing {
+                column,
+                value: value.clone(),
+                row,
+            });
+        }
+    }
+    for value in idx.keys().into_iter().flatten() {
+        if expected.contains_key(value) {
+            continue;
+        }
+        if let Some(row) = idx.lookup(value).next() {
+            return Err(IntegrityError::Stale {
+                column,
+                value: value.clone(),
+                row,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if the given tombstones (see `Store::mark_deleted`) mark the row with the given
+/// identifier as deleted.
+fn is_dead(tombstones: &[bool], rowid: usize) -> bool {
+    tombstones.get(rowid).cloned().unwrap_or(false)
+}
+
+/// Removes a row from the indices of a `Store` that `Store::index_row` has already added it to,
+/// unless `done` is set by the time it is dropped. This keeps the indices consistent with the rows
+/// of the `Store` if one of the indices, or a `clone` of one of the row's values, panics while the
+/// row is being indexed. The index that panicked is assumed not to have added the row.
+struct IndexGuard<'a, T, R>
+    where T: Ord + Clone + 'a,
+          R: Row<T> + 'a
+{
+    store: &'a mut Store<T, R>,
+    rowid: usize,
+    row: &'a R,
+    /// The number of indices in `Store::indices`, in order, that the row has been added to.
+    indexed: usize,
+    /// The key of the row in each index in `Store::composite` that it has been added to.
+    composite: Vec<Option<Vec<T>>>,
+    done: bool,
+}
+
+impl<'a, T, R> Drop for IndexGuard<'a, T, R>
+    where T: Ord + Clone + 'a,
+          R: Row<T> + 'a
+{
+    fn drop(&mut self) {
+        use EqualityIndex;
+        if self.done {
+            return;
+        }
+        let (rowid, row) = (self.rowid, self.row);
+        for (col, idx) in self.store.indices.iter_mut().take(self.indexed) {
+            idx.undex(row.index(*col), rowid);
+        }
+        for ((_, idx), key) in self.store.composite.iter_mut().zip(&self.composite) {
+            if let Some(ref key) = *key {
+                idx.undex(key, rowid);
+            }
+        }
+        // neither views nor covering indices mind being asked to remove a row they do not have
+        for (_, view) in self.store.views.iter_mut() {
+            view.rows.remove(&rowid);
+        }
+        self.store.uncover_row(rowid, row);
+    }
+}
+
+/// Extract the key for an index in `Store::composite` from a row. Returns `None` if the row does
+/// not belong in the index.
+fn composite_key<T, R>(key: &CompositeKey<T>, row: &R) -> Option<Vec<T>>
+    where T: Clone,
+          R: Row<T> + ?Sized
+{
+    match *key {
+        CompositeKey::Columns(ref columns) => {
+            Some(columns.iter().map(|&col| row.index(col).clone()).collect())
+        }
+        CompositeKey::Computed(col, ref kf) => Some(vec![kf.key(row.index(col))]),
+        CompositeKey::Sparse(col, ref null) => {
+            Some(row.index(col)).filter(|v| !null.is_null(v)).map(|v| vec![v.clone()])
+        }
+    }
+}
+
+/// Compute the key to look up in an index in `Store::composite` from the constants of the given
+/// conditions, as chosen by `Store::choose`.
+fn lookup_key<T: Clone>(key: &CompositeKey<T>, conds: &[cmp::Condition<T>], used: &[usize]) -> Vec<T> {
+    let value = |i: usize| match conds[i].cmp {
+        cmp::Comparison::Equal(cmp::Value::Const(ref v)) |
+        cmp::Comparison::KeyEqual(_, cmp::Value::Const(ref v)) => v,
+        _ => unreachable!(),
+    };
+    match *key {
+        CompositeKey::Columns(..) | CompositeKey::Sparse(..) => {
+            used.iter().map(|&i| value(i).clone().into_owned()).collect()
+        }
+        CompositeKey::Computed(_, ref kf) => vec![kf.key(value(used[0]))],
+    }
+}
+
+impl<T> Row<T> for &[T] {
+    fn index(&self, i: usize) -> &T {
+        &self[i]
+    }
+    fn columns(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T> Row<T> for [T] {
+    fn index(&self, i: usize) -> &T {
+        &self[i]
+    }
+    fn columns(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T> Row<T> for Vec<T> {
+    fn index(&self, i: usize) -> &T {
+        &self[i]
+    }
+    fn columns(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T, const N: usize> Row<T> for [T; N] {
+    fn index(&self, i: usize) -> &T {
+        &self[i]
+    }
+    fn columns(&self) -> usize {
+        N
+    }
+}
+
+impl<T> Row<T> for Box<[T]> {
+    fn index(&self, i: usize) -> &T {
+        &self[i]
+    }
+    fn columns(&self) -> usize {
+        self.len()
+    }
+}
+
+use std::sync;
+impl<T> Row<T> for sync::Arc<Vec<T>> {
+    fn index(&self, i: usize) -> &T {
+        &self[i]
+    }
+    fn columns(&self) -> usize {
+        self.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ops::Bound;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn it_works() {
+        let mut store = Store::new(2);
+        store.insert(vec!["a1", "a2"]);
+        store.insert(vec!["b1", "b2"]);
+        store.insert(vec!["c1", "c2"]);
+        assert_eq!(store.find(&[]).count(), 3);
+    }
+
+    #[test]
+    fn it_inserts_arrays() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        assert_eq!(store.insert(["a", "x"]), 0);
+        assert_eq!(store.insert(["b", "y"]), 1);
+        assert_eq!(store.find(&[Condition::eq(0, "b")]).collect::<Vec<_>>(), vec![&["b", "y"]]);
+
+        let mut wide = Store::<&str, [&str; 3]>::new(2);
+        match wide.try_insert(["a", "b", "c"]) {
+            Err(InsertError::WrongWidth { expected: 2, got: 3, row }) => {
+                assert_eq!(row, ["a", "b", "c"])
+            }
+            r => panic!("expected a width error, got {:?}", r),
+        }
+        assert!(wide.is_empty());
+    }
+
+    #[test]
+    fn it_inserts_slices() {
+        let values = ["a", "x", "b", "y", "c"];
+        let mut store: Store<&str> = Store::new(2);
+        assert_eq!(store.insert_row(&values[..2]), 0);
+        assert_eq!(store.insert_row(&["c", "z"]), 1);
+        assert_eq!(store.get(0), Some(&vec!["a", "x"]));
+        match store.try_insert_row(&values[2..]) {
+            Err(InsertError::WrongWidth { expected: 2, got: 3, .. }) => {}
+            r => panic!("expected a width error, got {:?}", r),
+        }
+
+        let mut boxed: Store<&str, Box<[&str]>> = Store::new(2);
+        assert_eq!(boxed.try_insert_row(&values[2..4]).unwrap(), 0);
+        assert_eq!(&boxed.get(0).unwrap()[..], &["b", "y"]);
+        match boxed.try_insert_row(&values[..1]) {
+            Err(e) => assert_eq!(&e.into_row()[..], &["a"]),
+            r => panic!("expected a width error, got {:?}", r),
+        }
+        assert_eq!(boxed.len(), 1);
+    }
+
+    #[test]
+    fn it_inserts_arrays_into_vec_rows() {
+        let mut store: Store<&str> = Store::new(2);
+        assert_eq!(store.insert_from(["a", "x"]), 0);
+        assert_eq!(store.insert_from(["b", "y"]), 1);
+        assert_eq!(store.get(1), Some(&vec!["b", "y"]));
+        match store.try_insert_from(["c"]) {
+            Err(InsertError::WrongWidth { expected: 2, got: 1, .. }) => {}
+            r => panic!("expected a width error, got {:?}", r),
+        }
+        assert_eq!(store.len(), 2);
+
+        let mut boxed: Store<&str, Box<[&str]>> = Store::new(2);
+        assert_eq!(boxed.insert_from(["a", "x"]), 0);
+        assert_eq!(boxed.insert_from(vec!["b", "y"]), 1);
+        assert_eq!(&boxed.get(1).unwrap()[..], &["b", "y"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "row has 3 columns, but the store has 2")]
+    fn it_rejects_wide_arrays() {
+        let mut store: Store<usize> = Store::new(2);
+        store.insert_from([1usize, 2, 3]);
+    }
+
+    #[test]
+    #[should_panic(expected = "row has 1 columns, but the store has 2")]
+    fn it_rejects_narrow_slices() {
+        let mut store: Store<usize> = Store::new(2);
+        store.insert_row(&[1]);
+    }
+
+    #[test]
+    fn it_try_inserts() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.index(1, idx::HashIndex::unique());
+        assert_eq!(store.try_insert(vec!["a", "x1"]).unwrap(), 0);
+
+        match store.try_insert(vec!["b"]) {
+            Err(InsertError::WrongWidth { expected: 2, got: 1, row }) => assert_eq!(row, vec!["b"]),
+            _ => unreachable!(),
+        }
+        match store.try_insert(vec!["b", "x1"]) {
+            Err(e @ InsertError::Duplicate { column: 1, existing: 0, .. }) => {
+                assert_eq!(e.into_row(), vec!["b", "x1"])
+            }
+            _ => unreachable!(),
+        }
+
+        // no index was touched by the failed inserts
+        let b = [cmp::Condition {
+                     column: 0,
+                     cmp: cmp::Comparison::Equal(cmp::Value::new("b")),
+                 }];
+        assert_eq!(store.find(&b).count(), 0);
+        assert_eq!(store.find(&[]).count(), 1);
+        assert_eq!(store.try_insert(vec!["b", "x2"]).unwrap(), 1);
+        assert_eq!(store.find(&b).count(), 1);
+    }
+
+    #[test]
+    fn it_try_inserts_w_composite_unique_indices() {
+        let lower = cmp::KeyFn::new("lower", |s: &String| s.to_lowercase());
+        let mut store = Store::new(3);
+        store.index_multi(&[0, 1], idx::HashIndex::unique());
+        store.index_keyed(2, lower, idx::HashIndex::unique());
+        let row = |a: usize, b: usize, c: &str| vec![a.to_string(), b.to_string(), c.to_string()];
+        assert_eq!(store.try_insert(row(1, 2, "a")).unwrap(), 0);
+        assert_eq!(store.try_insert(row(1, 3, "b")).unwrap(), 1);
+
+        match store.try_insert(row(1, 2, "c")) {
+            Err(e @ InsertError::Duplicate { column: 0, existing: 0, .. }) => {
+                assert_eq!(e.into_row(), row(1, 2, "c"))
+            }
+            r => panic!("expected a duplicate error, got {:?}", r),
+        }
+        match store.try_insert(row(2, 2, "B")) {
+            Err(InsertError::Duplicate { column: 2, existing: 1, .. }) => {}
+            r => panic!("expected a duplicate error, got {:?}", r),
+        }
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.check_integrity(), Ok(()));
+
+        // a row may keep its own key when it is updated
+        store.update(&[Condition::eq(1, "3".to_string())], |row| row[2] = "B".to_string());
+        assert_eq!(store.try_insert(row(2, 2, "c")).unwrap(), 2);
+        assert_eq!(store.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    #[should_panic(expected = "row conflicts with row 0 in unique column 1")]
+    fn it_rejects_composite_unique_indices_over_duplicates() {
+        let mut store = Store::new(3);
+        store.insert(vec![0usize, 1, 2]);
+        store.insert(vec![1usize, 1, 2]);
+        store.index_multi(&[1, 2], idx::HashIndex::unique());
+    }
+
+    #[test]
+    #[should_panic(expected = "row 1 conflicts with row 0 in unique column 0")]
+    fn it_rejects_composite_unique_conflicts_on_reindex() {
+        let mut store = Store::new(2);
+        store.index_multi(&[0, 1], idx::HashIndex::unique());
+        store.insert(vec![0usize, 0]);
+        store.insert(vec![1usize, 0]);
+        for row in store.rows_mut_unindexed() {
+            row[0] = 0;
+        }
+        store.reindex();
+    }
+
+    #[test]
+    #[should_panic(expected = "row has 1 columns, but the store has 2")]
+    fn it_rejects_wrong_width() {
+        let mut store = Store::new(2);
+        store.insert(vec!["a"]);
+    }
+
+    #[test]
+    fn it_validates() {
+        let mut store = Store::new(2);
+        store.insert(vec!["a", "x1"]);
+        let ok = [cmp::Condition {
+                      column: 1,
+                      cmp: cmp::Comparison::Equal(cmp::Value::column(0)),
+                  }];
+        assert!(store.validate(&ok).is_ok());
+        assert_eq!(store.try_find(&ok).unwrap().count(), 0);
+
+        let bad = [cmp::Condition {
+                       column: 2,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   }];
+        assert_eq!(store.validate(&bad),
+                   Err(QueryError::ColumnOutOfRange { column: 2, cols: 2 }));
+        assert!(store.try_find(&bad).is_err());
+
+        let bad = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::In(vec![cmp::Value::new("a"), cmp::Value::column(5)]),
+                   }];
+        assert_eq!(store.validate(&bad),
+                   Err(QueryError::ColumnOutOfRange { column: 5, cols: 2 }));
+    }
+
+    #[test]
+    fn it_tries_to_index() {
+        let mut store = Store::new(2);
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["b", "x1"]);
+        assert_eq!(store.try_index(2, idx::HashIndex::new()),
+                   Err(Error::ColumnOutOfRange { column: 2, cols: 2 }));
+        assert_eq!(store.try_index(1, idx::HashIndex::unique()),
+                   Err(Error::DuplicateKey {
+                       column: 1,
+                       existing: 0,
+                   }));
+        assert_eq!(store.indexed_columns().count(), 0);
+        assert_eq!(store.try_index(0, idx::HashIndex::unique()), Ok(()));
+        assert_eq!(store.indexed_columns().collect::<Vec<_>>(), vec![0]);
+    }
+
+    #[test]
+    fn it_converts_errors() {
+        type Rows = Vec<Vec<&'static str>>;
+        fn load(store: &mut Store<&'static str>, rows: Rows) -> Result<(), Error> {
+            for row in rows {
+                store.try_insert(row)?;
+            }
+            store.try_find(&[Condition::eq(3, "a")])?;
+            Ok(())
+        }
+
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::unique());
+        assert_eq!(load(&mut store, vec![vec!["a", "x1"], vec!["b"]]),
+                   Err(Error::WrongWidth {
+                       expected: 2,
+                       got: 1,
+                   }));
+        assert_eq!(load(&mut store, vec![vec!["a", "x2"]]),
+                   Err(Error::DuplicateKey {
+                       column: 0,
+                       existing: 0,
+                   }));
+        assert_eq!(load(&mut store, vec![]),
+                   Err(Error::ColumnOutOfRange { column: 3, cols: 2 }));
+        assert_eq!(Error::from(QueryError::ConflictingHints),
+                   Error::Query(QueryError::ConflictingHints));
+        assert_eq!(Error::DuplicateKey {
+                           column: 0,
+                           existing: 0,
+                       }
+                       .to_string(),
+                   "row conflicts with row 0 in unique column 0");
+    }
+
+    #[test]
+    #[should_panic(expected = "column 3 is out of range for a store with 2 columns")]
+    fn it_rejects_bad_index_columns() {
+        let mut store = Store::<usize>::new(2);
+        store.index(3, idx::HashIndex::new());
+    }
+
+    #[test]
+    #[should_panic(expected = "condition refers to column 3, but the store only has 2 columns")]
+    fn it_rejects_bad_conditions() {
+        let mut store = Store::new(2);
+        store.insert(vec!["a", "x1"]);
+        let bad = [cmp::Condition {
+                       column: 3,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   }];
+        // the panic happens before any rows are looked at
+        let _ = store.find(&bad);
+    }
+
+    #[test]
+    fn it_gets() {
+        let mut store = Store::new(2);
+        assert_eq!(store.insert(vec!["a1", "a2"]), 0);
+        assert_eq!(store.insert(vec!["b1", "b2"]), 1);
+        assert_eq!(store.insert(vec!["c1", "c2"]), 2);
+        assert_eq!(store.get(1), Some(&vec!["b1", "b2"]));
+        assert_eq!(store.get(3), None);
+
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("b1")),
+                   }];
+        store.delete(&cmp);
+        assert_eq!(store.get(1), None);
+        assert_eq!(store.insert(vec!["d1", "d2"]), 3);
+        assert_eq!(store.get(0), Some(&vec!["a1", "a2"]));
+    }
+
+    #[test]
+    fn it_finds_with_ids() {
+        let mut store = Store::new(2);
+        for i in 0..10usize {
+            store.insert(vec![i % 3, i]);
+        }
+        store.mark_deleted(3);
+        let check = |store: &Store<usize>, conds: &[cmp::Condition<usize>]| {
+            let found = store.find_with_ids(conds).collect::<Vec<_>>();
+            assert_eq!(found.iter().map(|&(_, row)| row).collect::<Vec<_>>(),
+                       store.find(conds).collect::<Vec<_>>());
+            for &(rowid, row) in &found {
+                assert_eq!(store.get(rowid), Some(row));
+                assert_eq!(row[1], rowid);
+            }
+            found.into_iter().map(|(rowid, _)| rowid).collect::<Vec<_>>()
+        };
+
+        let conds = [cmp::Condition::eq(0, 0usize)];
+        assert_eq!(store.explain(&conds).access, query::Access::Scan);
+        assert_eq!(check(&store, &conds), vec![0, 6, 9]);
+        assert_eq!(check(&store, &[]), vec![0, 1, 2, 4, 5, 6, 7, 8, 9]);
+
+        store.index(0, idx::HashIndex::new());
+        store.index(1, idx::BTreeIndex::new());
+        assert_eq!(store.explain(&conds).access, query::Access::Index(0));
+        assert_eq!(check(&store, &conds), vec![0, 6, 9]);
+        let conds = [cmp::Condition::ge(1, 5usize), cmp::Condition::ne(0, 2usize)];
+        assert_eq!(store.explain(&conds).access, query::Access::Index(1));
+        assert_eq!(check(&store, &conds), vec![6, 7, 9]);
+    }
+
+    #[test]
+    fn it_works_w_non_vec() {
+        use std::sync;
+        let mut store = Store::new(2);
+        store.insert(sync::Arc::new(vec!["a1", "a2"]));
+        store.insert(sync::Arc::new(vec!["b1", "b2"]));
+        store.insert(sync::Arc::new(vec!["c1", "c2"]));
+        assert_eq!(store.find(&[]).count(), 3);
+    }
+
+    #[test]
+    fn it_works_w_boxed_slices() {
+        assert!(mem::size_of::<Box<[usize]>>() < mem::size_of::<Vec<usize>>());
+
+        let mut vecs = Store::new(2);
+        let mut boxed: Store<usize, Box<[usize]>> = Store::new(2);
+        for i in 0..100usize {
+            vecs.insert(vec![i % 10, i]);
+            boxed.insert_from(vec![i % 10, i]);
+        }
+        let check = |vecs: &Store<usize>, boxed: &Store<usize, Box<[usize]>>| {
+            let cmp = [cmp::Condition {
+                           column: 0,
+                           cmp: cmp::Comparison::Equal(cmp::Value::new(3usize)),
+                       }];
+            let expected = vecs.find(&cmp).map(|r| &r[..]).collect::<Vec<_>>();
+            assert_eq!(boxed.find(&cmp).map(|r| &r[..]).collect::<Vec<_>>(), expected);
+            assert_eq!(boxed.len(), vecs.len());
+            assert_eq!(boxed.get(13).map(|r| &r[..]), vecs.get(13).map(|r| &r[..]));
+        };
+
+        check(&vecs, &boxed);
+        vecs.index(0, idx::HashIndex::new());
+        boxed.index(0, idx::HashIndex::new());
+        check(&vecs, &boxed);
+
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Less(cmp::Value::new(50usize)),
+                   }];
+        vecs.update(&cmp, |r| r[0] = 3);
+        boxed.update(&cmp, |r| r[0] = 3);
+        check(&vecs, &boxed);
+        vecs.delete(&cmp);
+        boxed.delete(&cmp);
+        check(&vecs, &boxed);
+    }
+
+    #[test]
+    fn it_works_with_indices() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.insert(vec!["a1", "a2"]);
+        store.insert(vec!["b1", "b2"]);
+        store.insert(vec!["c1", "c2"]);
+        assert_eq!(store.find(&[]).count(), 3);
+    }
+
+    #[test]
+    fn it_filters() {
+        let mut store = Store::new(2);
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["a", "x2"]);
+        store.insert(vec!["b", "x3"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   }];
+        assert_eq!(store.find(&cmp)
+                       .count(),
+                   2);
+        assert!(store.find(&cmp).all(|r| r[0] == "a"));
+    }
+
+    #[test]
+    fn it_filters_with_indices() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["a", "x2"]);
+        store.insert(vec!["b", "x3"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   }];
+        assert_eq!(store.find(&cmp)
+                       .count(),
+                   2);
+        assert!(store.find(&cmp).all(|r| r[0] == "a"));
+    }
+
+    #[test]
+    fn it_filters_with_partial_indices() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["a", "x2"]);
+        store.insert(vec!["b", "x3"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("x2")),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 1);
+        assert!(store.find(&cmp).all(|r| r[0] == "a" && r[1] == "x2"));
+    }
+
+    #[test]
+    fn it_filters_with_late_indices() {
+        let mut store = Store::new(2);
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["a", "x2"]);
+        store.insert(vec!["b", "x3"]);
+        store.index(0, idx::HashIndex::new());
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   }];
+        assert_eq!(store.find(&cmp)
+                       .count(),
+                   2);
+        assert!(store.find(&cmp).all(|r| r[0] == "a"));
+    }
+
+    /// A `RangeIndex` that counts how many row indices it has yielded, so that tests can check how
+    /// much work a query did.
+    struct CountingIndex<K: Ord> {
+        inner: idx::BTreeIndex<K>,
+        yielded: sync::Arc<AtomicUsize>,
+    }
+
+    impl<K: Ord> EqualityIndex<K> for CountingIndex<K> {
+        fn lookup<'a>(&'a self, key: &K) -> Box<dyn Iterator<Item = usize> + 'a> {
+            Box::new(self.inner.lookup(key).inspect(move |_| {
+                self.yielded.fetch_add(1, Ordering::SeqCst);
+            }))
+        }
+        fn index(&mut self, key: K, row: usize) {
+            self.inner.index(key, row)
+        }
+        fn undex(&mut self, key: &K, row: usize) {
+            self.inner.undex(key, row)
+        }
+        fn estimate(&self) -> usize {
+            self.inner.estimate()
+        }
+    }
+
+    impl<K: Ord> RangeIndex<K> for CountingIndex<K> {
+        fn between<'a>(&'a self,
+                       min: Bound<&K>,
+                       max: Bound<&K>)
+                       -> Box<dyn Iterator<Item = usize> + 'a> {
+            Box::new(self.inner.between(min, max).inspect(move |_| {
+                self.yielded.fetch_add(1, Ordering::SeqCst);
+            }))
+        }
+        fn between_rev<'a>(&'a self,
+                           min: Bound<&K>,
+                           max: Bound<&K>)
+                           -> Box<dyn Iterator<Item = usize> + 'a> {
+            Box::new(self.inner.between_rev(min, max).inspect(move |_| {
+                self.yielded.fetch_add(1, Ordering::SeqCst);
+            }))
+        }
+    }
+
+    #[test]
+    fn it_filters_not_equal() {
+        let mut store = Store::new(2);
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["a", "x2"]);
+        store.insert(vec!["b", "x3"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::NotEqual(cmp::Value::new("a")),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 1);
+        assert!(store.find(&cmp).all(|r| r[0] == "b"));
+    }
+
+    #[test]
+    fn it_filters_not_equal_with_indices() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.index(1, idx::HashIndex::new());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["a", "x2"]);
+        store.insert(vec!["b", "x1"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::NotEqual(cmp::Value::new("x1")),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 1);
+        assert!(store.find(&cmp).all(|r| r[0] == "a" && r[1] == "x2"));
+    }
+
+    #[test]
+    fn it_filters_in() {
+        let mut store = Store::new(2);
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["b", "x2"]);
+        store.insert(vec!["c", "x3"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::In(vec![cmp::Value::new("a"), cmp::Value::new("c")]),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 2);
+        assert!(store.find(&cmp).all(|r| r[0] == "a" || r[0] == "c"));
+    }
+
+    #[test]
+    fn it_filters_in_with_indices() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["a", "x2"]);
+        store.insert(vec!["b", "x3"]);
+        store.insert(vec!["c", "x4"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::In(vec![cmp::Value::new("a"),
+                                                     cmp::Value::new("c"),
+                                                     cmp::Value::new("a")]),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 3);
+        assert!(store.find(&cmp).all(|r| r[0] == "a" || r[0] == "c"));
+    }
+
+    #[test]
+    fn it_filters_empty_in() {
+        let mut store = Store::new(2);
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["b", "x2"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::In(vec![]),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 0);
+        store.index(0, idx::HashIndex::new());
+        assert_eq!(store.find(&cmp).count(), 0);
+    }
+
+    /// A `Row` that counts how many times its columns have been accessed.
+    struct CountingRow {
+        cols: Vec<usize>,
+        accessed: sync::Arc<AtomicUsize>,
+    }
+
+    impl Row<usize> for CountingRow {
+        fn index(&self, i: usize) -> &usize {
+            self.accessed.fetch_add(1, Ordering::SeqCst);
+            &self.cols[i]
+        }
+        fn columns(&self) -> usize {
+            self.cols.len()
+        }
+    }
+
+    #[test]
+    fn it_finds_ordered() {
+        let mut store = Store::new(2);
+        store.insert(vec![3, 0]);
+        store.insert(vec![1, 1]);
+        store.insert(vec![2, 2]);
+        store.insert(vec![1, 3]);
+        store.insert(vec![2, 4]);
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::NotEqual(cmp::Value::new(4)),
+                   }];
+        let check = |store: &Store<i32>| {
+            let rows: Vec<_> = store.find_ordered(&cmp, 0, false).map(|r| r[1]).collect();
+            assert_eq!(rows, vec![1, 3, 2, 0]);
+            let rows: Vec<_> = store.find_ordered(&cmp, 0, true).map(|r| r[1]).collect();
+            assert_eq!(rows, vec![0, 2, 1, 3]);
+        };
+
+        check(&store);
+        store.index(0, idx::HashIndex::new());
+        check(&store);
+        store.index(0, idx::BTreeIndex::new());
+        check(&store);
+    }
+
+    #[test]
+    fn it_finds_ordered_ranges() {
+        let yielded = sync::Arc::new(AtomicUsize::new(0));
+        let mut store = Store::new(1);
+        store.index(0,
+                    Index::Range(Box::new(CountingIndex {
+                        inner: idx::BTreeIndex::new(),
+                        yielded: yielded.clone(),
+                    })));
+        for i in 0..100usize {
+            store.insert(vec![(i * 37) % 100]);
+        }
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::GreaterOrEqual(cmp::Value::new(95usize)),
+                   }];
+        let rows: Vec<_> = store.find_ordered(&cmp, 0, true).map(|r| r[0]).collect();
+        assert_eq!(rows, vec![99, 98, 97, 96, 95]);
+        assert_eq!(yielded.load(Ordering::SeqCst), 5);
+    }
+
+    #[test]
+    fn it_limits() {
+        let mut store = Store::new(2);
+        for i in 0..10 {
+            store.insert(vec![i, i % 2]);
+        }
+        let odd = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(1)),
+                   }];
+        let rows: Vec<_> = store.query(&odd).offset(1).limit(2).iter().map(|r| r[0]).collect();
+        assert_eq!(rows, vec![3, 5]);
+        let rows: Vec<_> = store.query(&odd).offset(4).limit(2).iter().map(|r| r[0]).collect();
+        assert_eq!(rows, vec![9]);
+        assert_eq!(store.query(&odd).offset(5).iter().count(), 0);
+        assert_eq!(store.query(&[]).limit(3).iter().count(), 3);
+
+        store.index(0, idx::BTreeIndex::new());
+        let rows: Vec<_> = store.query(&odd)
+            .order_by(0, true)
+            .offset(1)
+            .limit(2)
+            .iter()
+            .map(|r| r[0])
+            .collect();
+        assert_eq!(rows, vec![7, 5]);
+    }
+
+    #[test]
+    fn it_limits_early() {
+        let accessed = sync::Arc::new(AtomicUsize::new(0));
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.index(1, idx::BTreeIndex::new());
+        for i in 0..10_000 {
+            store.insert(CountingRow {
+                cols: vec![i % 2, i],
+                accessed: accessed.clone(),
+            });
+        }
+
+        // scanning stops once the limit is reached
+        accessed.store(0, Ordering::SeqCst);
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::NotEqual(cmp::Value::new(0usize)),
+                   }];
+        let rows: Vec<_> = store.query(&cmp).limit(3).iter().map(|r| r.cols[1]).collect();
+        assert_eq!(rows, vec![1, 2, 3]);
+        assert_eq!(accessed.load(Ordering::SeqCst), 4);
+
+        // an index that answers the query exactly lets us skip rows without looking at them
+        accessed.store(0, Ordering::SeqCst);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(1usize)),
+                   }];
+        assert_eq!(store.query(&cmp).offset(1000).limit(2).iter().count(), 2);
+        assert_eq!(accessed.load(Ordering::SeqCst), 0);
+
+        accessed.store(0, Ordering::SeqCst);
+        let rows: Vec<_> = store.query(&[])
+            .order_by(1, true)
+            .offset(5000)
+            .limit(2)
+            .iter()
+            .map(|r| r.cols[1])
+            .collect();
+        assert_eq!(rows, vec![4999, 4998]);
+        assert_eq!(accessed.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn it_finds_latest_rows_first() {
+        let yielded = sync::Arc::new(AtomicUsize::new(0));
+        let mut store = Store::new(2);
+        store.index(0,
+                    Index::Range(Box::new(CountingIndex {
+                        inner: idx::BTreeIndex::new(),
+                        yielded: yielded.clone(),
+                    })));
+        // three events at each point in time
+        for i in 0..300usize {
+            store.insert(vec![i / 3, i]);
+        }
+
+        // the latest four events before time 50, found by walking the index backwards
+        let before = [Condition::lt(0, 50usize)];
+        let rows = store.query(&before)
+            .order_by(0, true)
+            .limit(4)
+            .iter()
+            .map(|r| r[1])
+            .collect::<Vec<_>>();
+        assert_eq!(rows, vec![147, 148, 149, 144]);
+        assert_eq!(yielded.load(Ordering::SeqCst), 4);
+
+        // both bounds can be exclusive
+        yielded.store(0, Ordering::SeqCst);
+        let between = [Condition::gt(0, 10usize), Condition::lt(0, 13usize)];
+        let rows = store.query(&between)
+            .order_by(0, true)
+            .iter()
+            .map(|r| r[1])
+            .collect::<Vec<_>>();
+        assert_eq!(rows, vec![36, 37, 38, 33, 34, 35]);
+        assert_eq!(yielded.load(Ordering::SeqCst), 6);
+    }
+
+    #[test]
+    fn it_knows_how_many_rows_it_finds() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        for i in 0..1000usize {
+            store.insert(vec![i % 10, i]);
+        }
+        let sevens = [Condition::eq(0, 7usize)];
+        match store.find(&sevens) {
+            Find::Exact(mut rows) => {
+                assert_eq!(rows.len(), 100);
+                rows.next();
+                assert_eq!(rows.size_hint(), (99, Some(99)));
+            }
+            _ => panic!("expected an exact lookup"),
+        }
+        let rows = store.find(&sevens).collect::<Vec<_>>();
+        assert_eq!((rows.len(), rows.capacity()), (100, 100));
+        assert_eq!(store.find(&[Condition::eq(0, 10usize)]).size_hint(), (0, Some(0)));
+        let never = [Condition::eq(0, 1usize), Condition::eq(0, 2usize)];
+        assert_eq!(store.find(&never).size_hint(), (0, Some(0)));
+
+        // with conditions left to check, the lookup gives an upper bound
+        let both = [Condition::eq(0, 7usize), Condition::lt(1, 500usize)];
+        let rows = store.find(&both);
+        assert!(matches!(rows, Find::Filtered(..)));
+        assert_eq!(rows.size_hint(), (0, Some(100)));
+        assert_eq!(rows.count(), 50);
+        let half = [Condition::lt(1, 500usize)];
+        let mut rows = store.find(&half);
+        assert_eq!(rows.size_hint(), (0, Some(1000)));
+        rows.next();
+        assert_eq!(rows.size_hint(), (0, Some(999)));
+        drop(rows);
+
+        // the length of the lookup includes rows marked as deleted
+        store.mark_deleted(7);
+        let rows = store.find(&sevens);
+        assert!(matches!(rows, Find::Filtered(..)));
+        assert_eq!(rows.size_hint(), (0, Some(100)));
+        assert_eq!(rows.count(), 99);
+    }
+
+    #[test]
+    fn it_finds_rows_from_any_source() {
+        let mut store = Store::new(2);
+        for i in 0..100usize {
+            store.insert(vec![i % 10, i]);
+        }
+        store.enable_query_stats();
+        store.mark_deleted(13);
+
+        // a scan walks the rows directly, and still skips the deleted ones
+        let threes = [Condition::eq(0, 3usize)];
+        assert_eq!(store.find(&threes).map(|r| r[1]).collect::<Vec<_>>(),
+                   (0..10).map(|i| i * 10 + 3).filter(|&i| i != 13).collect::<Vec<_>>());
+        let stats = store.query_stats();
+        assert_eq!((stats.rows_examined, stats.rows_returned), (99, 9));
+
+        // an index that cannot hand out its rows as a slice is looked up as before
+        let yielded = sync::Arc::new(AtomicUsize::new(0));
+        store.index(0,
+                    Index::Range(Box::new(CountingIndex {
+                        inner: idx::BTreeIndex::new(),
+                        yielded: yielded.clone(),
+                    })));
+        let rows = store.find(&threes).collect::<Vec<_>>();
+        assert_eq!(rows.len(), 9);
+        assert_eq!(yielded.load(Ordering::SeqCst), 10);
+
+        // a built-in index is walked directly
+        store.index(0, idx::HashIndex::new());
+        store.reset_query_stats();
+        let both = [Condition::eq(0, 4usize), Condition::lt(1, 50usize)];
+        assert_eq!(store.find(&both).map(|r| r[1]).collect::<Vec<_>>(),
+                   vec![4, 14, 24, 34, 44]);
+        let stats = store.query_stats();
+        assert_eq!((stats.rows_examined, stats.rows_returned), (10, 5));
+    }
+
+    #[test]
+    fn it_cuts_queries_short() {
+        use std::sync::atomic::AtomicBool;
+        let mut store = Store::new(2);
+        store.index(1, idx::BTreeIndex::new());
+        for i in 0..10_000usize {
+            store.insert(vec![i % 3, i]);
+        }
+        let ones = [Condition::eq(0, 1usize)];
+
+        // a budget bounds the number of rows examined, not the number produced
+        let collected = store.query(&ones).budget(100).try_collect().unwrap();
+        assert_eq!(collected.rows.len(), 33);
+        assert!(collected.truncated);
+        let collected = store.query(&ones).budget(10_000).try_collect().unwrap();
+        assert_eq!(collected.rows.len(), 3333);
+        assert!(!collected.truncated);
+        let collected = store.query(&ones).limit(5).budget(100).try_collect().unwrap();
+        assert_eq!(collected.rows.len(), 5);
+        assert!(!collected.truncated);
+        let small = [Condition::lt(1, 50usize)];
+        let collected = store.query(&small).budget(50).try_collect().unwrap();
+        assert_eq!((collected.rows.len(), collected.truncated), (50, false));
+        let collected = store.query(&small).order_by(0, false).budget(10).try_collect().unwrap();
+        assert_eq!(collected.rows.iter().map(|r| r[1]).collect::<Vec<_>>(),
+                   vec![0, 3, 6, 9, 1, 4, 7, 2, 5, 8]);
+        assert!(collected.truncated);
+
+        // a cancelled query stops within the next 1024 rows
+        let token = AtomicBool::new(false);
+        let mut rows = store.query(&ones).cancel_on(&token).iter();
+        assert_eq!(rows.by_ref().take(1000).count(), 1000);
+        token.store(true, Ordering::SeqCst);
+        assert_eq!(rows.count(), 3072 / 3 - 1000);
+
+        // one cancelled before it starts examines nothing
+        let collected = store.query(&ones).cancel_on(&token).try_collect().unwrap();
+        assert_eq!(collected.rows.len(), 0);
+        assert!(collected.truncated);
+        token.store(false, Ordering::SeqCst);
+        let collected = store.query(&ones).cancel_on(&token).try_collect().unwrap();
+        assert_eq!((collected.rows.len(), collected.truncated), (3333, false));
+        assert!(store.query(&[Condition::eq(5, 1usize)]).budget(1).try_collect().is_err());
+    }
+
+    #[test]
+    fn it_projects() {
+        let mut store = Store::new(3);
+        store.insert(vec!["a", "x1", "y1"]);
+        store.insert(vec!["b", "x2", "y2"]);
+        store.insert(vec!["a", "x3", "y3"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   }];
+        let rows: Vec<_> = store.find_project(&cmp, &[2, 0]).collect();
+        assert_eq!(rows, vec![vec![&"y1", &"a"], vec![&"y3", &"a"]]);
+        let rows: Vec<_> = store.find_project(&cmp, &[1, 1]).collect();
+        assert_eq!(rows, vec![vec![&"x1", &"x1"], vec![&"x3", &"x3"]]);
+        assert!(store.find_project(&[], &[]).all(|r| r.is_empty()));
+    }
+
+    #[test]
+    #[should_panic(expected = "projected column 3 is out of range for a store with 3 columns")]
+    fn it_rejects_bad_projections() {
+        let mut store = Store::new(3);
+        store.insert(vec!["a", "x1", "y1"]);
+        store.find_project(&[], &[0, 3]).count();
+    }
+
+    #[test]
+    fn it_counts() {
+        let mut store = Store::new(2);
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["a", "x2"]);
+        store.insert(vec!["b", "x3"]);
+        let a = [cmp::Condition {
+                     column: 0,
+                     cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                 }];
+        assert_eq!(store.count(&[]), 3);
+        assert_eq!(store.count(&a), 2);
+        store.index(0, idx::HashIndex::new());
+        assert_eq!(store.count(&a), 2);
+        let ax = [a[0].clone(),
+                  cmp::Condition {
+                      column: 1,
+                      cmp: cmp::Comparison::Equal(cmp::Value::new("x2")),
+                  }];
+        assert_eq!(store.count(&ax), 1);
+    }
+
+    #[test]
+    fn it_counts_without_rows() {
+        let accessed = sync::Arc::new(AtomicUsize::new(0));
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.index(1, idx::BTreeIndex::new());
+        for i in 0..100_000 {
+            store.insert(CountingRow {
+                cols: vec![i % 100, i],
+                accessed: accessed.clone(),
+            });
+        }
+
+        accessed.store(0, Ordering::SeqCst);
+        let eq = [cmp::Condition {
+                      column: 0,
+                      cmp: cmp::Comparison::Equal(cmp::Value::new(7usize)),
+                  }];
+        assert_eq!(store.count(&eq), 1000);
+        let range = [cmp::Condition {
+                         column: 1,
+                         cmp: cmp::Comparison::Less(cmp::Value::new(500usize)),
+                     }];
+        assert_eq!(store.count(&range), 500);
+        assert_eq!(store.count(&[]), 100_000);
+        assert_eq!(accessed.load(Ordering::SeqCst), 0);
+
+        // extra conditions need to look at the rows
+        let both = [eq[0].clone(), range[0].clone()];
+        assert_eq!(store.count(&both), 5);
+        assert!(accessed.load(Ordering::SeqCst) > 0);
+    }
+
+    #[test]
+    fn it_checks_existence() {
+        let accessed = sync::Arc::new(AtomicUsize::new(0));
+        let mut store = Store::new(2);
+        for i in 0..1000 {
+            store.insert(CountingRow {
+                cols: vec![i % 100, i],
+                accessed: accessed.clone(),
+            });
+        }
+
+        // without an index, every row is scanned before giving up
+        accessed.store(0, Ordering::SeqCst);
+        assert!(!store.exists(&[cmp::Condition::eq(0, 100usize)]));
+        assert!(accessed.load(Ordering::SeqCst) >= 1000);
+        // but the scan stops at the first match
+        accessed.store(0, Ordering::SeqCst);
+        assert!(store.exists(&[cmp::Condition::eq(0, 3usize)]));
+        assert!(accessed.load(Ordering::SeqCst) <= 4);
+        assert!(store.exists(&[]));
+
+        store.index(0, idx::HashIndex::new());
+        store.index(1, idx::BTreeIndex::new());
+        accessed.store(0, Ordering::SeqCst);
+        assert!(!store.exists(&[cmp::Condition::eq(0, 100usize)]));
+        assert!(store.exists(&[cmp::Condition::eq(0, 3usize)]));
+        assert!(!store.exists(&[cmp::Condition::eq(0, 3usize), cmp::Condition::eq(0, 4usize)]));
+        assert_eq!(accessed.load(Ordering::SeqCst), 0);
+
+        // rows marked as deleted do not exist, even if the index still has them
+        for rowid in (3..1000).step_by(100) {
+            store.mark_deleted(rowid);
+        }
+        assert!(!store.exists(&[cmp::Condition::eq(0, 3usize)]));
+        assert!(store.exists(&[cmp::Condition::eq(0, 4usize)]));
+        assert!(store.exists(&[cmp::Condition::eq(0, 4usize), cmp::Condition::gt(1, 900usize)]));
+        assert!(!store.exists(&[cmp::Condition::eq(0, 4usize), cmp::Condition::gt(1, 904usize)]));
+
+        store.truncate(0);
+        assert!(!store.exists(&[]));
+    }
+
+    #[test]
+    fn it_aggregates() {
+        let mut store = Store::new(2);
+        for &(a, b) in &[(1, 5), (2, 3), (1, 9), (2, 4), (1, 1)] {
+            store.insert(vec![a, b]);
+        }
+        let ones = [cmp::Condition {
+                        column: 0,
+                        cmp: cmp::Comparison::Equal(cmp::Value::new(1usize)),
+                    }];
+        assert_eq!(store.aggregate(&ones, 1),
+                   Aggregate {
+                       count: 3,
+                       min: Some(&1),
+                       max: Some(&9),
+                   });
+        assert_eq!(store.sum::<usize>(&ones, 1), 15);
+        assert_eq!(store.aggregate(&[], 1),
+                   Aggregate {
+                       count: 5,
+                       min: Some(&1),
+                       max: Some(&9),
+                   });
+
+        // with no matching rows
+        let threes = [cmp::Condition {
+                          column: 0,
+                          cmp: cmp::Comparison::Equal(cmp::Value::new(3usize)),
+                      }];
+        assert_eq!(store.aggregate(&threes, 1),
+                   Aggregate {
+                       count: 0,
+                       min: None,
+                       max: None,
+                   });
+        assert_eq!(store.sum::<usize>(&threes, 1), 0);
+    }
+
+    #[test]
+    fn it_aggregates_with_range_indices() {
+        let accessed = sync::Arc::new(AtomicUsize::new(0));
+        let mut store = Store::new(2);
+        store.index(1, idx::BTreeIndex::new());
+        let empty = store.aggregate(&[], 1);
+        assert_eq!((empty.count, empty.min, empty.max), (0, None, None));
+        for i in 0..1000 {
+            store.insert(CountingRow {
+                cols: vec![i % 10, (i * 7) % 1000],
+                accessed: accessed.clone(),
+            });
+        }
+        store.mark_deleted(0);
+
+        // only the rows at either end of the index are looked at
+        accessed.store(0, Ordering::SeqCst);
+        let agg = store.aggregate(&[], 1);
+        assert_eq!((agg.count, agg.min, agg.max), (999, Some(&1), Some(&999)));
+        assert_eq!(accessed.load(Ordering::SeqCst), 2);
+
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(3usize)),
+                   }];
+        let agg = store.aggregate(&cmp, 1);
+        assert_eq!((agg.count, agg.min, agg.max), (100, Some(&1), Some(&991)));
+    }
+
+    #[test]
+    fn it_groups() {
+        let mut store = Store::new(2);
+        for &(a, b) in &[("a", "1"), ("b", "2"), ("a", "3"), ("c", "4"), ("b", "5")] {
+            store.insert(vec![a, b]);
+        }
+        let groups = store.group_by(&[], 0)
+            .map(|(k, rows)| (*k, rows.into_iter().map(|r| r[1]).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        assert_eq!(groups,
+                   vec![("a", vec!["1", "3"]), ("b", vec!["2", "5"]), ("c", vec!["4"])]);
+
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Greater(cmp::Value::new("2")),
+                   }];
+        let groups = store.group_by(&cmp, 0)
+            .map(|(k, rows)| (*k, rows.into_iter().map(|r| r[1]).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        assert_eq!(groups, vec![("a", vec!["3"]), ("b", vec!["5"]), ("c", vec!["4"])]);
+    }
+
+    #[test]
+    fn it_groups_with_indices() {
+        let accessed = sync::Arc::new(AtomicUsize::new(0));
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        for i in 0..100 {
+            store.insert(CountingRow {
+                cols: vec![i % 3, i],
+                accessed: accessed.clone(),
+            });
+        }
+        store.delete(&[cmp::Condition {
+                           column: 1,
+                           cmp: cmp::Comparison::Equal(cmp::Value::new(4usize)),
+                       }]);
+        store.mark_deleted(7);
+
+        // the groups come straight from the index
+        accessed.store(0, Ordering::SeqCst);
+        let mut groups = store.group_by(&[], 0)
+            .map(|(&k, rows)| (k, rows.len(), rows[0].cols[1]))
+            .collect::<Vec<_>>();
+        assert_eq!(accessed.load(Ordering::SeqCst), 0);
+        groups.sort();
+        assert_eq!(groups, vec![(0, 34, 0), (1, 31, 1), (2, 33, 2)]);
+
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Less(cmp::Value::new(10usize)),
+                   }];
+        let groups = store.group_by(&cmp, 0)
+            .map(|(&k, rows)| (k, rows.into_iter().map(|r| r.cols[1]).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        assert_eq!(groups, vec![(0, vec![0, 3, 6, 9]), (1, vec![1]), (2, vec![2, 5, 8])]);
+    }
+
+    #[test]
+    fn it_finds_distinct_values() {
+        let mut store = Store::new(3);
+        for &(a, b) in &[(3, 1), (1, 2), (3, 3), (2, 4), (1, 5)] {
+            store.insert(vec![a, b, 0]);
+        }
+        assert_eq!(store.distinct(0).cloned().collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(store.cardinality(0), 3);
+        assert_eq!(store.distinct(2).cloned().collect::<Vec<_>>(), vec![0]);
+        assert_eq!(store.cardinality(2), 1);
+        assert_eq!(store.cardinality(1), 5);
+
+        store.index(0, idx::BTreeIndex::new());
+        store.index(2, idx::HashIndex::new());
+        assert_eq!(store.distinct(0).cloned().collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(store.distinct(2).cloned().collect::<Vec<_>>(), vec![0]);
+        assert_eq!(store.cardinality(2), 1);
+
+        // values only held by deleted rows are not yielded
+        store.mark_deleted(3);
+        assert_eq!(store.distinct(0).cloned().collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(store.cardinality(0), 2);
+        store.delete(&[cmp::Condition {
+                           column: 0,
+                           cmp: cmp::Comparison::Equal(cmp::Value::new(1)),
+                       }]);
+        assert_eq!(store.distinct(0).cloned().collect::<Vec<_>>(), vec![3]);
+        assert_eq!(Store::<usize>::new(1).cardinality(0), 0);
+    }
+
+    #[test]
+    fn it_summarizes_columns() {
+        let accessed = sync::Arc::new(AtomicUsize::new(0));
+        let mut store = Store::new(3);
+        let empty = ColumnSummary {
+            rows: 0,
+            distinct: 0,
+            min: None,
+            max: None,
+        };
+        assert_eq!(store.column_stats(0), empty);
+        for i in 0..100 {
+            store.insert(CountingRow {
+                cols: vec![i % 10, (i * 7) % 100 + 5, i / 50],
+                accessed: accessed.clone(),
+            });
+        }
+        let expected = |distinct, min, max| {
+            ColumnSummary {
+                rows: 100,
+                distinct,
+                min: Some(min),
+                max: Some(max),
+            }
+        };
+        assert_eq!(store.column_stats(0), expected(10, &0, &9));
+        assert_eq!(store.column_stats(1), expected(100, &5, &104));
+        assert_eq!(store.column_stats(2), expected(2, &0, &1));
+
+        // with a range index, nothing but the rows at either end of the index is looked at
+        store.index(0, idx::HashIndex::new());
+        store.index(1, idx::BTreeIndex::new());
+        accessed.store(0, Ordering::SeqCst);
+        assert_eq!(store.column_stats(1), expected(100, &5, &104));
+        assert_eq!(accessed.load(Ordering::SeqCst), 2);
+        assert_eq!(store.column_stats(0), expected(10, &0, &9));
+        assert_eq!(accessed.load(Ordering::SeqCst), 2 + 100);
+
+        // rows marked as deleted are not included
+        store.mark_deleted(1);
+        store.mark_deleted(11);
+        let stats = store.column_stats(1);
+        assert_eq!((stats.rows, stats.distinct, stats.min, stats.max),
+                   (98, 98, Some(&5), Some(&104)));
+        store.mark_deleted(0);
+        store.mark_deleted(5);
+        assert_eq!(store.column_stats(1).min, Some(&6));
+        assert_eq!(store.column_stats(0).distinct, 10);
+
+        // an empty store with indices does not panic either
+        store.clear();
+        assert_eq!(store.column_stats(1), empty);
+        assert_eq!(store.column_stats(0), empty);
+    }
+
+    #[test]
+    fn it_counts_nulls() {
+        let mut store = Store::new(2);
+        for i in 0..10 {
+            store.insert(vec![Some(i), if i % 3 == 0 { None } else { Some(i) }]);
+        }
+        assert_eq!(store.null_count(0), 0);
+        assert_eq!(store.null_count(1), 4);
+        assert_eq!(store.column_stats(1).min, Some(&None));
+    }
+
+    #[test]
+    fn it_finds_top_k() {
+        let accessed = sync::Arc::new(AtomicUsize::new(0));
+        let mut store = Store::new(2);
+        assert!(store.top_k(1, 3, false).is_empty());
+        assert!(store.min_row(1).is_none());
+        for (i, &v) in [5, 3, 8, 3, 8, 1, 8, 9].iter().enumerate() {
+            store.insert(CountingRow {
+                cols: vec![i, v],
+                accessed: accessed.clone(),
+            });
+        }
+        store.mark_deleted(7);
+        let ids = |rows: Vec<&CountingRow>| {
+            rows.into_iter().map(|r| r.cols[0]).collect::<Vec<_>>()
+        };
+        let values = |rows: Vec<&CountingRow>| {
+            rows.into_iter().map(|r| r.cols[1]).collect::<Vec<_>>()
+        };
+
+        // without an index, every row is looked at, and ties go to the earliest rows
+        accessed.store(0, Ordering::SeqCst);
+        assert_eq!(ids(store.top_k(1, 2, true)), vec![2, 4]);
+        assert_eq!(accessed.load(Ordering::SeqCst), 7);
+        assert_eq!(ids(store.top_k(1, 3, false)), vec![5, 1, 3]);
+        assert_eq!(ids(store.top_k(1, 2, false)), vec![5, 1]);
+        assert_eq!(values(store.top_k(1, 100, false)), vec![1, 3, 3, 5, 8, 8, 8]);
+        assert!(store.top_k(1, 0, true).is_empty());
+        assert_eq!(store.min_row(1).map(|r| r.cols[0]), Some(5));
+        assert_eq!(store.max_row(1).map(|r| r.cols[0]), Some(2));
+
+        // with an index, only the index is looked at, and exactly k rows are returned
+        store.index(1, idx::BTreeIndex::new());
+        accessed.store(0, Ordering::SeqCst);
+        assert_eq!(values(store.top_k(1, 2, true)), vec![8, 8]);
+        assert_eq!(values(store.top_k(1, 3, false)), vec![1, 3, 3]);
+        assert_eq!(values(store.top_k(1, 100, true)), vec![8, 8, 8, 5, 3, 3, 1]);
+        assert_eq!(store.min_row(1).map(|r| r.cols[0]), Some(5));
+        assert_eq!(store.max_row(1).map(|r| r.cols[1]), Some(8));
+        assert_eq!(accessed.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn it_gives_index_stats() {
+        let mut store = Store::new(3);
+        assert!(store.index_stats().is_empty());
+        for i in 0..100usize {
+            store.insert(vec![i % 10, i, 0]);
+        }
+        store.index(0, idx::HashIndex::new());
+        store.index(1, idx::BTreeIndex::new());
+        store.index(2,
+                    Index::Range(Box::new(CountingIndex {
+                        inner: idx::BTreeIndex::new(),
+                        yielded: sync::Arc::new(AtomicUsize::new(0)),
+                    })));
+
+        let stats = store.index_stats();
+        assert_eq!(stats.len(), 3);
+        assert_eq!((stats[&0].keys, stats[&0].entries), (Some(10), Some(100)));
+        assert_eq!((stats[&1].keys, stats[&1].entries), (Some(100), Some(100)));
+        assert!(stats[&1].bytes.unwrap() > stats[&0].bytes.unwrap());
+        // custom indices that cannot enumerate their values have no statistics
+        assert_eq!(stats[&2], IndexStats::default());
+
+        // re-indexing starts the counts over from the current rows
+        store.delete(&[cmp::Condition {
+                           column: 0,
+                           cmp: cmp::Comparison::Equal(cmp::Value::new(3usize)),
+                       }]);
+        store.index(1, idx::HashIndex::new());
+        store.unindex(2);
+        let stats = store.index_stats();
+        assert_eq!(stats.len(), 2);
+        assert_eq!((stats[&0].keys, stats[&0].entries), (Some(9), Some(90)));
+        assert_eq!((stats[&1].keys, stats[&1].entries), (Some(90), Some(90)));
+    }
+
+    /// A `HashIndex` that forgets to remove rows, and ignores every row indexed under `skip`.
+    struct ForgetfulIndex {
+        inner: idx::HashIndex<usize>,
+        skip: usize,
+    }
+
+    impl EqualityIndex<usize> for ForgetfulIndex {
+        fn lookup<'a>(&'a self, key: &usize) -> Box<dyn Iterator<Item = usize> + 'a> {
+            self.inner.lookup(key)
+        }
+        fn index(&mut self, key: usize, row: usize) {
+            if key != self.skip {
+                self.inner.index(key, row)
+            }
+        }
+        fn undex(&mut self, _: &usize, _: usize) {}
+        fn estimate(&self) -> usize {
+            self.inner.estimate()
+        }
+        fn keys<'a>(&'a self) -> Option<Box<dyn Iterator<Item = &'a usize> + 'a>> {
+            self.inner.keys()
+        }
+    }
+
+    #[test]
+    fn it_checks_integrity() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.index(1,
+                    Index::Equality(Box::new(ForgetfulIndex {
+                        inner: idx::HashIndex::new(),
+                        skip: 10,
+                    })));
+        for i in 0..10usize {
+            store.insert(vec![i % 3, i]);
+        }
+        assert_eq!(store.check_integrity(), Ok(()));
+
+        // a row that is missing from the index
+        store.insert(vec![9, 10]);
+        assert_eq!(store.check_integrity(),
+                   Err(IntegrityError::Missing {
+                       column: 1,
+                       value: 10,
+                       row: 10,
+                   }));
+        store.delete(&[cmp::Condition {
+                           column: 0,
+                           cmp: cmp::Comparison::Equal(cmp::Value::new(9usize)),
+                       }]);
+        assert_eq!(store.check_integrity(), Ok(()));
+
+        // a row that was not removed from the index
+        store.delete(&[cmp::Condition {
+                           column: 1,
+                           cmp: cmp::Comparison::Equal(cmp::Value::new(4usize)),
+                       }]);
+        assert_eq!(store.check_integrity(),
+                   Err(IntegrityError::Stale {
+                       column: 1,
+                       value: 4,
+                       row: 4,
+                   }));
+        let err = store.check_integrity().unwrap_err();
+        assert_eq!(err.to_string(),
+                   "index on column 1 yields row 4 for value 4, which the row does not hold");
+    }
+
+    #[test]
+    fn it_checks_composite_integrity() {
+        let bucket = cmp::KeyFn::new("bucket", |v: &Option<usize>| v.map(|v| v / 10));
+        let mut store = Store::new(2);
+        store.index_multi(&[0, 1], idx::HashIndex::new());
+        store.index_keyed(0, bucket, idx::BTreeIndex::new());
+        store.index_sparse(1, idx::HashIndex::new());
+        for i in 0..30usize {
+            store.insert(vec![Some(i), if i % 3 == 0 { Some(i % 4) } else { None }]);
+        }
+        store.update(&[Condition::eq(0, Some(4usize))], |row| row[1] = Some(1));
+        store.delete(&[Condition::eq(0, Some(5usize))]);
+        assert_eq!(store.check_integrity(), Ok(()));
+
+        // a row that is missing from the index on both columns
+        store.composite[0].1.undex(&vec![Some(3), Some(3)], 3);
+        assert_eq!(store.check_integrity(),
+                   Err(IntegrityError::MissingKey {
+                       columns: vec![0, 1],
+                       key: vec![Some(3), Some(3)],
+                       row: 3,
+                   }));
+        store.composite[0].1.index(vec![Some(3), Some(3)], 3);
+
+        // a row held under a key that is not computed from it
+        store.composite[1].1.index(vec![Some(2)], 7);
+        assert_eq!(store.check_integrity(),
+                   Err(IntegrityError::StaleKey {
+                       columns: vec![0],
+                       key: vec![Some(2)],
+                       row: 7,
+                   }));
+        store.composite[1].1.undex(&vec![Some(2)], 7);
+        assert_eq!(store.check_integrity(), Ok(()));
+
+        // a row with a null value does not belong in a sparse index
+        store.composite[2].1.index(vec![None], 1);
+        let err = store.check_integrity().unwrap_err();
+        assert_eq!(err,
+                   IntegrityError::StaleKey {
+                       columns: vec![1],
+                       key: vec![None],
+                       row: 1,
+                   });
+        assert_eq!(err.to_string(),
+                   "index on columns [1] yields row 1 for key [None], which is not computed from \
+                    the row");
+    }
+
+    /// A `HashIndex` that panics, without indexing the row, when given the value `fail`.
+    struct PanickyIndex {
+        inner: idx::HashIndex<usize>,
+        fail: usize,
+    }
+
+    impl EqualityIndex<usize> for PanickyIndex {
+        fn lookup<'a>(&'a self, key: &usize) -> Box<dyn Iterator<Item = usize> + 'a> {
+            self.inner.lookup(key)
+        }
+        fn index(&mut self, key: usize, row: usize) {
+            assert!(key != self.fail, "cannot index {}", key);
+            self.inner.index(key, row)
+        }
+        fn undex(&mut self, key: &usize, row: usize) {
+            self.inner.undex(key, row)
+        }
+        fn estimate(&self) -> usize {
+            self.inner.estimate()
+        }
+        fn keys<'a>(&'a self) -> Option<Box<dyn Iterator<Item = &'a usize> + 'a>> {
+            self.inner.keys()
+        }
+    }
+
+    #[test]
+    fn it_survives_panicking_index() {
+        use std::panic::{self, AssertUnwindSafe};
+
+        let mut store = Store::new(3);
+        store.index(0, idx::HashIndex::new());
+        store.index(1,
+                    Index::Equality(Box::new(PanickyIndex {
+                        inner: idx::HashIndex::new(),
+                        fail: 7,
+                    })));
+        store.index(2, idx::BTreeIndex::new());
+        store.index_multi(&[0, 2], idx::HashIndex::new());
+        let view = store.materialize(vec![cmp::Condition::eq(0, 1usize)]);
+        for i in 0..5usize {
+            store.insert(vec![i % 2, i, i]);
+        }
+
+        // the row reaches the index on column 0, but not the one on column 1
+        let result = panic::catch_unwind(AssertUnwindSafe(|| store.insert(vec![1, 7, 7])));
+        assert!(result.is_err());
+        assert_eq!(store.len(), 5);
+        assert_eq!(store.check_integrity(), Ok(()));
+        assert_eq!(store.count(&[cmp::Condition::eq(0, 1usize)]), 2);
+        assert_eq!(store.count(&[cmp::Condition::eq(0, 1usize), cmp::Condition::eq(2, 7usize)]),
+                   0);
+        assert_eq!(store.view(view).count(), 2);
+
+        // the store carries on as if the insertion never happened
+        let rowid = store.insert(vec![1, 8, 8]);
+        assert_eq!(store.check_integrity(), Ok(()));
+        assert_eq!(store.find(&[cmp::Condition::eq(0, 1usize)]).count(), 3);
+        assert_eq!(store.get(rowid), Some(&vec![1, 8, 8]));
+    }
+
+    #[test]
+    fn it_clones() {
+        let mut store = Store::with_key(3, 0);
+        store.index(1, idx::BTreeIndex::new());
+        store.index_multi(&[1, 2], idx::HashIndex::new());
+        store.index(2,
+                    Index::Range(Box::new(CountingIndex {
+                        inner: idx::BTreeIndex::new(),
+                        yielded: sync::Arc::new(AtomicUsize::new(0)),
+                    })));
+        for i in 0..10usize {
+            store.insert(vec![i, i % 3, i % 2]);
+        }
+        store.mark_deleted(4);
+        let view = store.materialize(vec![cmp::Condition::eq(1, 0usize)]);
+
+        let mut clone = store.clone();
+        assert_eq!(clone.rows().collect::<Vec<_>>(), store.rows().collect::<Vec<_>>());
+        assert_eq!(clone.view(view).count(), 4);
+        assert_eq!(clone.key_column(), Some(0));
+        // built-in indices are rebuilt, but custom ones are not
+        assert_eq!(clone.index_stats().keys().cloned().collect::<BTreeSet<_>>(),
+                   vec![0, 1].into_iter().collect());
+        assert_eq!(clone.check_integrity(), Ok(()));
+        let cmp = [cmp::Condition::eq(1, 1usize), cmp::Condition::eq(2, 1usize)];
+        assert_eq!(clone.explain(&cmp).access, store.explain(&cmp).access);
+        assert_eq!(clone.find(&cmp).count(), 2);
+
+        // the clone is independent of the original
+        clone.insert(vec![10, 0, 0]);
+        clone.delete(&[cmp::Condition::eq(0, 0usize)]);
+        assert_eq!(store.len(), 9);
+        assert_eq!(clone.len(), 9);
+        assert_eq!(store.view(view).count(), 4);
+        assert_eq!(clone.view(view).count(), 4);
+        assert_eq!(store.get_by_key(&0), Some(&vec![0, 0, 0]));
+        assert_eq!(clone.get_by_key(&0), None);
+    }
+
+    #[test]
+    fn it_debugs() {
+        let mut store = Store::new(2);
+        store.insert(vec!["a", "x"]);
+        store.insert(vec!["b", "y"]);
+        store.insert(vec!["c", "z"]);
+        store.mark_deleted(1);
+        store.index(1, idx::BTreeIndex::new());
+        assert_eq!(format!("{:?}", store),
+                   "Store { cols: 2, rows: {0: [\"a\", \"x\"], 2: [\"c\", \"z\"]}, \
+                    indices: {1: Some(BTree)}, key: None }");
+    }
+
+    #[test]
+    fn it_joins() {
+        let mut orders = Store::new(2);
+        for &(order, customer) in &[("o1", "c1"), ("o2", "c2"), ("o3", "c1"), ("o4", "c4")] {
+            orders.insert(vec![order, customer]);
+        }
+        let mut customers = Store::new(2);
+        for &(customer, name) in &[("c1", "ann"), ("c2", "bob"), ("c1", "amy"), ("c3", "cid")] {
+            customers.insert(vec![customer, name]);
+        }
+        let pairs = |orders: &Store<&'static str>, customers: &Store<&'static str>| {
+            let mut pairs = join(orders, 1, customers, 0)
+                .map(|(o, c)| (o[0], c[1]))
+                .collect::<Vec<_>>();
+            pairs.sort();
+            pairs
+        };
+        let expected = vec![("o1", "amy"), ("o1", "ann"), ("o2", "bob"), ("o3", "amy"),
+                            ("o3", "ann")];
+
+        // without any indices, with an index on the left, and with an index on the right
+        assert_eq!(pairs(&orders, &customers), expected);
+        orders.index(1, idx::HashIndex::new());
+        assert_eq!(pairs(&orders, &customers), expected);
+        customers.index(0, idx::HashIndex::new());
+        assert_eq!(pairs(&orders, &customers), expected);
+
+        // rows marked as deleted have no partners
+        customers.mark_deleted(2);
+        orders.mark_deleted(0);
+        assert_eq!(pairs(&orders, &customers), vec![("o2", "bob"), ("o3", "ann")]);
+    }
+
+    #[test]
+    fn it_left_joins() {
+        let mut orders = Store::new(2);
+        for &(order, customer) in &[("o1", "c1"), ("o2", "c4"), ("o3", "c1")] {
+            orders.insert(vec![order, customer]);
+        }
+        let mut customers = Store::new(2);
+        for &(customer, name) in &[("c1", "ann"), ("c1", "amy"), ("c3", "cid")] {
+            customers.insert(vec![customer, name]);
+        }
+        let expected = vec![("o1", Some("ann")),
+                            ("o1", Some("amy")),
+                            ("o2", None),
+                            ("o3", Some("ann")),
+                            ("o3", Some("amy"))];
+        let pairs = left_join(&orders, 1, &customers, 0)
+            .map(|(o, c)| (o[0], c.map(|c| c[1])))
+            .collect::<Vec<_>>();
+        assert_eq!(pairs, expected);
+
+        customers.index(0, idx::BTreeIndex::new());
+        let pairs = left_join(&orders, 1, &customers, 0)
+            .map(|(o, c)| (o[0], c.map(|c| c[1])))
+            .collect::<Vec<_>>();
+        assert_eq!(pairs, expected);
+    }
+
+    #[test]
+    fn it_finds_one() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["a", "x2"]);
+        store.insert(vec!["b", "x3"]);
+        let a = [cmp::Condition {
+                     column: 0,
+                     cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                 }];
+        let b = [cmp::Condition {
+                     column: 0,
+                     cmp: cmp::Comparison::Equal(cmp::Value::new("b")),
+                 }];
+        let c = [cmp::Condition {
+                     column: 0,
+                     cmp: cmp::Comparison::Equal(cmp::Value::new("c")),
+                 }];
+        assert_eq!(store.find_one(&a), Some(&vec!["a", "x1"]));
+        assert_eq!(store.find_one(&b), Some(&vec!["b", "x3"]));
+        assert_eq!(store.find_one(&c), None);
+        assert_eq!(store.find_single(&b), Some(&vec!["b", "x3"]));
+        assert_eq!(store.find_single(&c), None);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "more than one row matched")]
+    fn it_finds_single_strictly() {
+        let mut store = Store::new(2);
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["a", "x2"]);
+        let a = [cmp::Condition {
+                     column: 0,
+                     cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                 }];
+        store.find_single(&a);
+    }
+
+    #[test]
+    fn it_finds_any() {
+        let mut store = Store::new(2);
+        store.insert(vec!["a", "b"]);
+        store.insert(vec!["a", "x"]);
+        store.insert(vec!["c", "b"]);
+        store.insert(vec!["d", "b"]);
+        let branches = [vec![cmp::Condition {
+                                 column: 0,
+                                 cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                             },
+                             cmp::Condition {
+                                 column: 1,
+                                 cmp: cmp::Comparison::Equal(cmp::Value::new("b")),
+                             }],
+                        vec![cmp::Condition {
+                                 column: 0,
+                                 cmp: cmp::Comparison::Equal(cmp::Value::new("c")),
+                             }]];
+        let rows: Vec<_> = store.find_any(&branches).collect();
+        assert_eq!(rows, vec![&vec!["a", "b"], &vec!["c", "b"]]);
+
+        // all branches indexed
+        store.index(0, idx::HashIndex::new());
+        let rows: Vec<_> = store.find_any(&branches).collect();
+        assert_eq!(rows, vec![&vec!["a", "b"], &vec!["c", "b"]]);
+
+        assert_eq!(store.find_any(&[]).count(), 0);
+    }
+
+    #[test]
+    fn it_finds_any_once() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.index(1, idx::HashIndex::new());
+        store.insert(vec!["a", "b"]);
+        store.insert(vec!["a", "x"]);
+        store.insert(vec!["c", "b"]);
+        // the first row matches both branches, which use different indices
+        let branches = [vec![cmp::Condition {
+                                 column: 0,
+                                 cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                             }],
+                        vec![cmp::Condition {
+                                 column: 1,
+                                 cmp: cmp::Comparison::Equal(cmp::Value::new("b")),
+                             }]];
+        assert_eq!(store.find_any(&branches).count(), 3);
+
+        // an unindexable branch forces a scan
+        let branches = [vec![cmp::Condition {
+                                 column: 0,
+                                 cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                             }],
+                        vec![cmp::Condition {
+                                 column: 1,
+                                 cmp: cmp::Comparison::NotEqual(cmp::Value::new("x")),
+                             }]];
+        assert_eq!(store.find_any(&branches).count(), 3);
+    }
+
+    #[test]
+    fn it_filters_ranges() {
+        let mut store = Store::new(2);
+        store.insert(vec![1, 0]);
+        store.insert(vec![2, 0]);
+        store.insert(vec![3, 0]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Greater(cmp::Value::new(1)),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 2);
+        assert!(store.find(&cmp).all(|r| r[0] > 1));
+    }
+
+    #[test]
+    fn it_filters_ranges_with_equality_indices() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.insert(vec![1, 0]);
+        store.insert(vec![2, 0]);
+        store.insert(vec![3, 0]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::LessOrEqual(cmp::Value::new(2)),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 2);
+        assert!(store.find(&cmp).all(|r| r[0] <= 2));
+    }
+
+    #[test]
+    fn it_uses_range_indices() {
+        let yielded = sync::Arc::new(AtomicUsize::new(0));
+        let mut store = Store::new(2);
+        store.index(0,
+                    Index::Range(Box::new(CountingIndex {
+                        inner: idx::BTreeIndex::new(),
+                        yielded: yielded.clone(),
+                    })));
+        for i in 0..100_000 {
+            store.insert(vec![i, i % 10]);
+        }
+
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::GreaterOrEqual(cmp::Value::new(99_900usize)),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(0usize)),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 10);
+        assert_eq!(yielded.load(Ordering::SeqCst), 100);
+
+        yielded.store(0, Ordering::SeqCst);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Less(cmp::Value::new(10usize)),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 10);
+        assert_eq!(yielded.load(Ordering::SeqCst), 10);
+    }
+
+    #[test]
+    fn it_unindexes() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["b", "x2"]);
+        assert!(store.unindex(0).is_some());
+        assert!(store.unindex(0).is_none());
+        assert!(store.unindex(1).is_none());
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 1);
+    }
+
+    #[test]
+    fn it_reindexes() {
+        let yielded = sync::Arc::new(AtomicUsize::new(0));
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        for i in 0..100 {
+            store.insert(vec![i, i % 10]);
+        }
+        store.index(0,
+                    Index::Range(Box::new(CountingIndex {
+                        inner: idx::BTreeIndex::new(),
+                        yielded: yielded.clone(),
+                    })));
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(42usize)),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 1);
+        assert_eq!(yielded.load(Ordering::SeqCst), 1);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Less(cmp::Value::new(42usize)),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 42);
+        assert_eq!(yielded.load(Ordering::SeqCst), 43);
+    }
+
+    #[test]
+    fn it_rebuilds_indices_after_raw_mutation() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.index_multi(&[0, 1], idx::HashIndex::new());
+        for i in 0..100usize {
+            store.insert(vec![i % 10, i]);
+        }
+        let view = store.materialize(vec![Condition::eq(0, 10usize)]);
+        store.mark_deleted(3);
+
+        // move every row with a 3 in column 0 over to 10
+        let mut changed = 0;
+        for row in store.rows_mut_unindexed() {
+            if row[0] == 3 {
+                row[0] = 10;
+                changed += 1;
+            }
+        }
+        assert_eq!(changed, 9);
+        store.reindex();
+
+        assert_eq!(store.check_integrity(), Ok(()));
+        assert_eq!(store.find(&[Condition::eq(0, 3usize)]).count(), 0);
+        assert_eq!(store.find(&[Condition::eq(0, 10usize)]).count(), 9);
+        assert_eq!(store.explain(&[Condition::eq(0, 10usize)]).access, query::Access::Index(0));
+        let both = [Condition::eq(0, 10usize), Condition::eq(1, 13usize)];
+        assert_eq!(store.find(&both).count(), 1);
+        assert_eq!(store.view(view).map(|r| r[1]).collect::<Vec<_>>(),
+                   vec![13, 23, 33, 43, 53, 63, 73, 83, 93]);
+
+        // the store stays usable as before
+        store.delete(&[Condition::eq(1, 13usize)]);
+        store.update(&[Condition::eq(1, 23usize)], |r| r[0] = 2);
+        assert_eq!(store.find(&[Condition::eq(0, 10usize)]).count(), 7);
+        assert_eq!(store.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    #[should_panic(expected = "call reindex before querying")]
+    fn it_refuses_queries_with_stale_indices() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        for i in 0..10usize {
+            store.insert(vec![i, i]);
+        }
+        for row in store.rows_mut_unindexed() {
+            row[0] += 1;
+        }
+        assert_eq!(store.rows().count(), 10);
+        store.find(&[Condition::eq(0, 1usize)]).count();
+    }
+
+    /// A `Store` with an index on column 0 that is out of date, since every value in that column
+    /// has been changed through `rows_mut_unindexed`.
+    fn stale() -> Store<usize> {
+        let mut store = Store::new(2);
+        store.index(0, idx::BTreeIndex::new());
+        for i in 0..10usize {
+            store.insert(vec![i, i]);
+        }
+        for row in store.rows_mut_unindexed() {
+            row[0] += 100;
+        }
+        store
+    }
+
+    #[test]
+    #[should_panic(expected = "call reindex before querying")]
+    fn it_refuses_counts_with_stale_indices() {
+        stale().count(&[Condition::eq(0, 1usize)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "call reindex before querying")]
+    fn it_refuses_exists_with_stale_indices() {
+        stale().exists(&[Condition::eq(0, 1usize)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "call reindex before querying")]
+    fn it_refuses_ordered_finds_with_stale_indices() {
+        stale().find_ordered(&[], 0, false).count();
+    }
+
+    #[test]
+    #[should_panic(expected = "call reindex before querying")]
+    fn it_refuses_find_many_with_stale_indices() {
+        stale().find_many(0, &[1, 2]).count();
+    }
+
+    #[test]
+    #[should_panic(expected = "call reindex before querying")]
+    fn it_refuses_aggregates_with_stale_indices() {
+        stale().aggregate(&[], 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "call reindex before querying")]
+    fn it_refuses_group_by_with_stale_indices() {
+        stale().group_by(&[], 0).count();
+    }
+
+    #[test]
+    #[should_panic(expected = "call reindex before querying")]
+    fn it_refuses_distinct_with_stale_indices() {
+        stale().distinct(0).count();
+    }
+
+    #[test]
+    #[should_panic(expected = "call reindex before querying")]
+    fn it_refuses_column_stats_with_stale_indices() {
+        stale().column_stats(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "call reindex before querying")]
+    fn it_refuses_top_k_with_stale_indices() {
+        stale().top_k(0, 3, true);
+    }
+
+    #[test]
+    #[should_panic(expected = "call reindex before querying")]
+    fn it_refuses_joins_with_stale_indices() {
+        let store = stale();
+        join::join(&store, 1, &store, 0).count();
+    }
+
+    #[test]
+    #[should_panic(expected = "call reindex before querying")]
+    fn it_refuses_analyze_with_stale_indices() {
+        stale().analyze(0, 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "call reindex before querying")]
+    fn it_refuses_histograms_with_stale_indices() {
+        stale().histogram(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "call reindex before querying")]
+    fn it_refuses_cached_queries_with_stale_indices() {
+        let mut store = Store::new(2);
+        store.index(0, idx::BTreeIndex::new());
+        store.enable_query_cache(4);
+        store.insert(vec![1usize, 1]);
+        assert_eq!(store.find(&[Condition::eq(0, 1usize)]).count(), 1);
+        for row in store.rows_mut_unindexed() {
+            row[0] = 2;
+        }
+        store.find(&[Condition::eq(0, 1usize)]).count();
+    }
+
+    #[test]
+    fn it_queries_again_after_reindex() {
+        let mut store = stale();
+        store.reindex();
+        assert_eq!(store.count(&[Condition::eq(0, 1usize)]), 0);
+        assert!(store.exists(&[Condition::eq(0, 101usize)]));
+        assert_eq!(store.distinct(0).next(), Some(&100));
+        assert_eq!(store.top_k(0, 1, true)[0][0], 109);
+    }
+
+    #[test]
+    fn it_refuses_to_modify_rows_it_cannot_reindex() {
+        use std::panic::{self, AssertUnwindSafe};
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.index(1,
+                    Index::Range(Box::new(CountingIndex {
+                        inner: idx::BTreeIndex::new(),
+                        yielded: sync::Arc::new(AtomicUsize::new(0)),
+                    })));
+        for i in 0..10usize {
+            store.insert(vec![i % 3, i]);
+        }
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            store.rows_mut_unindexed().count();
+        }));
+        let message = *result.unwrap_err().downcast::<String>().unwrap();
+        assert_eq!(message,
+                   "the index on column 1 is not a built-in index, and cannot be rebuilt");
+        assert_eq!(store.count(&[Condition::eq(0, 1usize)]), 3);
+        assert_eq!(store.check_integrity(), Ok(()));
+
+        // once the index is gone, the rows can be modified and the other index rebuilt
+        store.unindex(1);
+        for row in store.rows_mut_unindexed() {
+            row[0] += 1;
+        }
+        store.reindex();
+        assert_eq!(store.count(&[Condition::eq(0, 1usize)]), 4);
+        assert_eq!(store.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    #[should_panic(expected = "row 1 conflicts with row 0 in unique column 0")]
+    fn it_rejects_unique_conflicts_on_reindex() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::unique());
+        store.insert(vec![0, 0]);
+        store.insert(vec![1, 1]);
+        for row in store.rows_mut_unindexed() {
+            row[0] = 0;
+        }
+        store.reindex();
+    }
+
+    #[test]
+    fn it_breaks_ties_by_condition_order() {
+        let mut store = Store::new(3);
+        for i in 0..40usize {
+            store.insert(vec![i % 10, (i * 7) % 10, i]);
+        }
+        store.index(1, idx::HashIndex::new());
+        store.index(0, idx::HashIndex::new());
+
+        // both indices are expected to yield 4 rows, so the first condition wins
+        let first = [Condition::eq(0, 3usize), Condition::eq(1, 1usize)];
+        let second = [Condition::eq(1, 1usize), Condition::eq(0, 3usize)];
+        for _ in 0..10 {
+            assert_eq!(store.explain(&first).access, query::Access::Index(0));
+            assert_eq!(store.explain(&second).access, query::Access::Index(1));
+        }
+        assert_eq!(store.find(&first).map(|r| r[2]).collect::<Vec<_>>(), vec![3, 13, 23, 33]);
+        assert_eq!(store.find(&second).map(|r| r[2]).collect::<Vec<_>>(), vec![3, 13, 23, 33]);
+
+        // a clone, whose indices are rebuilt, plans the same way
+        let clone = store.clone();
+        assert_eq!(clone.explain(&first), store.explain(&first));
+        assert_eq!(clone.explain(&second), store.explain(&second));
+    }
+
+    #[test]
+    fn it_uses_composite_indices() {
+        let yielded = sync::Arc::new(AtomicUsize::new(0));
+        let mut store = Store::new(3);
+        store.index(0, idx::HashIndex::new());
+        store.index(1, idx::HashIndex::new());
+        for i in 0..100 {
+            store.insert(vec![i % 10, i / 10, i]);
+        }
+        store.index_multi(&[0, 1],
+                          Index::Range(Box::new(CountingIndex {
+                              inner: idx::BTreeIndex::new(),
+                              yielded: yielded.clone(),
+                          })));
+
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(4usize)),
+                   },
+                   cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(2usize)),
+                   }];
+        let rows: Vec<_> = store.find(&cmp).collect();
+        assert_eq!(rows, vec![&vec![2, 4, 42]]);
+        assert_eq!(yielded.load(Ordering::SeqCst), 1);
+
+        // only one of the columns constrained
+        yielded.store(0, Ordering::SeqCst);
+        assert_eq!(store.find(&cmp[..1]).count(), 10);
+        assert_eq!(yielded.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn it_maintains_composite_indices() {
+        let mut store = Store::new(2);
+        store.index_multi(&[0, 1], idx::HashIndex::new());
+        store.insert(vec!["a", "x"]);
+        store.insert(vec!["a", "y"]);
+        store.insert(vec!["b", "x"]);
+        let ax = [cmp::Condition {
+                      column: 0,
+                      cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                  },
+                  cmp::Condition {
+                      column: 1,
+                      cmp: cmp::Comparison::Equal(cmp::Value::new("x")),
+                  }];
+        assert_eq!(store.find(&ax).count(), 1);
+        store.update(&ax[..1], |r| r[1] = "x");
+        assert_eq!(store.find(&ax).count(), 2);
+        assert_eq!(store.delete(&ax), 2);
+        assert_eq!(store.find(&ax).count(), 0);
+        assert_eq!(store.find(&[]).count(), 1);
+    }
+
+    #[test]
+    fn it_finds_unique() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::unique());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["b", "x2"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("b")),
+                   }];
+        let rows: Vec<_> = store.find(&cmp).collect();
+        assert_eq!(rows, vec![&vec!["b", "x2"]]);
+
+        // deleting a row frees up its value
+        store.delete(&cmp);
+        store.insert(vec!["b", "x3"]);
+        let rows: Vec<_> = store.find(&cmp).collect();
+        assert_eq!(rows, vec![&vec!["b", "x3"]]);
+    }
+
+    #[test]
+    #[should_panic(expected = "row conflicts with row 0 in unique column 0")]
+    fn it_rejects_unique_duplicates() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::unique());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["a", "x2"]);
+    }
+
+    #[test]
+    fn it_upserts() {
+        let mut store = Store::with_key(3, 0);
+        store.index(1, idx::HashIndex::new());
+        assert_eq!(store.upsert(vec!["a", "x", "1"]), 0);
+        assert_eq!(store.upsert(vec!["b", "x", "2"]), 1);
+
+        // replacing a row changes its indexed column, and keeps its identifier
+        assert_eq!(store.upsert(vec!["a", "y", "3"]), 0);
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get(0), Some(&vec!["a", "y", "3"]));
+        let x = [cmp::Condition {
+                     column: 1,
+                     cmp: cmp::Comparison::Equal(cmp::Value::new("x")),
+                 }];
+        assert_eq!(store.find(&x).collect::<Vec<_>>(), vec![&vec!["b", "x", "2"]]);
+        let y = [cmp::Condition {
+                     column: 1,
+                     cmp: cmp::Comparison::Equal(cmp::Value::new("y")),
+                 }];
+        assert_eq!(store.find(&y).collect::<Vec<_>>(), vec![&vec!["a", "y", "3"]]);
+
+        // replacing a row with an identical one changes nothing
+        assert_eq!(store.upsert(vec!["b", "x", "2"]), 1);
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.find(&x).count(), 1);
+        assert_eq!(store.get_by_key(&"b"), Some(&vec!["b", "x", "2"]));
+        assert_eq!(store.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn it_gets_by_key() {
+        let mut store = Store::with_key(2, 1);
+        assert_eq!(store.key_column(), Some(1));
+        assert_eq!(store.get_by_key(&"x1"), None);
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["b", "x2"]);
+        assert_eq!(store.get_by_key(&"x2"), Some(&vec!["b", "x2"]));
+        assert_eq!(store.get_by_key(&"a"), None);
+
+        let x2 = [cmp::Condition {
+                      column: 1,
+                      cmp: cmp::Comparison::Equal(cmp::Value::new("x2")),
+                  }];
+        store.delete(&x2);
+        assert_eq!(store.get_by_key(&"x2"), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "row conflicts with row 0 in unique column 1")]
+    fn it_rejects_unique_duplicates_on_upsert() {
+        let mut store = Store::with_key(2, 0);
+        store.index(1, idx::HashIndex::unique());
+        store.upsert(vec!["a", "x1"]);
+        store.upsert(vec!["b", "x2"]);
+        store.upsert(vec!["b", "x1"]);
+    }
+
+    #[test]
+    fn is_send_sync() {
+        use std::sync;
+        use std::thread;
+        let store = sync::Arc::new(Store::<()>::new(0));
+        thread::spawn(move || { drop(store); })
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn it_deletes() {
+        let mut store = Store::new(2);
+        store.insert(vec!["a1", "a2"]);
+        store.insert(vec!["b1", "b2"]);
+        store.insert(vec!["c1", "c2"]);
+        store.delete(&[]);
+        assert_eq!(store.find(&[]).count(), 0);
+    }
+
+    #[test]
+    fn filtered_delete() {
+        let mut store = Store::new(2);
+        store.insert(vec!["a1", "a2"]);
+        store.insert(vec!["b1", "b2"]);
+        store.insert(vec!["c1", "c2"]);
+        store.delete_filter(&[], |row| row[0] != "b1");
+        assert_eq!(store.find(&[]).count(), 1);
+        assert!(store.find(&[]).all(|r| r[0] == "b1"));
+    }
+
+    #[test]
+    fn it_deletes_with_filters() {
+        let mut store = Store::new(2);
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["a", "x2"]);
+        store.insert(vec!["b", "x3"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   }];
+        store.delete(&cmp);
+        assert_eq!(store.find(&cmp).count(), 0);
+        assert_eq!(store.find(&[]).count(), 1);
+        assert!(store.find(&[]).all(|r| r[0] == "b"));
+    }
+
+    #[test]
+    fn it_deletes_with_indices() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["a", "x2"]);
+        store.insert(vec!["b", "x3"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   }];
+        store.delete(&cmp);
+        assert_eq!(store.find(&cmp).count(), 0);
+        assert_eq!(store.find(&[]).count(), 1);
+        assert!(store.find(&[]).all(|r| r[0] == "b"));
+        assert_eq!(store.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn it_deletes_from_the_middle() {
+        let mut store = Store::new(2);
+        store.index(1, idx::BTreeIndex::new());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["b", "x2"]);
+        store.insert(vec!["c", "x1"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("b")),
+                   }];
+        assert_eq!(store.delete(&cmp), 1);
+        let x1 = [cmp::Condition {
+                      column: 1,
+                      cmp: cmp::Comparison::Equal(cmp::Value::new("x1")),
+                  }];
+        let rows: Vec<_> = store.find(&x1).collect();
+        assert_eq!(rows, vec![&vec!["a", "x1"], &vec!["c", "x1"]]);
+        let x2 = [cmp::Condition {
+                      column: 1,
+                      cmp: cmp::Comparison::Equal(cmp::Value::new("x2")),
+                  }];
+        assert_eq!(store.find(&x2).count(), 0);
+        assert_eq!(store.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn it_deletes_all_for_indexed_value() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["a", "x2"]);
+        store.insert(vec!["b", "x3"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   }];
+        assert_eq!(store.delete(&cmp), 2);
+        assert_eq!(store.find(&cmp).count(), 0);
+        store.insert(vec!["a", "x4"]);
+        assert_eq!(store.find(&cmp).count(), 1);
+        assert_eq!(store.find(&[]).count(), 2);
+        assert_eq!(store.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn it_deletes_nothing() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["b", "x2"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("c")),
+                   }];
+        assert_eq!(store.delete(&cmp), 0);
+        assert_eq!(store.find(&[]).count(), 2);
+    }
+
+    #[test]
+    fn it_updates() {
+        let mut store = Store::new(2);
+        store.insert(vec![1, 0]);
+        store.insert(vec![2, 0]);
+        store.insert(vec![1, 0]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(1)),
+                   }];
+        assert_eq!(store.update(&cmp, |r| r[1] += 1), 2);
+        assert!(store.find(&cmp).all(|r| r[1] == 1));
+        assert_eq!(store.find(&[]).filter(|r| r[1] == 0).count(), 1);
+    }
+
+    #[test]
+    fn it_updates_indexed_columns() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.index(1, idx::BTreeIndex::new());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["b", "x2"]);
+        let a = [cmp::Condition {
+                     column: 0,
+                     cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                 }];
+        let c = [cmp::Condition {
+                     column: 0,
+                     cmp: cmp::Comparison::Equal(cmp::Value::new("c")),
+                 }];
+        assert_eq!(store.update(&a, |r| r[0] = "c"), 1);
+        assert_eq!(store.find(&a).count(), 0);
+        let rows: Vec<_> = store.find(&c).collect();
+        assert_eq!(rows, vec![&vec!["c", "x1"]]);
+
+        // the index on the unchanged column must still work
+        let x1 = [cmp::Condition {
+                      column: 1,
+                      cmp: cmp::Comparison::Equal(cmp::Value::new("x1")),
+                  }];
+        let rows: Vec<_> = store.find(&x1).collect();
+        assert_eq!(rows, vec![&vec!["c", "x1"]]);
+        assert_eq!(store.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn it_survives_unique_conflicts_on_update() {
+        use std::panic::{self, AssertUnwindSafe};
+        let mut store = Store::new(3);
+        store.index(0, idx::HashIndex::unique());
+        store.index(1, idx::BTreeIndex::new());
+        store.index_multi(&[1, 2], idx::HashIndex::new());
+        for i in 0..5usize {
+            store.insert(vec![i, i % 2, i]);
+        }
+
+        // the second of the two rows updated takes the value of row 4 in the unique column
+        let conds = [Condition::eq(1, 1usize)];
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            store.update(&conds, |row| row[0] = if row[0] == 1 { 10 } else { 4 });
+        }));
+        let message = *result.unwrap_err().downcast::<String>().unwrap();
+        assert_eq!(message,
+                   "row 3 conflicts with row 4 in unique column 0 once updated");
+        assert_eq!(store.check_integrity(), Ok(()));
+        assert_eq!(store.len(), 5);
+        assert_eq!(store.get(1), Some(&vec![10, 1, 1]));
+        assert_eq!(store.get(3), Some(&vec![3, 1, 3]));
+        assert_eq!(store.find(&[Condition::eq(0, 3usize)]).map(|row| row[2]).collect::<Vec<_>>(),
+                   vec![3]);
+        assert_eq!(store.find(&[Condition::eq(0, 4usize)]).map(|row| row[2]).collect::<Vec<_>>(),
+                   vec![4]);
+        assert_eq!(store.count(&[Condition::eq(1, 1usize), Condition::eq(2, 3usize)]), 1);
+    }
+
+    #[test]
+    fn it_keeps_rows_that_conflict_on_update() {
+        use std::panic::{self, AssertUnwindSafe};
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::unique());
+        store.insert(vec![1usize, 10]);
+        store.insert(vec![2usize, 20]);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            store.update(&[Condition::eq(0, 2usize)], |row| row[0] = 1);
+        }));
+        assert!(result.is_err());
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get(1), Some(&vec![2, 20]));
+        assert_eq!(store.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn it_deletes_with_partial_indices() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["a", "x2"]);
+        store.insert(vec!["b", "x3"]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("x2")),
+                   }];
+        store.delete(&cmp);
+        assert_eq!(store.find(&cmp).count(), 0);
+        assert_eq!(store.find(&[]).count(), 2);
+        assert!(store.find(&[]).any(|r| r[0] == "a" && r[1] == "x1"));
+        assert!(store.find(&[]).any(|r| r[0] == "b" && r[1] == "x3"));
+        assert_eq!(store.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn it_builds_from_rows() {
+        let mut store = Store::from_rows(2, (0..100_000usize).map(|i| vec![i, i % 10]).collect());
+        assert_eq!(store.find(&[]).count(), 100_000);
+        assert_eq!(store.get(42), Some(&vec![42, 2]));
+
+        store.index(1, idx::HashIndex::new());
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(3usize)),
+                   }];
+        assert_eq!(store.count(&cmp), 10_000);
+        assert_eq!(store.insert(vec![0, 3]), 100_000);
+        assert_eq!(store.count(&cmp), 10_001);
+    }
+
+    #[test]
+    #[should_panic(expected = "row 1 has 1 columns, but the store has 2")]
+    fn it_builds_from_rows_with_wrong_width() {
+        Store::from_rows(2, vec![vec!["a", "b"], vec!["c"]]);
+    }
+
+    #[test]
+    fn it_extends() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.insert(vec!["a", "x1"]);
+        store.extend(vec![vec!["a", "x2"], vec!["b", "x3"]]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 2);
+        assert_eq!(store.get(2), Some(&vec!["b", "x3"]));
+    }
+
+    /// A `HashIndex` that records the most rows it was asked to reserve room for at once.
+    struct ReservingIndex {
+        inner: idx::HashIndex<usize>,
+        reserved: sync::Arc<AtomicUsize>,
+    }
+
+    impl EqualityIndex<usize> for ReservingIndex {
+        fn lookup<'a>(&'a self, key: &usize) -> Box<dyn Iterator<Item = usize> + 'a> {
+            self.inner.lookup(key)
+        }
+        fn index(&mut self, key: usize, row: usize) {
+            self.inner.index(key, row)
+        }
+        fn undex(&mut self, key: &usize, row: usize) {
+            self.inner.undex(key, row)
+        }
+        fn estimate(&self) -> usize {
+            self.inner.estimate()
+        }
+        fn reserve(&mut self, additional: usize) {
+            self.reserved.fetch_max(additional, Ordering::SeqCst);
+            self.inner.reserve(additional)
+        }
+    }
+
+    #[test]
+    fn it_reserves_when_extending() {
+        let reserved = sync::Arc::new(AtomicUsize::new(0));
+        let mut store = Store::new(2);
+        store.index(0,
+                    Index::Equality(Box::new(ReservingIndex {
+                        inner: idx::HashIndex::new(),
+                        reserved: reserved.clone(),
+                    })));
+        store.extend((0..1000usize).map(|i| vec![i % 10, i]));
+        assert_eq!(reserved.load(Ordering::SeqCst), 1000);
+        assert_eq!(store.len(), 1000);
+        assert_eq!(store.count(&[Condition::eq(0, 3usize)]), 100);
+        assert_eq!(store.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn it_collects() {
+        let mut store = (0..100usize).map(|i| vec![i % 10, i]).collect::<Store<_>>();
+        assert_eq!(store.cols(), 2);
+        assert_eq!(store.len(), 100);
+        assert_eq!(store.get(42), Some(&vec![2, 42]));
+        store.index(0, idx::HashIndex::new());
+        assert_eq!(store.count(&[Condition::eq(0, 3usize)]), 10);
+        assert_eq!(store.insert(vec![3, 100]), 100);
+
+        let empty = iter::empty::<Vec<usize>>().collect::<Store<_>>();
+        assert_eq!((empty.cols(), empty.len()), (0, 0));
+    }
+
+    #[test]
+    #[should_panic(expected = "row 1 has 1 columns, but the store has 2")]
+    fn it_rejects_collecting_rows_of_different_widths() {
+        let _: Store<_> = vec![vec!["a", "b"], vec!["c"]].into_iter().collect();
+    }
+
+    #[test]
+    fn it_iterates_rows() {
+        let mut store = Store::new(2);
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["b", "x2"]);
+        store.insert(vec!["c", "x3"]);
+        store.delete(&[cmp::Condition {
+                           column: 0,
+                           cmp: cmp::Comparison::Equal(cmp::Value::new("b")),
+                       }]);
+
+        let mut seen = Vec::new();
+        for row in &store {
+            seen.push(row[0]);
+        }
+        assert_eq!(seen, vec!["a", "c"]);
+        assert_eq!(store.rows().collect::<Vec<_>>(), store.find(&[]).collect::<Vec<_>>());
+        assert_eq!(store.into_rows(), vec![vec!["a", "x1"], vec!["c", "x3"]]);
+    }
+
+    #[test]
+    fn it_reports_its_size() {
+        let mut store = Store::new(3);
+        assert!(store.is_empty());
+        assert_eq!(store.len(), 0);
+        assert_eq!(store.cols(), 3);
+
+        store.insert(vec!["a", "x1", "y"]);
+        store.insert(vec!["b", "x2", "y"]);
+        assert!(!store.is_empty());
+        assert_eq!(store.len(), 2);
+
+        store.delete(&[cmp::Condition {
+                           column: 0,
+                           cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                       }]);
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.cols(), 3);
+    }
+
+    #[test]
+    fn it_lists_indexed_columns() {
+        let mut store = Store::<&str>::new(4);
+        assert_eq!(store.indexed_columns().count(), 0);
+        store.index(2, idx::HashIndex::new());
+        store.index(0, idx::BTreeIndex::new());
+        store.index(3, idx::HashIndex::new());
+        store.index_multi(&[1, 2], idx::HashIndex::new());
+        assert_eq!(store.indexed_columns().collect::<Vec<_>>(), vec![0, 2, 3]);
+        store.unindex(2);
+        assert_eq!(store.indexed_columns().collect::<Vec<_>>(), vec![0, 3]);
+    }
+
+    #[test]
+    fn it_clears() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.index(1, idx::BTreeIndex::new());
+        store.index_multi(&[0, 1], idx::HashIndex::new());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["a", "x2"]);
+        store.clear();
+        assert!(store.is_empty());
+        assert_eq!(store.indexed_columns().collect::<Vec<_>>(), vec![0, 1]);
+
+        assert_eq!(store.insert(vec!["a", "x3"]), 2);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   }];
+        assert_eq!(store.using_index(&cmp).count(), 1);
+        assert_eq!(store.find(&cmp).collect::<Vec<_>>(), vec![&vec!["a", "x3"]]);
+        assert_eq!(store.count(&cmp), 1);
+    }
+
+    #[test]
+    fn it_clears_custom_indices() {
+        let mut store = Store::new(1);
+        store.index(0,
+                    Index::Range(Box::new(CountingIndex {
+                        inner: idx::BTreeIndex::new(),
+                        yielded: sync::Arc::new(AtomicUsize::new(0)),
+                    })));
+        store.insert(vec![1usize]);
+        store.insert(vec![1]);
+        store.clear();
+        store.insert(vec![1]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(1usize)),
+                   }];
+        assert_eq!(store.count(&cmp), 1);
+    }
+
+    #[test]
+    fn it_truncates() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.index_multi(&[0, 1], idx::HashIndex::new());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["b", "x2"]);
+        store.insert(vec!["a", "x3"]);
+        store.insert(vec!["a", "x4"]);
+        store.truncate(3);
+        store.truncate(3);
+        store.truncate(10);
+        assert_eq!(store.len(), 3);
+
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   }];
+        assert_eq!(store.find(&cmp).collect::<Vec<_>>(),
+                   vec![&vec!["a", "x1"], &vec!["a", "x3"]]);
+        store.truncate(1);
+        assert_eq!(store.find(&cmp).collect::<Vec<_>>(), vec![&vec!["a", "x1"]]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("b")),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("x2")),
+                   }];
+        assert_eq!(store.count(&cmp), 0);
+        assert_eq!(store.find(&cmp).count(), 0);
+        assert_eq!(store.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn it_marks_deleted() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.index(1, idx::BTreeIndex::new());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["a", "x2"]);
+        store.insert(vec!["b", "x3"]);
+        assert!(store.mark_deleted(1));
+        assert!(!store.mark_deleted(1));
+        assert!(!store.mark_deleted(3));
+
+        // neither index lookups nor scans surface the row
+        let a = [cmp::Condition {
+                     column: 0,
+                     cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                 }];
+        assert_eq!(store.find(&a).collect::<Vec<_>>(), vec![&vec!["a", "x1"]]);
+        assert_eq!(store.count(&a), 1);
+        let x2 = [cmp::Condition {
+                      column: 1,
+                      cmp: cmp::Comparison::GreaterOrEqual(cmp::Value::new("x2")),
+                  }];
+        assert_eq!(store.find(&x2).collect::<Vec<_>>(), vec![&vec!["b", "x3"]]);
+        assert_eq!(store.find_ordered(&[], 1, true).collect::<Vec<_>>(),
+                   vec![&vec!["b", "x3"], &vec!["a", "x1"]]);
+        let not_a = [cmp::Condition {
+                         column: 0,
+                         cmp: cmp::Comparison::NotEqual(cmp::Value::new("b")),
+                     }];
+        assert_eq!(store.find(&not_a).collect::<Vec<_>>(), vec![&vec!["a", "x1"]]);
+
+        assert_eq!(store.get(1), None);
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.rows().count(), 2);
+        assert_eq!(store.update(&a, |r| r[1] = "x0"), 1);
+        assert_eq!(store.check_integrity(), Ok(()));
+        assert_eq!(store.into_rows(), vec![vec!["a", "x0"], vec!["b", "x3"]]);
+    }
+
+    #[test]
+    fn it_compacts() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.index_multi(&[0, 1], idx::HashIndex::new());
+        for i in 0..6 {
+            store.insert(vec![i % 2, i]);
+        }
+        store.mark_deleted(0);
+        store.mark_deleted(3);
+        store.delete(&[cmp::Condition {
+                           column: 1,
+                           cmp: cmp::Comparison::Equal(cmp::Value::new(4)),
+                       }]);
+
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(1)),
+                   }];
+        let before = store.find(&cmp).cloned().collect::<Vec<_>>();
+        assert_eq!(before, vec![vec![1, 1], vec![1, 5]]);
+
+        let moved = store.compact();
+        assert_eq!(moved.into_iter().collect::<Vec<_>>(),
+                   vec![(1, 0), (2, 1), (5, 2)]);
+        assert_eq!(store.find(&cmp).cloned().collect::<Vec<_>>(), before);
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.get(2), Some(&vec![1, 5]));
+
+        // identifiers continue from the compacted rows
+        assert_eq!(store.insert(vec![1, 6]), 3);
+        assert_eq!(store.find(&cmp).count(), 3);
+        let composite = [cmp::Condition {
+                             column: 0,
+                             cmp: cmp::Comparison::Equal(cmp::Value::new(0)),
+                         },
+                         cmp::Condition {
+                             column: 1,
+                             cmp: cmp::Comparison::Equal(cmp::Value::new(2)),
+                         }];
+        assert_eq!(store.find(&composite).collect::<Vec<_>>(), vec![&vec![0, 2]]);
+        assert_eq!(store.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn it_reserves() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.index(1, idx::BTreeIndex::new());
+        store.index_multi(&[0, 1], idx::HashIndex::new());
+        store.reserve(1000);
+        for i in 0..1000usize {
+            store.insert(vec![i, i % 2]);
+        }
+        store.delete(&[cmp::Condition {
+                           column: 1,
+                           cmp: cmp::Comparison::Equal(cmp::Value::new(0usize)),
+                       }]);
+        store.shrink_to_fit();
+
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(501usize)),
+                   }];
+        assert_eq!(store.find(&cmp).collect::<Vec<_>>(), vec![&vec![501, 1]]);
+        assert_eq!(store.len(), 500);
+    }
+
+    #[test]
+    fn it_inserts_many() {
+        let rows = || (0..100_000usize).map(|i| vec![i, i % 7, i % 13]);
+        let mut one = Store::new(3);
+        let mut many = Store::new(3);
+        for store in [&mut one, &mut many] {
+            store.index(0, idx::HashIndex::unique());
+            store.index(1, idx::BTreeIndex::new());
+            store.index_multi(&[1, 2], idx::HashIndex::new());
+            store.insert(vec![usize::MAX, 0, 0]);
+        }
+        for row in rows() {
+            one.insert(row);
+        }
+        assert_eq!(many.insert_many(rows()), 1..100_001);
+        assert_eq!(many.insert(vec![usize::MAX - 1, 0, 0]), 100_001);
+
+        let cmps = [vec![cmp::Condition {
+                             column: 1,
+                             cmp: cmp::Comparison::Less(cmp::Value::new(2usize)),
+                         }],
+                    vec![cmp::Condition {
+                             column: 1,
+                             cmp: cmp::Comparison::Equal(cmp::Value::new(3usize)),
+                         },
+                         cmp::Condition {
+                             column: 2,
+                             cmp: cmp::Comparison::Equal(cmp::Value::new(5usize)),
+                         }],
+                    vec![cmp::Condition {
+                             column: 0,
+                             cmp: cmp::Comparison::Equal(cmp::Value::new(4242usize)),
+                         }]];
+        for cmp in &cmps {
+            assert_eq!(many.find(cmp).filter(|r| r[0] != usize::MAX - 1).collect::<Vec<_>>(),
+                       one.find(cmp).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn it_inserts_many_atomically() {
+        use std::panic;
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::unique());
+        store.insert(vec!["a", "x"]);
+
+        let bad = vec![vec![vec!["b", "x"], vec!["c"]],
+                       vec![vec!["b", "x"], vec!["a", "y"]],
+                       vec![vec!["b", "x"], vec!["b", "y"]]];
+        for rows in bad {
+            let r = panic::catch_unwind(panic::AssertUnwindSafe(|| store.insert_many(rows)));
+            assert!(r.is_err());
+            assert_eq!(store.len(), 1);
+        }
+        assert_eq!(store.insert_many(vec![vec!["b", "x"]]), 1..2);
+        assert_eq!(store.find(&[]).count(), 2);
+    }
+
+    #[test]
+    fn it_inserts_many_atomically_w_composite_unique_indices() {
+        use std::panic;
+        let mut store = Store::new(2);
+        store.index_multi(&[0, 1], idx::HashIndex::unique());
+        store.insert(vec![0usize, 0]);
+
+        let bad = vec![vec![vec![1usize, 2], vec![1, 2]], vec![vec![1usize, 2], vec![0, 0]]];
+        for rows in bad {
+            let r = panic::catch_unwind(panic::AssertUnwindSafe(|| store.insert_many(rows)));
+            assert!(r.is_err());
+            assert_eq!(store.len(), 1);
+            assert_eq!(store.check_integrity(), Ok(()));
+        }
+        assert_eq!(store.insert_many(vec![vec![1usize, 2], vec![2, 1]]), 1..3);
+        assert_eq!(store.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn it_commits_transactions() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.insert(vec!["a", "x1"]);
+
+        let mut txn = store.transaction();
+        assert_eq!(txn.insert(vec!["a", "x2"]), 1);
+        assert_eq!(txn.insert(vec!["b", "x3"]), 2);
+        assert_eq!(txn.len(), 2);
+
+        // buffered rows are not visible until the transaction is committed
+        let a = [cmp::Condition {
+                     column: 0,
+                     cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                 }];
+        assert_eq!(txn.store().find(&a).count(), 1);
+        assert_eq!(txn.store().len(), 1);
+
+        assert_eq!(txn.commit(), 1..3);
+        assert_eq!(store.find(&a).count(), 2);
+        assert_eq!(store.get(2), Some(&vec!["b", "x3"]));
+    }
+
+    #[test]
+    fn it_rolls_back_transactions() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.insert(vec!["a", "x1"]);
+
+        {
+            let mut txn = store.transaction();
+            txn.insert(vec!["a", "x2"]);
+            txn.insert(vec!["b", "x3"]);
+        }
+        let mut txn = store.transaction();
+        txn.insert(vec!["c", "x4"]);
+        txn.rollback();
+
+        assert_eq!(store.len(), 1);
+        let a = [cmp::Condition {
+                     column: 0,
+                     cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                 }];
+        assert_eq!(store.find(&a).count(), 1);
+        assert_eq!(store.insert(vec!["b", "x5"]), 1);
+    }
+
+    #[test]
+    fn it_rolls_back_rejected_transactions() {
+        use std::panic;
+        let mut store = Store::new(2);
+        store.index(1, idx::HashIndex::unique());
+        store.insert(vec!["a", "x1"]);
+
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            let mut txn = store.transaction();
+            txn.insert(vec!["b", "x2"]);
+            txn.insert(vec!["c", "x1"]);
+            txn.commit();
+        }));
+        assert!(result.is_err());
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.insert(vec!["b", "x2"]), 1);
+    }
+
+    #[test]
+    fn it_notifies_observers() {
+        use std::sync::Mutex;
+        let seen = sync::Arc::new(Mutex::new(Vec::new()));
+        let mut store = Store::new(2);
+        store.insert(vec!["a", "x1"]);
+
+        let first = {
+            let seen = seen.clone();
+            store.on_insert(Box::new(move |rowid, row: &Vec<&str>| {
+                seen.lock().unwrap().push((1, rowid, row[1]));
+            }))
+        };
+        let second = {
+            let seen = seen.clone();
+            store.on_insert(Box::new(move |rowid, row: &Vec<&str>| {
+                seen.lock().unwrap().push((2, rowid, row[1]));
+            }))
+        };
+        assert_ne!(first, second);
+
+        store.insert(vec!["b", "x2"]);
+        store.insert_many(vec![vec!["c", "x3"], vec!["d", "x4"]]);
+        assert_eq!(*seen.lock().unwrap(),
+                   vec![(1, 1, "x2"), (2, 1, "x2"), (1, 2, "x3"), (2, 2, "x3"), (1, 3, "x4"),
+                        (2, 3, "x4")]);
+
+        seen.lock().unwrap().clear();
+        assert!(store.remove_observer(first).is_some());
+        assert!(store.remove_observer(first).is_none());
+        let mut txn = store.transaction();
+        txn.insert(vec!["e", "x5"]);
+        assert!(seen.lock().unwrap().is_empty());
+        txn.commit();
+        assert_eq!(*seen.lock().unwrap(), vec![(2, 4, "x5")]);
+    }
+
+    #[test]
+    fn it_materializes_views() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        let cmp = vec![cmp::Condition {
+                           column: 0,
+                           cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                       }];
+        let early = store.materialize(cmp.clone());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["b", "x2"]);
+        let late = store.materialize(cmp);
+        assert_ne!(early, late);
+
+        store.insert(vec!["a", "x3"]);
+        store.insert(vec!["c", "x4"]);
+        store.insert_many(vec![vec!["a", "x5"], vec!["b", "x6"]]);
+        let expected = vec![vec!["a", "x1"], vec!["a", "x3"], vec!["a", "x5"]];
+        assert_eq!(store.view(early).cloned().collect::<Vec<_>>(), expected);
+        assert_eq!(store.view(late).cloned().collect::<Vec<_>>(), expected);
+
+        // views follow deletes and updates
+        let x3 = [cmp::Condition {
+                      column: 1,
+                      cmp: cmp::Comparison::Equal(cmp::Value::new("x3")),
+                  }];
+        store.delete(&x3);
+        let x4 = [cmp::Condition {
+                      column: 1,
+                      cmp: cmp::Comparison::Equal(cmp::Value::new("x4")),
+                  }];
+        store.update(&x4, |r| r[0] = "a");
+        let x5 = [cmp::Condition {
+                      column: 1,
+                      cmp: cmp::Comparison::Equal(cmp::Value::new("x5")),
+                  }];
+        store.update(&x5, |r| r[0] = "b");
+        assert_eq!(store.view(early).collect::<Vec<_>>(),
+                   vec![&vec!["a", "x1"], &vec!["a", "x4"]]);
+
+        assert!(store.drop_view(early));
+        assert!(!store.drop_view(early));
+        store.insert(vec!["a", "x7"]);
+        assert_eq!(store.view(late).count(), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "no such view")]
+    fn it_rejects_dropped_views() {
+        let mut store = Store::<&str>::new(1);
+        let view = store.materialize(vec![]);
+        store.drop_view(view);
+        store.view(view).count();
+    }
+
+    #[test]
+    fn it_appends() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.index(1, idx::BTreeIndex::new());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["b", "x2"]);
+
+        let mut shard = Store::new(2);
+        shard.insert(vec!["c", "x3"]);
+        shard.insert(vec!["a", "x4"]);
+        shard.insert(vec!["c", "x5"]);
+        shard.mark_deleted(2);
+        assert_eq!(store.append(shard), 2..4);
+        assert_eq!(store.append(Store::new(2)), 4..4);
+        assert_eq!(store.len(), 4);
+
+        let c = [cmp::Condition {
+                     column: 0,
+                     cmp: cmp::Comparison::Equal(cmp::Value::new("c")),
+                 }];
+        assert_eq!(store.find(&c).collect::<Vec<_>>(), vec![&vec!["c", "x3"]]);
+        let a = [cmp::Condition {
+                     column: 0,
+                     cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                 }];
+        assert_eq!(store.find(&a).count(), 2);
+        let x = [cmp::Condition {
+                     column: 1,
+                     cmp: cmp::Comparison::Greater(cmp::Value::new("x2")),
+                 }];
+        assert_eq!(store.find(&x).collect::<Vec<_>>(),
+                   vec![&vec!["c", "x3"], &vec!["a", "x4"]]);
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot append a store with 3 columns to a store with 2 columns")]
+    fn it_rejects_appending_wrong_width() {
+        let mut store = Store::<&str>::new(2);
+        store.append(Store::new(3));
+    }
+
+    #[test]
+    fn it_drains() {
+        let mut store = Store::new(3);
+        store.index(0, idx::HashIndex::new());
+        store.index(2, idx::BTreeIndex::new());
+        store.index_multi(&[0, 2], idx::HashIndex::new());
+        for i in 0..10usize {
+            store.insert(vec![i % 3, i, if (3..6).contains(&i) { 1 } else { 0 }]);
+        }
+        let archived = [cmp::Condition {
+                            column: 2,
+                            cmp: cmp::Comparison::Equal(cmp::Value::new(1usize)),
+                        }];
+        assert_eq!(store.drain_where(&archived),
+                   vec![vec![0, 3, 1], vec![1, 4, 1], vec![2, 5, 1]]);
+        assert_eq!(store.drain_where(&archived), Vec::<Vec<usize>>::new());
+        assert_eq!(store.len(), 7);
+        assert_eq!(store.get(6), Some(&vec![0, 6, 0]));
+
+        // all indices agree with the remaining rows
+        let zero = [cmp::Condition {
+                        column: 0,
+                        cmp: cmp::Comparison::Equal(cmp::Value::new(0usize)),
+                    }];
+        let mut zeros = store.find(&zero).map(|r| r[1]).collect::<Vec<_>>();
+        zeros.sort();
+        assert_eq!(zeros, vec![0, 6, 9]);
+        let both = [zero[0].clone(),
+                    cmp::Condition {
+                        column: 2,
+                        cmp: cmp::Comparison::Equal(cmp::Value::new(0usize)),
+                    }];
+        assert_eq!(store.find(&both).map(|r| r[1]).collect::<Vec<_>>(), vec![0, 6, 9]);
+        assert_eq!(store.count(&archived), 0);
+        let live = [cmp::Condition {
+                        column: 2,
+                        cmp: cmp::Comparison::Less(cmp::Value::new(1usize)),
+                    }];
+        assert_eq!(store.find(&live).count(), 7);
+        assert_eq!(store.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn it_compares_columns() {
+        let mut store = Store::new(4);
+        store.index(0, idx::HashIndex::new());
+        store.index(1, idx::BTreeIndex::new());
+        store.insert(vec!["a", "x", "q", "x"]);
+        store.insert(vec!["a", "y", "q", "x"]);
+        store.insert(vec!["a", "z", "q", "y"]);
+        store.insert(vec!["b", "x", "q", "x"]);
+
+        // column-column comparisons on an indexed column cannot use the index
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::column(3)),
+                   }];
+        assert_eq!(store.using_index(&cmp).count(), 4);
+        assert_eq!(store.find(&cmp).count(), 2);
+
+        // but other conditions can, and both are applied
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::column(3)),
+                   }];
+        assert_eq!(store.using_index(&cmp).count(), 3);
+        assert_eq!(store.find(&cmp).collect::<Vec<_>>(), vec![&vec!["a", "x", "q", "x"]]);
+
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Greater(cmp::Value::column(3)),
+                   }];
+        assert_eq!(store.find(&cmp).collect::<Vec<_>>(),
+                   vec![&vec!["a", "y", "q", "x"], &vec!["a", "z", "q", "y"]]);
+        assert_eq!(store.count(&cmp), 2);
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::In(vec![cmp::Value::new("z"), cmp::Value::column(3)]),
+                   }];
+        assert_eq!(store.using_index(&cmp).count(), 4);
+        assert_eq!(store.find(&cmp).count(), 3);
+    }
+
+    #[test]
+    fn it_finds_at_snapshots() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.insert(vec!["a", "x1"]);
+        store.insert(vec!["b", "x2"]);
+        let snapshot = store.snapshot();
+        store.insert(vec!["a", "x3"]);
+        store.insert(vec!["b", "x4"]);
+
+        // through an index
+        let a = [cmp::Condition {
+                     column: 0,
+                     cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                 }];
+        assert_eq!(store.find_at(&snapshot, &a).collect::<Vec<_>>(), vec![&vec!["a", "x1"]]);
+        assert_eq!(store.find(&a).count(), 2);
+
+        // and through a scan
+        let x = [cmp::Condition {
+                     column: 1,
+                     cmp: cmp::Comparison::NotEqual(cmp::Value::new("x1")),
+                 }];
+        assert_eq!(store.find_at(&snapshot, &x).collect::<Vec<_>>(), vec![&vec!["b", "x2"]]);
+        assert_eq!(store.find(&x).count(), 3);
+
+        store.insert(vec!["a", "x5"]);
+        assert_eq!(store.find_at(&snapshot, &a).count(), 1);
+        assert_eq!(store.find_at(&store.snapshot(), &a).count(), 3);
+    }
+
+    #[test]
+    fn it_finds_with_predicate() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.insert(vec!["a".to_string(), "x1".to_string()]);
+        store.insert(vec!["a".to_string(), "x22".to_string()]);
+        store.insert(vec!["a".to_string(), "x333".to_string()]);
+        store.insert(vec!["b".to_string(), "x4444".to_string()]);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a".to_string())),
+                   }];
+
+        let checked = AtomicUsize::new(0);
+        let even = |r: &Vec<String>| {
+            checked.fetch_add(1, Ordering::SeqCst);
+            r[1].len().is_multiple_of(2)
+        };
+        let rows: Vec<_> = store.find_where(&cmp, even).map(|r| &r[1][..]).collect();
+        assert_eq!(rows, vec!["x1", "x333"]);
+        // the predicate is only run for rows that the indexed condition selected
+        assert_eq!(checked.load(Ordering::SeqCst), 3);
+
+        assert_eq!(store.find_where(&[], |r| r[1].len().is_multiple_of(2)).count(), 2);
+    }
+
+    #[test]
+    fn it_finds_between() {
+        let yielded = sync::Arc::new(AtomicUsize::new(0));
+        let mut store = Store::new(2);
+        store.index(0,
+                    Index::Range(Box::new(CountingIndex {
+                        inner: idx::BTreeIndex::new(),
+                        yielded: yielded.clone(),
+                    })));
+        for i in 0..20usize {
+            store.insert(vec![i, i % 2]);
+        }
+
+        let between = |min, max| {
+            [cmp::Condition {
+                 column: 0,
+                 cmp: cmp::Comparison::Between { min, max },
+             }]
+        };
+        let check = |cmp: &[cmp::Condition<usize>], expected: Vec<usize>| {
+            yielded.store(0, Ordering::SeqCst);
+            let rows: Vec<_> = store.find(cmp).map(|r| r[0]).collect();
+            assert_eq!(yielded.load(Ordering::SeqCst), expected.len());
+            assert_eq!(rows, expected);
+            assert_eq!(store.count(cmp), expected.len());
+        };
+
+        let v = cmp::Value::new;
+        check(&between(Bound::Included(v(3usize)), Bound::Excluded(v(7usize))),
+              vec![3, 4, 5, 6]);
+        check(&between(Bound::Excluded(v(3usize)), Bound::Included(v(7usize))),
+              vec![4, 5, 6, 7]);
+        check(&between(Bound::Included(v(3usize)), Bound::Included(v(3usize))),
+              vec![3]);
+        check(&between(Bound::Excluded(v(16usize)), Bound::Unbounded),
+              vec![17, 18, 19]);
+        check(&between(Bound::Unbounded, Bound::Excluded(v(2usize))), vec![0, 1]);
+
+        // empty ranges do not touch the index at all
+        check(&between(Bound::Included(v(7usize)), Bound::Included(v(3usize))),
+              vec![]);
+        check(&between(Bound::Excluded(v(3usize)), Bound::Excluded(v(3usize))),
+              vec![]);
+        check(&between(Bound::Included(v(3usize)), Bound::Excluded(v(3usize))),
+              vec![]);
+
+        let cmp = between(Bound::Included(v(3usize)), Bound::Excluded(v(7usize)));
+        let rows: Vec<_> = store.find_ordered(&cmp, 0, true).map(|r| r[0]).collect();
+        assert_eq!(rows, vec![6, 5, 4, 3]);
+        let cmp = between(Bound::Included(v(7usize)), Bound::Excluded(v(3usize)));
+        assert_eq!(store.find_ordered(&cmp, 0, true).count(), 0);
+        assert_eq!(store.query(&cmp).order_by(0, false).iter().count(), 0);
+    }
+
+    #[test]
+    fn it_finds_eq() {
+        let mut store = Store::new(2);
+        for i in 0..100usize {
+            store.insert(vec![i % 10, i]);
+        }
+        let seven = 7;
+        let scanned = store.find_eq(0, &seven).map(|row| row[1]).collect::<Vec<_>>();
+        assert_eq!(scanned, (0..10).map(|i| i * 10 + 7).collect::<Vec<_>>());
+        assert_eq!(store.explain(&[Condition::eq(0, seven)]).access, query::Access::Scan);
+
+        store.index(0, idx::HashIndex::new());
+        let indexed = store.find_eq(0, &seven).map(|row| row[1]).collect::<Vec<_>>();
+        assert_eq!(indexed, scanned);
+        // the lookup gives exactly the matching rows, so their number is known up front
+        assert_eq!(store.find_eq(0, &seven).size_hint(), (10, Some(10)));
+        assert_eq!(store.find_eq(0, &10).count(), 0);
+        assert_eq!(store.find_eq(1, &42).collect::<Vec<_>>(), vec![&vec![2, 42]]);
+        assert_eq!(store.find_eq(1, &100).count(), 0);
+    }
+
+    #[test]
+    fn it_finds_range() {
+        let mut store = Store::new(2);
+        for i in 0..100usize {
+            store.insert(vec![i % 10, i]);
+        }
+        let values = |rows: Find<usize, Vec<usize>>| {
+            let mut values = rows.map(|row| row[1]).collect::<Vec<_>>();
+            values.sort_unstable();
+            values
+        };
+        assert_eq!(values(store.find_range(1, 10..13)), vec![10, 11, 12]);
+
+        store.index(1, idx::BTreeIndex::new());
+        assert_eq!(values(store.find_range(1, 10..13)), vec![10, 11, 12]);
+        assert_eq!(values(store.find_range(1, 10..=13)), vec![10, 11, 12, 13]);
+        assert_eq!(values(store.find_range(1, 97..)), vec![97, 98, 99]);
+        assert_eq!(values(store.find_range(1, ..2)), vec![0, 1]);
+        assert_eq!(store.find_range(1, ..).count(), 100);
+        assert_eq!(store.find_range(0, 3..5).count(), 20);
+        // ranges that hold no values, or only values that no row has, are empty
+        assert_eq!(store.find_range(1, (Bound::Included(13), Bound::Excluded(10))).count(), 0);
+        assert_eq!(store.find_range(1, 5..5).count(), 0);
+        assert_eq!(store.find_range(1, 100..).count(), 0);
+        let range = (Bound::Excluded(3), Bound::Excluded(4));
+        assert_eq!(store.find_range(1, range).count(), 0);
+    }
+
+    #[test]
+    fn it_finds_many() {
+        let mut store = Store::new(2);
+        for i in 0..100usize {
+            store.insert(vec![i % 10, i]);
+        }
+        store.mark_deleted(13);
+        let keys = [3, 42, 7, 3];
+        let found = |store: &Store<usize>| {
+            let mut found = store.find_many(0, &keys)
+                .map(|(i, row)| (i, row[1]))
+                .collect::<Vec<_>>();
+            found.sort_unstable();
+            found
+        };
+
+        // every row is yielded for each position of its key, and missing keys are skipped
+        let scanned = found(&store);
+        assert_eq!(scanned.len(), 28);
+        assert_eq!(scanned.iter().filter(|&&(i, _)| i == 0).count(), 9);
+        assert_eq!(scanned.iter().filter(|&&(i, _)| i == 1).count(), 0);
+        assert_eq!(scanned.iter().filter(|&&(i, _)| i == 2).count(), 10);
+        assert!(scanned.iter().all(|&(i, value)| value % 10 == keys[i] && value != 13));
+        assert_eq!(&scanned[..2], &[(0, 3), (0, 23)]);
+
+        // the index finds the same rows, grouped by key in the order of the keys
+        store.index(0, idx::HashIndex::new());
+        assert_eq!(found(&store), scanned);
+        let positions = store.find_many(0, &keys).map(|(i, _)| i).collect::<Vec<_>>();
+        assert!(positions.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(store.find_many(0, &[]).count(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn it_rejects_find_eq_on_missing_column() {
+        let store = Store::<usize>::new(2);
+        store.find_eq(2, &1).count();
+    }
+
+    #[test]
+    fn it_finds_in_ranges() {
+        let yielded = sync::Arc::new(AtomicUsize::new(0));
+        let mut store = Store::new(2);
+        store.index(0,
+                    Index::Range(Box::new(CountingIndex {
+                        inner: idx::BTreeIndex::new(),
+                        yielded: yielded.clone(),
+                    })));
+        for i in 0..40usize {
+            store.insert(vec![i % 20, i]);
+        }
+
+        let check = |ranges: Vec<(Bound<usize>, Bound<usize>)>, expected: Vec<usize>| {
+            let cmp = [cmp::Condition::in_ranges(0, ranges)];
+            assert_eq!(store.explain(&cmp).access, query::Access::Index(0));
+            yielded.store(0, Ordering::SeqCst);
+            let rows: Vec<_> = store.find(&cmp).map(|r| r[1]).collect();
+            // every row is yielded by the index exactly once
+            assert_eq!(yielded.load(Ordering::SeqCst), rows.len());
+            assert_eq!(rows, expected);
+
+            let mut scanned: Vec<_> =
+                store.rows.values().filter(|r| cmp[0].matches(&r[..])).map(|r| r[1]).collect();
+            scanned.sort_unstable_by_key(|&i| (i % 20, i));
+            assert_eq!(rows, scanned);
+        };
+
+        // overlapping ranges
+        check(vec![(Bound::Included(2), Bound::Excluded(5)),
+                   (Bound::Included(3), Bound::Included(6))],
+              vec![2, 22, 3, 23, 4, 24, 5, 25, 6, 26]);
+        // touching ranges, out of order, and a range contained in another
+        check(vec![(Bound::Excluded(17), Bound::Unbounded),
+                   (Bound::Included(15), Bound::Included(17)),
+                   (Bound::Included(16), Bound::Included(16)),
+                   (Bound::Unbounded, Bound::Excluded(1))],
+              vec![0, 20, 15, 35, 16, 36, 17, 37, 18, 38, 19, 39]);
+        // the same range twice
+        check(vec![(Bound::Included(8), Bound::Included(8)),
+                   (Bound::Included(8), Bound::Included(8))],
+              vec![8, 28]);
+        // ranges that leave out the value between them
+        check(vec![(Bound::Excluded(10), Bound::Included(11)),
+                   (Bound::Included(9), Bound::Excluded(10))],
+              vec![9, 29, 11, 31]);
+
+        // no ranges at all match nothing, without touching the index
+        yielded.store(0, Ordering::SeqCst);
+        let none = [cmp::Condition::in_ranges::<usize, _>(0, vec![])];
+        assert_eq!(store.explain(&none).access, query::Access::Nothing);
+        assert_eq!(store.find(&none).count(), 0);
+        assert_eq!(yielded.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn it_finds_empty_between_without_scanning() {
+        let accessed = sync::Arc::new(AtomicUsize::new(0));
+        let mut store = Store::new(2);
+        for i in 0..10 {
+            store.insert(CountingRow {
+                cols: vec![i, i],
+                accessed: accessed.clone(),
+            });
+        }
+        accessed.store(0, Ordering::SeqCst);
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Between {
+                           min: Bound::Included(cmp::Value::new(5usize)),
+                           max: Bound::Excluded(cmp::Value::new(2usize)),
+                       },
+                   }];
+        assert_eq!(store.find(&cmp).count(), 0);
+        assert_eq!(store.count(&cmp), 0);
+        assert_eq!(accessed.load(Ordering::SeqCst), 0);
+
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Between {
+                           min: Bound::Included(cmp::Value::new(2usize)),
+                           max: Bound::Excluded(cmp::Value::new(5usize)),
+                       },
+                   }];
+        assert_eq!(store.find(&cmp).count(), 3);
+    }
+
+    #[test]
+    fn it_merges_ranges() {
+        let yielded = sync::Arc::new(AtomicUsize::new(0));
+        let mut store = Store::new(2);
+        store.index(0,
+                    Index::Range(Box::new(CountingIndex {
+                        inner: idx::BTreeIndex::new(),
+                        yielded: yielded.clone(),
+                    })));
+        for i in 0..100usize {
+            store.insert(vec![i, i % 2]);
+        }
+
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Greater(cmp::Value::new(3usize)),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(0usize)),
+                   },
+                   cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Less(cmp::Value::new(10usize)),
+                   }];
+        let rows: Vec<_> = store.find(&cmp).map(|r| r[0]).collect();
+        assert_eq!(rows, vec![4, 6, 8]);
+        // only 4..10 should have been scanned
+        assert_eq!(yielded.load(Ordering::SeqCst), 6);
+
+        yielded.store(0, Ordering::SeqCst);
+        let rows: Vec<_> = store.find_ordered(&cmp, 0, true).map(|r| r[0]).collect();
+        assert_eq!(rows, vec![8, 6, 4]);
+        assert_eq!(yielded.load(Ordering::SeqCst), 6);
+
+        // contradictory ranges don't touch the index at all
+        yielded.store(0, Ordering::SeqCst);
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Greater(cmp::Value::new(10usize)),
+                   },
+                   cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::LessOrEqual(cmp::Value::new(10usize)),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 0);
+        assert_eq!(store.find_ordered(&cmp, 0, false).count(), 0);
+        assert_eq!(yielded.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn it_prunes_redundant_conditions() {
+        use query::Access;
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        for i in 0..20usize {
+            store.insert(vec![i % 5, i]);
+        }
+
+        // a repeated condition is only checked once, even if it compares against another column
+        let cmp = [cmp::Condition::lt(1, 12usize),
+                   cmp::Condition::new(1, cmp::Comparison::Greater(cmp::Value::column(0))),
+                   cmp::Condition::lt(1, 12usize),
+                   cmp::Condition::new(1, cmp::Comparison::Greater(cmp::Value::column(0)))];
+        assert_eq!(store.explain(&cmp).filters, vec![0, 1]);
+        assert_eq!(store.find(&cmp).count(), 7);
+
+        // a condition implied by a stricter one on the same column is dropped
+        let cmp = [cmp::Condition::lt(1, 12usize),
+                   cmp::Condition::ne(0, 4usize),
+                   cmp::Condition::lt(1, 8usize),
+                   cmp::Condition::eq(0, 2usize),
+                   cmp::Condition::is_in(0, vec![1usize, 2])];
+        assert_eq!(store.explain(&cmp),
+                   QueryPlan {
+                       access: Access::Index(0),
+                       estimate: Some(4),
+                       filters: vec![2],
+                   });
+        assert_eq!(store.find(&cmp).map(|row| row[1]).collect::<Vec<_>>(), vec![2, 7]);
+
+        // of two equivalent conditions, the first is kept
+        let cmp = [cmp::Condition::between(1, Bound::Unbounded, Bound::Excluded(8usize)),
+                   cmp::Condition::lt(1, 8usize)];
+        assert_eq!(store.explain(&cmp).filters, vec![0]);
+        assert_eq!(store.find(&cmp).count(), 8);
+
+        // conditions against other columns are never taken to imply another
+        let cmp = [cmp::Condition::new(1, cmp::Comparison::Less(cmp::Value::column(0))),
+                   cmp::Condition::new(1, cmp::Comparison::LessOrEqual(cmp::Value::column(0)))];
+        assert_eq!(store.explain(&cmp).filters, vec![0, 1]);
+        assert_eq!(store.find(&cmp).count(), 0);
+    }
+
+    #[test]
+    fn it_skips_indices_for_contradictions() {
+        use query::Access;
+        let yielded = sync::Arc::new(AtomicUsize::new(0));
+        let mut store = Store::new(2);
+        store.index(0,
+                    Index::Range(Box::new(CountingIndex {
+                        inner: idx::BTreeIndex::new(),
+                        yielded: yielded.clone(),
+                    })));
+        for i in 0..20usize {
+            store.insert(vec![i % 5, i]);
+        }
+
+        let cmp = [cmp::Condition::eq(0, 1usize),
+                   cmp::Condition::eq(0, 1usize),
+                   cmp::Condition::lt(1, 10usize),
+                   cmp::Condition::eq(0, 2usize)];
+        assert_eq!(store.explain(&cmp),
+                   QueryPlan {
+                       access: Access::Nothing,
+                       estimate: Some(0),
+                       filters: vec![],
+                   });
+        assert_eq!(store.find(&cmp).count(), 0);
+        assert_eq!(yielded.load(Ordering::SeqCst), 0);
+        assert_eq!(store.find(&cmp[..3]).count(), 2);
+        assert_eq!(yielded.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn it_short_circuits_contradictions() {
+        let accessed = sync::Arc::new(AtomicUsize::new(0));
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        for i in 0..10 {
+            store.insert(CountingRow {
+                cols: vec![i % 3, i],
+                accessed: accessed.clone(),
+            });
+        }
+        accessed.store(0, Ordering::SeqCst);
+
+        let contradictions = [[cmp::Condition {
+                                   column: 1,
+                                   cmp: cmp::Comparison::Equal(cmp::Value::new(1usize)),
+                               },
+                               cmp::Condition {
+                                   column: 1,
+                                   cmp: cmp::Comparison::Equal(cmp::Value::new(2usize)),
+                               }],
+                              [cmp::Condition {
+                                   column: 0,
+                                   cmp: cmp::Comparison::Equal(cmp::Value::new(1usize)),
+                               },
+                               cmp::Condition {
+                                   column: 0,
+                                   cmp: cmp::Comparison::Greater(cmp::Value::new(1usize)),
+                               }]];
+        for cmp in &contradictions {
+            assert_eq!(store.find(cmp).count(), 0);
+            assert_eq!(store.count(cmp), 0);
+        }
+        assert_eq!(accessed.load(Ordering::SeqCst), 0);
+
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(1usize)),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::GreaterOrEqual(cmp::Value::new(1usize)),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 1);
+    }
+
+    #[test]
+    fn it_finds_prefixes() {
+        let words = ["fo", "foo", "bar", "food", "fop", "foo", "fooo", "f", "zoo"];
+        let starts_with = |prefix| {
+            [cmp::Condition {
+                 column: 0,
+                 cmp: cmp::Comparison::StartsWith(cmp::Prefix::new(cmp::Value::new(prefix))),
+             }]
+        };
+
+        let mut scan = Store::new(1);
+        let mut indexed = Store::new(1);
+        indexed.index(0, idx::BTreeIndex::new());
+        for &w in &words {
+            scan.insert(vec![w]);
+            indexed.insert(vec![w]);
+        }
+
+        for store in [&scan, &indexed] {
+            let mut rows: Vec<_> = store.find(&starts_with("foo")).map(|r| r[0]).collect();
+            rows.sort();
+            assert_eq!(rows, vec!["foo", "foo", "food", "fooo"]);
+            assert_eq!(store.find(&starts_with("")).count(), words.len());
+            assert_eq!(store.find(&starts_with("zoo")).count(), 1);
+            assert_eq!(store.find(&starts_with("zooo")).count(), 0);
+            assert_eq!(store.find(&starts_with("a")).count(), 0);
+        }
+        let rows: Vec<_> = indexed.find_ordered(&starts_with("fo"), 0, false)
+            .map(|r| r[0])
+            .collect();
+        assert_eq!(rows, vec!["fo", "foo", "foo", "food", "fooo", "fop"]);
+    }
+
+    #[test]
+    fn it_finds_prefixes_with_index() {
+        let yielded = sync::Arc::new(AtomicUsize::new(0));
+        let mut store = Store::new(1);
+        store.index(0,
+                    Index::Range(Box::new(CountingIndex {
+                        inner: idx::BTreeIndex::new(),
+                        yielded: yielded.clone(),
+                    })));
+        for v in [vec![1u8, 254, 9], vec![1, 255], vec![2, 0], vec![1, 255, 255], vec![1, 255, 0]] {
+            store.insert(vec![v]);
+        }
+
+        let prefix = cmp::Prefix::new(cmp::Value::new(vec![1u8, 255]));
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::StartsWith(prefix),
+                   }];
+        let rows: Vec<_> = store.find(&cmp).map(|r| r[0].clone()).collect();
+        assert_eq!(rows, vec![vec![1, 255], vec![1, 255, 0], vec![1, 255, 255]]);
+        // the scan stops at the first value past the prefix
+        assert_eq!(yielded.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    #[cfg(feature = "regex")]
+    fn it_finds_regex_matches() {
+        let mut store = Store::new(2);
+        store.index(0, idx::HashIndex::new());
+        store.index(1, idx::BTreeIndex::new());
+        store.insert(vec!["a", "apple"]);
+        store.insert(vec!["a", "banana"]);
+        store.insert(vec!["a", "pineapple"]);
+        store.insert(vec!["b", "apple"]);
+
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Regex(cmp::Pattern::parse("^apple$").unwrap()),
+                   }];
+        // regular expressions cannot use an index, even on an indexed column
+        assert_eq!(store.using_index(&cmp).count(), 4);
+        assert_eq!(store.find(&cmp).count(), 2);
+
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Regex(cmp::Pattern::parse("apple").unwrap()),
+                   }];
+        assert_eq!(store.using_index(&cmp).count(), 3);
+        assert_eq!(store.find(&cmp).collect::<Vec<_>>(),
+                   vec![&vec!["a", "apple"], &vec!["a", "pineapple"]]);
+    }
+
+    #[test]
+    fn it_finds_with_keyed_index() {
+        let lower = cmp::KeyFn::new("lower", |s: &String| s.to_lowercase());
+        let upper = cmp::KeyFn::new("upper", |s: &String| s.to_uppercase());
         let mut store = Store::new(2);
-        store.index(0, idx::HashIndex::new());
-        store.insert(vec!["a1", "a2"]);
-        store.insert(vec!["b1", "b2"]);
-        store.insert(vec!["c1", "c2"]);
-        assert_eq!(store.find(&[]).count(), 3);
+        store.insert(vec!["Foo".to_string(), "1".to_string()]);
+        store.index_keyed(0, lower, idx::HashIndex::new());
+        store.insert(vec!["FOO".to_string(), "2".to_string()]);
+        store.insert(vec!["bar".to_string(), "3".to_string()]);
+        store.insert(vec!["foo".to_string(), "4".to_string()]);
+        store.update(&[cmp::Condition {
+                         column: 1,
+                         cmp: cmp::Comparison::Equal(cmp::Value::new("3".to_string())),
+                     }],
+                     |r| r[0] = "fOo".to_string());
+        store.delete(&[cmp::Condition {
+                         column: 1,
+                         cmp: cmp::Comparison::Equal(cmp::Value::new("4".to_string())),
+                     }]);
+
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::KeyEqual(lower, cmp::Value::new("foo".to_string())),
+                   }];
+        assert_eq!(store.using_index(&cmp).count(), 3);
+        let rows: Vec<_> = store.find(&cmp).map(|r| &r[1][..]).collect();
+        assert_eq!(rows, vec!["1", "2", "3"]);
+
+        // a different key function cannot use the index
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::KeyEqual(upper, cmp::Value::new("foo".to_string())),
+                   }];
+        assert_eq!(store.using_index(&cmp).count(), 3);
+        store.index_keyed(0, upper, idx::HashIndex::new());
+        store.index_keyed(0, upper, idx::HashIndex::new());
+        store.index_multi(&[0], idx::HashIndex::new());
+        assert_eq!(store.composite.len(), 3);
+        assert_eq!(store.find(&cmp).count(), 3);
+
+        // nor can a plain equality comparison
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new("foo".to_string())),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 0);
+    }
+
+    /// A value that may be missing, like an `Option`.
+    #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    enum Cell {
+        Missing,
+        Int(usize),
+    }
+
+    impl cmp::Nullable for Cell {
+        fn is_null(&self) -> bool {
+            *self == Cell::Missing
+        }
     }
 
     #[test]
-    fn it_filters() {
+    fn it_finds_nulls() {
         let mut store = Store::new(2);
-        store.insert(vec!["a", "x1"]);
-        store.insert(vec!["a", "x2"]);
-        store.insert(vec!["b", "x3"]);
+        for i in 0..100 {
+            let cell = if i % 10 == 0 { Cell::Int(i / 10) } else { Cell::Missing };
+            store.insert(vec![Cell::Int(i), cell]);
+        }
+        let null = [cmp::Condition::is_null(1)];
+        let not_null = [cmp::Condition::is_not_null(1)];
+        assert_eq!(store.find(&null).count(), 90);
+        assert_eq!(store.find(&not_null).count(), 10);
+        assert!(store.find(&not_null).all(|r| r[0] == Cell::Int(match r[1] {
+            Cell::Int(i) => i * 10,
+            Cell::Missing => unreachable!(),
+        })));
+        let both = [null[0].clone(), not_null[0].clone()];
+        assert_eq!(store.explain(&both).access, query::Access::Nothing);
+        assert_eq!(store.find(&both).count(), 0);
+
+        // neither comparison can use an index, but other conditions still can
+        store.index(0, idx::BTreeIndex::new());
+        store.index(1, idx::HashIndex::new());
+        assert_eq!(store.explain(&null).access, query::Access::Scan);
+        let low = [cmp::Condition::lt(0, Cell::Int(25)), not_null[0].clone()];
+        assert_eq!(store.explain(&low).access, query::Access::Index(0));
+        assert_eq!(store.find(&low).count(), 3);
+    }
+
+    #[test]
+    fn it_finds_with_sparse_index() {
+        let mut store = Store::new(2);
+        for i in 0..100 {
+            let cell = if i % 10 == 0 { Cell::Int(i % 30) } else { Cell::Missing };
+            store.insert(vec![Cell::Int(i), cell]);
+        }
+        store.index_sparse(1, idx::HashIndex::new());
+        let (_, ref idx) = store.composite[0];
+        assert_eq!(idx.stats().entries, Some(10));
+
+        let ten = [cmp::Condition::eq(1, Cell::Int(10))];
+        assert_eq!(store.explain(&ten).access, query::Access::Sparse(1));
+        assert_eq!(store.explain(&ten).filters, Vec::<usize>::new());
+        let rows: Vec<_> = store.find(&ten).map(|r| r[0].clone()).collect();
+        assert_eq!(rows, vec![Cell::Int(10), Cell::Int(40), Cell::Int(70)]);
+
+        // looking for nulls has to scan, and still finds all of them
+        let missing = [cmp::Condition::eq(1, Cell::Missing)];
+        assert_eq!(store.explain(&missing).access, query::Access::Scan);
+        assert_eq!(store.find(&missing).count(), 90);
+        assert_eq!(store.find(&[cmp::Condition::is_null(1)]).count(), 90);
+
+        // the index follows values that become null, and nulls that become values
+        store.update(&[cmp::Condition::eq(0, Cell::Int(40))], |r| r[1] = Cell::Missing);
+        store.update(&[cmp::Condition::eq(0, Cell::Int(41))], |r| r[1] = Cell::Int(10));
+        store.delete(&[cmp::Condition::eq(0, Cell::Int(70))]);
+        store.insert(vec![Cell::Int(100), Cell::Int(10)]);
+        store.insert(vec![Cell::Int(101), Cell::Missing]);
+        let rows: Vec<_> = store.find(&ten).map(|r| r[0].clone()).collect();
+        assert_eq!(rows, vec![Cell::Int(10), Cell::Int(41), Cell::Int(100)]);
+        let (_, ref idx) = store.composite[0];
+        assert_eq!(idx.stats().entries, Some(10));
+        assert_eq!(store.find(&missing).count(), 91);
+
+        // replacing the index keeps a single sparse index per column
+        store.index_sparse(1, idx::BTreeIndex::new());
+        assert_eq!(store.composite.len(), 1);
+        assert_eq!(store.find(&ten).count(), 3);
+    }
+
+    #[test]
+    fn it_intersects_indices() {
+        let accessed = sync::Arc::new(AtomicUsize::new(0));
+        let mut store = Store::new(3);
+        store.index(0, idx::HashIndex::new());
+        store.index(1, idx::BTreeIndex::new());
+        for i in 0..100 {
+            store.insert(CountingRow {
+                cols: vec![i % 4, (i / 4) % 4, i],
+                accessed: accessed.clone(),
+            });
+        }
+        accessed.store(0, Ordering::SeqCst);
+
+        // each condition matches 25 rows, but only 6 rows match both
         let cmp = [cmp::Condition {
                        column: 0,
-                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(1usize)),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(2usize)),
                    }];
-        assert_eq!(store.find(&cmp)
-                       .count(),
-                   2);
-        assert!(store.find(&cmp).all(|r| r[0] == "a"));
+        let rows: Vec<_> = store.find(&cmp).map(|r| r.cols[2]).collect();
+        assert_eq!(rows, vec![9, 25, 41, 57, 73, 89]);
+        // the intersected rows are known to match both conditions, so they are never checked
+        assert_eq!(accessed.load(Ordering::SeqCst), 0);
+        assert_eq!(store.count(&cmp), 6);
+
+        // a condition without an index is still checked
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(1usize)),
+                   },
+                   cmp::Condition {
+                       column: 2,
+                       cmp: cmp::Comparison::Greater(cmp::Value::new(50usize)),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(2usize)),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 3);
     }
 
     #[test]
-    fn it_filters_with_indices() {
+    fn it_skips_intersection_for_selective_indices() {
+        let accessed = sync::Arc::new(AtomicUsize::new(0));
         let mut store = Store::new(2);
         store.index(0, idx::HashIndex::new());
-        store.insert(vec!["a", "x1"]);
-        store.insert(vec!["a", "x2"]);
-        store.insert(vec!["b", "x3"]);
+        store.index(1, idx::HashIndex::new());
+        for i in 0..100 {
+            store.insert(CountingRow {
+                cols: vec![i, i % 2],
+                accessed: accessed.clone(),
+            });
+        }
+        accessed.store(0, Ordering::SeqCst);
+
+        // the index on column 0 is selective enough on its own
         let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(1usize)),
+                   },
+                   cmp::Condition {
                        column: 0,
-                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(7usize)),
                    }];
-        assert_eq!(store.find(&cmp)
-                       .count(),
-                   2);
-        assert!(store.find(&cmp).all(|r| r[0] == "a"));
+        let candidates = store.using_index(&cmp).collect::<Vec<_>>();
+        assert_eq!(candidates, vec![7]);
+        assert_eq!(store.find(&cmp).count(), 1);
+        // only the condition the index does not answer is checked
+        assert_eq!(accessed.load(Ordering::SeqCst), 1);
     }
 
     #[test]
-    fn it_filters_with_partial_indices() {
+    fn it_avoids_indices_for_hot_values() {
         let mut store = Store::new(2);
         store.index(0, idx::HashIndex::new());
-        store.insert(vec!["a", "x1"]);
-        store.insert(vec!["a", "x2"]);
-        store.insert(vec!["b", "x3"]);
+        store.index(1, idx::HashIndex::new());
+        for i in 0..200usize {
+            // half the rows have the same value in column 0, and the rest are unique
+            let hot = if i < 100 { 0 } else { i };
+            store.insert(vec![hot, i % 40]);
+        }
+
         let cmp = [cmp::Condition {
                        column: 0,
-                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(0usize)),
                    },
                    cmp::Condition {
                        column: 1,
-                       cmp: cmp::Comparison::Equal(cmp::Value::new("x2")),
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(3usize)),
+                   }];
+        assert_eq!(store.using_index(&cmp).count(), 5);
+        assert_eq!(store.find(&cmp).count(), 3);
+
+        // but a cold value is still looked up in column 0
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(123usize)),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(3usize)),
                    }];
+        assert_eq!(store.using_index(&cmp).collect::<Vec<_>>(), vec![123]);
         assert_eq!(store.find(&cmp).count(), 1);
-        assert!(store.find(&cmp).all(|r| r[0] == "a" && r[1] == "x2"));
     }
 
     #[test]
-    fn it_filters_with_late_indices() {
+    fn it_explains_plans() {
+        use query::Access;
         let mut store = Store::new(2);
-        store.insert(vec!["a", "x1"]);
-        store.insert(vec!["a", "x2"]);
-        store.insert(vec!["b", "x3"]);
         store.index(0, idx::HashIndex::new());
+        for i in 0..10usize {
+            store.insert(vec![i % 5, i]);
+        }
+
+        // an indexed equality uses the index, and the other condition is checked
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Less(cmp::Value::new(5usize)),
+                   },
+                   cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(2usize)),
+                   }];
+        assert_eq!(store.explain(&cmp),
+                   QueryPlan {
+                       access: Access::Index(0),
+                       estimate: Some(2),
+                       filters: vec![0],
+                   });
+        assert_eq!(store.find(&cmp).count(), 1);
+
+        // a comparison the index cannot answer requires a full scan
         let cmp = [cmp::Condition {
                        column: 0,
-                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                       cmp: cmp::Comparison::NotEqual(cmp::Value::new(2usize)),
                    }];
-        assert_eq!(store.find(&cmp)
-                       .count(),
-                   2);
-        assert!(store.find(&cmp).all(|r| r[0] == "a"));
+        assert_eq!(store.explain(&cmp),
+                   QueryPlan {
+                       access: Access::Scan,
+                       estimate: None,
+                       filters: vec![0],
+                   });
+
+        // with no conditions, every row is yielded without being checked
+        assert_eq!(store.explain(&[]),
+                   QueryPlan {
+                       access: Access::Scan,
+                       estimate: None,
+                       filters: vec![],
+                   });
+
+        // contradicting conditions examine nothing
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(1usize)),
+                   },
+                   cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(2usize)),
+                   }];
+        assert_eq!(store.explain(&cmp).access, Access::Nothing);
     }
 
     #[test]
-    fn is_send_sync() {
-        use std::sync;
-        use std::thread;
-        let store = sync::Arc::new(Store::<()>::new(0));
-        thread::spawn(move || { drop(store); })
-            .join()
-            .unwrap();
+    fn it_explains_range_scans() {
+        use query::Access;
+        let mut store = Store::new(2);
+        store.index(1, idx::BTreeIndex::new());
+        for i in 0..10usize {
+            store.insert(vec![i % 5, i]);
+        }
+
+        // all range conditions on the indexed column are answered by the index
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::GreaterOrEqual(cmp::Value::new(2usize)),
+                   },
+                   cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(3usize)),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Less(cmp::Value::new(9usize)),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::NotEqual(cmp::Value::new(3usize)),
+                   }];
+        let plan = store.explain(&cmp);
+        assert_eq!(plan.access, Access::Index(1));
+        assert_eq!(plan.filters, vec![1, 3]);
+        assert_eq!(store.find(&cmp).map(|r| r[1]).collect::<Vec<_>>(), vec![8]);
     }
 
     #[test]
-    fn it_deletes() {
+    fn it_estimates_ranges_with_histograms() {
+        use query::Access;
         let mut store = Store::new(2);
-        store.insert(vec!["a1", "a2"]);
-        store.insert(vec!["b1", "b2"]);
-        store.insert(vec!["c1", "c2"]);
-        store.delete(&[]);
-        assert_eq!(store.find(&[]).count(), 0);
+        store.index(0, idx::BTreeIndex::new());
+        store.index(1, idx::HashIndex::new());
+        assert!(!store.analyze(1, 10));
+        // 900 rows with the value 0, and one row for each of 1 through 100
+        for i in 0..1000usize {
+            store.insert(vec![i.saturating_sub(899), i % 20]);
+        }
+        let low = [Condition::le(0, 10usize), Condition::eq(1, 3usize)];
+        let high = [Condition::gt(0, 90usize), Condition::eq(1, 3usize)];
+
+        // without a histogram, any range is expected to match as many rows as a single value
+        assert_eq!(store.explain(&low).access, Access::Index(0));
+        assert_eq!(store.explain(&low).estimate, Some(9));
+        assert_eq!(store.explain(&high).access, Access::Index(0));
+
+        // with one, the range that covers the frequent value is known to match far more rows
+        // than the equality condition on the other column
+        assert!(store.analyze(0, 100));
+        assert_eq!(store.histogram(0).map(Histogram::rows), Some(1000));
+        assert_eq!(store.explain(&low).access, Access::Index(1));
+        assert_eq!(store.explain(&low).estimate, Some(50));
+        assert_eq!(store.explain(&high).access, Access::Index(0));
+        assert_eq!(store.explain(&high).estimate, Some(10));
+        let expected = |conds: &[Condition<usize>]| {
+            store.rows().filter(|r| conds.iter().all(|c| c.matches(*r))).count()
+        };
+        assert_eq!(store.find(&low).count(), expected(&low));
+        assert_eq!(store.find(&high).count(), expected(&high));
+
+        // replacing the index discards the histogram
+        store.index(0, idx::BTreeIndex::new());
+        assert!(store.histogram(0).is_none());
+        assert_eq!(store.explain(&low).access, Access::Index(0));
     }
 
     #[test]
-    fn filtered_delete() {
-        let mut store = Store::new(2);
-        store.insert(vec!["a1", "a2"]);
-        store.insert(vec!["b1", "b2"]);
-        store.insert(vec!["c1", "c2"]);
-        store.delete_filter(&[], |row| row[0] != "b1");
-        assert_eq!(store.find(&[]).count(), 1);
-        assert!(store.find(&[]).all(|r| r[0] == "b1"));
+    fn it_follows_index_hints() {
+        use query::Access;
+        let mut store = Store::new(3);
+        store.index(0, idx::HashIndex::new());
+        store.index(1, idx::HashIndex::new());
+        store.index(2, idx::BTreeIndex::new());
+        for i in 0..100usize {
+            store.insert(vec![i % 50, i % 2, i]);
+        }
+        let cmp = [Condition::eq(0, 3usize), Condition::eq(1, 1usize)];
+        let expected = store.find(&cmp).cloned().collect::<Vec<_>>();
+        assert_eq!(store.query(&cmp).explain().unwrap(), store.explain(&cmp));
+        assert_eq!(store.explain(&cmp).access, Access::Index(0));
+
+        // a forced index is used even though it is expected to yield far more rows
+        let query = store.query(&cmp).use_index(1);
+        assert_eq!(query.explain(),
+                   Ok(QueryPlan {
+                       access: Access::Index(1),
+                       estimate: Some(50),
+                       filters: vec![0],
+                   }));
+        assert_eq!(query.iter().cloned().collect::<Vec<_>>(), expected);
+        let query = store.query(&cmp).ignore_index(0);
+        assert_eq!(query.explain().unwrap().access, Access::Index(1));
+        assert_eq!(query.iter().cloned().collect::<Vec<_>>(), expected);
+        let query = store.query(&cmp).force_scan();
+        assert_eq!(query.explain().unwrap().access, Access::Scan);
+        assert_eq!(query.iter().cloned().collect::<Vec<_>>(), expected);
+        let query = store.query(&cmp).ignore_index(0).ignore_index(1);
+        assert_eq!(query.explain().unwrap().access, Access::Scan);
+
+        // a forced scan does not walk the index used for ordering either
+        let range = [Condition::lt(2, 5usize)];
+        let query = store.query(&range).order_by(2, true).force_scan();
+        assert_eq!(query.explain().unwrap().access, Access::Scan);
+        assert_eq!(query.iter().map(|r| r[2]).collect::<Vec<_>>(), vec![4, 3, 2, 1, 0]);
+
+        // hints that cannot be followed are errors
+        assert_eq!(store.query(&cmp).use_index(5).try_iter().err(),
+                   Some(QueryError::NoIndex { column: 5 }));
+        let mut unindexed = store.clone();
+        unindexed.unindex(1);
+        assert_eq!(unindexed.query(&cmp).ignore_index(1).explain(),
+                   Err(QueryError::NoIndex { column: 1 }));
+        assert_eq!(store.query(&cmp).use_index(2).explain(),
+                   Err(QueryError::UnusableIndex { column: 2 }));
+        let ne = [Condition::ne(0, 3usize)];
+        assert_eq!(store.query(&ne).use_index(0).explain(),
+                   Err(QueryError::UnusableIndex { column: 0 }));
+        assert_eq!(store.query(&cmp).use_index(0).use_index(1).explain(),
+                   Err(QueryError::ConflictingHints));
+        assert_eq!(store.query(&cmp).use_index(0).force_scan().explain(),
+                   Err(QueryError::ConflictingHints));
+        assert_eq!(store.query(&cmp).use_index(0).ignore_index(0).explain(),
+                   Err(QueryError::ConflictingHints));
     }
 
     #[test]
-    fn it_deletes_with_filters() {
+    #[should_panic(expected = "has no index")]
+    fn it_rejects_bad_index_hints() {
+        let store = Store::<usize>::new(2);
+        store.query(&[]).use_index(0).iter().count();
+    }
+
+    #[test]
+    fn it_executes_prepared_queries() {
         let mut store = Store::new(2);
-        store.insert(vec!["a", "x1"]);
-        store.insert(vec!["a", "x2"]);
-        store.insert(vec!["b", "x3"]);
+        store.index(0, idx::HashIndex::new());
+        for i in 0..10usize {
+            store.insert(vec![i % 5, i]);
+        }
+
         let cmp = [cmp::Condition {
                        column: 0,
-                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(0usize)),
+                   },
+                   cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Between {
+                           min: Bound::Included(cmp::Value::new(0usize)),
+                           max: Bound::Excluded(cmp::Value::new(0usize)),
+                       },
                    }];
-        store.delete(&cmp);
-        assert_eq!(store.find(&cmp).count(), 0);
-        assert_eq!(store.find(&[]).count(), 1);
-        assert!(store.find(&[]).all(|r| r[0] == "b"));
+        let prepared = store.prepare(&cmp);
+        assert_eq!(prepared.placeholders(), 3);
+
+        let rows = |values: &[usize]| {
+            prepared.execute(&store, values).map(|r| r[1]).collect::<Vec<_>>()
+        };
+        assert_eq!(rows(&[2, 0, 10]), vec![2, 7]);
+        assert_eq!(rows(&[2, 5, 10]), vec![7]);
+        assert_eq!(rows(&[3, 0, 5]), vec![3]);
+        assert_eq!(rows(&[4, 5, 5]), Vec::<usize>::new());
     }
 
     #[test]
-    fn it_deletes_with_indices() {
-        let mut store = Store::new(2);
-        store.index(0, idx::HashIndex::new());
-        store.insert(vec!["a", "x1"]);
-        store.insert(vec!["a", "x2"]);
-        store.insert(vec!["b", "x3"]);
+    #[should_panic]
+    fn it_checks_prepared_query_values() {
+        let store = Store::<usize>::new(1);
         let cmp = [cmp::Condition {
                        column: 0,
-                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(0usize)),
                    }];
-        store.delete(&cmp);
-        assert_eq!(store.find(&cmp).count(), 0);
-        assert_eq!(store.find(&[]).count(), 1);
-        assert!(store.find(&[]).all(|r| r[0] == "b"));
+        store.prepare(&cmp).execute(&store, &[1, 2]).count();
     }
 
     #[test]
-    fn it_deletes_with_partial_indices() {
+    fn it_replans_prepared_queries() {
+        let accessed = sync::Arc::new(AtomicUsize::new(0));
         let mut store = Store::new(2);
+        for i in 0..100 {
+            store.insert(CountingRow {
+                cols: vec![i % 10, i],
+                accessed: accessed.clone(),
+            });
+        }
+
+        let cmp = [cmp::Condition {
+                       column: 0,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(0usize)),
+                   }];
+        let prepared = store.prepare(&cmp);
+
+        // without an index, every row has to be checked
+        accessed.store(0, Ordering::SeqCst);
+        assert_eq!(prepared.execute(&store, &[3]).count(), 10);
+        assert_eq!(accessed.load(Ordering::SeqCst), 100);
+
+        // once an index is added, it is used
         store.index(0, idx::HashIndex::new());
-        store.insert(vec!["a", "x1"]);
-        store.insert(vec!["a", "x2"]);
-        store.insert(vec!["b", "x3"]);
+        accessed.store(0, Ordering::SeqCst);
+        assert_eq!(prepared.execute(&store, &[3]).count(), 10);
+        assert_eq!(accessed.load(Ordering::SeqCst), 0);
+
+        // and once it is removed again, it is no longer used
+        store.unindex(0);
+        accessed.store(0, Ordering::SeqCst);
+        assert_eq!(prepared.execute(&store, &[4]).count(), 10);
+        assert_eq!(accessed.load(Ordering::SeqCst), 100);
+    }
+
+    /// Counts how many times a `Large` has been cloned.
+    static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+    /// A value that is expensive to copy, and that counts how many times it has been.
+    #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    struct Large(String);
+
+    impl Clone for Large {
+        fn clone(&self) -> Self {
+            CLONES.fetch_add(1, Ordering::SeqCst);
+            Large(self.0.clone())
+        }
+    }
+
+    #[test]
+    fn it_shares_values_with_indices() {
+        let large = |s: &str| Large(s.repeat(1024));
+
+        // every index gets its own copy of a plain value
+        let mut store = Store::new(3);
+        store.index(0, idx::HashIndex::new());
+        store.index(1, idx::BTreeIndex::new());
+        store.index(2, idx::HashIndex::new());
+        CLONES.store(0, Ordering::SeqCst);
+        store.insert(vec![large("a"), large("b"), large("c")]);
+        assert_eq!(CLONES.load(Ordering::SeqCst), 3);
+
+        // whereas an Arc'd value is shared between the row and the indices
+        let mut store = Store::new(3);
+        store.index(0, idx::HashIndex::new());
+        store.index(1, idx::BTreeIndex::new());
+        store.index(2, idx::HashIndex::new());
+        let row = vec![sync::Arc::new(large("a")),
+                       sync::Arc::new(large("b")),
+                       sync::Arc::new(large("c"))];
+        CLONES.store(0, Ordering::SeqCst);
+        store.insert(row);
+        assert_eq!(CLONES.load(Ordering::SeqCst), 0);
+        assert_eq!(sync::Arc::strong_count(&store.get(0).unwrap()[1]), 2);
+
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(sync::Arc::new(large("b")))),
+                   }];
+        assert_eq!(store.find(&cmp).count(), 1);
+    }
+
+    #[test]
+    fn it_dictionary_encodes() {
+        let countries = ["Norway", "Sweden", "Denmark"];
+        let country = |i: usize| sync::Arc::new(countries[i % 3].repeat(100));
+        let distinct = |store: &Store<sync::Arc<String>>| {
+            store.rows().map(|row| sync::Arc::as_ptr(&row[0])).collect::<BTreeSet<_>>().len()
+        };
+
+        let mut plain = Store::new(2);
+        let mut encoded = Store::new(2);
+        encoded.dictionary_encode(0);
+        encoded.index(0, idx::HashIndex::new());
+        for i in 0..1000 {
+            plain.insert(vec![country(i), sync::Arc::new(i.to_string())]);
+            encoded.insert(vec![country(i), sync::Arc::new(i.to_string())]);
+        }
+        assert_eq!(plain.dictionary_len(0), None);
+        assert_eq!(encoded.dictionary_len(0), Some(3));
+        assert_eq!(encoded.dictionary_len(1), None);
+
+        // every row holds one of the three values in the dictionary
+        assert_eq!(distinct(&plain), 1000);
+        assert_eq!(distinct(&encoded), 3);
+
         let cmp = [cmp::Condition {
                        column: 0,
-                       cmp: cmp::Comparison::Equal(cmp::Value::new("a")),
-                   },
-                   cmp::Condition {
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(country(1))),
+                   }];
+        assert_eq!(encoded.find(&cmp).count(), 333);
+        assert!(encoded.find(&cmp).all(|row| row[1].parse::<usize>().unwrap() % 3 == 1));
+
+        // values changed by an update are also taken from the dictionary
+        let first = sync::Arc::new("0".to_string());
+        let cmp = [cmp::Condition {
                        column: 1,
-                       cmp: cmp::Comparison::Equal(cmp::Value::new("x2")),
+                       cmp: cmp::Comparison::Equal(cmp::Value::new(first)),
                    }];
-        store.delete(&cmp);
-        assert_eq!(store.find(&cmp).count(), 0);
-        assert_eq!(store.find(&[]).count(), 2);
-        assert!(store.find(&[]).any(|r| r[0] == "a" && r[1] == "x1"));
-        assert!(store.find(&[]).any(|r| r[0] == "b" && r[1] == "x3"));
+        encoded.update(&cmp, |row| row[0] = country(1));
+        assert_eq!(encoded.dictionary_len(0), Some(3));
+        assert_eq!(distinct(&encoded), 3);
+        encoded.update(&cmp, |row| row[0] = sync::Arc::new("Finland".to_string()));
+        assert_eq!(encoded.dictionary_len(0), Some(4));
+        assert_eq!(distinct(&encoded), 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "columns must be dictionary-encoded before any rows are inserted")]
+    fn it_dictionary_encodes_only_empty_stores() {
+        let mut store = Store::new(1);
+        store.insert(vec![sync::Arc::new("a")]);
+        store.dictionary_encode(0);
     }
 }
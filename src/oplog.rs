@@ -0,0 +1,408 @@
+//! Logging of the modifications made to a `Store`, so that they can be replayed on a replica.
+//!
+//! Every modification is described by an `Op`, which carries the identifiers of the rows it
+//! affects, and the new values of every row it adds or changes. A replica that starts out empty,
+//! has the same indices as the `Store` the log is attached to, and is given every `Op` in order
+//! through `Store::apply`, thus ends up with the same rows under the same identifiers, and
+//! answers every query with the same rows in the same order.
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use Row;
+use Store;
+
+/// A log attached with `Store::attach_log`.
+pub(crate) type Log<T> = Box<dyn FnMut(Op<T>) + Send + Sync>;
+
+/// A single modification of a `Store`, as passed to the log attached with `Store::attach_log`, and
+/// as replayed by `Store::apply`. With the `serde` feature enabled, operations can be serialized
+/// and deserialized, so that they can be sent to a replica in another process.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Op<T> {
+    /// A row was inserted with the given identifier, by `insert`, `insert_many`, a `BulkLoader`,
+    /// or a `Txn`.
+    Insert {
+        /// The identifier of the new row.
+        rowid: usize,
+        /// The values of the new row.
+        row: Vec<T>,
+    },
+    /// The row with the given identifier was changed by `update` (or, for every row, by
+    /// `reindex`). Only the indices over columns that changed value are updated.
+    Update {
+        /// The identifier of the changed row.
+        rowid: usize,
+        /// The values of the row after the change.
+        row: Vec<T>,
+    },
+    /// The row with the given identifier was replaced by `upsert`, which removes the old row from
+    /// every index before adding the new one.
+    Replace {
+        /// The identifier of the replaced row.
+        rowid: usize,
+        /// The values of the new row.
+        row: Vec<T>,
+    },
+    /// The row with the given identifier was removed by `delete` (or any of the methods built on
+    /// it), `drain_where`, or `truncate`.
+    Delete {
+        /// The identifier of the removed row.
+        rowid: usize,
+    },
+    /// The row with the given identifier was marked as deleted by `mark_deleted`.
+    MarkDeleted {
+        /// The identifier of the row.
+        rowid: usize,
+    },
+    /// All rows were removed by `clear`.
+    Clear,
+    /// The rows were given new identifiers by `compact`.
+    Compact,
+    /// All indices were rebuilt by `reindex`, after the new values of all rows were logged.
+    Reindex,
+}
+
+impl<T, R> Store<T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Pass every modification made to the `Store` from now on to `sink`, as an `Op`, right after
+    /// it has been made. Replaces any log that was attached before.
+    ///
+    /// Every method that modifies the rows is logged, except `rows_mut_unindexed`, whose changes
+    /// are only logged when `reindex` is called, as an update of every row. Indices, views,
+    /// dictionaries, and other settings are not logged, so a replica should be set up with the
+    /// same indices before any operations are applied to it (see `apply`). The log is not kept
+    /// when the `Store` is cloned.
+    pub fn attach_log<F>(&mut self, sink: F)
+        where F: FnMut(Op<T>) + Send + Sync + 'static
+    {
+        self.log = Some(Box::new(sink));
+    }
+
+    /// Stop passing modifications to the log attached with `attach_log`. Returns true if a log was
+    /// attached.
+    pub fn detach_log(&mut self) -> bool {
+        self.log.take().is_some()
+    }
+
+    /// Apply an operation logged by another `Store` (see `attach_log`), keeping all indices up to
+    /// date, just as if the method that produced the operation had been called on this `Store`.
+    /// Operations are passed on to the log attached to this `Store`, if any, so replicas can be
+    /// chained.
+    ///
+    /// Operations must be applied in the order they were logged, starting from a `Store` that had
+    /// the same rows under the same identifiers as the logging `Store` had when its log was
+    /// attached (typically, both are empty). Since the identifiers of the rows inserted into the
+    /// replica are taken from the operations, rows should not be inserted into the replica other
+    /// than through `apply`.
+    ///
+    /// Panics if the operation does not fit this `Store`: if it inserts a row with an identifier
+    /// that has already been handed out, refers to a row that does not exist, has the wrong
+    /// number of columns, or conflicts with another row in a column with a unique index.
+    pub fn apply(&mut self, op: Op<T>)
+        where R: From<Vec<T>>
+    {
+        self.flush_indices();
+        match op {
+            Op::Insert { rowid, row } => {
+                assert!(rowid >= self.rowid,
+                        "cannot insert row {}, since rows up to {} have been inserted",
+                        rowid,
+                        self.rowid);
+                let mut row = self.checked(row, None);
+                self.intern(&mut row);
+                self.index_row(rowid, &row);
+                self.rows.insert(rowid, row);
+                self.rowid = rowid + 1;
+                self.mark_indexed();
+                self.notify(rowid..self.rowid);
+            }
+            Op::Update { rowid, row } => {
+                self.existing(rowid);
+                let row = self.checked(row, Some(rowid));
+                self.adopt_auto_indices();
+                self.update_row(rowid, row);
+                if let Some(row) = self.logged(rowid) {
+                    self.log(Op::Update { rowid, row });
+                }
+            }
+            Op::Replace { rowid, row } => {
+                self.existing(rowid);
+                let row = self.checked(row, Some(rowid));
+                self.replace_row(rowid, row);
+                if let Some(row) = self.logged(rowid) {
+                    self.log(Op::Replace { rowid, row });
+                }
+            }
+            Op::Delete { rowid } => {
+                self.existing(rowid);
+                let row = self.rows.remove(&rowid).unwrap();
+                if self.is_dead(rowid) {
+                    self.tombstones[rowid] = false;
+                    self.dead -= 1;
+                }
+                self.undex_row(rowid, &row);
+                self.log(Op::Delete { rowid });
+            }
+            Op::MarkDeleted { rowid } => {
+                self.existing(rowid);
+                self.mark_deleted(rowid);
+            }
+            Op::Clear => self.clear(),
+            Op::Compact => {
+                self.compact();
+            }
+            Op::Reindex => self.reindex(),
+        }
+    }
+
+    /// Pass the given operation to the log attached with `attach_log`, if any.
+    pub(crate) fn log(&mut self, op: Op<T>) {
+        if let Some(ref mut log) = self.log {
+            log(op);
+        }
+    }
+
+    /// Returns the values of the row with the given identifier, for an operation that is about to
+    /// be logged, or `None` if no log is attached.
+    pub(crate) fn logged(&self, rowid: usize) -> Option<Vec<T>> {
+        self.log.as_ref()?;
+        let row = &self.rows[&rowid];
+        Some((0..self.cols).map(|col| row.index(col).clone()).collect())
+    }
+
+    /// Panic if there is no row with the given identifier, for `apply`.
+    fn existing(&self, rowid: usize) {
+        assert!(self.rows.contains_key(&rowid),
+                "operation refers to row {}, which does not exist",
+                rowid);
+    }
+
+    /// Turn the values of a logged row into a row of this `Store`, and panic if they do not fit.
+    fn checked(&self, row: Vec<T>, replacing: Option<usize>) -> R
+        where R: From<Vec<T>>
+    {
+        assert!(row.len() == self.cols,
+                "row has {} columns, but the store has {}",
+                row.len(),
+                self.cols);
+        let row = R::from(row);
+        if let Some((column, existing)) = self.unique_conflict(&row, replacing) {
+            panic!("row conflicts with row {} in unique column {}",
+                   existing,
+                   column);
+        }
+        row
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use idx::{BTreeIndex, HashIndex};
+    use std::ops::Bound;
+    use std::sync::{Arc, Mutex};
+    use Condition;
+
+    /// A pseudo-random number generator, so that every run performs the same operations.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((self.0 >> 33) as usize) % n
+        }
+    }
+
+    /// The operations collected by a log.
+    type Ops = Arc<Mutex<Vec<Op<usize>>>>;
+
+    /// A store with an index on the first two columns, and a log that collects its operations.
+    fn logged() -> (Store<usize>, Ops) {
+        let mut store = Store::new(3);
+        store.index(0, HashIndex::new());
+        store.index(1, BTreeIndex::new());
+        let ops = Arc::new(Mutex::new(Vec::new()));
+        let log = ops.clone();
+        store.attach_log(move |op| log.lock().unwrap().push(op));
+        (store, ops)
+    }
+
+    /// Check that both stores hold the same rows under the same identifiers, and answer queries
+    /// with the same rows in the same order.
+    fn same(primary: &Store<usize>, replica: &Store<usize>) {
+        assert_eq!(replica.check_integrity(), Ok(()));
+        assert_eq!(replica.len(), primary.len());
+        assert!(replica.find_with_ids(&[]).eq(primary.find_with_ids(&[])));
+        for value in 0..10usize {
+            let queries = [vec![Condition::eq(0, value)],
+                           vec![Condition::eq(0, value), Condition::eq(2, value % 3)],
+                           vec![Condition::ge(1, value * 50), Condition::lt(1, value * 50 + 40)],
+                           vec![Condition::eq(2, value)]];
+            for conds in &queries {
+                assert!(replica.find(conds).eq(primary.find(conds)), "{:?}", conds);
+            }
+        }
+    }
+
+    #[test]
+    fn it_replicates_random_operations() {
+        let (mut primary, ops) = logged();
+        let mut replica = Store::new(3);
+        replica.index(0, HashIndex::new());
+        replica.index(1, BTreeIndex::new());
+
+        let mut rng = Rng(42);
+        let row = |rng: &mut Rng| vec![rng.below(10), rng.below(500), rng.below(1000)];
+        for round in 0..2000 {
+            match rng.below(100) {
+                0..=39 => {
+                    primary.insert(row(&mut rng));
+                }
+                40..=44 => {
+                    let rows = (0..rng.below(5)).map(|_| row(&mut rng)).collect::<Vec<_>>();
+                    primary.insert_many(rows);
+                }
+                45..=49 => {
+                    let mut loader = primary.begin_bulk_load();
+                    loader.insert(row(&mut rng));
+                    loader.insert(row(&mut rng));
+                    loader.finish();
+                }
+                50..=54 => {
+                    let mut txn = primary.transaction();
+                    txn.insert(row(&mut rng));
+                    txn.commit();
+                }
+                55..=64 => {
+                    let value = rng.below(10);
+                    let low = rng.below(500);
+                    primary.delete(&[Condition::eq(0, value),
+                                     Condition::between(1,
+                                                        Bound::Included(low),
+                                                        Bound::Excluded(low + 100))]);
+                }
+                65..=79 => {
+                    let (low, to) = (rng.below(500), rng.below(10));
+                    primary.update(&[Condition::ge(1, low), Condition::lt(1, low + 30)],
+                                   |r| r[0] = to);
+                }
+                80..=84 => {
+                    let value = rng.below(500);
+                    primary.update(&[Condition::eq(1, value)], |r| r[2] += 1);
+                }
+                85..=91 => {
+                    let rowid = primary.find_with_ids(&[Condition::eq(0, 1usize)])
+                        .nth(rng.below(3))
+                        .map(|(rowid, _)| rowid);
+                    if let Some(rowid) = rowid {
+                        primary.mark_deleted(rowid);
+                    }
+                }
+                92..=93 => {
+                    primary.compact();
+                }
+                94..=95 => {
+                    let len = primary.len() * 9 / 10;
+                    primary.truncate(len);
+                }
+                96 => {
+                    primary.drain_where(&[Condition::eq(2, rng.below(1000))]);
+                }
+                97 => {
+                    for r in primary.rows_mut_unindexed() {
+                        r[1] = (r[1] + 7) % 500;
+                    }
+                    primary.reindex();
+                }
+                98 if round % 10 == 0 => primary.clear(),
+                _ => {}
+            }
+            // replay in batches, so the replica is checked at many points along the way
+            if round % 100 == 0 {
+                for op in ops.lock().unwrap().drain(..) {
+                    replica.apply(op);
+                }
+                same(&primary, &replica);
+            }
+        }
+        for op in ops.lock().unwrap().drain(..) {
+            replica.apply(op);
+        }
+        same(&primary, &replica);
+        assert!(primary.len() > 100);
+    }
+
+    #[test]
+    fn it_replicates_upserts() {
+        let mut primary = Store::<usize>::with_key(2, 0);
+        let ops = Arc::new(Mutex::new(Vec::new()));
+        let log = ops.clone();
+        primary.attach_log(move |op| log.lock().unwrap().push(op));
+        primary.insert(vec![1, 10]);
+        primary.upsert(vec![2, 20]);
+        primary.upsert(vec![1, 30]);
+        assert!(primary.detach_log());
+        primary.insert(vec![3, 30]);
+        assert_eq!(*ops.lock().unwrap(),
+                   vec![Op::Insert {
+                            rowid: 0,
+                            row: vec![1, 10],
+                        },
+                        Op::Insert {
+                            rowid: 1,
+                            row: vec![2, 20],
+                        },
+                        Op::Replace {
+                            rowid: 0,
+                            row: vec![1, 30],
+                        }]);
+
+        // a replica passes the operations on to its own log
+        let mut replica = Store::<usize>::with_key(2, 0);
+        let chained = Arc::new(Mutex::new(Vec::new()));
+        let log = chained.clone();
+        replica.attach_log(move |op| log.lock().unwrap().push(op));
+        for op in ops.lock().unwrap().iter().cloned() {
+            replica.apply(op);
+        }
+        assert_eq!(*chained.lock().unwrap(), *ops.lock().unwrap());
+        assert_eq!(replica.get_by_key(&1), Some(&vec![1, 30]));
+        assert_eq!(replica.len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "operation refers to row 5, which does not exist")]
+    fn it_rejects_operations_on_missing_rows() {
+        let mut store = Store::<usize>::new(2);
+        store.apply(Op::Delete { rowid: 5 });
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot insert row 0, since rows up to 1 have been inserted")]
+    fn it_rejects_reused_identifiers() {
+        let mut store = Store::<usize>::new(2);
+        store.insert(vec![1, 2]);
+        store.apply(Op::Insert {
+            rowid: 0,
+            row: vec![1, 2],
+        });
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn it_serializes_operations() {
+        extern crate serde_json;
+        let ops = vec![Op::Insert {
+                           rowid: 3,
+                           row: vec![1usize, 2],
+                       },
+                       Op::Delete { rowid: 3 },
+                       Op::Compact];
+        let json = serde_json::to_string(&ops).unwrap();
+        assert_eq!(serde_json::from_str::<Vec<Op<usize>>>(&json).unwrap(), ops);
+    }
+}
@@ -0,0 +1,368 @@
+//! Read-only stores whose indices are sorted arrays.
+//!
+//! Once a `Store` is no longer modified, its indices need not support insertion and removal, and
+//! can instead be laid out as flat arrays of `(value, position)` pairs sorted by value. These take
+//! up far less memory than the maps behind `HashIndex` and `BTreeIndex`, and are searched with
+//! binary search, which touches few cache lines. `Store::freeze` turns a `Store` into a
+//! `PackedStore`, and `PackedStore::unfreeze` turns it back.
+
+use std::hash::Hash;
+use std::ops::{Bound, Range};
+
+use EqualityIndex;
+use IndexKind;
+use QueryError;
+use Row;
+use Store;
+use cmp::{self, Condition};
+use contradicts;
+use is_dead;
+use query::{Access, QueryPlan};
+
+/// An index of a `PackedStore` on a single column.
+struct Packed<T> {
+    column: usize,
+    /// The kind of index the `Store` had on the column, so that `unfreeze` can rebuild it.
+    kind: Option<IndexKind>,
+    /// The position of every row, along with its value in the column, sorted by value and then
+    /// by position.
+    entries: Vec<(T, usize)>,
+}
+
+/// A read-only `Store`, whose rows are kept in a single array, and whose indices are sorted
+/// arrays (see the module documentation). Create one with `Store::freeze`.
+///
+/// A `PackedStore` answers the same queries as the `Store` it was made from, but has no way of
+/// being modified. Unlike a `FrozenStore`, it lives entirely in memory, and works with any value
+/// and row type.
+pub struct PackedStore<T, R = Vec<T>> {
+    cols: usize,
+    /// The identifier the `Store` would have given the next row it inserted.
+    rowid: usize,
+    /// The identifier of every row, in ascending order.
+    ids: Vec<usize>,
+    rows: Vec<R>,
+    indices: Vec<Packed<T>>,
+}
+
+impl<T, R> Store<T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Turn this `Store` into a `PackedStore`, which answers the same queries using sorted arrays
+    /// in place of the indices, and cannot be modified.
+    ///
+    /// Every column with a single-column index (see `index`) is indexed in the `PackedStore`,
+    /// whatever kind of index it had; rows keep their identifiers, and rows that have been marked
+    /// as deleted are left out. Composite, keyed, sparse, and covering indices are dropped, as are
+    /// views, callbacks, and any attached log.
+    pub fn freeze(mut self) -> PackedStore<T, R> {
+        self.adopt_auto_indices();
+        self.flush_indices();
+        let mut ids = Vec::with_capacity(self.len());
+        let mut rows = Vec::with_capacity(self.len());
+        for (rowid, row) in self.rows {
+            if !is_dead(&self.tombstones, rowid) {
+                ids.push(rowid);
+                rows.push(row);
+            }
+        }
+        let indices = self.indices
+            .iter()
+            .map(|(&column, idx)| {
+                let mut entries = rows.iter()
+                    .enumerate()
+                    .map(|(i, row)| (row.index(column).clone(), i))
+                    .collect::<Vec<_>>();
+                entries.sort_unstable();
+                Packed {
+                    column,
+                    kind: idx.kind(),
+                    entries,
+                }
+            })
+            .collect();
+        PackedStore {
+            cols: self.cols,
+            rowid: self.rowid,
+            ids,
+            rows,
+            indices,
+        }
+    }
+}
+
+impl<T, R> PackedStore<T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Turn this `PackedStore` back into a `Store` that holds the same rows, with the same
+    /// identifiers. Every built-in index the `Store` had when it was frozen is rebuilt (see
+    /// `EqualityIndex::kind`); columns that had a custom index are left without one.
+    pub fn unfreeze(self) -> Store<T, R>
+        where T: Hash + Send + Sync + 'static
+    {
+        let mut store = Store::new(self.cols);
+        store.rows = self.ids.into_iter().zip(self.rows).collect();
+        store.rowid = self.rowid;
+        for packed in self.indices {
+            if let Some(kind) = packed.kind {
+                store.index(packed.column, kind.build());
+            }
+        }
+        store
+    }
+
+    /// Returns the number of columns of every row.
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    /// Returns the number of rows.
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Returns true if there are no rows.
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Returns the indexed columns, in ascending order.
+    pub fn indexed_columns(&self) -> impl Iterator<Item = usize> + '_ {
+        self.indices.iter().map(|packed| packed.column)
+    }
+
+    /// Look up the row with the given identifier, which is the identifier it had in the `Store`.
+    pub fn get(&self, row: usize) -> Option<&R> {
+        self.ids.binary_search(&row).ok().map(|i| &self.rows[i])
+    }
+
+    /// Iterate over all rows, in the order they were inserted into the `Store`.
+    pub fn rows(&self) -> impl Iterator<Item = &R> + '_ {
+        self.rows.iter()
+    }
+
+    /// Check that all the given conditions only refer to columns that exist in this store.
+    pub fn validate(&self, conds: &[Condition<T>]) -> Result<(), QueryError> {
+        for c in conds {
+            let columns = c.cmp
+                .iter_values()
+                .filter_map(|v| match *v {
+                    cmp::Value::Column(col) => Some(col),
+                    cmp::Value::Const(..) => None,
+                });
+            for column in Some(c.column).into_iter().chain(columns) {
+                if column >= self.cols {
+                    return Err(QueryError::ColumnOutOfRange {
+                        column,
+                        cols: self.cols,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns an iterator that yields all rows matching all the given `Condition`s, just like
+    /// `Store::find`.
+    ///
+    /// If any of the conditions compare an indexed column for equality against a constant, or
+    /// are range conditions on an indexed column, the index that narrows the candidates down the
+    /// most is searched, and only the rows it yields are checked. The rows are then yielded in
+    /// the order of their values in that column, and rows with the same value in the order they
+    /// were inserted. Otherwise, every row is checked, in the order they were inserted.
+    ///
+    /// Panics if any of the conditions refer to a column that does not exist.
+    pub fn find<'c, 's: 'c>(&'s self,
+                            conds: &'c [Condition<'c, T>])
+                            -> impl Iterator<Item = &'s R> + 'c {
+        self.find_with_ids(conds).map(|(_, row)| row)
+    }
+
+    /// Like `find`, but also yields the identifier of each matching row.
+    pub fn find_with_ids<'c, 's: 'c>(&'s self,
+                                     conds: &'c [Condition<'c, T>])
+                                     -> impl Iterator<Item = (usize, &'s R)> + 'c {
+        if let Err(e) = self.validate(conds) {
+            panic!("{}", e);
+        }
+        let (packed, range) = self.candidates(conds);
+        range.map(move |i| match packed {
+                Some(packed) => packed.entries[i].1,
+                None => i,
+            })
+            .map(move |i| (self.ids[i], &self.rows[i]))
+            .filter(move |&(_, row)| conds.iter().all(|c| c.matches(row)))
+    }
+
+    /// Returns the number of rows matching all the given `Condition`s.
+    pub fn count(&self, conds: &[Condition<T>]) -> usize {
+        self.find(conds).count()
+    }
+
+    /// Describe how `find` would look for rows matching the given conditions, just like
+    /// `Store::explain`. Every condition is checked against every candidate.
+    pub fn explain(&self, conds: &[Condition<T>]) -> QueryPlan {
+        let (access, estimate) = match self.candidates(conds) {
+            _ if contradicts(conds) => (Access::Nothing, Some(0)),
+            (Some(packed), range) => (Access::Index(packed.column), Some(range.len())),
+            (None, _) => (Access::Scan, None),
+        };
+        QueryPlan {
+            access,
+            estimate,
+            filters: (0..conds.len()).collect(),
+        }
+    }
+
+    /// Decide which rows to look at for the given conditions: either the given positions in the
+    /// entries of an index, or, if there are none, the given positions in `self.rows`.
+    fn candidates(&self, conds: &[Condition<T>]) -> (Option<&Packed<T>>, Range<usize>) {
+        if contradicts(conds) {
+            return (None, 0..0);
+        }
+        let mut best: Option<(&Packed<T>, Range<usize>)> = None;
+        for c in conds {
+            let packed = match self.indices.iter().find(|packed| packed.column == c.column) {
+                Some(packed) => packed,
+                None => continue,
+            };
+            let (min, max) = match cmp::column_range(conds, c.column) {
+                Some(range) => range,
+                None => continue,
+            };
+            let start = packed.entries.partition_point(|(v, _)| match min {
+                Bound::Included(min) => v < min,
+                Bound::Excluded(min) => v <= min,
+                Bound::Unbounded => false,
+            });
+            let end = packed.entries.partition_point(|(v, _)| match max {
+                Bound::Included(max) => v <= max,
+                Bound::Excluded(max) => v < max,
+                Bound::Unbounded => true,
+            });
+            let range = start..end.max(start);
+            // ties go to the earliest condition, as with `Store::find`
+            if best.as_ref().is_none_or(|(_, b)| range.len() < b.len()) {
+                best = Some((packed, range));
+            }
+        }
+        match best {
+            Some((packed, range)) => (Some(packed), range),
+            None => (None, 0..self.rows.len()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use idx::{BTreeIndex, HashIndex};
+
+    /// A pseudo-random number generator, so that every run builds the same store.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((self.0 >> 33) as usize) % n
+        }
+    }
+
+    /// A store of random rows, with a hash index on the first column and a B-tree index on the
+    /// second, some of whose rows have been deleted or marked as deleted.
+    fn store(rng: &mut Rng) -> Store<usize> {
+        let mut store = Store::new(4);
+        store.index(0, HashIndex::new());
+        store.index(1, BTreeIndex::new());
+        for _ in 0..5000 {
+            let row = vec![rng.below(50), rng.below(1000), rng.below(10), rng.below(100)];
+            store.insert(row);
+        }
+        store.delete(&[Condition::eq(2, 3usize)]);
+        for _ in 0..200 {
+            let rowid = rng.below(5000);
+            store.mark_deleted(rowid);
+        }
+        store
+    }
+
+    fn sorted<'a, I: Iterator<Item = (usize, &'a Vec<usize>)>>(rows: I) -> Vec<usize> {
+        let mut ids = rows.map(|(rowid, _)| rowid).collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids
+    }
+
+    #[test]
+    fn it_finds_like_a_store() {
+        let mut rng = Rng(7);
+        let store = store(&mut rng);
+        let packed = store.clone().freeze();
+        assert_eq!(packed.len(), store.len());
+        assert_eq!(packed.indexed_columns().collect::<Vec<_>>(), vec![0, 1]);
+        assert!(packed.rows().eq(store.rows()));
+
+        for _ in 0..500 {
+            let (a, b, c) = (rng.below(60), rng.below(1100), rng.below(1100));
+            let conds = match rng.below(6) {
+                0 => vec![Condition::eq(0, a)],
+                1 => vec![Condition::ge(1, b.min(c)), Condition::lt(1, b.max(c))],
+                2 => vec![Condition::eq(0, a), Condition::le(1, b)],
+                3 => vec![Condition::eq(2, a % 10), Condition::gt(3, a)],
+                4 => vec![Condition::eq(0, a), Condition::eq(0, b % 60)],
+                _ => vec![Condition::ne(0, a), Condition::eq(1, b)],
+            };
+            assert_eq!(sorted(packed.find_with_ids(&conds)),
+                       sorted(store.find_with_ids(&conds)),
+                       "{:?}",
+                       conds);
+            if let Some(estimate) = packed.explain(&conds).estimate {
+                assert!(estimate >= packed.count(&conds));
+            }
+        }
+    }
+
+    #[test]
+    fn it_orders_by_index() {
+        let mut store = Store::new(2);
+        store.index(1, HashIndex::new());
+        for i in 0..10usize {
+            store.insert(vec![i, (10 - i) / 3]);
+        }
+        let packed = store.freeze();
+        let conds = [Condition::le(1, 1usize)];
+        assert_eq!(packed.explain(&conds).access, Access::Index(1));
+        assert_eq!(packed.explain(&conds).estimate, Some(5));
+        let found = packed.find(&conds).map(|row| row[0]).collect::<Vec<_>>();
+        assert_eq!(found, vec![8, 9, 5, 6, 7]);
+        assert_eq!(packed.find(&[]).map(|row| row[0]).collect::<Vec<_>>(),
+                   (0..10).collect::<Vec<_>>());
+        assert_eq!(packed.get(3), Some(&vec![3, 2]));
+        assert_eq!(packed.get(10), None);
+    }
+
+    #[test]
+    fn it_unfreezes() {
+        let mut rng = Rng(11);
+        let store = store(&mut rng);
+        let restored = store.clone().freeze().unfreeze();
+        assert_eq!(restored.indexed_columns().collect::<Vec<_>>(), vec![0, 1]);
+        assert!(restored.rows().eq(store.rows()));
+        let conds = [Condition::eq(0, 7usize)];
+        assert_eq!(sorted(restored.find_with_ids(&conds)),
+                   sorted(store.find_with_ids(&conds)));
+        assert_eq!(restored.check_integrity(), Ok(()));
+
+        // rows inserted after unfreezing get fresh identifiers
+        let mut restored = restored;
+        assert_eq!(restored.insert(vec![0, 0, 0, 0]), 5000);
+    }
+
+    #[test]
+    #[should_panic(expected = "column 4")]
+    fn it_rejects_bad_conditions() {
+        let packed = Store::<usize>::new(4).freeze();
+        packed.find(&[Condition::eq(4, 1usize)]).count();
+    }
+}
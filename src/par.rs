@@ -0,0 +1,116 @@
+//! `rayon` support for `Store`.
+//!
+//! Rows are matched against conditions in parallel. When an index is used, the candidate row
+//! identifiers are collected from the index first, and are then split between threads.
+
+use rayon::iter::Either;
+use rayon::prelude::*;
+
+use Row;
+use Source;
+use Store;
+use cmp::Condition;
+
+impl<T, R> Store<T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Like `find`, but checks the candidate rows against the conditions in parallel, using
+    /// `rayon`. The same index is used as for `find`.
+    ///
+    /// If every row has to be scanned, the rows are split between threads directly. Otherwise,
+    /// the rows found through the index are collected before they are split between threads, so
+    /// this is mostly useful when many rows have to be checked. Rows are *not* necessarily yielded
+    /// in the same order as by `find`.
+    pub fn par_find<'c, 's: 'c>(&'s self,
+                                conds: &'c [Condition<'c, T>])
+                                -> impl ParallelIterator<Item = &'s R> + 'c
+        where T: Sync,
+              R: Sync
+    {
+        let plan = self.plan(conds);
+        self.count_query(conds, &plan);
+        let filters = conds.iter()
+            .enumerate()
+            .filter(|&(i, _)| !plan.exact.holds(i))
+            .map(|(_, c)| c)
+            .collect::<Vec<_>>();
+        let rows = match plan.source {
+            Source::Scan => {
+                Either::Left(self.rows
+                    .par_iter()
+                    .filter(move |&(&rowid, _)| !self.is_dead(rowid))
+                    .map(|(_, row)| row))
+            }
+            ref source => {
+                let rowids = self.rowids(conds, source).collect::<Vec<_>>();
+                Either::Right(rowids.into_par_iter().map(move |rowid| &self.rows[&rowid]))
+            }
+        };
+        let stats = self.stats.as_ref();
+        rows.inspect(move |_| if let Some(stats) = stats {
+                stats.examined();
+            })
+            .filter(move |row| filters.iter().all(|c| c.matches(*row)))
+            .inspect(move |_| if let Some(stats) = stats {
+                stats.returned();
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cmp;
+    use idx::{BTreeIndex, HashIndex};
+
+    /// A store with many rows of pseudo-random values.
+    fn store() -> Store<usize> {
+        let mut store = Store::new(3);
+        let mut x: usize = 1;
+        for _ in 0..50_000 {
+            // a simple linear congruential generator is random enough here
+            x = x.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            store.insert(vec![(x >> 33) % 100, (x >> 17) % 1000, x % 7]);
+        }
+        store
+    }
+
+    fn compare(store: &Store<usize>, conds: &[cmp::Condition<usize>]) {
+        let mut expected = store.find(conds).collect::<Vec<_>>();
+        expected.sort();
+        let mut got = store.par_find(conds).collect::<Vec<_>>();
+        got.sort();
+        assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn it_finds_in_parallel() {
+        let mut store = store();
+        let conds = [cmp::Condition {
+                         column: 0,
+                         cmp: cmp::Comparison::Equal(cmp::Value::new(42usize)),
+                     },
+                     cmp::Condition {
+                         column: 1,
+                         cmp: cmp::Comparison::Less(cmp::Value::new(500usize)),
+                     },
+                     cmp::Condition {
+                         column: 2,
+                         cmp: cmp::Comparison::NotEqual(cmp::Value::new(3usize)),
+                     }];
+        compare(&store, &[]);
+        compare(&store, &conds);
+        compare(&store, &conds[1..]);
+
+        store.index(0, HashIndex::new());
+        store.index(1, BTreeIndex::new());
+        for i in (0..50_000).step_by(7) {
+            store.mark_deleted(i);
+        }
+        compare(&store, &[]);
+        compare(&store, &conds);
+        compare(&store, &conds[1..]);
+        compare(&store, &conds[2..]);
+    }
+}
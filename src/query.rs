@@ -0,0 +1,748 @@
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, btree_map};
+use std::fmt;
+use std::iter;
+use std::rc::Rc;
+use std::slice;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use Plan;
+use QueryError;
+use Row;
+use Store;
+use cmp::{Condition, Value};
+use stats::Report;
+use Known;
+use contradicts;
+
+/// A `Query` describes a set of rows to fetch from a `Store`, along with options for how those
+/// rows should be returned. A `Query` is constructed using `Store::query`, and is executed by
+/// calling `iter` (or by iterating over it directly).
+///
+/// Limits and offsets are applied while the rows are being produced, so a query with a limit
+/// stops examining rows as soon as it has produced enough of them. If the index used to satisfy
+/// the query is known to give exactly the matching rows (see `Store::explain`), the offset is
+/// applied to the index results directly, without looking at the skipped rows at all.
+///
+/// If the index chosen for a query is a poor fit for the data, the choice can be overridden with
+/// `use_index`, `ignore_index`, and `force_scan`. A query that may have to examine too many rows
+/// can be cut short with `budget` and `cancel_on`.
+pub struct Query<'s, 'c, T: Clone + 'c, R: 's> {
+    store: &'s Store<T, R>,
+    conds: &'c [Condition<'c, T>],
+    limit: Option<usize>,
+    offset: usize,
+    order: Option<(usize, bool)>,
+    hints: Vec<Hint>,
+    budget: Option<usize>,
+    cancel: Option<&'c AtomicBool>,
+}
+
+/// The cancellation token of a `Query` is only checked once every this many rows, so that
+/// checking it does not slow down the scan.
+const CANCEL_INTERVAL: usize = 1024;
+
+/// The rows produced by `Query::try_collect`, and whether the query was cut short.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Collected<'s, R: 's> {
+    /// The rows produced, in the order the query produced them.
+    pub rows: Vec<&'s R>,
+    /// True if the query stopped examining rows before it had examined all its candidate rows,
+    /// because it used up its budget (see `Query::budget`) or was cancelled (see
+    /// `Query::cancel_on`). If so, `rows` may be missing some of the rows the query would
+    /// otherwise have produced.
+    pub truncated: bool,
+}
+
+/// A hint given to a `Query` about which index to use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Hint {
+    /// Use the index on the given column (see `Query::use_index`).
+    Use(usize),
+    /// Do not use the index on the given column (see `Query::ignore_index`).
+    Ignore(usize),
+    /// Do not use any index (see `Query::force_scan`).
+    Scan,
+}
+
+impl<'s, 'c, T, R> Query<'s, 'c, T, R>
+    where T: Ord + Clone + 'c,
+          R: Row<T> + 's,
+          's: 'c
+{
+    /// Construct a new `Query` for the rows in the given `Store` that match all the given
+    /// `Condition`s.
+    pub fn new(store: &'s Store<T, R>, conds: &'c [Condition<'c, T>]) -> Self {
+        Query {
+            store,
+            conds,
+            limit: None,
+            offset: 0,
+            order: None,
+            hints: Vec::new(),
+            budget: None,
+            cancel: None,
+        }
+    }
+
+    /// Produce at most `n` rows.
+    pub fn limit(mut self, n: usize) -> Self {
+        self.limit = Some(n);
+        self
+    }
+
+    /// Skip the first `n` matching rows.
+    pub fn offset(mut self, n: usize) -> Self {
+        self.offset = n;
+        self
+    }
+
+    /// Produce rows ordered by their value in the given column. See `Store::find_ordered` for
+    /// details.
+    ///
+    /// If the column has a range index, the index is walked in the requested direction, and the
+    /// walk stops as soon as the limit is reached. For example, the latest `n` rows before some
+    /// point in time are found by ordering by the time column, descending, with a condition that
+    /// the time is less than that point, and a limit of `n`: only those `n` rows are looked at.
+    pub fn order_by(mut self, column: usize, descending: bool) -> Self {
+        self.order = Some((column, descending));
+        self
+    }
+
+    /// Find the candidate rows using the index on the given column, even if another index (or a
+    /// scan of all rows) is expected to be cheaper. Composite indices and intersections of
+    /// several indices are then not considered either.
+    ///
+    /// Executing the query fails with `QueryError::NoIndex` if the column has no index, and with
+    /// `QueryError::UnusableIndex` if its index cannot answer any of the conditions.
+    pub fn use_index(mut self, column: usize) -> Self {
+        self.hints.push(Hint::Use(column));
+        self
+    }
+
+    /// Do not use the index on the given column to find the candidate rows. This may be given
+    /// for several columns. Composite, keyed, and sparse indices that cover the column may still
+    /// be used.
+    ///
+    /// Executing the query fails with `QueryError::NoIndex` if the column has no index.
+    pub fn ignore_index(mut self, column: usize) -> Self {
+        self.hints.push(Hint::Ignore(column));
+        self
+    }
+
+    /// Do not use any index, and check every row against the conditions instead. Rows are still
+    /// not examined at all if the conditions contradict each other.
+    pub fn force_scan(mut self) -> Self {
+        self.hints.push(Hint::Scan);
+        self
+    }
+
+    /// Stop once `rows` candidate rows have been examined, whether or not they matched. This
+    /// bounds the time taken by a query that has to scan many rows, at the cost of missing some
+    /// of the matching rows; use `try_collect` to find out whether any were missed.
+    ///
+    /// The candidate rows are those counted as examined by `Store::query_stats`. If the query is
+    /// ordered and cannot walk a range index, the rows have to be sorted, and so only the
+    /// candidates examined within the budget are sorted and produced.
+    pub fn budget(mut self, rows: usize) -> Self {
+        self.budget = Some(rows);
+        self
+    }
+
+    /// Stop examining rows once the given token is set to true, for instance by another thread
+    /// that has lost interest in the results. The token is checked before the first candidate
+    /// row is examined, and then once every 1024 rows, so the query may examine a few more rows
+    /// after the token is set. Use `try_collect` to find out whether the query was cancelled
+    /// before it had examined all its candidate rows.
+    pub fn cancel_on(mut self, token: &'c AtomicBool) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Describe how the candidate rows for this query are found, taking any hints into account.
+    /// See `Store::explain`. Ordering and limits are not described.
+    pub fn explain(&self) -> Result<QueryPlan, QueryError> {
+        let plan = self.store.plan_hinted(self.conds, &self.hints)?;
+        Ok(self.store.describe(self.conds, plan))
+    }
+
+    /// Execute the query, and return an iterator over the resulting rows.
+    ///
+    /// Panics if any of the conditions refer to a column that does not exist, or if the hints
+    /// given for the query are invalid. Use `try_iter` to get an error instead.
+    pub fn iter(&self) -> Box<dyn Iterator<Item = &'s R> + 'c> {
+        self.try_iter().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like `iter`, but returns an error rather than panicking if any of the conditions refer to
+    /// a column that does not exist, or if the hints given for the query are invalid.
+    pub fn try_iter(&self) -> Result<Box<dyn Iterator<Item = &'s R> + 'c>, QueryError> {
+        self.execute(&Rc::new(Cell::new(false)))
+    }
+
+    /// Execute the query, and collect the resulting rows, along with whether the query was cut
+    /// short by its budget or its cancellation token (see `budget` and `cancel_on`). Returns an
+    /// error if any of the conditions refer to a column that does not exist, or if the hints
+    /// given for the query are invalid.
+    pub fn try_collect(&self) -> Result<Collected<'s, R>, QueryError> {
+        let truncated = Rc::new(Cell::new(false));
+        let rows = self.execute(&truncated)?.collect();
+        Ok(Collected {
+            rows,
+            truncated: truncated.get(),
+        })
+    }
+
+    /// Like `try_iter`, but sets `truncated` if the query is cut short.
+    fn execute(&self,
+               truncated: &Rc<Cell<bool>>)
+               -> Result<Box<dyn Iterator<Item = &'s R> + 'c>, QueryError> {
+        let store = self.store;
+        let conds = self.conds;
+        let offset = self.offset;
+        let limit = self.limit.unwrap_or(usize::MAX);
+        let plan = store.plan_hinted(conds, &self.hints)?;
+
+        let (rowids, filters) = match self.order {
+            Some((col, descending)) => {
+                match self.may_walk(col)
+                    .then(|| store.ordered_rowids(conds, col, descending))
+                    .flatten() {
+                    Some(rowids) => {
+                        let filters = match *conds {
+                            [] => Vec::new(),
+                            [ref c] if c.column == col && c.cmp.range().is_some() => Vec::new(),
+                            _ => conds.iter().collect(),
+                        };
+                        (self.interrupt(rowids, truncated), filters)
+                    }
+                    None => {
+                        // the rows have to be sorted, so there's no way to skip early
+                        let (rowids, filters) = store.candidates_for(conds, plan);
+                        let rowids = self.interrupt(rowids, truncated).filter(|&rowi| {
+                            filters.iter().all(|c| c.matches(&store.rows[&rowi]))
+                        });
+                        let rows = store.sort_rows(rowids, col, descending);
+                        return Ok(store.count_returned(rows.into_iter().skip(offset).take(limit)));
+                    }
+                }
+            }
+            None => {
+                let (rowids, filters) = store.candidates_for(conds, plan);
+                (self.interrupt(rowids, truncated), filters)
+            }
+        };
+
+        Ok(if filters.is_empty() {
+            let rows = rowids.skip(offset).take(limit).map(move |rowi| &store.rows[&rowi]);
+            store.count_returned(rows)
+        } else {
+            store.count_returned(rowids.map(move |rowi| &store.rows[&rowi])
+                .filter(move |r| filters.iter().all(|c| c.matches(*r)))
+                .skip(offset)
+                .take(limit))
+        })
+    }
+
+    /// Stop yielding the given candidate rows once the budget is used up or the query is
+    /// cancelled, and set `truncated` if there were any candidates left.
+    fn interrupt(&self,
+                 rowids: Box<dyn Iterator<Item = usize> + 's>,
+                 truncated: &Rc<Cell<bool>>)
+                 -> Box<dyn Iterator<Item = usize> + 'c> {
+        if self.budget.is_none() && self.cancel.is_none() {
+            return rowids;
+        }
+        Box::new(Interrupt {
+            rowids,
+            examined: 0,
+            budget: self.budget.unwrap_or(usize::MAX),
+            cancel: self.cancel,
+            truncated: truncated.clone(),
+        })
+    }
+
+    /// Returns true if the hints allow the rows to be produced in order by walking the range
+    /// index on the given column, if it has one.
+    fn may_walk(&self, col: usize) -> bool {
+        self.hints.iter().all(|hint| match *hint {
+            Hint::Use(column) => column == col,
+            Hint::Ignore(column) => column != col,
+            Hint::Scan => false,
+        })
+    }
+}
+
+/// The candidate rows of a `Query` with a budget or a cancellation token.
+struct Interrupt<'c, I> {
+    rowids: I,
+    examined: usize,
+    budget: usize,
+    cancel: Option<&'c AtomicBool>,
+    truncated: Rc<Cell<bool>>,
+}
+
+impl<'c, I: Iterator<Item = usize>> Iterator for Interrupt<'c, I> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.truncated.get() {
+            return None;
+        }
+        let cancelled = || {
+            self.examined.is_multiple_of(CANCEL_INTERVAL) &&
+            self.cancel.map(|token| token.load(Ordering::Relaxed)) == Some(true)
+        };
+        if self.examined == self.budget || cancelled() {
+            // the query was only cut short if there was anything left to examine
+            if self.rowids.next().is_some() {
+                self.truncated.set(true);
+            }
+            return None;
+        }
+        self.examined += 1;
+        self.rowids.next()
+    }
+}
+
+/// The rows found by `Store::find`.
+///
+/// The number of matching rows is known up front if they are found by looking up a single value
+/// in one of the built-in indices, that index gives exactly the matching rows (see
+/// `Store::explain`), and no rows have been marked as deleted. `Find::Exact` is then returned,
+/// which implements `ExactSizeIterator`, so that collecting the rows allocates exactly the space
+/// needed. Otherwise, `Find::Filtered` is returned, whose `size_hint` gives an upper bound on the
+/// number of matching rows, or `Find::Cached` if the query cache is enabled.
+///
+/// Looking up a single value in one of the built-in indices, or scanning every row, does not
+/// allocate.
+pub enum Find<'s, 'c, T: Clone + 's + 'c, R: 's> {
+    /// Rows whose number is known up front.
+    Exact(Exact<'s, T, R>),
+    /// Rows that are checked against the conditions as they are found.
+    Filtered(Filtered<'s, 'c, T, R>),
+    /// Rows found through the cache enabled with `Store::enable_query_cache`.
+    Cached(Cached<'s, 'c, R>),
+}
+
+/// The rows found by `Store::find` when their number is known up front. See `Find`.
+pub struct Exact<'s, T: 's, R: 's> {
+    store: &'s Store<T, R>,
+    candidates: Candidates<'s, R>,
+    len: usize,
+    report: Option<Report<'s>>,
+}
+
+/// The rows found by `Store::find` when their number is not known up front. See `Find`.
+pub struct Filtered<'s, 'c, T: Clone + 's + 'c, R: 's> {
+    store: &'s Store<T, R>,
+    candidates: Candidates<'s, R>,
+    conds: Cow<'c, [Condition<'c, T>]>,
+    /// Which conditions the candidates are already known to match.
+    exact: Known,
+    /// No more than this many rows are left.
+    upper: usize,
+    report: Option<Report<'s>>,
+}
+
+/// The rows found by `Store::find` through the query cache. See `Find`.
+pub struct Cached<'s, 'c, R: 's> {
+    rows: Box<dyn Iterator<Item = &'s R> + 'c>,
+    /// No more than this many rows are left.
+    upper: usize,
+    report: Option<Report<'s>>,
+}
+
+/// The identifiers of the rows that `Store::find` looks at. Lookups in the built-in indices and
+/// scans are walked directly, and only other sources of rows are boxed.
+pub(crate) enum Candidates<'s, R: 's> {
+    /// The rows indexed under a single value, as returned by `EqualityIndex::lookup_slice`.
+    Slice(slice::Iter<'s, usize>),
+    /// Every row in the `Store`.
+    Scan(btree_map::Iter<'s, usize, R>),
+    /// The copies of the rows with a single value kept by a covering index (see
+    /// `Store::index_covering`).
+    Covered(slice::Iter<'s, (usize, R)>),
+    /// Any other source of rows.
+    Boxed(Box<dyn Iterator<Item = usize> + 's>),
+}
+
+impl<'s, R> Candidates<'s, R> {
+    /// Returns the next candidate row, which is looked up in `rows` unless it came from a scan or
+    /// a covering index.
+    fn next(&mut self, rows: &'s BTreeMap<usize, R>) -> Option<(usize, &'s R)> {
+        let rowid = match *self {
+            Candidates::Slice(ref mut rowids) => *rowids.next()?,
+            Candidates::Scan(ref mut rows) => return rows.next().map(|(&rowid, row)| (rowid, row)),
+            Candidates::Covered(ref mut rows) => {
+                return rows.next().map(|&(rowid, ref row)| (rowid, row));
+            }
+            Candidates::Boxed(ref mut rowids) => rowids.next()?,
+        };
+        Some((rowid, &rows[&rowid]))
+    }
+}
+
+impl<'s, 'c, T: Clone, R> Find<'s, 'c, T, R> {
+    /// Rows whose number is known to be `len`. `candidates` must not include rows that have been
+    /// marked as deleted.
+    pub(crate) fn exact(store: &'s Store<T, R>, candidates: Candidates<'s, R>, len: usize) -> Self {
+        Find::Exact(Exact {
+            store,
+            candidates,
+            len,
+            report: None,
+        })
+    }
+
+    /// Rows of which there are at most `upper`, found by checking each of the candidates against
+    /// the conditions that `exact` does not mark as already met.
+    pub(crate) fn filtered(store: &'s Store<T, R>,
+                           candidates: Candidates<'s, R>,
+                           conds: Cow<'c, [Condition<'c, T>]>,
+                           exact: Known,
+                           upper: usize)
+                           -> Self {
+        Find::Filtered(Filtered {
+            store,
+            candidates,
+            conds,
+            exact,
+            upper,
+            report: None,
+        })
+    }
+
+    /// Rows found through the query cache, of which there are at most `upper`.
+    pub(crate) fn cached(rows: Box<dyn Iterator<Item = &'s R> + 'c>, upper: usize) -> Self {
+        Find::Cached(Cached {
+            rows,
+            upper,
+            report: None,
+        })
+    }
+
+    /// Report on the rows found to the query observer (see `Store::set_query_observer`) through
+    /// the given report, if any, once they have been dropped.
+    pub(crate) fn observed(mut self, report: Option<Report<'s>>) -> Self {
+        match self {
+            Find::Exact(ref mut rows) => rows.report = report,
+            Find::Filtered(ref mut rows) => rows.report = report,
+            Find::Cached(ref mut rows) => rows.report = report,
+        }
+        self
+    }
+}
+
+impl<'s, 'c, T, R> Iterator for Find<'s, 'c, T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    type Item = &'s R;
+
+    fn next(&mut self) -> Option<&'s R> {
+        match *self {
+            Find::Exact(ref mut rows) => rows.next(),
+            Find::Filtered(ref mut rows) => rows.next(),
+            Find::Cached(ref mut rows) => rows.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match *self {
+            Find::Exact(ref rows) => rows.size_hint(),
+            Find::Filtered(ref rows) => rows.size_hint(),
+            Find::Cached(ref rows) => rows.size_hint(),
+        }
+    }
+}
+
+impl<'s, T, R> Iterator for Exact<'s, T, R> {
+    type Item = &'s R;
+
+    fn next(&mut self) -> Option<&'s R> {
+        let (_, row) = self.candidates.next(&self.store.rows)?;
+        self.len = self.len.saturating_sub(1);
+        if let Some(ref stats) = self.store.stats {
+            stats.examined();
+            stats.returned();
+        }
+        if let Some(ref mut report) = self.report {
+            report.examined();
+            report.returned();
+        }
+        Some(row)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<'s, T, R> ExactSizeIterator for Exact<'s, T, R> {}
+
+impl<'s, 'c, T, R> Iterator for Filtered<'s, 'c, T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    type Item = &'s R;
+
+    fn next(&mut self) -> Option<&'s R> {
+        let store = self.store;
+        loop {
+            let (rowid, row) = self.candidates.next(&store.rows)?;
+            if store.dead != 0 && store.is_dead(rowid) {
+                continue;
+            }
+            if let Some(ref stats) = store.stats {
+                stats.examined();
+            }
+            if let Some(ref mut report) = self.report {
+                report.examined();
+            }
+            let exact = &self.exact;
+            let matches = self.conds
+                .iter()
+                .enumerate()
+                .all(|(i, c)| exact.holds(i) || c.matches(row));
+            if matches {
+                self.upper = self.upper.saturating_sub(1);
+                if let Some(ref stats) = store.stats {
+                    stats.returned();
+                }
+                if let Some(ref mut report) = self.report {
+                    report.returned();
+                }
+                return Some(row);
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.upper))
+    }
+}
+
+impl<'s, 'c, R> Iterator for Cached<'s, 'c, R> {
+    type Item = &'s R;
+
+    fn next(&mut self) -> Option<&'s R> {
+        let row = self.rows.next()?;
+        self.upper = self.upper.saturating_sub(1);
+        if let Some(ref mut report) = self.report {
+            report.examined();
+            report.returned();
+        }
+        Some(row)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.upper))
+    }
+}
+
+/// A `PreparedQuery` is a set of conditions that is executed many times with different constants.
+/// It is constructed using `Store::prepare`, and is executed by calling `execute` with the values
+/// to use for its placeholders.
+///
+/// Every `Value::Const` in the conditions is a placeholder, and placeholders are bound in the
+/// order the conditions were given (within a single comparison, in the order returned by
+/// `Comparison::values`). The constants in the conditions only serve as example values for
+/// choosing an index.
+///
+/// The choice of index is made once, when the query is prepared, and is reused for every
+/// execution. It is made again if the query is executed after indices have been added to or
+/// removed from the `Store`, or against a different `Store`.
+pub struct PreparedQuery<'c, T: Clone + 'c> {
+    conds: Vec<Condition<'c, T>>,
+    placeholders: usize,
+    plan: RefCell<(usize, Plan)>,
+}
+
+impl<'c, T: Ord + Clone + 'c> PreparedQuery<'c, T> {
+    /// Prepare the given conditions for execution against the given `Store`.
+    pub fn new<R: Row<T>>(store: &Store<T, R>, conds: &[Condition<'c, T>]) -> Self {
+        store.check(conds);
+        let placeholders = conds.iter()
+            .flat_map(|c| c.cmp.values())
+            .filter(|v| matches!(**v, Value::Const(..)))
+            .count();
+        PreparedQuery {
+            conds: conds.to_vec(),
+            placeholders,
+            plan: RefCell::new((store.generation, store.choose(conds))),
+        }
+    }
+
+    /// The number of values that must be given to `execute`.
+    pub fn placeholders(&self) -> usize {
+        self.placeholders
+    }
+
+    /// Returns an iterator that yields all rows in the given `Store` that match the prepared
+    /// conditions, with the placeholders bound to the given values.
+    ///
+    /// Panics if the number of values is not the same as the number of placeholders.
+    pub fn execute<'s, 'v, R>(&self,
+                              store: &'s Store<T, R>,
+                              values: &'v [T])
+                              -> Box<dyn Iterator<Item = &'s R> + 'v>
+        where R: Row<T>,
+              's: 'v,
+              'c: 'v
+    {
+        assert!(values.len() == self.placeholders,
+                "prepared query has {} placeholders, but {} values were given",
+                self.placeholders,
+                values.len());
+        let mut values = values.iter();
+        let conds: Vec<Condition<'v, T>> = self.conds.iter().map(|c| c.bind(&mut values)).collect();
+        store.check(&conds);
+        if contradicts(&conds) {
+            return Box::new(iter::empty());
+        }
+
+        let mut plan = self.plan.borrow_mut();
+        if plan.0 != store.generation {
+            *plan = (store.generation, store.choose(&conds));
+        }
+        let rowids = store.rowids(&conds, &plan.1.source);
+        let filters = (0..conds.len()).filter(|&i| !plan.1.exact.holds(i)).collect::<Vec<_>>();
+        Box::new(rowids.map(move |rowi| &store.rows[&rowi])
+            .filter(move |r| filters.iter().all(|&i| conds[i].matches(*r))))
+    }
+}
+
+/// A `Snapshot` marks the set of rows that were in a `Store` at some point in time. It is taken
+/// using `Store::snapshot`, and is used with `Store::find_at` to query the `Store` as if no rows
+/// had been inserted since.
+///
+/// Since row identifiers are handed out in increasing order, a snapshot is simply the identifier
+/// the next inserted row would get, and taking one does not copy any data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    end: usize,
+}
+
+impl Snapshot {
+    pub(crate) fn new(end: usize) -> Self {
+        Snapshot { end }
+    }
+
+    /// The identifier of the first row that is not part of this snapshot.
+    pub(crate) fn end(&self) -> usize {
+        self.end
+    }
+}
+
+/// A description of how `Store::find` finds the rows matching a set of conditions, as returned by
+/// `Store::explain`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryPlan {
+    /// How the candidate rows are found.
+    pub access: Access,
+    /// The number of candidate rows the chosen index is expected to yield, or `None` if every row
+    /// is a candidate.
+    pub estimate: Option<usize>,
+    /// The positions of the conditions that every candidate row is checked against. All other
+    /// conditions are guaranteed to hold for the candidates, and are not checked again.
+    pub filters: Vec<usize>,
+}
+
+/// How the candidate rows for a set of conditions are found. See `QueryPlan`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Access {
+    /// The conditions contradict each other, so no rows are examined.
+    Nothing,
+    /// Every row in the `Store` is a candidate.
+    Scan,
+    /// The candidates are found using the index on the given column.
+    Index(usize),
+    /// The candidates are the rows found by the indices on all the given columns.
+    Intersect(Vec<usize>),
+    /// The candidates are found using the composite index over the given columns (see
+    /// `Store::index_multi`).
+    Composite(Vec<usize>),
+    /// The candidates are found using the index on the given column that is keyed by the key
+    /// function with the given name (see `Store::index_keyed`).
+    Keyed(usize, &'static str),
+    /// The candidates are found using the sparse index on the given column (see
+    /// `Store::index_sparse`).
+    Sparse(usize),
+}
+
+/// A plan is written on a single line, for example for logging slow queries: first how the
+/// candidates are found (see `Access`), then the estimated number of candidates, if any, and
+/// finally the positions of the conditions that are checked against every candidate, if any.
+///
+/// ```
+/// use shortcut::{Condition, Store};
+/// use shortcut::idx::HashIndex;
+///
+/// let mut store = Store::new(2);
+/// store.index(0, HashIndex::new());
+/// for i in 0..10 {
+///     store.insert(vec![i % 5, i]);
+/// }
+///
+/// let conds = [Condition::eq(0, 3), Condition::gt(1, 5)];
+/// assert_eq!(store.explain(&conds).to_string(), "index [0] ~2 rows, filter #1");
+/// assert_eq!(store.explain(&conds[1..]).to_string(), "scan, filter #0");
+/// let conds = [Condition::eq(0, 3), Condition::eq(0, 4)];
+/// assert_eq!(store.explain(&conds).to_string(), "nothing ~0 rows");
+/// ```
+impl fmt::Display for QueryPlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.access)?;
+        if let Some(estimate) = self.estimate {
+            write!(f, " ~{} rows", estimate)?;
+        }
+        for (i, filter) in self.filters.iter().enumerate() {
+            write!(f, "{}#{}", if i == 0 { ", filter " } else { " " }, filter)?;
+        }
+        Ok(())
+    }
+}
+
+/// Written as the kind of access, followed by the columns it uses, as in `index [0]` or
+/// `intersect [0] [2]`.
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let columns = |f: &mut fmt::Formatter, columns: &[usize], sep: &str| {
+            for (i, column) in columns.iter().enumerate() {
+                write!(f, "{}[{}]", if i == 0 { "" } else { sep }, column)?;
+            }
+            Ok(())
+        };
+        match *self {
+            Access::Nothing => write!(f, "nothing"),
+            Access::Scan => write!(f, "scan"),
+            Access::Index(column) => write!(f, "index [{}]", column),
+            Access::Intersect(ref cs) => {
+                write!(f, "intersect ")?;
+                columns(f, cs, " ")
+            }
+            Access::Composite(ref cs) => {
+                write!(f, "composite (")?;
+                columns(f, cs, ", ")?;
+                write!(f, ")")
+            }
+            Access::Keyed(column, name) => write!(f, "index [{}] BY {}", column, name),
+            Access::Sparse(column) => write!(f, "sparse [{}]", column),
+        }
+    }
+}
+
+impl<'s, 'c, T, R> IntoIterator for Query<'s, 'c, T, R>
+    where T: Ord + Clone + 'c,
+          R: Row<T> + 's,
+          's: 'c
+{
+    type Item = &'s R;
+    type IntoIter = Box<dyn Iterator<Item = &'s R> + 'c>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
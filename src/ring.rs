@@ -0,0 +1,162 @@
+//! Bounded stores that keep only the most recently inserted rows.
+//!
+//! Once a `Store` with a row limit holds that many rows, every insertion evicts the oldest row, so
+//! the `Store` acts as a sliding window over the rows inserted into it. Row identifiers are never
+//! re-used, so the surviving rows keep the identifiers they were inserted with.
+
+use Row;
+use Store;
+use oplog;
+
+impl<T, R> Store<T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Allocate a new `Store` with the given number of columns that holds at most `max` rows (see
+    /// `set_max_rows`). Panics if `max` is zero.
+    pub fn with_max_rows(cols: usize, max: usize) -> Store<T, R> {
+        let mut store = Store::new(cols);
+        store.set_max_rows(Some(max));
+        store
+    }
+
+    /// Limit the `Store` to at most `max` rows, or lift the limit with `None`. Panics if `max` is
+    /// zero.
+    ///
+    /// Whenever an insertion takes the `Store` past the limit, the oldest rows (in the order they
+    /// were inserted) are deleted until it is back at the limit, and are removed from all indices
+    /// just as with `delete`. This happens after the new rows have been reported to the callbacks
+    /// registered with `on_insert`, so a batch inserted with `insert_many` that is larger than the
+    /// limit is reported in full, even though only its last `max` rows are kept. If the `Store`
+    /// already holds more than `max` rows, the oldest are deleted right away.
+    ///
+    /// Rows that have been marked as deleted do not count towards the limit, but are removed
+    /// along with the rows around them once they are the oldest rows in the `Store`. Evictions
+    /// are passed to the log attached with `attach_log` as deletions, so a replica that applies
+    /// the log should not have a limit of its own.
+    pub fn set_max_rows(&mut self, max: Option<usize>) {
+        assert!(max != Some(0), "a store must be allowed to hold at least one row");
+        self.max_rows = max;
+        self.evict();
+    }
+
+    /// Returns the row limit set with `set_max_rows`, if any.
+    pub fn max_rows(&self) -> Option<usize> {
+        self.max_rows
+    }
+
+    /// Delete the oldest rows until the `Store` holds no more rows than its limit allows.
+    pub(crate) fn evict(&mut self) {
+        let max = match self.max_rows {
+            Some(max) => max,
+            None => return,
+        };
+        while self.len() > max {
+            let (rowid, row) = self.rows.pop_first().unwrap();
+            if self.is_dead(rowid) {
+                self.tombstones[rowid] = false;
+                self.dead -= 1;
+            }
+            if self.is_queued(rowid) {
+                self.invalidate_cached(&row);
+            } else {
+                self.undex_row(rowid, &row);
+            }
+            self.log(oplog::Op::Delete { rowid });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use bulk::BulkLoader;
+    use cmp::Condition;
+    use idx::{BTreeIndex, HashIndex};
+
+    fn oldest(store: &Store<usize>) -> Option<usize> {
+        store.find(&[]).map(|row| row[1]).min()
+    }
+
+    #[test]
+    fn it_keeps_newest_rows() {
+        let max = 50;
+        let mut store = Store::with_max_rows(3, max);
+        store.index(0, HashIndex::new());
+        store.index(1, BTreeIndex::new());
+        assert_eq!(store.max_rows(), Some(max));
+
+        for i in 0..3 * max {
+            let rowid = store.insert(vec![i % 7, i, i % 3]);
+            assert_eq!(rowid, i);
+            assert_eq!(store.len(), (i + 1).min(max));
+
+            let oldest = (i + 1).saturating_sub(max);
+            let mut seen = store.find(&[]).map(|row| row[1]).collect::<Vec<_>>();
+            seen.sort_unstable();
+            assert_eq!(seen, (oldest..i + 1).collect::<Vec<_>>());
+            // the indices only yield surviving rows
+            for v in 0..7usize {
+                let expected = (oldest..i + 1).filter(|j| j % 7 == v).count();
+                assert_eq!(store.find(&[Condition::eq(0, v)]).count(), expected);
+            }
+            assert_eq!(store.find(&[Condition::lt(1, oldest)]).count(), 0);
+            assert_eq!(store.get(oldest).map(|row| row[1]), Some(oldest));
+            if oldest > 0 {
+                assert!(store.get(oldest - 1).is_none());
+            }
+        }
+        assert_eq!(store.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn it_evicts_batches() {
+        let mut store = Store::with_max_rows(2, 10);
+        store.index(0, HashIndex::new());
+        let inserted = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&inserted);
+        store.on_insert(Box::new(move |_, _: &Vec<usize>| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }));
+
+        // a batch larger than the limit keeps only its last rows
+        let ids = store.insert_many((0..25usize).map(|i| vec![i % 4, i]));
+        assert_eq!(ids, 0..25);
+        assert_eq!(inserted.load(Ordering::Relaxed), 25);
+        assert_eq!(store.len(), 10);
+        assert_eq!(oldest(&store), Some(15));
+        assert_eq!(store.find(&[Condition::eq(0, 0usize)]).count(), 3);
+
+        let mut loader = BulkLoader::new(&mut store);
+        for i in 25..30usize {
+            loader.insert(vec![i % 4, i]);
+        }
+        loader.finish();
+        assert_eq!(oldest(&store), Some(20));
+        assert_eq!(store.check_integrity(), Ok(()));
+
+        // marked rows do not count towards the limit
+        assert!(store.mark_deleted(29));
+        store.insert(vec![2, 30]);
+        assert_eq!(store.len(), 10);
+        assert_eq!(oldest(&store), Some(20));
+        store.insert(vec![3, 31]);
+        assert_eq!(oldest(&store), Some(21));
+
+        // lowering the limit evicts right away, and lifting it stops eviction
+        store.set_max_rows(Some(3));
+        assert_eq!(store.find(&[]).map(|row| row[1]).collect::<Vec<_>>(), vec![28, 30, 31]);
+        store.set_max_rows(None);
+        store.insert_many((32..41usize).map(|i| vec![i % 4, i]));
+        assert_eq!(store.len(), 12);
+        assert_eq!(store.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one row")]
+    fn it_rejects_zero_rows() {
+        Store::<usize>::with_max_rows(2, 0);
+    }
+}
@@ -0,0 +1,161 @@
+//! `rand` support for `Store`.
+//!
+//! Matching rows are sampled as they are found, so that only the sampled rows are kept in memory
+//! however many rows match.
+
+use rand::Rng;
+use rand::seq::index;
+
+use EqualityIndex;
+use Row;
+use Source;
+use Store;
+use cmp::{Comparison, Condition, Value};
+
+impl<T, R> Store<T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Pick `n` of the rows matching all the given `Condition`s at random, or all of them if
+    /// fewer than `n` rows match. Every matching row is equally likely to be picked, and no row
+    /// is picked twice. The rows are returned in no particular order.
+    ///
+    /// Rows are found as by `find`, and are sampled as they are found, so memory use is
+    /// proportional to `n` rather than to the number of matching rows. If the rows are found by
+    /// looking up a single value in an index that gives exactly the matching rows, the number of
+    /// matching rows is known up front, and only the picked rows are looked at.
+    ///
+    /// Panics if any of the conditions refer to a column that does not exist.
+    pub fn sample<'s>(&'s self,
+                      conds: &[Condition<T>],
+                      n: usize,
+                      rng: &mut impl Rng)
+                      -> Vec<&'s R> {
+        if n == 0 {
+            self.check(conds);
+            return Vec::new();
+        }
+        if let Some(picked) = self.sample_lookup(conds, n, rng) {
+            return picked;
+        }
+
+        let mut rows = self.find(conds);
+        let mut picked = rows.by_ref().take(n).collect::<Vec<_>>();
+        for (seen, row) in (n + 1..).zip(rows) {
+            // keep every row seen so far with probability n / seen
+            let i = rng.random_range(0..seen);
+            if i < n {
+                picked[i] = row;
+            }
+        }
+        picked
+    }
+
+    /// Like `sample`, but only if the rows can be found by looking up a single value in an index
+    /// that gives exactly the matching rows, and no rows have been marked as deleted, so that the
+    /// number of matching rows is known up front.
+    fn sample_lookup<'s>(&'s self,
+                         conds: &[Condition<T>],
+                         n: usize,
+                         rng: &mut impl Rng)
+                         -> Option<Vec<&'s R>> {
+        let plan = self.plan(conds);
+        let i = match plan.source {
+            Source::Index(i) if self.dead == 0 && plan.exact.all(conds.len()) => i,
+            _ => return None,
+        };
+        let key = match conds[i].cmp {
+            Comparison::Equal(Value::Const(ref key)) => key,
+            _ => return None,
+        };
+        self.count_query(conds, &plan);
+        let idx = self.column_index(conds[i].column).unwrap();
+        let len = idx.count(key);
+        let mut offsets = index::sample(rng, len, n.min(len)).into_vec();
+        offsets.sort_unstable();
+
+        let mut rowids = idx.lookup(key);
+        let mut next = 0;
+        Some(offsets.into_iter()
+            .map(|offset| {
+                let rowid = rowids.nth(offset - next).unwrap();
+                next = offset + 1;
+                &self.rows[&rowid]
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use idx::HashIndex;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    /// A store of `[i, i % 2]` for every `i` in `0..10_000`, with an index on column 1.
+    fn store() -> Store<usize> {
+        let mut store = Store::new(2);
+        store.index(1, HashIndex::new());
+        for i in 0..10_000 {
+            store.insert(vec![i, i % 2]);
+        }
+        store
+    }
+
+    /// Check that the given values are distinct, and spread evenly across `0..10_000`.
+    fn check_uniform(mut values: Vec<usize>) {
+        let n = values.len();
+        values.sort_unstable();
+        values.dedup();
+        assert_eq!(values.len(), n);
+        let mut deciles = [0; 10];
+        for v in values {
+            deciles[v / 1000] += 1;
+        }
+        // each decile holds n / 10 values on average, with a standard deviation of about
+        // sqrt(n / 10); allow for five of those
+        let expected = n as f64 / 10.0;
+        for &count in &deciles {
+            assert!((count as f64 - expected).abs() < 5.0 * expected.sqrt(),
+                    "{:?}",
+                    deciles);
+        }
+    }
+
+    #[test]
+    fn it_samples() {
+        let store = store();
+        let mut rng = StdRng::seed_from_u64(42);
+        let odd = [Condition::eq(1, 1usize)];
+        let small = [Condition::lt(0, 10usize)];
+        assert_eq!(store.sample(&odd, 1000, &mut rng).len(), 1000);
+        assert_eq!(store.sample(&small, 1000, &mut rng).len(), 10);
+        assert_eq!(store.sample(&small, 10, &mut rng).len(), 10);
+        assert_eq!(store.sample(&small, 3, &mut rng).len(), 3);
+        assert_eq!(store.sample(&[], 0, &mut rng).len(), 0);
+        assert!(store.sample(&[Condition::eq(1, 2usize)], 10, &mut rng).is_empty());
+
+        let sample = store.sample(&small, 5, &mut rng);
+        assert!(sample.iter().all(|row| small[0].matches(*row)));
+        let sample = store.sample(&odd, 20, &mut rng);
+        assert!(sample.iter().all(|row| odd[0].matches(*row)));
+    }
+
+    #[test]
+    fn it_samples_uniformly() {
+        let mut store = store();
+        let mut rng = StdRng::seed_from_u64(7);
+        let values = |rows: Vec<&Vec<usize>>| rows.into_iter().map(|r| r[0]).collect::<Vec<_>>();
+        // by scanning all rows
+        check_uniform(values(store.sample(&[], 2000, &mut rng)));
+        check_uniform(values(store.sample(&[Condition::ge(0, 0usize)], 2000, &mut rng)));
+        // by picking offsets in the index
+        check_uniform(values(store.sample(&[Condition::eq(1, 0usize)], 2000, &mut rng)));
+        // which can't be done once rows have been marked as deleted
+        store.mark_deleted(0);
+        let sample = values(store.sample(&[Condition::eq(1, 0usize)], 5000, &mut rng));
+        assert_eq!(sample.len(), 4999);
+        check_uniform(sample);
+    }
+}
@@ -0,0 +1,242 @@
+//! `serde` support for `Store`.
+//!
+//! A `Store` is serialized as its width, its next row id, its rows, the kinds of the built-in
+//! indices it maintains, and its key and expiry columns (if any). The indices themselves are not
+//! serialized; they are rebuilt from the rows on deserialization.
+
+use serde::de;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use std::collections::BTreeMap;
+use std::hash::Hash;
+
+use idx::EqualityIndex;
+use idx::IndexKind;
+use CompositeKey;
+use is_dead;
+use Row;
+use Store;
+
+#[derive(Serialize)]
+struct StoreRef<'a, R: 'a> {
+    cols: usize,
+    rowid: usize,
+    rows: BTreeMap<usize, &'a R>,
+    indices: Vec<(usize, IndexKind)>,
+    composite: Vec<(&'a [usize], IndexKind)>,
+    key: Option<usize>,
+    expiry: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct StoreData<R> {
+    cols: usize,
+    rowid: usize,
+    rows: BTreeMap<usize, R>,
+    indices: Vec<(usize, IndexKind)>,
+    composite: Vec<(Vec<usize>, IndexKind)>,
+    #[serde(default)]
+    key: Option<usize>,
+    #[serde(default)]
+    expiry: Option<usize>,
+}
+
+impl<T, R: Serialize> Serialize for Store<T, R> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let indices = self.indices
+                          .iter()
+                          .filter_map(|(&col, idx)| idx.kind().map(|k| (col, k)))
+                          .collect();
+        // keyed and sparse indices cannot be serialized, since their functions cannot
+        let composite = self.composite
+                            .iter()
+                            .filter_map(|(key, idx)| match *key {
+                                CompositeKey::Columns(ref columns) => {
+                                    idx.kind().map(|k| (&columns[..], k))
+                                }
+                                CompositeKey::Computed(..) | CompositeKey::Sparse(..) => None,
+                            })
+                            .collect();
+
+        StoreRef {
+            cols: self.cols,
+            rowid: self.rowid,
+            // rows that have been marked as deleted are not preserved
+            rows: self.rows
+                      .iter()
+                      .filter(|&(&rowid, _)| !is_dead(&self.tombstones, rowid))
+                      .map(|(&rowid, row)| (rowid, row))
+                      .collect(),
+            indices,
+            composite,
+            key: self.key,
+            expiry: self.expiry,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de, T, R> Deserialize<'de> for Store<T, R>
+    where T: Ord + Hash + Clone + Send + Sync + 'static,
+          R: Row<T> + Deserialize<'de>
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let data = StoreData::<R>::deserialize(deserializer)?;
+
+        if let Some((&rowid, row)) = data.rows.iter().find(|&(_, row)| row.columns() != data.cols) {
+            return Err(de::Error::custom(format_args!("row {} has {} columns, but the store has {}",
+                                                      rowid,
+                                                      row.columns(),
+                                                      data.cols)));
+        }
+        if let Some(&last) = data.rows.keys().next_back() {
+            if last >= data.rowid {
+                return Err(de::Error::custom(format_args!("row id {} is not below the next row \
+                                                           id {}",
+                                                          last,
+                                                          data.rowid)));
+            }
+        }
+        let columns = data.indices
+                          .iter()
+                          .map(|&(col, _)| col)
+                          .chain(data.composite.iter().flat_map(|(cs, _)| cs.iter().cloned()))
+                          .chain(data.key)
+                          .chain(data.expiry);
+        for col in columns {
+            if col >= data.cols {
+                return Err(de::Error::custom(format_args!("index on column {}, but the store \
+                                                           only has {} columns",
+                                                          col,
+                                                          data.cols)));
+            }
+        }
+
+        let mut store = Store::new(data.cols);
+        store.rowid = data.rowid;
+        store.rows = data.rows;
+        let unique = data.indices
+                         .iter()
+                         .map(|&(col, kind)| (vec![col], kind))
+                         .chain(data.composite.iter().cloned())
+                         .filter(|&(_, kind)| kind == IndexKind::UniqueHash);
+        for (columns, _) in unique {
+            let mut seen = BTreeMap::new();
+            let duplicate = store.rows.iter().find_map(|(&rowid, row)| {
+                let key = columns.iter().map(|&col| row.index(col)).collect::<Vec<_>>();
+                seen.insert(key, rowid).map(|existing| (rowid, existing))
+            });
+            if let Some((rowid, existing)) = duplicate {
+                return Err(de::Error::custom(format_args!("row {} has the same value as row {} \
+                                                           in unique columns {:?}",
+                                                          rowid,
+                                                          existing,
+                                                          columns)));
+            }
+        }
+        for (col, kind) in data.indices {
+            store.index(col, kind.build());
+        }
+        for (columns, kind) in data.composite {
+            store.index_multi(&columns, kind.build());
+        }
+        store.key = data.key;
+        store.expiry = data.expiry;
+        Ok(store)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate serde_json;
+
+    use super::*;
+    use cmp;
+    use idx::{BTreeIndex, HashIndex};
+
+    #[test]
+    fn roundtrip() {
+        let mut store = Store::new(3);
+        store.index(0, HashIndex::new());
+        store.index(1, BTreeIndex::new());
+        store.index_multi(&[0, 2], HashIndex::new());
+        store.insert(vec![1usize, 2, 3]);
+        store.insert(vec![1, 5, 4]);
+        store.insert(vec![2, 5, 3]);
+        store.delete(&[cmp::Condition {
+                           column: 2,
+                           cmp: cmp::Comparison::Equal(cmp::Value::new(4usize)),
+                       }]);
+
+        let json = serde_json::to_string(&store).unwrap();
+        let mut back: Store<usize> = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&back).unwrap(), json);
+
+        let cmp = [cmp::Condition {
+                       column: 1,
+                       cmp: cmp::Comparison::GreaterOrEqual(cmp::Value::new(2usize)),
+                   }];
+        let expected: Vec<_> = store.find(&cmp).collect();
+        assert_eq!(back.find(&cmp).collect::<Vec<_>>(), expected);
+        assert_eq!(back.indices[&0].kind(), Some(IndexKind::Hash));
+        assert_eq!(back.indices[&1].kind(), Some(IndexKind::BTree));
+        assert_eq!(back.composite.len(), 1);
+
+        // row ids are not reused after a round-trip
+        back.insert(vec![3, 3, 3]);
+        assert_eq!(back.get(3), Some(&vec![3, 3, 3]));
+    }
+
+    #[test]
+    fn roundtrip_key() {
+        let mut store = Store::with_key(2, 0);
+        store.insert(vec![1usize, 2]);
+        store.insert(vec![2, 3]);
+
+        let json = serde_json::to_string(&store).unwrap();
+        let mut back: Store<usize> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.key_column(), Some(0));
+        assert_eq!(back.upsert(vec![2, 4]), 1);
+        assert_eq!(back.get_by_key(&2), Some(&vec![2, 4]));
+    }
+
+    #[test]
+    fn roundtrip_expiry() {
+        let mut store = Store::new(2);
+        store.insert(vec![1usize, 2]);
+        store.insert(vec![2, 3]);
+        store.set_expiry_column(1);
+
+        let json = serde_json::to_string(&store).unwrap();
+        let mut back: Store<usize> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.expiry_column(), Some(1));
+        assert_eq!(back.expire(&2), 1);
+    }
+
+    #[test]
+    fn rejects_bad_width() {
+        let json = r#"{"cols":2,"rowid":1,"rows":{"0":[1]},"indices":[],"composite":[]}"#;
+        assert!(serde_json::from_str::<Store<usize>>(json).is_err());
+    }
+
+    #[test]
+    fn rejects_unique_duplicates() {
+        let json = r#"{"cols":2,"rowid":2,"rows":{"0":[1,1],"1":[1,2]},
+                       "indices":[[0,"UniqueHash"]],"composite":[]}"#;
+        let err = serde_json::from_str::<Store<usize>>(json).err().unwrap();
+        assert!(err.to_string().starts_with("row 1 has the same value as row 0 in unique columns \
+                                             [0]"));
+
+        let json = r#"{"cols":2,"rowid":2,"rows":{"0":[1,1],"1":[1,2]},
+                       "indices":[],"composite":[[[0,1],"UniqueHash"]]}"#;
+        assert!(serde_json::from_str::<Store<usize>>(json).is_ok());
+        let json = json.replace("[1,2]", "[1,1]");
+        assert!(serde_json::from_str::<Store<usize>>(&json).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_rowid() {
+        let json = r#"{"cols":1,"rowid":0,"rows":{"0":[1]},"indices":[],"composite":[]}"#;
+        assert!(serde_json::from_str::<Store<usize>>(json).is_err());
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use Index;
+use InsertError;
+use Row;
+use Store;
+use cmp::{Comparison, Condition, Value};
+
+/// A `ShardedStore` spreads its rows over several `Store`s, called shards, so that many threads
+/// can insert rows at the same time.
+///
+/// Every row is sent to a shard by hashing its value in a designated shard column, and each shard
+/// sits behind its own `RwLock`, so an insertion only locks the one shard the row belongs to.
+/// Writer threads thus only wait for each other when their rows land in the same shard; a writer
+/// that only inserts rows belonging to a single shard never waits for any other writer.
+///
+/// Queries go through `read`, which locks every shard for reading. A query that compares the
+/// shard column for equality against a constant (or against a list of constants with `In`) only
+/// searches the shards that can hold matching rows; all other queries search every shard, one
+/// after the other. Each shard has its own indices (see `index`), and identifies its rows with its
+/// own row identifiers, so a row is identified by its shard along with its identifier within it.
+///
+/// All methods panic if a thread panicked while holding the write lock of a shard (see `write`),
+/// since the shard may then have been left partially modified. A row rejected by `insert` does
+/// not cause this: it is checked while the lock is held, but `insert` only panics once the lock
+/// has been released.
+pub struct ShardedStore<T, R = Vec<T>> {
+    cols: usize,
+    column: usize,
+    shards: Vec<RwLock<Store<T, R>>>,
+}
+
+/// The shards of a `ShardedStore`, all locked for reading. See `ShardedStore::read`.
+pub struct ShardedRead<'a, T: 'a, R: 'a = Vec<T>> {
+    sharded: &'a ShardedStore<T, R>,
+    shards: Vec<RwLockReadGuard<'a, Store<T, R>>>,
+}
+
+impl<T, R> ShardedStore<T, R>
+    where T: Ord + Hash + Clone,
+          R: Row<T>
+{
+    /// Allocate a new `ShardedStore` with the given number of columns, whose rows are spread
+    /// over `shards` shards by their value in the given column.
+    ///
+    /// Panics if there are no shards, or if the shard column does not exist.
+    pub fn new(cols: usize, column: usize, shards: usize) -> Self {
+        assert!(shards > 0, "a sharded store needs at least one shard");
+        assert!(column < cols,
+                "shard column {} is out of range for a store with {} columns",
+                column,
+                cols);
+        ShardedStore {
+            cols,
+            column,
+            shards: (0..shards).map(|_| RwLock::new(Store::new(cols))).collect(),
+        }
+    }
+
+    /// Returns the number of columns of every row.
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    /// Returns the column whose value decides which shard a row belongs to.
+    pub fn shard_column(&self) -> usize {
+        self.column
+    }
+
+    /// Returns the number of shards.
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Returns the shard that holds the rows with the given value in the shard column. The same
+    /// value always maps to the same shard, also across runs of the program.
+    pub fn shard_of(&self, value: &T) -> usize {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    /// Add an index, made by `factory`, on the given column of every shard. See `Store::index`.
+    pub fn index<F, I>(&mut self, column: usize, factory: F)
+        where F: Fn() -> I,
+              I: Into<Index<T>>
+    {
+        for shard in &mut self.shards {
+            let shard = shard.get_mut().expect("a writer panicked while holding the lock");
+            shard.index(column, factory());
+        }
+    }
+
+    /// Insert a new row into the shard it belongs to, holding the write lock of only that shard
+    /// for the duration of the insertion. Returns the shard, along with the identifier of the row
+    /// within it. See `Store::insert`.
+    ///
+    /// Panics in the same cases as `Store::insert`, leaving every shard usable. Use `try_insert`
+    /// to handle these cases gracefully.
+    pub fn insert(&self, row: R) -> (usize, usize) {
+        match self.try_insert(row) {
+            Ok(inserted) => inserted,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Like `insert`, but returns an error instead of panicking if the row cannot be inserted.
+    /// See `Store::try_insert`.
+    pub fn try_insert(&self, row: R) -> Result<(usize, usize), InsertError<R>> {
+        if row.columns() != self.cols {
+            return Err(InsertError::WrongWidth {
+                expected: self.cols,
+                got: row.columns(),
+                row,
+            });
+        }
+        let shard = self.shard_of(row.index(self.column));
+        let rowid = self.write(shard).try_insert(row)?;
+        Ok((shard, rowid))
+    }
+
+    /// Lock every shard for reading, blocking until no writer holds any of their locks, so that
+    /// they can be queried. Shards are locked in order, so a reader never deadlocks with another.
+    pub fn read(&self) -> ShardedRead<'_, T, R> {
+        ShardedRead {
+            sharded: self,
+            shards: self.shards
+                .iter()
+                .map(|shard| shard.read().expect("a writer panicked while holding the lock"))
+                .collect(),
+        }
+    }
+
+    /// Lock the given shard for writing, blocking until no other reader or writer holds its lock.
+    /// Panics if there is no such shard.
+    pub fn write(&self, shard: usize) -> RwLockWriteGuard<'_, Store<T, R>> {
+        self.shards[shard].write().expect("a writer panicked while holding the lock")
+    }
+
+    /// Returns the shards that `ShardedRead::find` searches for rows matching the given
+    /// conditions, in ascending order.
+    ///
+    /// If any condition compares the shard column for equality against a constant, or against a
+    /// list of constants with `In`, only the shards those constants belong to can hold matching
+    /// rows. Otherwise, every shard is searched.
+    pub fn shards_for(&self, conds: &[Condition<T>]) -> Vec<usize> {
+        let mut shards = vec![true; self.shards.len()];
+        for c in conds.iter().filter(|c| c.column == self.column) {
+            let values = match c.cmp {
+                Comparison::Equal(ref v) => vec![v],
+                Comparison::In(ref vs) => vs.iter().collect(),
+                _ => continue,
+            };
+            let mut owners = vec![false; self.shards.len()];
+            for v in values {
+                match *v {
+                    Value::Const(ref v) => owners[self.shard_of(v)] = true,
+                    Value::Column(..) => owners = vec![true; self.shards.len()],
+                }
+            }
+            for (shard, owner) in shards.iter_mut().zip(owners) {
+                *shard &= owner;
+            }
+        }
+        (0..self.shards.len()).filter(|&shard| shards[shard]).collect()
+    }
+
+    /// Returns the number of rows in all shards.
+    pub fn len(&self) -> usize {
+        self.read().len()
+    }
+
+    /// Returns true if no shard has any rows.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Unwrap the shards.
+    pub fn into_shards(self) -> Vec<Store<T, R>> {
+        self.shards
+            .into_iter()
+            .map(|shard| shard.into_inner().expect("a writer panicked while holding the lock"))
+            .collect()
+    }
+}
+
+impl<'a, T, R> ShardedRead<'a, T, R>
+    where T: Ord + Hash + Clone,
+          R: Row<T>
+{
+    /// Returns the locked shard with the given index. Panics if there is no such shard.
+    pub fn shard(&self, shard: usize) -> &Store<T, R> {
+        &self.shards[shard]
+    }
+
+    /// Returns an iterator that yields all rows, in any shard, that match all the given
+    /// conditions. The shards given by `ShardedStore::shards_for` are searched one after the
+    /// other, each just as `Store::find` would.
+    ///
+    /// Panics if any of the conditions refer to a column that does not exist.
+    pub fn find<'c, 's: 'c>(&'s self,
+                            conds: &'c [Condition<'c, T>])
+                            -> Box<dyn Iterator<Item = &'s R> + 'c> {
+        if let Err(e) = self.shards[0].validate(conds) {
+            panic!("{}", e);
+        }
+        let shards = self.sharded.shards_for(conds);
+        Box::new(shards.into_iter().flat_map(move |shard| self.shards[shard].find(conds)))
+    }
+
+    /// Returns the number of rows, in any shard, that match all the given conditions.
+    pub fn count(&self, conds: &[Condition<T>]) -> usize {
+        self.find(conds).count()
+    }
+
+    /// Returns the number of rows in all shards.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.len()).sum()
+    }
+
+    /// Returns true if no shard has any rows.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use idx::HashIndex;
+    use query::Access;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn store() -> ShardedStore<usize> {
+        let mut sharded = ShardedStore::new(3, 0, 4);
+        sharded.index(0, HashIndex::new);
+        sharded.index(1, HashIndex::new);
+        sharded
+    }
+
+    #[test]
+    fn it_ingests_concurrently() {
+        let sharded = Arc::new(store());
+        let writers = (0..4usize)
+            .map(|w| {
+                let sharded = sharded.clone();
+                thread::spawn(move || for i in 0..1000usize {
+                    sharded.insert(vec![i % 50, i % 7, w]);
+                })
+            })
+            .collect::<Vec<_>>();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        assert_eq!(sharded.len(), 4000);
+        let read = sharded.read();
+        assert!((0..4).all(|shard| !read.shard(shard).is_empty()));
+        assert_eq!(read.count(&[Condition::eq(0, 3usize)]), 80);
+        assert_eq!(read.count(&[Condition::eq(1, 3usize)]), 4 * 143);
+        assert_eq!(read.count(&[Condition::eq(2, 1usize), Condition::eq(1, 0usize)]), 143);
+        for shard in 0..4 {
+            // every row is in the shard its key belongs to, and is indexed there
+            assert!(read.shard(shard).find(&[]).all(|row| sharded.shard_of(&row[0]) == shard));
+            assert_eq!(read.shard(shard).check_integrity(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn it_probes_owning_shard() {
+        let sharded = store();
+        for i in 0..400usize {
+            let (shard, _) = sharded.insert(vec![i % 40, i, 0]);
+            assert_eq!(shard, sharded.shard_of(&(i % 40)));
+        }
+
+        let key = 17usize;
+        let owner = sharded.shard_of(&key);
+        let conds = [Condition::eq(0, key), Condition::gt(1, 100usize)];
+        assert_eq!(sharded.shards_for(&conds), vec![owner]);
+        let read = sharded.read();
+        assert_eq!(read.count(&conds), 7);
+        assert_eq!(read.shard(owner).explain(&conds).access, Access::Index(0));
+        // no other shard holds a row with the key
+        for shard in (0..4).filter(|&shard| shard != owner) {
+            assert_eq!(read.shard(shard).count(&conds[..1]), 0);
+        }
+
+        // a list of keys probes the shards they belong to
+        let conds = [Condition::new(0, Comparison::In(vec![Value::new(1usize),
+                                                          Value::new(2usize)]))];
+        let mut owners = vec![sharded.shard_of(&1), sharded.shard_of(&2)];
+        owners.sort_unstable();
+        owners.dedup();
+        assert_eq!(sharded.shards_for(&conds), owners);
+        assert_eq!(read.count(&conds), 20);
+
+        // other conditions search every shard
+        let conds = [Condition::eq(1, 17usize)];
+        assert_eq!(sharded.shards_for(&conds), vec![0, 1, 2, 3]);
+        assert_eq!(read.find(&conds).map(|row| row[0]).collect::<Vec<_>>(), vec![17]);
+        assert_eq!(sharded.shards_for(&[Condition::lt(0, 3usize)]), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn it_rejects_duplicates_without_poisoning() {
+        use std::panic::{self, AssertUnwindSafe};
+        let mut sharded = ShardedStore::new(2, 0, 4);
+        sharded.index(1, HashIndex::unique);
+        assert_eq!(sharded.insert(vec![1usize, 10]), (sharded.shard_of(&1), 0));
+        match sharded.try_insert(vec![1usize, 10]) {
+            Err(InsertError::Duplicate { column: 1, existing: 0, .. }) => {}
+            r => panic!("expected a duplicate error, got {:?}", r),
+        }
+        match sharded.try_insert(vec![1usize]) {
+            Err(InsertError::WrongWidth { expected: 2, got: 1, .. }) => {}
+            r => panic!("expected a width error, got {:?}", r),
+        }
+
+        let r = panic::catch_unwind(AssertUnwindSafe(|| sharded.insert(vec![1usize, 10])));
+        assert!(r.is_err());
+        assert_eq!(sharded.len(), 1);
+        assert_eq!(sharded.insert(vec![1usize, 11]), (sharded.shard_of(&1), 1));
+        assert_eq!(sharded.read().count(&[Condition::eq(0, 1usize)]), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one shard")]
+    fn it_rejects_no_shards() {
+        ShardedStore::<usize>::new(2, 0, 0);
+    }
+}
@@ -0,0 +1,133 @@
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use Row;
+use Store;
+
+/// A `SyncStore` wraps a `Store` in a `RwLock`, so that it can be shared between threads (for
+/// example in an `Arc`), queried by many readers at once, and modified by one writer at a time.
+///
+/// The guards returned by `read` and `write` dereference to the `Store`, so a query is written as
+/// `shared.read().find(&conds)`. The read lock is held for as long as the guard lives; when the
+/// guard is a temporary, that is until the end of the statement, so results that borrow from the
+/// `Store` must be consumed (or collected) in the same statement. Bind the guard to a variable to
+/// hold the lock for longer.
+///
+/// All methods panic if a thread panicked while holding the write lock, since the `Store` may
+/// then have been left partially modified.
+pub struct SyncStore<T, R = Vec<T>> {
+    store: RwLock<Store<T, R>>,
+}
+
+impl<T, R> SyncStore<T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Wrap the given `Store` so that it can be shared between threads.
+    pub fn new(store: Store<T, R>) -> Self {
+        SyncStore { store: RwLock::new(store) }
+    }
+
+    /// Lock the `Store` for reading, blocking until no writer holds the lock. Any number of
+    /// readers may hold the lock at the same time.
+    pub fn read(&self) -> RwLockReadGuard<'_, Store<T, R>> {
+        self.store.read().expect("a writer panicked while holding the lock")
+    }
+
+    /// Lock the `Store` for writing, blocking until no other reader or writer holds the lock.
+    pub fn write(&self) -> RwLockWriteGuard<'_, Store<T, R>> {
+        self.store.write().expect("a writer panicked while holding the lock")
+    }
+
+    /// Insert a new row into the `Store`, holding the write lock only for the duration of the
+    /// insertion. See `Store::insert`.
+    pub fn insert(&self, row: R) -> usize {
+        self.write().insert(row)
+    }
+
+    /// Returns the number of rows in the `Store`. See `Store::len`.
+    pub fn len(&self) -> usize {
+        self.read().len()
+    }
+
+    /// Returns true if the `Store` has no rows. See `Store::is_empty`.
+    pub fn is_empty(&self) -> bool {
+        self.read().is_empty()
+    }
+
+    /// Unwrap the `Store`.
+    pub fn into_inner(self) -> Store<T, R> {
+        self.store.into_inner().expect("a writer panicked while holding the lock")
+    }
+}
+
+impl<T, R> From<Store<T, R>> for SyncStore<T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    fn from(store: Store<T, R>) -> Self {
+        SyncStore::new(store)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cmp;
+    use idx::HashIndex;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn assert_send_sync<S: Send + Sync>() {}
+
+    #[test]
+    fn it_is_send_and_sync() {
+        assert_send_sync::<Store<String>>();
+        assert_send_sync::<Store<usize, Arc<Vec<usize>>>>();
+        assert_send_sync::<SyncStore<String>>();
+    }
+
+    #[test]
+    fn it_reads_while_writing() {
+        let mut store = Store::new(2);
+        store.index(0, HashIndex::new());
+        let shared = Arc::new(SyncStore::new(store));
+
+        let writer = {
+            let shared = shared.clone();
+            thread::spawn(move || for i in 0..1000usize {
+                shared.insert(vec![i % 10, i]);
+            })
+        };
+        let readers = (0..4)
+            .map(|_| {
+                let shared = shared.clone();
+                thread::spawn(move || {
+                    let conds = [cmp::Condition {
+                                     column: 0,
+                                     cmp: cmp::Comparison::Equal(cmp::Value::new(3usize)),
+                                 }];
+                    let mut last = 0;
+                    while last < 100 {
+                        let guard = shared.read();
+                        let found = guard.find(&conds).collect::<Vec<_>>();
+                        // rows are only ever added, and every row is complete
+                        assert!(found.len() >= last);
+                        assert!(found.iter().all(|row| row[0] == 3 && row[1] % 10 == 3));
+                        last = found.len();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        writer.join().unwrap();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert_eq!(shared.len(), 1000);
+        let conds = [cmp::Condition {
+                         column: 0,
+                         cmp: cmp::Comparison::Equal(cmp::Value::new(7usize)),
+                     }];
+        assert_eq!(shared.read().find(&conds).count(), 100);
+    }
+}
@@ -0,0 +1,388 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use CompositeKey;
+use Plan;
+use Row;
+use Source;
+use Store;
+use cmp::Condition;
+use query::Access;
+
+/// Counters of how the queries made against a `Store` were answered, as returned by
+/// `Store::query_stats`. Queries made through `find` (and the methods built on it, such as
+/// `find_one` or `aggregate`), `find_with_ids`, `find_at`, `par_find`, and `query` are counted,
+/// except for queries ordered with `Query::order_by` whose rows are produced by walking a range
+/// index.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryStats {
+    /// The number of queries made.
+    pub queries: usize,
+    /// The number of rows looked at while answering those queries, including rows that turned
+    /// out not to match.
+    pub rows_examined: usize,
+    /// The number of matching rows yielded by those queries.
+    pub rows_returned: usize,
+    /// How the queries with a condition on each column were answered, by column. Columns that no
+    /// query has used are not included.
+    pub columns: BTreeMap<usize, ColumnStats>,
+}
+
+/// How the queries with a condition on a single column were answered. See `QueryStats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ColumnStats {
+    /// The number of queries that were answered using an index on this column, including
+    /// composite indices that cover it.
+    pub indexed: usize,
+    /// The number of queries with a condition on this column that had to scan every row.
+    pub scanned: usize,
+}
+
+/// Counters of how often `find` was answered from the cache enabled with
+/// `Store::enable_query_cache`, as returned by `Store::query_cache_stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// The number of queries answered from the cache.
+    pub hits: usize,
+    /// The number of queries that were not cached, and so had to look for the matching rows.
+    pub misses: usize,
+    /// The number of sets of conditions currently cached.
+    pub entries: usize,
+}
+
+/// A summary of a single query made with `Store::find`, as given to the observer set with
+/// `Store::set_query_observer`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuerySummary {
+    /// How the candidate rows were found (see `Store::explain`), or `None` if the query was
+    /// answered from the cache enabled with `Store::enable_query_cache`.
+    pub access: Option<Access>,
+    /// The number of rows looked at, including rows that turned out not to match.
+    pub examined: usize,
+    /// The number of matching rows yielded.
+    pub returned: usize,
+    /// The time from the call to `find` until its iterator was dropped. Since rows are only found
+    /// as they are asked for, this includes whatever the caller did between asking for rows.
+    pub elapsed: Duration,
+}
+
+/// A callback set with `Store::set_query_observer`.
+pub(crate) type QueryObserver = Box<dyn Fn(&QuerySummary) + Send + Sync>;
+
+/// The summary of a query that is still running, which is given to the query observer when the
+/// iterator that holds it is dropped.
+pub(crate) struct Report<'s> {
+    observer: &'s (dyn Fn(&QuerySummary) + Send + Sync),
+    summary: QuerySummary,
+    start: Instant,
+}
+
+impl<'s> Report<'s> {
+    /// Count a row looked at while answering the query.
+    pub(crate) fn examined(&mut self) {
+        self.summary.examined += 1;
+    }
+
+    /// Count a matching row yielded by the query.
+    pub(crate) fn returned(&mut self) {
+        self.summary.returned += 1;
+    }
+}
+
+impl<'s> Drop for Report<'s> {
+    fn drop(&mut self) {
+        self.summary.elapsed = self.start.elapsed();
+        (self.observer)(&self.summary);
+    }
+}
+
+/// The counters enabled with `Store::enable_query_stats`. They are atomic only so that they can
+/// be updated while the `Store` is borrowed immutably, and never make a query wait.
+pub(crate) struct Collector {
+    queries: AtomicUsize,
+    examined: AtomicUsize,
+    returned: AtomicUsize,
+    indexed: Vec<AtomicUsize>,
+    scanned: Vec<AtomicUsize>,
+}
+
+impl Collector {
+    pub(crate) fn new(cols: usize) -> Self {
+        Collector {
+            queries: AtomicUsize::new(0),
+            examined: AtomicUsize::new(0),
+            returned: AtomicUsize::new(0),
+            indexed: (0..cols).map(|_| AtomicUsize::new(0)).collect(),
+            scanned: (0..cols).map(|_| AtomicUsize::new(0)).collect(),
+        }
+    }
+
+    /// Count a row looked at while answering a query.
+    pub(crate) fn examined(&self) {
+        self.examined.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a matching row yielded by a query.
+    pub(crate) fn returned(&self) {
+        self.returned.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl<T, R> Store<T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Start counting how queries are answered; see `QueryStats`. Counting is off by default.
+    /// Calling this when counting is already on has no effect.
+    ///
+    /// While counting is on, every row a query looks at and every row it yields costs an atomic
+    /// increment, which is cheap, but not free.
+    pub fn enable_query_stats(&mut self) {
+        if self.stats.is_none() {
+            self.stats = Some(Collector::new(self.cols));
+        }
+    }
+
+    /// Stop counting how queries are answered, and discard the counts so far.
+    pub fn disable_query_stats(&mut self) {
+        self.stats = None;
+    }
+
+    /// Returns the counts of how the queries made since counting was enabled with
+    /// `enable_query_stats` (or since the counts were last reset) were answered. All counts are
+    /// zero if counting is off.
+    ///
+    /// The counts for a query are only complete once its iterator has been used up (or
+    /// dropped), since rows are only examined as they are asked for.
+    pub fn query_stats(&self) -> QueryStats {
+        let stats = match self.stats {
+            Some(ref stats) => stats,
+            None => return QueryStats::default(),
+        };
+        let columns = stats.indexed
+            .iter()
+            .zip(&stats.scanned)
+            .map(|(indexed, scanned)| {
+                ColumnStats {
+                    indexed: indexed.load(Ordering::Relaxed),
+                    scanned: scanned.load(Ordering::Relaxed),
+                }
+            })
+            .enumerate()
+            .filter(|&(_, column)| column != ColumnStats::default())
+            .collect();
+        QueryStats {
+            queries: stats.queries.load(Ordering::Relaxed),
+            rows_examined: stats.examined.load(Ordering::Relaxed),
+            rows_returned: stats.returned.load(Ordering::Relaxed),
+            columns,
+        }
+    }
+
+    /// Set all the counts returned by `query_stats` back to zero, without turning counting off.
+    pub fn reset_query_stats(&self) {
+        if let Some(ref stats) = self.stats {
+            let totals = [&stats.queries, &stats.examined, &stats.returned];
+            for counter in totals.iter().copied().chain(&stats.indexed).chain(&stats.scanned) {
+                counter.store(0, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Call `observer` with a summary of every query made with `find` from now on, or stop
+    /// calling the previous observer with `None`. The summary says how the matching rows were
+    /// found, how many rows were looked at, how many were yielded, and how long the query took.
+    ///
+    /// Since `find` yields rows lazily, the observer is called when the iterator it returned is
+    /// dropped, whether or not every row was asked for; the counts only cover the rows that were.
+    /// While no observer is set, queries pay only for checking that none is.
+    pub fn set_query_observer(&mut self, observer: Option<QueryObserver>) {
+        self.query_observer = observer;
+    }
+
+    /// Start reporting on a query that is about to be answered using the given plan, or from the
+    /// query cache if there is none, if a query observer is set.
+    pub(crate) fn report(&self, plan: Option<(&[Condition<T>], &Plan)>) -> Option<Report<'_>> {
+        let observer = self.query_observer.as_ref()?;
+        Some(Report {
+            observer: &**observer,
+            summary: QuerySummary {
+                access: plan.map(|(conds, plan)| self.access(conds, &plan.source)),
+                examined: 0,
+                returned: 0,
+                elapsed: Duration::default(),
+            },
+            start: Instant::now(),
+        })
+    }
+
+    /// Count a query that is about to be answered using the given plan.
+    pub(crate) fn count_query(&self, conds: &[Condition<T>], plan: &Plan) {
+        let stats = match self.stats {
+            Some(ref stats) => stats,
+            None => return,
+        };
+        stats.queries.fetch_add(1, Ordering::Relaxed);
+        let (mut columns, counters): (Vec<_>, _) = match plan.source {
+            Source::Empty => return,
+            Source::Scan => (conds.iter().map(|c| c.column).collect(), &stats.scanned),
+            Source::Index(i) => (vec![conds[i].column], &stats.indexed),
+            Source::Intersect(ref used) => {
+                (used.iter().map(|&i| conds[i].column).collect(), &stats.indexed)
+            }
+            Source::Composite(j, _) => {
+                let columns = match self.composite[j].0 {
+                    CompositeKey::Columns(ref columns) => columns.clone(),
+                    CompositeKey::Computed(column, _) |
+                    CompositeKey::Sparse(column, _) => vec![column],
+                };
+                (columns, &stats.indexed)
+            }
+        };
+        // a query with several conditions on the same column only counts once for that column
+        columns.sort_unstable();
+        columns.dedup();
+        for column in columns {
+            counters[column].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Count every row yielded by the given iterator as examined, if counting is on.
+    pub(crate) fn count_examined<'s>(&'s self,
+                                     rowids: Box<dyn Iterator<Item = usize> + 's>)
+                                     -> Box<dyn Iterator<Item = usize> + 's> {
+        match self.stats {
+            Some(ref stats) => Box::new(rowids.inspect(move |_| stats.examined())),
+            None => rowids,
+        }
+    }
+
+    /// Count every row yielded by the given iterator as returned, if counting is on.
+    pub(crate) fn count_returned<'a, I>(&'a self,
+                                        rows: I)
+                                        -> Box<dyn Iterator<Item = I::Item> + 'a>
+        where I: Iterator + 'a
+    {
+        match self.stats {
+            Some(ref stats) => Box::new(rows.inspect(move |_| stats.returned())),
+            None => Box::new(rows),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use idx::HashIndex;
+    use std::sync::{Arc, Mutex};
+
+    /// A store of `[i % 10, i, i % 3]` for every `i` in `0..100`, with an index on column 0.
+    fn store() -> Store<usize> {
+        let mut store = Store::new(3);
+        for i in 0..100 {
+            store.insert(vec![i % 10, i, i % 3]);
+        }
+        store.index(0, HashIndex::new());
+        store
+    }
+
+    #[test]
+    fn it_counts_queries() {
+        let mut store = store();
+        store.find(&[Condition::eq(0, 3usize)]).count();
+        assert_eq!(store.query_stats(), QueryStats::default());
+
+        store.enable_query_stats();
+        assert_eq!(store.find(&[Condition::eq(0, 3usize)]).count(), 10);
+        assert_eq!(store.find(&[Condition::eq(2, 1usize)]).count(), 33);
+        let both = [Condition::eq(0, 3usize), Condition::eq(2, 0usize)];
+        assert_eq!(store.find(&both).count(), 4);
+        let never = [Condition::eq(0, 1usize), Condition::eq(0, 2usize)];
+        assert_eq!(store.find(&never).count(), 0);
+        assert_eq!(store.query(&[Condition::eq(0, 5usize)]).limit(2).iter().count(), 2);
+
+        let stats = store.query_stats();
+        assert_eq!(stats.queries, 5);
+        assert_eq!(stats.rows_examined, 10 + 100 + 10 + 2);
+        assert_eq!(stats.rows_returned, 10 + 33 + 4 + 2);
+        assert_eq!(stats.columns.len(), 2);
+        assert_eq!(stats.columns[&0],
+                   ColumnStats {
+                       indexed: 3,
+                       scanned: 0,
+                   });
+        assert_eq!(stats.columns[&2],
+                   ColumnStats {
+                       indexed: 0,
+                       scanned: 1,
+                   });
+
+        // a clone counts its own queries
+        let clone = store.clone();
+        clone.find(&[]).count();
+        assert_eq!(clone.query_stats().queries, 1);
+        assert_eq!(store.query_stats().queries, 5);
+
+        store.reset_query_stats();
+        assert_eq!(store.query_stats(), QueryStats::default());
+        store.find(&[Condition::eq(1, 7usize)]).next();
+        assert_eq!(store.query_stats().rows_examined, 8);
+        assert_eq!(store.query_stats().columns[&1].scanned, 1);
+
+        store.disable_query_stats();
+        store.find(&[]).count();
+        assert_eq!(store.query_stats(), QueryStats::default());
+    }
+
+    /// Set an observer on the given store that collects the summaries of all queries.
+    fn observe(store: &mut Store<usize>) -> Arc<Mutex<Vec<QuerySummary>>> {
+        let summaries = Arc::new(Mutex::new(Vec::new()));
+        let collected = Arc::clone(&summaries);
+        store.set_query_observer(Some(Box::new(move |summary: &QuerySummary| {
+            collected.lock().unwrap().push(summary.clone());
+        })));
+        summaries
+    }
+
+    #[test]
+    fn it_observes_queries() {
+        let mut store = store();
+        let summaries = observe(&mut store);
+
+        // an indexed query only looks at the rows with the value
+        let indexed = [Condition::eq(0, 3usize), Condition::lt(1, 50usize)];
+        assert_eq!(store.find(&indexed).count(), 5);
+        // an unindexed query looks at every row
+        let unindexed = [Condition::eq(2, 1usize)];
+        assert_eq!(store.find(&unindexed).count(), 33);
+        {
+            let summaries = summaries.lock().unwrap();
+            assert_eq!(summaries.len(), 2);
+            assert_eq!(summaries[0].access, Some(Access::Index(0)));
+            assert_eq!((summaries[0].examined, summaries[0].returned), (10, 5));
+            assert_eq!(summaries[1].access, Some(Access::Scan));
+            assert_eq!((summaries[1].examined, summaries[1].returned), (100, 33));
+        }
+
+        // a query that is dropped early reports the rows it got to
+        {
+            let mut rows = store.find(&unindexed);
+            rows.next();
+            rows.next();
+            assert_eq!(summaries.lock().unwrap().len(), 2);
+        }
+        let last = summaries.lock().unwrap().pop().unwrap();
+        assert_eq!((last.examined, last.returned), (5, 2));
+
+        // an exact lookup counts its rows just the same
+        assert_eq!(store.find(&[Condition::eq(0, 4usize)]).count(), 10);
+        let last = summaries.lock().unwrap().pop().unwrap();
+        assert_eq!(last.access, Some(Access::Index(0)));
+        assert_eq!((last.examined, last.returned), (10, 10));
+
+        store.set_query_observer(None);
+        store.find(&indexed).count();
+        assert_eq!(summaries.lock().unwrap().len(), 2);
+    }
+}
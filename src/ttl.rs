@@ -0,0 +1,161 @@
+//! Expiration of rows by the value in a designated expiry column.
+//!
+//! The expiry column holds the time at which each row expires, in whatever unit `T` uses for time.
+//! Expired rows are only removed when `Store::expire` is called with the current time; until
+//! then, `Store::find_unexpired` can be used to skip them.
+
+use std::borrow::Cow;
+
+use idx::BTreeIndex;
+use Index;
+use Row;
+use Store;
+use cmp::{Comparison, Condition, Value};
+
+impl<T, R> Store<T, R>
+    where T: Ord + Clone + Send + Sync + 'static,
+          R: Row<T>
+{
+    /// Designate the given column as the expiry column, which holds the time at which each row
+    /// expires. Rows whose expiry time is at or before a given time can then be removed in bulk
+    /// with `expire`.
+    ///
+    /// Unless the column already has an `Index::Range`, a `BTreeIndex` is added to it, so that
+    /// `expire` only has to look at the rows that have expired. Panics if the column does not
+    /// exist.
+    pub fn set_expiry_column(&mut self, column: usize) {
+        assert!(column < self.cols,
+                "expiry column {} is out of range for a store with {} columns",
+                column,
+                self.cols);
+        if !matches!(self.indices.get(&column), Some(Index::Range(..))) {
+            self.index(column, BTreeIndex::new());
+        }
+        self.expiry = Some(column);
+    }
+}
+
+impl<T, R> Store<T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Returns the expiry column, if one has been set with `set_expiry_column`.
+    pub fn expiry_column(&self) -> Option<usize> {
+        self.expiry
+    }
+
+    /// Delete every row whose value in the expiry column is at or before `now`, and return the
+    /// number of rows deleted. The rows are found through the range index on the expiry column,
+    /// and are removed from all indices, just as with `delete`.
+    ///
+    /// If the index on the expiry column has since been replaced by one that cannot answer range
+    /// queries, all rows are scanned instead. Panics if no expiry column has been set.
+    pub fn expire(&mut self, now: &T) -> usize {
+        let conds = [self.expiry_condition(now, false)];
+        self.delete(&conds)
+    }
+
+    /// Like `find`, but skips rows whose value in the expiry column is at or before `now`, even if
+    /// they have not yet been removed by `expire`. Querying with the same `now` that is later
+    /// passed to `expire` thus yields exactly the rows that `expire` leaves in place.
+    ///
+    /// The extra condition is only checked for rows that match all the given conditions, so it
+    /// never changes which index is used. Panics if no expiry column has been set.
+    pub fn find_unexpired<'c, 's: 'c>(&'s self,
+                                      now: &'c T,
+                                      conds: &'c [Condition<'c, T>])
+                                      -> Box<dyn Iterator<Item = &'s R> + 'c> {
+        let unexpired = self.expiry_condition(now, true);
+        Box::new(self.find(conds).filter(move |row| unexpired.matches(*row)))
+    }
+
+    /// Returns a condition on the expiry column that matches rows that have expired by `now`, or,
+    /// if `unexpired` is true, rows that have not.
+    fn expiry_condition<'c>(&self, now: &'c T, unexpired: bool) -> Condition<'c, T> {
+        let column = self.expiry.expect("no expiry column has been set");
+        let now = Value::Const(Cow::Borrowed(now));
+        Condition {
+            column,
+            cmp: if unexpired {
+                Comparison::Greater(now)
+            } else {
+                Comparison::LessOrEqual(now)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use idx::HashIndex;
+    use query;
+
+    /// A store of `[key, expiry]` rows, where the rows with keys `0..10` expire at time `key / 3`.
+    fn store() -> Store<usize> {
+        let mut store = Store::new(2);
+        for i in 0..10 {
+            store.insert(vec![i, i / 3]);
+        }
+        store.set_expiry_column(1);
+        store
+    }
+
+    fn keys<'a, I: Iterator<Item = &'a Vec<usize>>>(rows: I) -> Vec<usize> {
+        rows.map(|row| row[0]).collect()
+    }
+
+    #[test]
+    fn it_expires() {
+        let mut store = store();
+        assert_eq!(store.expiry_column(), Some(1));
+        let expired = [Condition::le(1, 1usize)];
+        assert_eq!(store.explain(&expired).access, query::Access::Index(1));
+
+        // rows 3, 4, and 5 all expire at time 1
+        assert_eq!(store.expire(&0), 3);
+        assert_eq!(store.expire(&0), 0);
+        assert_eq!(store.expire(&1), 3);
+        assert_eq!(keys(store.find(&[])), vec![6, 7, 8, 9]);
+        assert_eq!(store.check_integrity(), Ok(()));
+
+        // rows inserted after a sweep can expire in the next one
+        store.insert(vec![10, 1]);
+        assert_eq!(store.expire(&2), 4);
+        assert_eq!(keys(store.find(&[])), vec![9]);
+        assert_eq!(store.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn it_finds_unexpired() {
+        let mut store = store();
+        store.index(0, HashIndex::new());
+
+        // expired rows are still found by `find` until they are collected
+        assert_eq!(keys(store.find_unexpired(&1, &[])), vec![6, 7, 8, 9]);
+        assert_eq!(store.find(&[]).count(), 10);
+        let four = [Condition::eq(0, 4usize)];
+        assert_eq!(store.find_unexpired(&0, &four).count(), 1);
+        assert_eq!(store.find_unexpired(&1, &four).count(), 0);
+        assert_eq!(store.find(&four).count(), 1);
+
+        store.expire(&1);
+        assert_eq!(keys(store.find(&[])), keys(store.find_unexpired(&1, &[])));
+        assert_eq!(store.find(&four).count(), 0);
+    }
+
+    #[test]
+    fn it_adds_range_index() {
+        let mut store = Store::<usize>::new(2);
+        store.index(1, HashIndex::new());
+        store.set_expiry_column(1);
+        assert!(matches!(store.indices[&1], Index::Range(..)));
+    }
+
+    #[test]
+    #[should_panic(expected = "no expiry column")]
+    fn it_needs_expiry_column() {
+        let mut store = Store::<usize>::new(2);
+        store.expire(&0);
+    }
+}
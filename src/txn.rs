@@ -0,0 +1,75 @@
+use std::ops;
+
+use Row;
+use Store;
+
+/// A `Txn` groups a number of insertions into a `Store` so that either all or none of them take
+/// effect. It is constructed using `Store::transaction`.
+///
+/// Rows inserted into a `Txn` are buffered, and are only added to the `Store` and its indices
+/// when `commit` is called. If the `Txn` is dropped without being committed, all the buffered
+/// rows are discarded, and the `Store` is left as it was.
+///
+/// The buffered rows are *not* visible to queries until the `Txn` is committed: `store` gives
+/// access to the `Store` as it was when the transaction started.
+pub struct Txn<'s, T: 's, R: 's = Vec<T>> {
+    store: &'s mut Store<T, R>,
+    rows: Vec<R>,
+}
+
+impl<'s, T, R> Txn<'s, T, R>
+    where T: Ord + Clone,
+          R: Row<T>
+{
+    /// Start a new transaction against the given `Store`.
+    pub fn new(store: &'s mut Store<T, R>) -> Self {
+        Txn {
+            store,
+            rows: Vec::new(),
+        }
+    }
+
+    /// Buffer the given row for insertion when the transaction is committed, and return the
+    /// identifier the row will have once it is.
+    ///
+    /// The row **must** have the same number of columns as the `Store`. If it does not, the code
+    /// will panic, and the transaction is rolled back.
+    pub fn insert(&mut self, row: R) -> usize {
+        assert!(row.columns() == self.store.cols,
+                "row has {} columns, but the store has {}",
+                row.columns(),
+                self.store.cols);
+        self.rows.push(row);
+        self.store.rowid + self.rows.len() - 1
+    }
+
+    /// Returns the number of rows buffered in this transaction.
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Returns true if no rows have been buffered in this transaction.
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// The `Store` as it was before the transaction started. Rows buffered in the transaction are
+    /// not visible through it.
+    pub fn store(&self) -> &Store<T, R> {
+        self.store
+    }
+
+    /// Insert all buffered rows into the `Store`, and return the range of identifiers assigned to
+    /// them.
+    ///
+    /// The rows are inserted using `Store::insert_many`, so if any of them conflicts with an
+    /// existing row (or another buffered row) in a column with a unique index, this method panics
+    /// without modifying the `Store`.
+    pub fn commit(self) -> ops::Range<usize> {
+        self.store.insert_many(self.rows)
+    }
+
+    /// Discard all buffered rows, leaving the `Store` unmodified. This is equivalent to dropping
+    /// the `Txn`.
+    pub fn rollback(self) {}
+}